@@ -1 +1,2 @@
-#include "esp_lcd_panel_rgb.h"
\ No newline at end of file
+#include "esp_lcd_panel_rgb.h"
+#include "esp_wireguard.h"
@@ -1,5 +1,23 @@
 fn main() {
+    // Chip cfgs (esp32s3, esp32, esp32c3, ...) that pick the board module
+    embuild::espidf::sysenv::output();
 
+    // Short git hash for the about screen, "unknown" when building outside a checkout
+    let build_hash = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|hash| hash.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=BUILD_HASH={}", build_hash);
+    println!("cargo:rerun-if-changed=.git/HEAD");
+
+    // Headless builds have no UI to compile
+    if std::env::var_os("CARGO_FEATURE_NO_UI").is_some() {
+        return;
+    }
     slint_build::compile_with_config(
         "ui/main.slint",
         slint_build::CompilerConfiguration::new()
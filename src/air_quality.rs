@@ -0,0 +1,222 @@
+// Optional Sensirion SCD40 CO2 sensor on the shared I2C bus, probed at boot. It measures every
+// five seconds on its own; a thread picks up the readings and publishes the CO2 level and the
+// humidity it measures along the way. When an ERV/HRV is wired to the ventilation relay the
+// backend runs it while CO2 is high, see `VentilationSettings`. Its interlocks (fan, outdoor
+// temperature) are enforced by `Controller::set_ventilation`.
+
+use std::sync::PoisonError;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+
+use crate::bsp::SharedI2c;
+use crate::bus::EventBus;
+use crate::events::{CommandSource, UiEvent};
+
+const ADDRESS: u8 = 0x62;
+const CMD_START_PERIODIC: u16 = 0x21B1;
+const CMD_STOP_PERIODIC: u16 = 0x3F86;
+const CMD_DATA_READY: u16 = 0xE4B8;
+const CMD_READ_MEASUREMENT: u16 = 0xEC05;
+const CMD_SERIAL_NUMBER: u16 = 0x3682;
+/// The sensor ignores commands for this long after stopping periodic measurement
+const STOP_DELAY: Duration = Duration::from_millis(500);
+/// Time the sensor needs between a read command and the data being ready to clock out
+const COMMAND_DELAY: Duration = Duration::from_millis(1);
+const I2C_TIMEOUT: u32 = 1000;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+const STACK_SIZE: usize = 4096;
+
+/// Run an ERV/HRV on the ventilation relay while CO2 is high
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VentilationSettings {
+    /// Ventilation starts above this CO2 level (ppm)
+    pub co2_threshold_ppm: u16,
+    /// And stops once CO2 is this far below the threshold again (ppm)
+    pub hysteresis_ppm: u16,
+    pub interlock: VentilationInterlock,
+}
+
+impl Default for VentilationSettings {
+    fn default() -> Self {
+        Self {
+            co2_threshold_ppm: 1000,
+            hysteresis_ppm: 150,
+            interlock: VentilationInterlock::default(),
+        }
+    }
+}
+
+/// Conditions the ERV/HRV only runs under
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct VentilationInterlock {
+    /// Hold the air handler fan on while ventilating, for units ducted into the return
+    pub require_fan: bool,
+    /// No ventilating while it's colder than this outside (Celsius), None for no limit
+    pub min_outdoor_c: Option<f32>,
+    /// No ventilating while it's warmer than this outside (Celsius), None for no limit
+    pub max_outdoor_c: Option<f32>,
+}
+
+impl Default for VentilationInterlock {
+    fn default() -> Self {
+        Self {
+            require_fan: true,
+            min_outdoor_c: Some(-15.0),
+            max_outdoor_c: Some(35.0),
+        }
+    }
+}
+
+impl VentilationInterlock {
+    /// Whether the outdoor temperature allows ventilating, as last sent to the MQTT
+    /// `outdoor_temp_c` topic. Fresh air isn't worth stopping for when nothing reports the
+    /// outdoor temperature or the last reading went stale, so that allows it.
+    pub fn outdoor_allows(&self, outdoor_temp_c: Option<f32>) -> bool {
+        let Some(outdoor_temp_c) = outdoor_temp_c else {
+            return true;
+        };
+        self.min_outdoor_c.is_none_or(|min| outdoor_temp_c >= min)
+            && self.max_outdoor_c.is_none_or(|max| outdoor_temp_c <= max)
+    }
+}
+
+impl VentilationSettings {
+    /// Whether to ventilate at this CO2 level, given whether ventilation is running now
+    pub fn should_ventilate(&self, co2_ppm: u16, ventilating: bool) -> bool {
+        co2_ppm > self.co2_threshold_ppm
+            || (ventilating && co2_ppm > self.co2_threshold_ppm.saturating_sub(self.hysteresis_ppm))
+    }
+}
+
+/// One SCD40 measurement
+#[derive(Debug, Clone, Copy)]
+pub struct Measurement {
+    pub co2_ppm: u16,
+    pub temperature_c: f32,
+    pub humidity_percent: f32,
+}
+
+/// CRC-8 the sensor sends after each 16 bit word (polynomial 0x31, init 0xFF)
+fn crc8(data: &[u8]) -> u8 {
+    let mut crc = 0xFFu8;
+    for byte in data {
+        crc ^= byte;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 { (crc << 1) ^ 0x31 } else { crc << 1 };
+        }
+    }
+    crc
+}
+
+pub struct Scd40 {
+    i2c: SharedI2c,
+}
+
+impl Scd40 {
+    /// Returns None when there is no SCD40 on the bus. Starts periodic measurement when found.
+    pub fn probe(i2c: SharedI2c) -> Option<Self> {
+        let sensor = Self { i2c };
+        // It may still be measuring from before a reset, and then only answers to stop
+        if sensor.command(CMD_STOP_PERIODIC).is_err() {
+            return None;
+        }
+        thread::sleep(STOP_DELAY);
+        let mut serial = [0u16; 3];
+        if sensor.read_words(CMD_SERIAL_NUMBER, &mut serial).is_err() {
+            return None;
+        }
+        if let Err(e) = sensor.command(CMD_START_PERIODIC) {
+            log::error!("Failed to start SCD40 measurements: {}", e);
+            return None;
+        }
+        log::info!("SCD40 CO2 sensor found, serial {:04x}{:04x}{:04x}", serial[0], serial[1], serial[2]);
+        Some(sensor)
+    }
+
+    fn command(&self, command: u16) -> anyhow::Result<()> {
+        let mut i2c = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+        i2c.write(ADDRESS, &command.to_be_bytes(), I2C_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Send a command and read back words, checking each one's CRC
+    fn read_words(&self, command: u16, words: &mut [u16]) -> anyhow::Result<()> {
+        let mut raw = [0u8; 9];
+        let raw = &mut raw[..words.len() * 3];
+        {
+            let mut i2c = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+            i2c.write(ADDRESS, &command.to_be_bytes(), I2C_TIMEOUT)?;
+            thread::sleep(COMMAND_DELAY);
+            i2c.read(ADDRESS, raw, I2C_TIMEOUT)?;
+        }
+        for (word, chunk) in words.iter_mut().zip(raw.chunks_exact(3)) {
+            if crc8(&chunk[..2]) != chunk[2] {
+                return Err(anyhow!("SCD40 CRC mismatch"));
+            }
+            *word = u16::from_be_bytes([chunk[0], chunk[1]]);
+        }
+        Ok(())
+    }
+
+    /// The latest measurement, None if there is no new one since the last read
+    pub fn read(&self) -> anyhow::Result<Option<Measurement>> {
+        let mut status = [0u16];
+        self.read_words(CMD_DATA_READY, &mut status)?;
+        if status[0] & 0x07FF == 0 {
+            return Ok(None);
+        }
+        let mut words = [0u16; 3];
+        self.read_words(CMD_READ_MEASUREMENT, &mut words)?;
+        Ok(Some(Measurement {
+            co2_ppm: words[0],
+            temperature_c: -45.0 + 175.0 * words[1] as f32 / 65535.0,
+            humidity_percent: 100.0 * words[2] as f32 / 65535.0,
+        }))
+    }
+}
+
+/// Poll the sensor on its own thread and publish its readings to the backend
+pub fn spawn(sensor: Scd40, bus: EventBus) -> anyhow::Result<()> {
+    thread::Builder::new()
+        .name("air_quality".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || loop {
+            thread::sleep(POLL_INTERVAL);
+            match sensor.read() {
+                Ok(Some(measurement)) => {
+                    log::debug!("SCD40: {:?}", measurement);
+                    bus.publish_command(CommandSource::Sensor, UiEvent::Co2Update(measurement.co2_ppm));
+                    bus.publish_command(CommandSource::Sensor, UiEvent::HumidityUpdate(measurement.humidity_percent));
+                }
+                Ok(None) => {}
+                Err(e) => log::warn!("Failed to read SCD40: {}", e),
+            }
+        })?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_outdoor_temperature_holds_ventilation_off_outside_the_limits() {
+        let interlock = VentilationInterlock::default();
+        assert!(!interlock.outdoor_allows(Some(-20.0)));
+        assert!(interlock.outdoor_allows(Some(10.0)));
+        assert!(!interlock.outdoor_allows(Some(38.0)));
+    }
+
+    #[test]
+    fn no_outdoor_temperature_allows_ventilating() {
+        assert!(VentilationInterlock::default().outdoor_allows(None));
+        let unlimited = VentilationInterlock { min_outdoor_c: None, max_outdoor_c: None, ..Default::default() };
+        assert!(unlimited.outdoor_allows(Some(-40.0)));
+    }
+}
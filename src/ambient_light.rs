@@ -0,0 +1,150 @@
+// Optional ambient light sensor (BH1750 or VEML7700) on the shared I2C bus, used to scale
+// the screen brightness with the room. The backlight on this board can only be switched on
+// or off, so brightness is applied by the ui as a dimming overlay, same as quiet hours.
+
+use std::sync::PoisonError;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bsp::SharedI2c;
+
+const BH1750_ADDRESS: u8 = 0x23;
+const BH1750_POWER_ON: u8 = 0x01;
+/// Continuous high resolution mode, 1 lux steps, a new reading every 120ms
+const BH1750_CONTINUOUS_HIGH_RES: u8 = 0x10;
+const VEML7700_ADDRESS: u8 = 0x10;
+const VEML7700_REG_CONFIG: u8 = 0x00;
+const VEML7700_REG_ALS: u8 = 0x04;
+/// Lux per count at gain 1 and 100ms integration, the power on defaults
+const VEML7700_LUX_PER_COUNT: f32 = 0.0576;
+const I2C_TIMEOUT: u32 = 1000;
+/// Light level the brightness tops out at, a bright room during the day
+const FULL_BRIGHTNESS_LUX: f32 = 1000.0;
+/// Weight of each new reading, so a passing shadow doesn't flicker the screen
+const SMOOTHING: f32 = 0.2;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SensorKind {
+    Bh1750,
+    Veml7700,
+}
+
+pub struct AmbientLightSensor {
+    i2c: SharedI2c,
+    kind: SensorKind,
+}
+
+impl AmbientLightSensor {
+    /// Look for a supported sensor on the bus and start it measuring
+    pub fn probe(i2c: SharedI2c) -> Option<Self> {
+        let found = {
+            let mut bus = i2c.lock().unwrap_or_else(PoisonError::into_inner);
+            if bus.write(BH1750_ADDRESS, &[BH1750_POWER_ON], I2C_TIMEOUT).is_ok()
+                && bus.write(BH1750_ADDRESS, &[BH1750_CONTINUOUS_HIGH_RES], I2C_TIMEOUT).is_ok()
+            {
+                Some(SensorKind::Bh1750)
+            } else if bus.write(VEML7700_ADDRESS, &[VEML7700_REG_CONFIG, 0x00, 0x00], I2C_TIMEOUT).is_ok() {
+                // Gain 1, 100ms integration, powered on
+                Some(SensorKind::Veml7700)
+            } else {
+                None
+            }
+        };
+        found.map(|kind| {
+            log::info!("Ambient light sensor found: {:?}", kind);
+            Self { i2c, kind }
+        })
+    }
+
+    pub fn read_lux(&self) -> anyhow::Result<f32> {
+        let mut raw = [0u8; 2];
+        let mut bus = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+        match self.kind {
+            SensorKind::Bh1750 => {
+                bus.read(BH1750_ADDRESS, &mut raw, I2C_TIMEOUT)?;
+                Ok(u16::from_be_bytes(raw) as f32 / 1.2)
+            }
+            SensorKind::Veml7700 => {
+                bus.write_read(VEML7700_ADDRESS, &[VEML7700_REG_ALS], &mut raw, I2C_TIMEOUT)?;
+                Ok(u16::from_le_bytes(raw) as f32 * VEML7700_LUX_PER_COUNT)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrightnessSettings {
+    /// Follow the ambient light sensor, when there is one
+    pub auto: bool,
+    /// Brightness in a dark room with auto brightness (percent)
+    pub min_percent: u8,
+    /// Brightness in a bright room with auto brightness (percent)
+    pub max_percent: u8,
+    /// Brightness when auto brightness is off or there is no sensor (percent)
+    pub manual_percent: u8,
+}
+
+impl Default for BrightnessSettings {
+    fn default() -> Self {
+        Self {
+            auto: true,
+            min_percent: 20,
+            max_percent: 100,
+            manual_percent: 100,
+        }
+    }
+}
+
+impl BrightnessSettings {
+    /// Brightness for a light level. Eyes respond to light logarithmically, so the scale is too.
+    pub fn for_lux(&self, lux: f32) -> u8 {
+        let max = self.max_percent.min(100);
+        let min = self.min_percent.min(max);
+        let level = ((lux.max(0.0) + 1.0).log10() / (FULL_BRIGHTNESS_LUX + 1.0).log10()).clamp(0.0, 1.0);
+        min + ((max - min) as f32 * level).round() as u8
+    }
+}
+
+/// Picks the brightness to show from the settings and the sensor, if any
+pub struct AutoBrightness {
+    sensor: Option<AmbientLightSensor>,
+    settings: BrightnessSettings,
+    smoothed_lux: Option<f32>,
+}
+
+impl AutoBrightness {
+    pub fn new(sensor: Option<AmbientLightSensor>) -> Self {
+        Self {
+            sensor,
+            settings: BrightnessSettings::default(),
+            smoothed_lux: None,
+        }
+    }
+
+    pub fn has_sensor(&self) -> bool {
+        self.sensor.is_some()
+    }
+
+    pub fn configure(&mut self, settings: BrightnessSettings) {
+        self.settings = settings;
+    }
+
+    /// Read the sensor and return the brightness to show (percent)
+    pub fn update(&mut self) -> u8 {
+        let Some(sensor) = self.sensor.as_ref().filter(|_| self.settings.auto) else {
+            return self.settings.manual_percent.min(100);
+        };
+        match sensor.read_lux() {
+            Ok(lux) => {
+                let smoothed = self.smoothed_lux.map_or(lux, |previous| previous + (lux - previous) * SMOOTHING);
+                self.smoothed_lux = Some(smoothed);
+            }
+            Err(e) => log::warn!("Failed to read ambient light: {}", e),
+        }
+        match self.smoothed_lux {
+            Some(lux) => self.settings.for_lux(lux),
+            None => self.settings.manual_percent.min(100),
+        }
+    }
+}
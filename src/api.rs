@@ -0,0 +1,562 @@
+// The network api: a small REST server on the ESP-IDF http server, which runs the handlers on
+// its own task. Every request needs the credentials from `auth`, a token made up on first boot
+// until an installer sets others. With a certificate uploaded it is served over HTTPS only.
+//
+// Nothing here touches the backend directly. The state comes off the bus like it does for the
+// touch ui, and what the backend keeps is asked for over the bus, the reply matched up by
+// command id. A slow client can't hold up the control loop.
+//
+// Installer only commands need an installer session once an installer code is set: log in with
+// `POST /installer/login` and send the token it answers with as `X-Installer-Session`.
+
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use chrono::{NaiveDate, Weekday};
+use embedded_svc::http::Headers;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::server::{Configuration, EspHttpConnection, EspHttpServer, Request};
+use esp_idf_svc::http::Method;
+use esp_idf_svc::tls::X509;
+use serde::Deserialize;
+
+use crate::auth::{ApiCredentials, TlsMaterial};
+use crate::bus::{EventBus, Message, Topic};
+use crate::comfort_profile::ComfortSettings;
+use crate::demand_response;
+use crate::events::{BackendEvent, CommandId, CommandOutcome, CommandSource, Diagnostics, DisplayPrecision, Snapshot, UiEvent};
+use crate::installer::{InstallerSettings, Secret};
+use crate::log_tail;
+use crate::remote_sensors::BleSensor;
+use crate::schedule::ScheduleProfile;
+use crate::settings::ConfigBackup;
+use crate::storage::Storage;
+use crate::vacation::Vacation;
+use crate::validation::CommandRejection;
+
+/// Handlers format JSON on the http server's task
+const STACK_SIZE: usize = 10 * 1024;
+/// Largest request body taken
+const MAX_BODY_LEN: usize = 16 * 1024;
+const STATE_POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// How long a request waits for the backend, which takes commands every few seconds
+const REPLY_TIMEOUT: Duration = Duration::from_secs(15);
+
+type HttpRequest<'r, 'c> = Request<&'r mut EspHttpConnection<'c>>;
+
+/// What a handler answers with
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: String,
+}
+
+impl Reply {
+    fn json(body: String) -> Self {
+        Self { status: 200, content_type: "application/json", body }
+    }
+
+    fn text(status: u16, message: impl Into<String>) -> Self {
+        Self { status, content_type: "text/plain", body: message.into() }
+    }
+
+    fn timeout() -> Self {
+        Self::text(504, "no answer from the thermostat")
+    }
+}
+
+/// What a handler gets, the same for every request apart from the session
+#[derive(Clone)]
+struct Context {
+    bus: EventBus,
+    /// Replaced when an installer sets new ones
+    credentials: Arc<Mutex<ApiCredentials>>,
+    /// Latest snapshot the backend published
+    snapshot: Arc<Mutex<Option<Snapshot>>>,
+    /// The installer session token the request came with
+    session: Option<Secret>,
+}
+
+type Handler = fn(&Context, &str, Vec<u8>) -> Reply;
+
+/// Start the api. It is served for as long as the returned server is kept.
+pub fn spawn(bus: EventBus, storage: &mut Storage) -> anyhow::Result<EspHttpServer<'static>> {
+    let credentials = ApiCredentials::load_or_generate(storage);
+    if !credentials.is_configured() {
+        log::warn!("No api credentials configured, the api refuses every request");
+    }
+    let mut configuration = Configuration { stack_size: STACK_SIZE, ..Default::default() };
+    if let Some(tls) = TlsMaterial::load(storage) {
+        // The server keeps pointers to these for as long as it runs, which is until power off
+        configuration.server_certificate = Some(X509::pem_until_nul(leak_pem(tls.cert_pem.into_bytes())));
+        configuration.private_key = Some(X509::pem_until_nul(leak_pem(tls.key_pem.into_bytes())));
+        log::info!("Serving the api over HTTPS");
+    } else {
+        log::warn!("No TLS certificate provisioned, serving the api over plain HTTP");
+    }
+    let context = Context {
+        bus: bus.clone(),
+        credentials: Arc::new(Mutex::new(credentials)),
+        snapshot: Arc::new(Mutex::new(None)),
+        session: None,
+    };
+    watch_state(bus, context.clone())?;
+
+    let mut server = EspHttpServer::new(&configuration)?;
+    route(&mut server, &context, "/status", Method::Get, status)?;
+    route(&mut server, &context, "/audit", Method::Get, audit)?;
+    route(&mut server, &context, "/transitions", Method::Get, transitions)?;
+    route(&mut server, &context, "/metrics", Method::Get, metrics)?;
+    route(&mut server, &context, "/history", Method::Get, history)?;
+    route(&mut server, &context, "/logs", Method::Get, logs)?;
+    route(&mut server, &context, "/refresh", Method::Post, refresh)?;
+    route(&mut server, &context, "/config", Method::Get, export_config)?;
+    route(&mut server, &context, "/config", Method::Put, import_config)?;
+    route(&mut server, &context, "/signing-secret", Method::Put, set_signing_secret)?;
+    route(&mut server, &context, "/installer/login", Method::Post, installer_login)?;
+    route(&mut server, &context, "/installer/logout", Method::Post, installer_logout)?;
+    route(&mut server, &context, "/installer/code", Method::Put, set_installer_code)?;
+    route(&mut server, &context, "/installer/settings", Method::Put, set_installer_settings)?;
+    route(&mut server, &context, "/credentials", Method::Put, set_credentials)?;
+    route(&mut server, &context, "/tls", Method::Put, set_tls)?;
+    route(&mut server, &context, "/tls", Method::Delete, remove_tls)?;
+    route(&mut server, &context, "/demand-response", Method::Post, start_demand_response)?;
+    route(&mut server, &context, "/demand-response", Method::Delete, end_demand_response)?;
+    route(&mut server, &context, "/demo-temperature", Method::Put, start_demo_temperature)?;
+    route(&mut server, &context, "/demo-temperature", Method::Delete, end_demo_temperature)?;
+    route(&mut server, &context, "/schedules", Method::Get, schedules)?;
+    route(&mut server, &context, "/schedules", Method::Put, set_schedule)?;
+    route(&mut server, &context, "/schedules", Method::Delete, remove_schedule)?;
+    route(&mut server, &context, "/schedules/rename", Method::Post, rename_schedule)?;
+    route(&mut server, &context, "/schedules/exception", Method::Put, set_schedule_exception)?;
+    route(&mut server, &context, "/vacation", Method::Put, set_vacation)?;
+    route(&mut server, &context, "/vacation", Method::Delete, end_vacation)?;
+    route(&mut server, &context, "/comfort", Method::Put, set_custom_comfort)?;
+    route(&mut server, &context, "/sensors/espnow", Method::Delete, unpair_sensor)?;
+    route(&mut server, &context, "/sensors/ble", Method::Get, ble_sensors)?;
+    route(&mut server, &context, "/sensors/ble", Method::Put, set_ble_sensors)?;
+    Ok(server)
+}
+
+/// Keep the latest snapshot for `/status`, the backend only publishes one when something
+/// changed, and pick up new credentials
+fn watch_state(bus: EventBus, context: Context) -> anyhow::Result<()> {
+    let state_rx = bus.subscribe(&[Topic::State]);
+    // Temperatures go out rounded like the screen shows them
+    let mut units = (false, DisplayPrecision::Tenth);
+    thread::Builder::new().name("api-state".to_string()).spawn(move || loop {
+        match state_rx.recv_timeout(STATE_POLL_INTERVAL) {
+            Ok(Message::State(BackendEvent::Snapshot(snapshot))) => {
+                let snapshot = snapshot.at_display_precision(units.0, units.1);
+                *context.snapshot.lock().unwrap_or_else(PoisonError::into_inner) = Some(snapshot);
+            }
+            Ok(Message::State(BackendEvent::SettingsUpdate(settings))) => {
+                units = (settings.use_fahrenheit, settings.display_precision);
+            }
+            Ok(Message::State(BackendEvent::ApiCredentialsChanged(credentials))) => {
+                *context.credentials.lock().unwrap_or_else(PoisonError::into_inner) = credentials;
+                log::info!("Api credentials replaced");
+            }
+            _ => {}
+        }
+    })?;
+    Ok(())
+}
+
+/// Register `handler` for `uri`, behind the credentials check
+fn route(server: &mut EspHttpServer<'static>, context: &Context, uri: &str, method: Method, handler: Handler) -> anyhow::Result<()> {
+    let shared = context.clone();
+    let path = uri.to_string();
+    server.fn_handler(uri, method, move |mut request| -> anyhow::Result<()> {
+        let context = Context {
+            session: request.header("X-Installer-Session").map(|token| Secret(token.trim().to_string())),
+            ..shared.clone()
+        };
+        let authorized = context.credentials.lock().unwrap_or_else(PoisonError::into_inner).authorize(request.header("Authorization"));
+        let reply = if !authorized {
+            Reply::text(401, "unauthorized")
+        } else {
+            let query = request.uri().split_once('?').map(|(_, query)| query.to_string()).unwrap_or_default();
+            match read_body(&mut request) {
+                Ok(body) => handler(&context, &query, body),
+                Err(reply) => reply,
+            }
+        };
+        if reply.status >= 400 {
+            log::warn!("Api {} answered {}: {}", path, reply.status, reply.body);
+        }
+        let mut headers = vec![("content-type", reply.content_type)];
+        if reply.status == 401 {
+            headers.push(("www-authenticate", "Basic realm=\"thermostat\""));
+        }
+        let mut response = request.into_response(reply.status, None, &headers)?;
+        response.write_all(reply.body.as_bytes())?;
+        Ok(())
+    })?;
+    Ok(())
+}
+
+fn read_body(request: &mut HttpRequest) -> Result<Vec<u8>, Reply> {
+    let len = request.content_len().unwrap_or(0) as usize;
+    if len > MAX_BODY_LEN {
+        return Err(Reply::text(413, "request body too large"));
+    }
+    let mut body = vec![0; len];
+    request.read_exact(&mut body).map_err(|_| Reply::text(400, "failed to read the request body"))?;
+    Ok(body)
+}
+
+/// Publish `event` from the api and wait for the backend's reply to it, picked out by `reply`
+fn request<T>(context: &Context, event: UiEvent, mut reply: impl FnMut(CommandId, BackendEvent) -> Option<T>) -> Option<T> {
+    // Subscribed first so the reply can't come before
+    let state_rx = context.bus.subscribe(&[Topic::State]);
+    let id = context.bus.publish_command_in_session(CommandSource::Http, context.session.clone(), event);
+    let deadline = Instant::now() + REPLY_TIMEOUT;
+    loop {
+        match state_rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(Message::State(event)) => {
+                if let Some(reply) = reply(id, event) {
+                    return Some(reply);
+                }
+            }
+            Ok(Message::Command(_)) => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Publish a state changing command from the api and answer with what the backend did with it
+fn command(context: &Context, event: UiEvent) -> Reply {
+    let outcome = request(context, event, |id, event| match event {
+        BackendEvent::CommandAck { id: acked, outcome, .. } if acked == id => Some(outcome),
+        _ => None,
+    });
+    match outcome {
+        Some(CommandOutcome::Applied) => Reply::text(200, "applied"),
+        Some(CommandOutcome::Adjusted(event)) => Reply::text(200, format!("applied as {:?}", event)),
+        Some(CommandOutcome::Rejected(rejection)) => Reply::text(rejection_status(&rejection), rejection.to_string()),
+        None => Reply::timeout(),
+    }
+}
+
+fn rejection_status(rejection: &CommandRejection) -> u16 {
+    match rejection {
+        CommandRejection::RateLimited(_) => 429,
+        CommandRejection::InstallerOnly | CommandRejection::WrongInstallerCode => 403,
+        CommandRejection::QueueFull => 503,
+        _ => 422,
+    }
+}
+
+/// Ask the backend for a diagnostics export
+fn diagnostics(context: &Context, diagnostics: Diagnostics, content_type: &'static str) -> Reply {
+    let export = request(context, UiEvent::DiagnosticsRequest(diagnostics), |id, event| match event {
+        BackendEvent::DiagnosticsExport { id: replied, export } if replied == id => Some(export),
+        _ => None,
+    });
+    match export {
+        Some(Ok(body)) => Reply { status: 200, content_type, body },
+        Some(Err(e)) => Reply::text(500, e),
+        None => Reply::timeout(),
+    }
+}
+
+/// The certificate and key as the server wants them: NUL terminated and never freed
+fn leak_pem(mut pem: Vec<u8>) -> &'static [u8] {
+    if pem.last() != Some(&0) {
+        pem.push(0);
+    }
+    Vec::leak(pem)
+}
+
+/// `GET /status`: the backend state as last published, the same the touch ui shows
+fn status(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    let snapshot = context.snapshot.lock().unwrap_or_else(PoisonError::into_inner);
+    match snapshot.as_ref().map(serde_json::to_string) {
+        Some(Ok(json)) => Reply::json(json),
+        Some(Err(e)) => Reply::text(500, e.to_string()),
+        None => Reply::text(503, "the thermostat is still starting"),
+    }
+}
+
+/// `GET /audit`: the recent state changing commands and where they came from
+fn audit(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    diagnostics(context, Diagnostics::AuditLog, "application/json")
+}
+
+/// `GET /transitions`: the recent state changes and why they happened
+fn transitions(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    diagnostics(context, Diagnostics::Transitions, "application/json")
+}
+
+/// `GET /metrics`: heap, stack, timing and comfort metrics for Prometheus to scrape
+fn metrics(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    diagnostics(context, Diagnostics::Metrics, "text/plain; version=0.0.4")
+}
+
+/// `GET /history`: the daily minimum and maximum temperatures, with when they were measured
+fn history(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    diagnostics(context, Diagnostics::TemperatureHistory, "application/json")
+}
+
+/// `GET /logs?level=warn`: the buffered log lines, at the given level and above if there is one
+fn logs(_context: &Context, query: &str, _body: Vec<u8>) -> Reply {
+    let level = query.split('&').find_map(|pair| pair.strip_prefix("level="));
+    match log_tail::to_json(level) {
+        Ok(json) => Reply::json(json),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `POST /refresh`: read the sensor and run the state machine now. Refreshes aren't acked, the
+/// result shows up in `/status`.
+fn refresh(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    context.bus.publish_command(CommandSource::Http, UiEvent::ForceRefresh);
+    Reply::text(202, "refresh requested")
+}
+
+/// `POST /demand-response` with the length of the event in minutes: start a demand response
+/// event, replacing any running one
+fn start_demand_response(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match std::str::from_utf8(&body).map_err(anyhow::Error::from).and_then(demand_response::parse_signal) {
+        Ok(duration_mins) => command(context, UiEvent::DemandResponseSignal(duration_mins)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `DELETE /demand-response`: end the running demand response event
+fn end_demand_response(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    command(context, UiEvent::DemandResponseSignal(None))
+}
+
+/// `PUT /demo-temperature` with a room temperature in Celsius: show and control to it instead of
+/// the measured one for a while, to demonstrate a heat or cool call. Installer only.
+fn start_demo_temperature(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match std::str::from_utf8(&body).map_err(anyhow::Error::from).and_then(|text| Ok(text.trim().parse::<f32>()?)) {
+        Ok(temp_c) => command(context, UiEvent::DemoTemperature(Some(temp_c))),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `DELETE /demo-temperature`: go back to the measured temperature
+fn end_demo_temperature(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    command(context, UiEvent::DemoTemperature(None))
+}
+
+/// `GET /schedules`: the schedule profiles with their periods and exception days
+fn schedules(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    // The profiles only leave the backend as part of a backup
+    let export = request(context, UiEvent::ExportSettingsRequest, |_, event| match event {
+        BackendEvent::SettingsExport(json) => Some(json),
+        _ => None,
+    });
+    let Some(export) = export else {
+        return Reply::timeout();
+    };
+    match serde_json::from_str::<serde_json::Value>(&export) {
+        Ok(backup) => Reply::json(backup["schedule_profiles"].to_string()),
+        Err(e) => Reply::text(500, e.to_string()),
+    }
+}
+
+/// `PUT /schedules` with `{"name": "Home", "schedule": {"days": [...], "exceptions": [...]}}`:
+/// create the profile or replace the one with that name
+fn set_schedule(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<ScheduleProfile>(&body) {
+        Ok(profile) => command(context, UiEvent::ScheduleProfileUpdate { name: profile.name, schedule: Some(profile.schedule) }),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `DELETE /schedules` with the name of the profile to remove
+fn remove_schedule(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match String::from_utf8(body) {
+        Ok(name) => command(context, UiEvent::ScheduleProfileUpdate { name, schedule: None }),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct Rename {
+    from: String,
+    to: String,
+}
+
+/// `POST /schedules/rename` with `{"from": "Home", "to": "Winter"}`
+fn rename_schedule(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<Rename>(&body) {
+        Ok(Rename { from, to }) => command(context, UiEvent::RenameScheduleProfile { from, to }),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+#[derive(Deserialize)]
+struct Exception {
+    name: String,
+    date: NaiveDate,
+    follows: Option<Weekday>,
+}
+
+/// `PUT /schedules/exception` with `{"name": "Home", "date": "2026-12-25", "follows": "Sun"}`:
+/// run that day of the profile like the weekday it follows, or with `"follows": null` like itself
+fn set_schedule_exception(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<Exception>(&body) {
+        Ok(Exception { name, date, follows }) => command(context, UiEvent::ScheduleExceptionUpdate { name, date, follows }),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `PUT /vacation` with `{"start": "2026-07-01", "end": "2026-07-10", "heat_setpoint_c": 15,
+/// "cool_setpoint_c": 27}`: set the away period, replacing any other
+fn set_vacation(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<Vacation>(&body) {
+        Ok(vacation) => command(context, UiEvent::VacationUpdate(Some(vacation))),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `DELETE /vacation`: end or cancel the away period
+fn end_vacation(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    command(context, UiEvent::VacationUpdate(None))
+}
+
+/// `PUT /comfort` with `{"heat_differential_c": 0.4, "cool_differential_c": 0.7,
+/// "heat_anticipator_c": 0.0, "heat_fan_run_on_secs": 90, "cool_fan_run_on_secs": 45}`: the
+/// custom comfort profile, used while the comfort profile is set to custom
+fn set_custom_comfort(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<ComfortSettings>(&body) {
+        Ok(comfort) => command(context, UiEvent::CustomComfortUpdate(comfort)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `DELETE /sensors/espnow` with the name of the paired sensor to forget. Pairing a new one
+/// needs its button pressed next to the thermostat, so that is only done from the touch screen
+fn unpair_sensor(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match String::from_utf8(body) {
+        Ok(name) => command(context, UiEvent::UnpairSensor(name)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `GET /sensors/ble`: the BLE thermometers listened to
+fn ble_sensors(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    let export = request(context, UiEvent::ExportSettingsRequest, |_, event| match event {
+        BackendEvent::SettingsExport(json) => Some(json),
+        _ => None,
+    });
+    let Some(export) = export else {
+        return Reply::timeout();
+    };
+    match serde_json::from_str::<serde_json::Value>(&export) {
+        Ok(backup) => Reply::json(backup["settings"]["ble_sensors"].to_string()),
+        Err(e) => Reply::text(500, e.to_string()),
+    }
+}
+
+/// `PUT /sensors/ble` with `[{"address": "a4:c1:38:12:34:56", "name": "Bedroom"}]`: the BLE
+/// thermometers to listen to, replacing the list. Unlisted ones in range are logged with their
+/// address, to find the one to add.
+fn set_ble_sensors(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<Vec<BleSensor>>(&body) {
+        Ok(sensors) => command(context, UiEvent::BleSensorsUpdate(sensors)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `GET /config`: the settings and schedule profiles as one JSON backup, without the secrets
+/// and network identity of this unit
+fn export_config(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    // Exports carry no command id, any export is as current as the one asked for
+    let export = request(context, UiEvent::ExportSettingsRequest, |_, event| match event {
+        BackendEvent::SettingsExport(json) => Some(json),
+        _ => None,
+    });
+    export.map_or_else(Reply::timeout, Reply::json)
+}
+
+/// `PUT /config` with a backup from `GET /config`, possibly from another thermostat: restore it.
+/// Homeowners can't change the installer settings with it.
+fn import_config(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match std::str::from_utf8(&body).map_err(anyhow::Error::from).and_then(ConfigBackup::from_json) {
+        Ok(backup) => command(context, UiEvent::ImportConfig(backup)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `PUT /signing-secret` with the hex encoded secret MQTT commands have to be signed with, or
+/// an empty body to take them unsigned again. Installer only.
+fn set_signing_secret(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match String::from_utf8(body) {
+        Ok(secret) if secret.trim().is_empty() => command(context, UiEvent::SetSigningSecret(None)),
+        Ok(secret) => command(context, UiEvent::SetSigningSecret(Some(Secret(secret)))),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `POST /installer/login` with the installer code: start an installer session, answered with
+/// the token to send as `X-Installer-Session`
+fn installer_login(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    let code = match String::from_utf8(body) {
+        Ok(code) => Secret(code.trim().to_string()),
+        Err(e) => return Reply::text(400, e.to_string()),
+    };
+    let session = request(context, UiEvent::InstallerLogin(code), |id, event| match event {
+        BackendEvent::InstallerSession { id: replied, token } if replied == id => Some(Ok(token)),
+        BackendEvent::CommandAck { id: acked, outcome: CommandOutcome::Rejected(rejection), .. } if acked == id => Some(Err(rejection)),
+        _ => None,
+    });
+    match session {
+        Some(Ok(token)) => Reply::text(200, token.0),
+        Some(Err(rejection)) => Reply::text(rejection_status(&rejection), rejection.to_string()),
+        None => Reply::timeout(),
+    }
+}
+
+/// `POST /installer/logout`: end the installer session in `X-Installer-Session`
+fn installer_logout(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    command(context, UiEvent::InstallerLogout)
+}
+
+/// `PUT /installer/code` with the new installer code, or an empty body to remove it. Ends every
+/// installer session. Installer only.
+fn set_installer_code(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match String::from_utf8(body) {
+        Ok(code) if code.trim().is_empty() => command(context, UiEvent::SetInstallerCode(None)),
+        Ok(code) => command(context, UiEvent::SetInstallerCode(Some(Secret(code)))),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `PUT /installer/settings` with all of the installer settings, as in the `settings` of
+/// `GET /config`. Installer only.
+fn set_installer_settings(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<InstallerSettings>(&body) {
+        Ok(installer_settings) => command(context, UiEvent::InstallerSettingsUpdate(installer_settings)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `PUT /credentials` with `{"token": "...", "basic": ["user", "password"]}`, either or both:
+/// replace the credentials the api takes, from the next request on. Installer only.
+fn set_credentials(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<ApiCredentials>(&body) {
+        Ok(credentials) => command(context, UiEvent::SetApiCredentials(credentials)),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `PUT /tls` with `{"cert_pem": "...", "key_pem": "..."}`: serve HTTPS with this certificate,
+/// self-signed or CA issued, from the next restart. Installer only.
+fn set_tls(context: &Context, _query: &str, body: Vec<u8>) -> Reply {
+    match serde_json::from_slice::<TlsMaterial>(&body) {
+        Ok(tls) => command(context, UiEvent::SetTlsMaterial(Some(tls))),
+        Err(e) => Reply::text(400, e.to_string()),
+    }
+}
+
+/// `DELETE /tls`: go back to plain HTTP from the next restart. Installer only.
+fn remove_tls(context: &Context, _query: &str, _body: Vec<u8>) -> Reply {
+    command(context, UiEvent::SetTlsMaterial(None))
+}
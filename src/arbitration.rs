@@ -0,0 +1,75 @@
+// Arbitration between command sources fighting over the same setting. The last writer wins,
+// except that a higher priority source holds what it set for a while: a network client can't
+// flip back a mode picked on the touch screen a moment ago, and the schedule leaves a manual
+// setpoint alone for the whole hold period before catching up.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use crate::events::{CommandSource, UiEvent};
+
+/// How long a write holds against lower priority network sources
+const CONTENTION_WINDOW: Duration = Duration::from_secs(2 * 60);
+
+/// What sources contend over, commands only conflict when they set the same thing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Contended {
+    Mode,
+    Setpoint,
+    Fan,
+}
+
+impl Contended {
+    pub fn of(event: &UiEvent) -> Option<Self> {
+        match event {
+            UiEvent::ModeUpdate(_) => Some(Contended::Mode),
+            UiEvent::TargetTempUpdate(_) => Some(Contended::Setpoint),
+            UiEvent::FanUpdate(_) | UiEvent::FanTimer(_) => Some(Contended::Fan),
+            _ => None,
+        }
+    }
+}
+
+/// Someone at the thermostat beats home automation, which beats the schedule
+fn priority(source: CommandSource) -> u8 {
+    match source {
+        CommandSource::Touch | CommandSource::Recovery => 3,
+        CommandSource::Mqtt | CommandSource::Http => 2,
+        CommandSource::Schedule => 1,
+        CommandSource::Sensor => 0,
+    }
+}
+
+#[derive(Default)]
+pub struct Arbiter {
+    last_writes: HashMap<Contended, (CommandSource, Instant)>,
+}
+
+impl Arbiter {
+    /// Whether `source` may set `what` now. Fails with the source holding it otherwise.
+    /// `manual_hold` is how long manual changes hold against the schedule.
+    pub fn check(&self, source: CommandSource, what: Contended, manual_hold: Duration) -> Result<(), CommandSource> {
+        let Some(&(holder, written)) = self.last_writes.get(&what) else {
+            return Ok(());
+        };
+        if priority(source) >= priority(holder) {
+            return Ok(());
+        }
+        let hold = if source == CommandSource::Schedule { manual_hold } else { CONTENTION_WINDOW };
+        if written.elapsed() < hold {
+            Err(holder)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Note that `source` just set `what`
+    pub fn record(&mut self, source: CommandSource, what: Contended) {
+        self.last_writes.insert(what, (source, Instant::now()));
+    }
+
+    /// Drop the hold on `what`, the next write wins whoever it's from
+    pub fn release(&mut self, what: Contended) {
+        self.last_writes.remove(&what);
+    }
+}
@@ -0,0 +1,98 @@
+// Ring buffer of the last state changing commands and where they came from.
+// Persisted to NVS so it survives reboots.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::events::{Command, CommandSource};
+use crate::storage::Storage;
+use crate::time_format::TimeFormat;
+
+const STORAGE_KEY: &str = "audit_log";
+/// Number of entries kept before the oldest ones are dropped
+pub const AUDIT_LOG_CAPACITY: usize = 32;
+/// Repeated changes of the same kind from the same source within this window replace each other
+const COALESCE_WINDOW_SECS: u64 = 10;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// Seconds since the unix epoch (only meaningful once the clock has been set)
+    pub timestamp: u64,
+    pub source: CommandSource,
+    /// Human readable description of the change
+    pub change: String,
+}
+
+impl AuditEntry {
+    /// Single line summary, used by the audit log screen
+    pub fn summary(&self, format: &TimeFormat) -> String {
+        format!("{} {:?}: {}", format.timestamp(self.timestamp), self.source, self.change)
+    }
+}
+
+#[derive(Default)]
+pub struct AuditLog {
+    entries: VecDeque<AuditEntry>,
+    dirty: bool,
+}
+
+impl AuditLog {
+    /// Restore the log from storage, starting empty if nothing was persisted.
+    pub fn load(storage: &Storage) -> Self {
+        let entries: VecDeque<AuditEntry> = storage.load(STORAGE_KEY).unwrap_or_default();
+        Self { entries, dirty: false }
+    }
+
+    pub fn record(&mut self, command: &Command) {
+        let timestamp = clock::unix_secs();
+        let entry = AuditEntry {
+            timestamp,
+            source: command.source,
+            change: format!("{:?}", command.event),
+        };
+        self.dirty = true;
+        // Dragging the slider sends a stream of updates, only keep the final value
+        if let Some(last) = self.entries.back_mut() {
+            if last.source == entry.source
+                && entry.timestamp.saturating_sub(last.timestamp) < COALESCE_WINDOW_SECS
+                && kind(&last.change) == kind(&entry.change)
+            {
+                *last = entry;
+                return;
+            }
+        }
+        if self.entries.len() == AUDIT_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(entry);
+    }
+
+    /// Oldest entry first
+    pub fn entries(&self) -> impl Iterator<Item = &AuditEntry> {
+        self.entries.iter()
+    }
+
+    /// Export the log as JSON, oldest entry first
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&self.entries)?)
+    }
+
+    /// Write the log to storage if anything was recorded since the last save.
+    /// Batched so a burst of slider updates doesn't turn into a burst of flash writes.
+    pub fn save_if_dirty(&mut self, storage: &mut Storage) {
+        if !self.dirty {
+            return;
+        }
+        match storage.save(STORAGE_KEY, &self.entries) {
+            Ok(()) => self.dirty = false,
+            Err(e) => log::error!("Failed to persist audit log: {}", e),
+        }
+    }
+}
+
+/// The event variant name, e.g. "TargetTempUpdate" for "TargetTempUpdate(21.5)"
+fn kind(change: &str) -> &str {
+    change.split('(').next().unwrap_or(change)
+}
@@ -0,0 +1,149 @@
+// Credentials protecting the network api (see `api`): the stored credentials, the
+// Authorization header check and the optional TLS certificate/key to serve HTTPS with.
+//
+// A fresh device makes up a random token on first boot, shown on the installer screen. From
+// there an installer can replace the credentials and upload a certificate through the api.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::hex;
+use crate::installer::Secret;
+use crate::storage::Storage;
+
+const CREDENTIALS_KEY: &str = "api_creds";
+const TLS_CERT_KEY: &str = "tls_cert";
+const TLS_KEY_KEY: &str = "tls_key";
+/// Random bytes in a generated token
+const TOKEN_BYTES: usize = 16;
+/// Shortest token taken, and the shortest password for basic auth
+pub const MIN_TOKEN_LEN: usize = 16;
+pub const MIN_PASSWORD_LEN: usize = 8;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ApiCredentials {
+    /// Accepted as `Authorization: Bearer <token>`
+    pub token: Option<Secret>,
+    /// Accepted as `Authorization: Basic <base64(username:password)>`
+    pub basic: Option<(String, Secret)>,
+}
+
+impl ApiCredentials {
+    /// Stored credentials, or a token baked in at build time through `API_TOKEN`
+    pub fn load(storage: &Storage) -> Option<Self> {
+        storage.load(CREDENTIALS_KEY).or_else(|| {
+            option_env!("API_TOKEN").map(|token| Self {
+                token: Some(Secret(token.to_string())),
+                basic: None,
+            })
+        })
+    }
+
+    /// Like `load`, but on first boot a random token is made up and stored
+    pub fn load_or_generate(storage: &mut Storage) -> Self {
+        if let Some(credentials) = Self::load(storage) {
+            return credentials;
+        }
+        // SAFETY: plain getter with no preconditions, random once the radio is up and pseudo random before
+        let bytes: Vec<u8> = (0..TOKEN_BYTES / 4).flat_map(|_| unsafe { esp_idf_svc::sys::esp_random() }.to_le_bytes()).collect();
+        let credentials = Self { token: Some(Secret(hex::encode(&bytes))), basic: None };
+        match credentials.save(storage) {
+            Ok(()) => log::info!("Generated an api token, it is shown on the installer screen"),
+            Err(e) => log::error!("Failed to store the generated api token: {}", e),
+        }
+        credentials
+    }
+
+    /// Store new credentials, replacing the generated token
+    pub fn save(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save(CREDENTIALS_KEY, self)
+    }
+
+    /// No credentials configured means the api refuses everything rather than
+    /// being open, since it switches mains connected equipment.
+    pub fn is_configured(&self) -> bool {
+        self.token.is_some() || self.basic.is_some()
+    }
+
+    /// Check the value of an `Authorization` header against the stored credentials.
+    pub fn authorize(&self, header: Option<&str>) -> bool {
+        let Some(header) = header else {
+            return false;
+        };
+        if let (Some(token), Some(given)) = (&self.token, header.strip_prefix("Bearer ")) {
+            return constant_time_eq(token.0.as_bytes(), given.trim().as_bytes());
+        }
+        if let (Some((username, password)), Some(given)) = (&self.basic, header.strip_prefix("Basic ")) {
+            let expected = base64_encode(format!("{}:{}", username, password.0).as_bytes());
+            return constant_time_eq(expected.as_bytes(), given.trim().as_bytes());
+        }
+        false
+    }
+}
+
+/// PEM encoded certificate and private key for serving HTTPS, self-signed or CA issued. Made
+/// off the device (e.g. with openssl) and uploaded by an installer through the api.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsMaterial {
+    pub cert_pem: String,
+    pub key_pem: String,
+}
+
+/// Leaves the private key out, commands end up in the audit log as Debug output
+impl fmt::Debug for TlsMaterial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsMaterial").field("cert_pem", &self.cert_pem).finish_non_exhaustive()
+    }
+}
+
+impl TlsMaterial {
+    /// Returns None if no certificate was provisioned, in which case the api is served over plain http.
+    pub fn load(storage: &Storage) -> Option<Self> {
+        Some(Self {
+            cert_pem: String::from_utf8(storage.load_raw(TLS_CERT_KEY)?).ok()?,
+            key_pem: String::from_utf8(storage.load_raw(TLS_KEY_KEY)?).ok()?,
+        })
+    }
+
+    pub fn save(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save_raw(TLS_CERT_KEY, self.cert_pem.as_bytes())?;
+        storage.save_raw(TLS_KEY_KEY, self.key_pem.as_bytes())
+    }
+
+    /// Go back to plain http
+    pub fn remove(storage: &mut Storage) -> anyhow::Result<()> {
+        storage.remove(TLS_CERT_KEY)?;
+        storage.remove(TLS_KEY_KEY)
+    }
+
+    /// Whether both look like PEM, the http server only finds out what's inside at startup
+    pub fn is_valid(&self) -> bool {
+        self.cert_pem.contains("-----BEGIN CERTIFICATE-----") && self.key_pem.contains("PRIVATE KEY-----")
+    }
+}
+
+/// Compare without returning early so the time taken doesn't leak how much matched.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+    a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn base64_encode(input: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(input.len().div_ceil(3) * 4);
+    for chunk in input.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
@@ -1,31 +1,162 @@
 // Core logic for the thermostat to do all the things that
 // the ui cant, like reading the temp and sending events to the ui.
 
-use std::{time::{Duration, Instant}};
-use std::{
-    sync::mpsc::{self, Receiver, Sender, SyncSender},
-    sync::Arc,
-    thread,
-};
-use crate::{controller::Controller, events::{BackendEvent, DiffStatus, FanStatus, ModeStatus, RestStatus, UiEvent}};
+use std::{collections::VecDeque, fmt::Write, str::FromStr, time::{Duration, Instant}};
+use std::sync::mpsc::RecvTimeoutError;
+use chrono::NaiveDateTime;
+use serde::{Deserialize, Serialize};
+use crate::{arbitration::{Arbiter, Contended}, audit::AuditLog, auth::{ApiCredentials, TlsMaterial}, boost::Boost, checkpoint::{CoolingCheckpoint, CHECKPOINT_INTERVAL}, clock, timezone, handoff::Handoff, comfort, demand_response::DemandResponseEvent, enclosure::EnclosureMonitor, dual_fuel::HeatSource, open_window::OpenWindowDetector, overshoot::OvershootTracker, installer::{self, InstallerAccess, InstallerSettings}, metrics::{self, MemoryMonitor}, network, notify::{Notifier, Severity}, signing, wireguard, timing::{self, Probe, TimingMonitor}, ota::{BootHealthCheck, UpdateStatus, Updater}, peak::PeakPhase, power::BrownoutMonitor, remote_sensors::{RemoteSensors, SensorRef, WeightedSensor}, validation::{self, CommandRejection, RateLimiter}, bus::{self, EventBus, Message, Subscription, Topic}, sleep, schedule::{ScheduleProfile, ScheduleProfiles, DEFAULT_SCHEDULE_PROFILE, MAX_SCHEDULE_EXCEPTIONS}, self_test::SelfTestReport, settings::{ConfigBackup, Settings}, stats::{CycleCounts, CycleStats}, storage::Storage, trace::{TraceEvent, TraceRecorder}, trend::TemperatureTrend, transitions::{TransitionLog, TransitionReason}, summary::SummaryTracker, history::TemperatureHistory, webhook, wiring, controller::{Controller, ControllerError, TemperatureReading}, events::{BackendEvent, Capabilities, ComfortProfile, Command, CommandId, CommandOutcome, CommandSource, Diagnostics, SetpointEstimate, Snapshot, StatusMessage, FanStatus, ModeStatus, RestStatus, UiEvent}};
 
 
 const REST_DURATION_MINS: u64 = 30;
+/// How long the last valid reading is trusted while the sensor fails to read
+const SENSOR_HOLD_MINS: u64 = 5;
+/// Onboard readings outside this range are taken as failed reads, e.g. the 85°C a DS18B20
+/// reports after a power glitch (Celsius)
+const ONBOARD_TEMP_MIN_C: f32 = -30.0;
+const ONBOARD_TEMP_MAX_C: f32 = 70.0;
+/// After a state ran into its timeout guard, no new calls start for this long
+const STATE_TIMEOUT_LOCKOUT_MINS: u64 = 15;
+/// Minimum time the compressor stays off before starting again, whatever the mode, so the
+/// refrigerant pressures equalize
+const COMPRESSOR_MIN_OFF_MINS: u64 = 5;
+/// A demo temperature override ends on its own after this long, so a forgotten one can't
+/// leave the system controlling to a made up temperature
+const DEMO_TEMP_MINS: u64 = 15;
+/// An outdoor temperature older than this is forgotten. Without one the seasonal lockouts don't
+/// apply, dual fuel heats with the furnace and ventilation runs regardless of the weather.
+const OUTDOOR_TEMP_MAX_AGE: Duration = Duration::from_hours(1);
+/// Heating stays cut off until the temperature is this far below the high temperature cutoff
+const HIGH_TEMP_CUTOFF_HYSTERESIS_C: f32 = 2.0;
+/// Dehumidifying stops once humidity drops this far below the max humidity setting (percent RH)
+const DEHUMIDIFY_HYSTERESIS: f32 = 3.0;
+/// Estimates further out than this are too unreliable to show
+const MAX_SETPOINT_ESTIMATE_MINS: f32 = 8.0 * 60.0;
+/// An unchanged status is sent again this often, for subscribers that joined since
+const STATUS_KEEPALIVE: Duration = Duration::from_secs(30);
 
 pub struct ThermostatState {
-    ui_events_rx: Receiver<UiEvent>,
-    actor_events_tx: Sender<BackendEvent>,
-    /// Current temperature in Celsius (base unit)
-    current_temp_c: f32,
-    /// Target temperature in Celsius (base unit)
-    target_temp_c: f32,
-    mode: ModeStatus,
-    diff_mode: DiffStatus,
-    rest_mode: RestStatus,
-    fan_mode: FanStatus,
-    use_fahrenheit: bool,
+    bus: EventBus,
+    commands_rx: Subscription,
+    /// Commands received while waiting for the next tick, handled before the rest. No more than
+    /// the bus queue holds, see `wait_for_next_tick`
+    pending_commands: VecDeque<Message>,
+    /// A ForceRefresh came in: skip the wait and read the sensor right away
+    refresh_requested: bool,
+    updater: Updater,
+    installer: InstallerAccess,
+    /// What the api takes, kept to show the token on the installer screen
+    api_credentials: ApiCredentials,
+    /// Results of the boot self-test until they're published
+    self_test_report: Option<SelfTestReport>,
+    /// Only while a freshly updated firmware is on probation
+    boot_health_check: Option<BootHealthCheck>,
+    storage: Storage,
+    audit_log: AuditLog,
+    /// Recent inputs and transitions, to reproduce field issues
+    trace: TraceRecorder,
+    /// The trace persisted by the previous boot, as JSON
+    previous_trace: Option<String>,
+    /// Last onboard reading recorded in the trace, only changes are recorded
+    traced_onboard: Option<Option<f32>>,
+    #[cfg(feature = "replay")]
+    replay: Option<crate::trace::TraceReplay>,
+    /// Onboard reading from the replayed trace, taken instead of the sensor's on the next poll
+    #[cfg(feature = "replay")]
+    replay_onboard: Option<Option<f32>>,
+    rate_limiter: RateLimiter,
+    /// Who last set the mode, setpoint and fan, so a lower priority source can't undo it right away
+    arbiter: Arbiter,
+    summary: SummaryTracker,
+    cycle_stats: CycleStats,
+    temperature_history: TemperatureHistory,
+    temperature_history_published: bool,
+    trend: TemperatureTrend,
+    brownouts: BrownoutMonitor,
+    memory: MemoryMonitor,
+    timings: TimingMonitor,
+    overshoot: OvershootTracker,
+    /// Last counts sent out, to only publish when they change
+    published_cycle_counts: Option<CycleCounts>,
+    /// Set while the short cycling alert is active so it is only raised once
+    short_cycling: bool,
+    /// Set when the last tick ended in a controller fault
+    faulted: bool,
+    /// Set while the controller fault alert is active so it is only raised once
+    controller_faulted: bool,
+    /// Pushes critical alerts to the notification target
+    notifier: Notifier,
+    /// Set once the audit log has been sent to the ui at least once
+    audit_log_published: bool,
+    /// Onboard sensor temperature in Celsius (base unit), None once the sensor failed for too long
+    current_temp_c: Option<f32>,
+    /// Latest readings of the remote room sensors
+    remote_sensors: RemoteSensors,
+    /// When the sensor last gave a valid reading
+    last_temp_reading_time: Option<Instant>,
+    /// When the last sensor read was started
+    last_temp_poll_time: Option<Instant>,
+    /// Set while the sensor failure alert is active so it is only raised once
+    sensor_fault: bool,
+    /// Relative humidity in percent, if a humidity sensor reports it
+    current_humidity: Option<f32>,
+    /// Outdoor temperature in Celsius and when it came in, if an outdoor sensor or weather
+    /// service reports it (over MQTT)
+    outdoor_reading: Option<(f32, Instant)>,
+    /// Set while humidity is above the max humidity setting and cooling may overcool to dehumidify
+    dehumidifying: bool,
+    /// CO2 level in ppm, if an air quality sensor reports it
+    co2_ppm: Option<u16>,
+    /// Set while CO2 calls for ventilation, whether or not the interlocks allow it
+    ventilation_wanted: bool,
+    /// Set while the ERV/HRV runs to bring CO2 down
+    ventilating: bool,
+    enclosure: EnclosureMonitor,
+    #[cfg(feature = "chaos")]
+    chaos: crate::chaos::Chaos,
+    /// Set while CO2 calls for ventilation but the interlocks hold it off
+    ventilation_held: bool,
+    /// Set while the frost-stat relay is closed because a watched sensor is near freezing
+    frost_protecting: bool,
+    /// Set while heating is cut off for being above the high temperature cutoff
+    high_temp_cutoff: bool,
+    /// Set while inside the configured quiet hours
+    quiet_hours: bool,
+    open_window: OpenWindowDetector,
+    /// Heating is paused until then because an open window was detected
+    open_window_until: Option<Instant>,
+    /// Demand response event signalled by the utility, if one is running
+    demand_response: Option<DemandResponseEvent>,
+    /// Temporary setpoint override from the boost button, if one is running
+    boost: Option<Boost>,
+    /// The sleep preset is on until then (local time)
+    sleep_until: Option<NaiveDateTime>,
+    /// Where we are relative to the configured peak pricing windows
+    peak_phase: Option<PeakPhase>,
+    /// Start of the schedule period that was last applied
+    last_schedule_period_start: Option<NaiveDateTime>,
+    schedule_profiles: ScheduleProfiles,
+    /// Set once the schedule profile names have been sent to the ui
+    schedule_profiles_published: bool,
+    settings: Settings,
+    /// Set when the settings changed and haven't been persisted yet
+    settings_dirty: bool,
+    /// Set once the settings have been sent to the ui
+    settings_published: bool,
+    /// Status message and snapshot last sent out, and when, to only send them when they change
+    published_status: Option<(StatusMessage, Snapshot, Instant)>,
 
     runtime_state: ThermostatRuntimeState,
+    transitions: TransitionLog,
+    /// Why the state is about to change, recorded with the transition at the end of the tick
+    transition_reason: Option<TransitionReason>,
+    /// Set once the transition log has been sent to the ui since it last changed
+    transitions_published: bool,
+    /// State the timeout guard is timing, and since when
+    timed_state: ThermostatRuntimeState,
+    timed_state_since: Instant,
+    /// No new calls start until then because a state ran into its timeout guard
+    state_timeout_lockout_until: Option<Instant>,
 
 
     /// Used to track cumulative cooling duration since last resting
@@ -34,72 +165,767 @@ pub struct ThermostatState {
     total_heating_duration: Duration,
     
     last_resting_start_time: Instant,
+    /// Rest that happened before a reboot, counted on top of the time since `last_resting_start_time`
+    rest_credit: Duration,
+    /// Last cooling counters written to storage
+    last_checkpoint: Option<CoolingCheckpoint>,
+    last_checkpoint_time: Instant,
 
     /// Used to debounce user interaction and prevent rapid changes in mode.
     last_user_interaction_time: Instant,
 
+    /// What is heating on a dual fuel system, while heating
+    heat_source: Option<HeatSource>,
+    /// When the heat pump started in the current heat call, if it did
+    heat_pump_start_time: Option<Instant>,
+
+    /// When the fan lead before cooling started
+    fan_lead_start_time: Instant,
+    /// When the fan should be turned off after a heat/cool call ended, if it is running on
+    fan_off_at: Option<Instant>,
+    /// When the fan timer started from the ui or network runs out, if one is running
+    fan_timer_until: Option<Instant>,
+    /// Room temperature pretended for a showroom demo, and when that ends
+    demo_temp: Option<(f32, Instant)>,
+    /// When the hydronic zone valve was told to open for the running heat call
+    valve_opened_at: Option<Instant>,
+    /// Set once the zone valve end switch alert was raised for the running heat call
+    end_switch_alerted: bool,
+    /// Whether the compressor ran after the last tick, to notice it stopping
+    compressor_was_running: bool,
+    /// End of the compressor's minimum off time, counted from when it last stopped
+    compressor_lockout_until: Option<Instant>,
+
     /// Used to track time passed since last run was called. Can be appended to durations
     last_run_finished_time: Instant,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ThermostatRuntimeState {
     Waiting,
     Heating,
+    /// Fan runs alone for a short while before the compressor is energized
+    FanLead,
     Cooling,
     Resting,
+    /// Nothing running after a controller fault, the next tick picks Waiting or Off from the mode
     Idle,
+    /// Mode is Off. Heating and cooling stay off until the mode changes, only the fan may run
+    /// when asked for by the fan mode or timer.
+    Off,
+}
+
+impl ThermostatRuntimeState {
+    /// Longest a state may last before something is assumed to be wrong, None if it may last forever
+    fn max_duration(&self) -> Option<Duration> {
+        match self {
+            ThermostatRuntimeState::Heating => Some(Duration::from_hours(6)),
+            ThermostatRuntimeState::Cooling => Some(Duration::from_hours(4)),
+            ThermostatRuntimeState::FanLead => Some(Duration::from_mins(10)),
+            ThermostatRuntimeState::Resting => Some(Duration::from_mins(2 * REST_DURATION_MINS)),
+            ThermostatRuntimeState::Waiting | ThermostatRuntimeState::Idle | ThermostatRuntimeState::Off => None,
+        }
+    }
+
+    /// No heat or cool call is running or being prepared
+    fn call_inactive(self) -> bool {
+        matches!(self, ThermostatRuntimeState::Waiting | ThermostatRuntimeState::Idle | ThermostatRuntimeState::Off)
+    }
+
+    /// The serde name, as in the transition log and trace
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ThermostatRuntimeState::Waiting => "waiting",
+            ThermostatRuntimeState::Heating => "heating",
+            ThermostatRuntimeState::FanLead => "fan_lead",
+            ThermostatRuntimeState::Cooling => "cooling",
+            ThermostatRuntimeState::Resting => "resting",
+            ThermostatRuntimeState::Idle => "idle",
+            ThermostatRuntimeState::Off => "off",
+        }
+    }
+}
+
+impl FromStr for ThermostatRuntimeState {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "waiting" => Ok(ThermostatRuntimeState::Waiting),
+            "heating" => Ok(ThermostatRuntimeState::Heating),
+            "fan_lead" => Ok(ThermostatRuntimeState::FanLead),
+            "cooling" => Ok(ThermostatRuntimeState::Cooling),
+            "resting" => Ok(ThermostatRuntimeState::Resting),
+            "idle" => Ok(ThermostatRuntimeState::Idle),
+            "off" => Ok(ThermostatRuntimeState::Off),
+            _ => Err(anyhow::anyhow!("Invalid runtime state: {}", value)),
+        }
+    }
 }
 
 impl ThermostatState {
-    pub fn new(ui_events_rx: Receiver<UiEvent>, actor_events_tx: Sender<BackendEvent>) -> Self {
-        Self {
-            ui_events_rx,
-            actor_events_tx,
-            current_temp_c: 21.0,  // ~70°F
-            target_temp_c: 21.0,   // ~70°F
-            mode: ModeStatus::Off,
-            diff_mode: DiffStatus::Normal,
-            rest_mode: RestStatus::Off,
-            fan_mode: FanStatus::Auto,
-            use_fahrenheit: true,
+    pub fn new(bus: EventBus, mut storage: Storage) -> Self {
+        let brownouts = BrownoutMonitor::on_boot(&mut storage);
+        let mut settings = Settings::load(&storage);
+        let mut schedule_profiles = ScheduleProfiles::load(&storage);
+        let mut settings_dirty = false;
+        // Settings from before schedule profiles existed carry their schedule inline
+        if let Some(schedule) = settings.legacy_schedule.take() {
+            log::info!("Moving the schedule into the {} profile", DEFAULT_SCHEDULE_PROFILE);
+            let profile = ScheduleProfile {
+                name: DEFAULT_SCHEDULE_PROFILE.to_string(),
+                schedule,
+            };
+            if let Err(e) = schedule_profiles.upsert(&mut storage, profile) {
+                log::error!("Failed to persist migrated schedule: {}", e);
+            }
+            settings_dirty = true;
+        }
+        let mut state = Self {
+            commands_rx: bus.subscribe(&[Topic::Commands]),
+            pending_commands: VecDeque::new(),
+            refresh_requested: false,
+            updater: Updater::new(bus.clone()),
+            installer: InstallerAccess::load(&storage),
+            api_credentials: ApiCredentials::load(&storage).unwrap_or_default(),
+            self_test_report: None,
+            boot_health_check: BootHealthCheck::start(Duration::from_mins(settings.ota_health_check_mins as u64)),
+            bus,
+            audit_log: AuditLog::load(&storage),
+            trace: TraceRecorder::new(),
+            previous_trace: TraceRecorder::persisted_json(&storage),
+            traced_onboard: None,
+            #[cfg(feature = "replay")]
+            replay: None,
+            #[cfg(feature = "replay")]
+            replay_onboard: None,
+            audit_log_published: false,
+            faulted: false,
+            controller_faulted: false,
+            notifier: Notifier::default(),
+            rate_limiter: RateLimiter::default(),
+            arbiter: Arbiter::default(),
+            summary: SummaryTracker::default(),
+            cycle_stats: CycleStats::default(),
+            temperature_history: TemperatureHistory::load(&storage),
+            temperature_history_published: false,
+            trend: TemperatureTrend::default(),
+            brownouts,
+            memory: MemoryMonitor::default(),
+            timings: TimingMonitor::default(),
+            overshoot: OvershootTracker::default(),
+            published_cycle_counts: None,
+            short_cycling: false,
+            settings,
+            settings_dirty,
+            settings_published: false,
+            published_status: None,
+            storage,
+            current_temp_c: None,
+            last_temp_reading_time: None,
+            last_temp_poll_time: None,
+            sensor_fault: false,
+            current_humidity: None,
+            remote_sensors: RemoteSensors::default(),
+            outdoor_reading: None,
+            dehumidifying: false,
+            co2_ppm: None,
+            ventilation_wanted: false,
+            ventilating: false,
+            enclosure: EnclosureMonitor::default(),
+            #[cfg(feature = "chaos")]
+            chaos: crate::chaos::Chaos::default(),
+            ventilation_held: false,
+            frost_protecting: false,
+            high_temp_cutoff: false,
+            quiet_hours: false,
+            open_window: OpenWindowDetector::default(),
+            open_window_until: None,
+            demand_response: None,
+            boost: None,
+            sleep_until: None,
+            peak_phase: None,
+            last_schedule_period_start: None,
+            schedule_profiles,
+            schedule_profiles_published: false,
             runtime_state: ThermostatRuntimeState::Waiting,
+            transitions: TransitionLog::default(),
+            transition_reason: None,
+            transitions_published: false,
+            timed_state: ThermostatRuntimeState::Waiting,
+            timed_state_since: Instant::now(),
+            state_timeout_lockout_until: None,
             total_cooling_duration: Duration::from_secs(0),
             total_heating_duration: Duration::from_secs(0),
             last_resting_start_time: Instant::now(),
+            rest_credit: Duration::ZERO,
+            last_checkpoint: None,
+            last_checkpoint_time: Instant::now(),
             last_user_interaction_time: Instant::now(),
+            heat_source: None,
+            heat_pump_start_time: None,
+            fan_lead_start_time: Instant::now(),
+            fan_off_at: None,
+            fan_timer_until: None,
+            demo_temp: None,
+            valve_opened_at: None,
+            end_switch_alerted: false,
+            compressor_was_running: false,
+            // Power may have been cut with the compressor running
+            compressor_lockout_until: Some(Instant::now() + Duration::from_mins(COMPRESSOR_MIN_OFF_MINS)),
             last_run_finished_time: Instant::now(),
+        };
+        state.restore_cooling_checkpoint();
+        state.restore_handoff();
+        state.apply_timezone();
+        network::configure(&state.settings.network);
+        wireguard::configure(state.settings.wireguard.as_ref());
+        if state.brownouts.reduced_power() {
+            state.raise_alert(Severity::Warning, "Repeated brownouts, check the power supply. Running with a dimmed display".to_string());
+        }
+        state
+    }
+
+    /// Pick up the compressor runtime from before a reboot. Time spent powered off counts as
+    /// rest when it is known, otherwise only the rest already done before the reboot counts.
+    fn restore_cooling_checkpoint(&mut self) {
+        let Some(checkpoint) = CoolingCheckpoint::load(&self.storage) else {
+            return;
+        };
+        let rested = Duration::from_secs(checkpoint.rest_elapsed_secs.unwrap_or(0)) + checkpoint.downtime().unwrap_or_default();
+        if rested > Duration::from_mins(REST_DURATION_MINS) {
+            log::info!("Compressor rested while powered off, starting with a clean runtime");
+        } else {
+            self.total_cooling_duration = Duration::from_secs(checkpoint.total_cooling_secs);
+            self.rest_credit = rested;
+            if checkpoint.rest_elapsed_secs.is_some() {
+                self.runtime_state = ThermostatRuntimeState::Resting;
+            }
+            log::info!(
+                "Restored compressor runtime of {}, {} rested",
+                Self::format_time(self.total_cooling_duration),
+                Self::format_time(rested)
+            );
+        }
+        self.last_checkpoint = Some(checkpoint);
+    }
+
+    /// Pick up the runtime context handed over by a maintenance reboot. It is more recent and
+    /// more complete than the cooling checkpoint, so it wins over it.
+    fn restore_handoff(&mut self) {
+        let Some(handoff) = Handoff::take(&mut self.storage) else {
+            return;
+        };
+        let downtime = handoff.downtime().unwrap_or_default();
+        let remaining = |secs: Option<u64>| {
+            secs.map(Duration::from_secs)
+                .and_then(|left| left.checked_sub(downtime))
+                .filter(|left| !left.is_zero())
+                .map(|left| Instant::now() + left)
+        };
+        self.runtime_state = handoff.runtime_state;
+        self.total_cooling_duration = Duration::from_secs(handoff.total_cooling_secs);
+        self.total_heating_duration = Duration::from_secs(handoff.total_heating_secs);
+        self.rest_credit = Duration::from_secs(handoff.rest_elapsed_secs) + downtime;
+        self.state_timeout_lockout_until = remaining(handoff.state_timeout_lockout_secs);
+        self.fan_timer_until = remaining(handoff.fan_timer_secs);
+        self.open_window_until = remaining(handoff.open_window_secs);
+        self.compressor_lockout_until = remaining(handoff.compressor_lockout_secs);
+        self.cycle_stats.restore(&handoff.compressor_start_ages_secs, handoff.compressor_starts_total, downtime);
+        self.summary.restore(handoff.today, handoff.week);
+        log::info!("Restored the runtime context from before the maintenance reboot, down for {}s", downtime.as_secs());
+    }
+
+    /// Everything `restore_handoff` needs to carry on after a reboot
+    fn handoff(&self) -> Handoff {
+        let now = Instant::now();
+        let remaining = |until: Option<Instant>| until.map(|until| until.saturating_duration_since(now).as_secs()).filter(|&secs| secs > 0);
+        let (compressor_start_ages_secs, compressor_starts_total) = self.cycle_stats.handoff();
+        let (today, week) = self.summary.handoff();
+        Handoff {
+            runtime_state: self.runtime_state,
+            total_cooling_secs: self.total_cooling_duration.as_secs(),
+            total_heating_secs: self.total_heating_duration.as_secs(),
+            rest_elapsed_secs: self.rest_elapsed().as_secs(),
+            state_timeout_lockout_secs: remaining(self.state_timeout_lockout_until),
+            fan_timer_secs: remaining(self.fan_timer_until),
+            open_window_secs: remaining(self.open_window_until),
+            compressor_lockout_secs: remaining(self.compressor_lockout_until),
+            compressor_start_ages_secs,
+            compressor_starts_total,
+            today,
+            week,
+            saved_at: 0,
+        }
+    }
+
+    /// Write the cooling counters to storage every so often while they change
+    fn checkpoint_cooling(&mut self) {
+        if self.last_checkpoint_time.elapsed() < CHECKPOINT_INTERVAL {
+            return;
+        }
+        self.last_checkpoint_time = Instant::now();
+        let resting = self.runtime_state == ThermostatRuntimeState::Resting;
+        let checkpoint = CoolingCheckpoint::new(self.total_cooling_duration, resting.then(|| self.rest_elapsed()));
+        let changed = self.last_checkpoint.as_ref().is_none_or(|last| checkpoint.counters_differ(last));
+        if !changed {
+            return;
+        }
+        match checkpoint.save(&mut self.storage) {
+            Ok(()) => self.last_checkpoint = Some(checkpoint),
+            Err(e) => log::error!("Failed to checkpoint cooling runtime: {}", e),
+        }
+    }
+
+    /// Time since the compressor last stopped for a rest, including rest from before a reboot
+    fn rest_elapsed(&self) -> Duration {
+        self.last_resting_start_time.elapsed() + self.rest_credit
+    }
+
+    /// Target temperature currently controlled to (in Celsius): the boost setpoint while boosting,
+    /// the night setpoint with the sleep preset on, the away setpoint during a vacation, otherwise
+    /// the user's setpoint, pushed a little further ahead of a peak pricing window. A demand
+    /// response event relaxes whichever of these is in use.
+    pub fn get_target_temp(&self) -> f32 {
+        let target_temp_c = match &self.boost {
+            Some(boost) => boost.setpoint_c(self.settings.target_temp_c),
+            None => self
+                .sleep_setpoint()
+                .or_else(|| self.away_setpoint())
+                .unwrap_or_else(|| self.settings.target_temp_c + self.precondition_offset_c()),
+        };
+        target_temp_c + self.demand_response_offset_c()
+    }
+
+    /// Drop the boost once it ran its course
+    fn update_boost(&mut self) {
+        let control_temp_c = self.get_control_temp();
+        if self
+            .boost
+            .as_ref()
+            .is_some_and(|boost| boost.is_over(self.settings.target_temp_c, control_temp_c, &self.settings.mode))
+        {
+            log::info!("Boost over, back to the normal setpoint");
+            self.boost = None;
+        }
+    }
+
+    fn update_demo_temp(&mut self) {
+        if self.demo_temp.is_some_and(|(_, until)| Instant::now() >= until) {
+            log::info!("Demo temperature over, back to the measured temperature");
+            self.demo_temp = None;
+        }
+    }
+
+    fn demand_response_active(&self) -> bool {
+        self.demand_response.as_ref().is_some_and(|event| event.is_active())
+    }
+
+    fn demand_response_offset_c(&self) -> f32 {
+        if self.demand_response_active() {
+            self.settings.demand_response.setpoint_offset_c(&self.settings.mode)
+        } else {
+            0.0
+        }
+    }
+
+    /// Whether a running demand response event leaves cooling any duty in the current period
+    fn demand_response_allows_cooling(&mut self) -> bool {
+        let max_duty_percent = self.settings.demand_response.max_cooling_duty_percent;
+        match self.demand_response.as_mut().filter(|event| event.is_active()) {
+            Some(event) => event.cooling_allowed(max_duty_percent),
+            None => true,
+        }
+    }
+
+    /// Drop the demand response event once it ran its course
+    fn update_demand_response(&mut self) {
+        if self.demand_response.as_ref().is_some_and(|event| event.is_over()) {
+            log::info!("Demand response event over");
+            self.demand_response = None;
+        }
+    }
+
+    fn precondition_offset_c(&self) -> f32 {
+        match (self.peak_phase, &self.settings.peak_pricing) {
+            (Some(PeakPhase::Precondition), Some(peak)) => peak.precondition_offset_c(&self.settings.mode),
+            _ => 0.0,
+        }
+    }
+
+    /// Extra deadband while inside a peak pricing window (Celsius)
+    fn peak_deadband_widening_c(&self) -> f32 {
+        match (self.peak_phase, &self.settings.peak_pricing) {
+            (Some(PeakPhase::Peak), Some(peak)) => peak.deadband_widening_c,
+            _ => 0.0,
+        }
+    }
+
+    fn update_peak_pricing(&mut self) {
+        let phase = self
+            .settings
+            .peak_pricing
+            .as_ref()
+            .filter(|_| clock::is_set())
+            .and_then(|peak| peak.phase(clock::local_now().time()));
+        if phase != self.peak_phase {
+            log::info!("Peak pricing phase changed to {:?}", phase);
+            self.peak_phase = phase;
+        }
+    }
+
+    fn sleep_setpoint(&self) -> Option<f32> {
+        self.sleep_until?;
+        self.settings.sleep.setpoint_c(&self.settings.mode)
+    }
+
+    /// End the sleep preset at the wake time and go back to the normal program
+    fn update_sleep(&mut self) {
+        if !self.sleep_until.is_some_and(|until| clock::local_now() >= until) {
+            return;
+        }
+        log::info!("Sleep preset over, resuming the normal program");
+        self.sleep_until = None;
+        // Apply the current schedule period as if it had just started
+        self.reapply_schedule();
+    }
+
+    /// Apply the current schedule period on the next tick, even over a held manual setpoint
+    fn reapply_schedule(&mut self) {
+        self.last_schedule_period_start = None;
+        self.arbiter.release(Contended::Setpoint);
+    }
+
+    fn manual_hold(&self) -> Duration {
+        Duration::from_mins(self.settings.manual_hold_mins as u64)
+    }
+
+    fn away_setpoint(&self) -> Option<f32> {
+        let vacation = self.settings.vacation.as_ref()?;
+        if !clock::is_set() || !vacation.is_active(clock::local_now().date()) {
+            return None;
+        }
+        vacation.setpoint_c(&self.settings.mode)
+    }
+
+    /// When a new schedule period starts, apply its setpoint as if the schedule sent a command.
+    /// Manual changes in between hold until the next period starts.
+    fn update_schedule(&mut self) {
+        if !clock::is_set() {
+            return;
+        }
+        let Some(schedule) = self.settings.active_schedule.as_deref().and_then(|name| self.schedule_profiles.get(name)) else {
+            return;
+        };
+        let Some((start, period)) = schedule.active_period(clock::local_now()) else {
+            return;
+        };
+        if self.last_schedule_period_start == Some(start) {
+            return;
+        }
+        // A recent manual setpoint holds, the period is applied once the hold is over
+        if let Err(holder) = self.arbiter.check(CommandSource::Schedule, Contended::Setpoint, self.manual_hold()) {
+            log::debug!("Schedule period held off by a setpoint from {:?}", holder);
+            return;
+        }
+        self.last_schedule_period_start = Some(start);
+        let Some(setpoint_c) = period.setpoint_c(&self.settings.mode) else {
+            return;
+        };
+        log::info!("Schedule period started at {}, setpoint {:.1}°C", start, setpoint_c);
+        let command = Command {
+            id: bus::next_command_id(),
+            source: CommandSource::Schedule,
+            session: None,
+            event: UiEvent::TargetTempUpdate(setpoint_c),
+        };
+        self.arbiter.record(CommandSource::Schedule, Contended::Setpoint);
+        self.audit_log.record(&command);
+        self.audit_log_published = false;
+        self.settings.set_setpoint(setpoint_c);
+        self.settings_changed();
+    }
+
+    /// Clear a vacation once it is over so normal operation resumes
+    fn update_vacation(&mut self) {
+        let over = self
+            .settings
+            .vacation
+            .as_ref()
+            .is_some_and(|vacation| clock::is_set() && vacation.is_over(clock::local_now().date()));
+        if over {
+            log::info!("Vacation over, resuming normal setpoint");
+            self.settings.vacation = None;
+            self.settings_changed();
+            self.save_settings_if_dirty();
         }
     }
 
     /// Get target temp needed to transition from waiting mode to heating or cooling mode (in Celsius)
     pub fn get_waiting_target_temp(&self) -> f32 {
-        match self.mode {
+        match self.settings.mode {
             ModeStatus::Heat => {
-                match self.diff_mode {
-                    // Differential offsets in Celsius
-                    DiffStatus::Slow => self.target_temp_c - 1.0,    // ~1.9°F
-                    DiffStatus::Normal => self.target_temp_c - 0.4, // ~0.75°F
-                    DiffStatus::Fast => self.target_temp_c - 0.3    // ~0.5°F
-                }
+                self.get_target_temp() - self.settings.comfort().heat_differential_c - self.peak_deadband_widening_c()
             },
             ModeStatus::Cool => {
-                match self.diff_mode {
-                    // Differential offsets in Celsius
-                    DiffStatus::Slow => self.target_temp_c + 0.9,   // ~1.7°F
-                    DiffStatus::Normal => self.target_temp_c + 0.7, // ~1.2°F
-                    DiffStatus::Fast => self.target_temp_c + 0.5    // ~0.9°F
-                }
+                self.get_target_temp() + self.settings.comfort().cool_differential_c + self.peak_deadband_widening_c()
             },
-            ModeStatus::Off => self.current_temp_c,
+            ModeStatus::Off => self.get_target_temp(),
+        }
+    }
+
+    /// Room temperature (in Celsius) control works with: the measured one, unless a demo
+    /// overrides it
+    pub fn get_room_temp(&self) -> Option<f32> {
+        self.demo_temp.map(|(temp_c, _)| temp_c).or_else(|| self.measured_room_temp())
+    }
+
+    /// Room temperature (in Celsius) from the sensors selected for this time of day. Falls back
+    /// to the onboard sensor when none of them reported recently.
+    fn measured_room_temp(&self) -> Option<f32> {
+        let sensors = self.active_control_sensors();
+        if sensors.is_empty() {
+            return self.current_temp_c;
+        }
+        self.remote_sensors.blend(sensors, self.current_temp_c).or(self.current_temp_c)
+    }
+
+    /// Alert when a remote sensor driving control stops reporting
+    fn update_remote_sensors(&mut self) {
+        self.remote_sensors.set_stale_after(Duration::from_mins(self.settings.remote_sensor_stale_mins.max(1) as u64));
+        let sensors = self.active_control_sensors().to_vec();
+        for name in self.remote_sensors.newly_stale(&sensors) {
+            let fallback = if self.remote_sensors.in_use(&sensors).is_empty() { "the onboard sensor" } else { "the other sensors" };
+            self.raise_alert(Severity::Warning, format!("{} sensor stopped reporting, controlling with {}", name, fallback));
+        }
+        for (name, percent) in self.remote_sensors.newly_low_battery() {
+            self.raise_alert(Severity::Warning, format!("{} sensor battery low ({}%)", name, percent));
+        }
+    }
+
+    fn active_control_sensors(&self) -> &[WeightedSensor] {
+        let time = clock::is_set().then(|| clock::local_now().time());
+        self.settings.sensor_selection.active(time)
+    }
+
+    /// Temperature the state machine controls to (in Celsius). When cooling with feels like
+    /// control enabled and humidity known this is the heat index, so muggy air still gets cooled.
+    /// None while there is no trustworthy temperature.
+    pub fn get_control_temp(&self) -> Option<f32> {
+        let current_temp_c = self.get_room_temp()?;
+        Some(match (&self.settings.mode, self.settings.feels_like_control, self.current_humidity) {
+            (ModeStatus::Cool, true, Some(humidity)) => comfort::heat_index_c(current_temp_c, humidity),
+            _ => current_temp_c,
+        })
+    }
+
+    /// Poll the sensor. Reads don't block the loop: a conversion is started at the poll interval
+    /// and read on a later tick once it is done.
+    fn update_temperature(&mut self, controller: &mut Controller) {
+        let reading = if std::mem::take(&mut self.refresh_requested) {
+            self.last_temp_poll_time = Some(Instant::now());
+            Self::read_temperature_now(controller)
+        } else if controller.temperature_conversion_running() {
+            Self::poll_conversion(controller)
+        } else if self.last_temp_poll_time.is_none_or(|at| at.elapsed() >= self.settings.sensor_poll_interval()) {
+            self.last_temp_poll_time = Some(Instant::now());
+            if Self::start_conversion(controller) {
+                TemperatureReading::Pending
+            } else {
+                TemperatureReading::Failed
+            }
+        } else {
+            TemperatureReading::Pending
+        };
+        #[cfg(feature = "replay")]
+        let reading = match (&self.replay, self.replay_onboard.take()) {
+            (None, _) => reading,
+            (Some(_), Some(Some(temp_c))) => TemperatureReading::Ready(temp_c),
+            (Some(_), Some(None)) => TemperatureReading::Failed,
+            (Some(_), None) => TemperatureReading::Pending,
+        };
+        #[cfg(feature = "chaos")]
+        let reading = self.chaos.temperature(reading);
+        let traced = match reading {
+            TemperatureReading::Pending => None,
+            TemperatureReading::Ready(temp_c) => Some(Some(temp_c)),
+            TemperatureReading::Failed => Some(None),
+        };
+        if let Some(traced) = traced.filter(|traced| Some(*traced) != self.traced_onboard) {
+            self.trace.record(None, TraceEvent::OnboardTemp(traced));
+            self.traced_onboard = Some(traced);
+        }
+        match reading {
+            TemperatureReading::Pending => {}
+            TemperatureReading::Ready(temp_c) => self.temperature_read(Some(temp_c)),
+            TemperatureReading::Failed => self.temperature_read(None),
+        }
+    }
+
+    /// Read the sensor, waiting for the conversion instead of picking it up on a later tick
+    fn read_temperature_now(controller: &mut Controller) -> TemperatureReading {
+        if !controller.temperature_conversion_running() && !Self::start_conversion(controller) {
+            return TemperatureReading::Failed;
+        }
+        loop {
+            match Self::poll_conversion(controller) {
+                TemperatureReading::Pending => std::thread::sleep(Duration::from_millis(50)),
+                reading => return reading,
+            }
+        }
+    }
+
+    /// Start a conversion, timed as a sensor read
+    fn start_conversion(controller: &mut Controller) -> bool {
+        let _timing = timing::measure(Probe::SensorRead);
+        controller.start_temperature_conversion()
+    }
+
+    /// Poll the running conversion, timing the poll that reads it off the bus as a sensor read
+    fn poll_conversion(controller: &mut Controller) -> TemperatureReading {
+        let _timing = controller.temperature_conversion_done().then(|| timing::measure(Probe::SensorRead));
+        controller.poll_temperature_conversion()
+    }
+
+    /// A failed read holds the last valid value for a few minutes, after that the temperature
+    /// is unknown and the state machine fails safe.
+    fn temperature_read(&mut self, reading: Option<f32>) {
+        let reading = reading.filter(|temp_c| {
+            let plausible = (ONBOARD_TEMP_MIN_C..=ONBOARD_TEMP_MAX_C).contains(temp_c);
+            if !plausible {
+                log::warn!("Ignoring implausible temperature reading {}°C", temp_c);
+            }
+            plausible
+        });
+        if let Some(temp_c) = reading {
+            if self.sensor_fault {
+                log::info!("Temperature sensor recovered");
+                self.sensor_fault = false;
+            }
+            self.current_temp_c = Some(temp_c);
+            self.last_temp_reading_time = Some(Instant::now());
+            return;
+        }
+        let holding = self
+            .last_temp_reading_time
+            .is_some_and(|at| at.elapsed() < Duration::from_mins(SENSOR_HOLD_MINS));
+        if holding || self.sensor_fault {
+            return;
+        }
+        self.current_temp_c = None;
+        self.sensor_fault = true;
+        self.raise_alert(Severity::Critical, "Temperature sensor not responding, heating and cooling stopped".to_string());
+    }
+
+    /// Track whether humidity is high enough to overcool, with some hysteresis so it doesn't flap.
+    fn update_dehumidifying(&mut self) {
+        self.dehumidifying = match (&self.settings.mode, self.settings.max_humidity, self.current_humidity) {
+            (ModeStatus::Cool, Some(max), Some(humidity)) => {
+                humidity > max || (self.dehumidifying && humidity > max - DEHUMIDIFY_HYSTERESIS)
+            }
+            _ => false,
+        };
+    }
+
+    /// Run the ERV/HRV while CO2 is above the threshold, with some hysteresis so it doesn't flap.
+    /// The controller holds it off outside the interlocks.
+    fn update_ventilation(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let wanted = match (self.settings.ventilation, self.co2_ppm) {
+            (Some(ventilation), Some(co2_ppm)) => ventilation.should_ventilate(co2_ppm, self.ventilation_wanted),
+            _ => false,
+        };
+        if wanted != self.ventilation_wanted {
+            log::info!("Ventilation {} at {:?} ppm CO2", if wanted { "called" } else { "satisfied" }, self.co2_ppm);
+        }
+        self.ventilation_wanted = wanted;
+        if let Some(ventilation) = self.settings.ventilation {
+            controller.set_ventilation_interlock(ventilation.interlock);
+        }
+        self.ventilating = controller.set_ventilation(wanted, self.outdoor_temp_c())?;
+        let held = wanted && !self.ventilating && controller.has_ventilation();
+        if held && !self.ventilation_held {
+            log::info!("Ventilation held off by the outdoor temperature ({:?}°C)", self.outdoor_temp_c());
+        }
+        self.ventilation_held = held;
+        Ok(())
+    }
+
+    /// Cut heating off while the onboard sensor or the room temperature is above the ceiling,
+    /// until it is back below it by the hysteresis. Works on real readings, never a demo's.
+    fn update_high_temp_cutoff(&mut self) {
+        let Some(cutoff_c) = self.settings.high_temp_cutoff_c else {
+            self.high_temp_cutoff = false;
+            return;
+        };
+        // Without any reading the cutoff stays as it is
+        let Some(temp_c) = self.current_temp_c.into_iter().chain(self.measured_room_temp()).reduce(f32::max) else {
+            return;
+        };
+        let release_c = if self.high_temp_cutoff { cutoff_c - HIGH_TEMP_CUTOFF_HYSTERESIS_C } else { cutoff_c };
+        let tripped = temp_c > release_c;
+        if tripped && !self.high_temp_cutoff {
+            let stuck = if matches!(self.runtime_state, ThermostatRuntimeState::Heating) {
+                ""
+            } else {
+                ", with no heat call running. Check for a stuck heat relay or a runaway heater"
+            };
+            self.raise_alert(Severity::Critical, format!("{:.1}°C is above the {:.1}°C cutoff, heating cut off{}", temp_c, cutoff_c, stuck));
+        } else if !tripped && self.high_temp_cutoff {
+            log::info!("Back below the high temperature cutoff at {:.1}°C, heating allowed again", temp_c);
+        }
+        self.high_temp_cutoff = tripped;
+    }
+
+    /// Close the frost-stat relay while a watched sensor is near freezing, whatever the mode.
+    /// Works on the sensors' own readings, a demo temperature doesn't count.
+    fn update_frost_stat(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let Some(frost_stat) = self.settings.frost_stat.as_ref() else {
+            self.frost_protecting = false;
+            return controller.set_frost_stat(false);
+        };
+        let readings: Vec<Option<f32>> = frost_stat
+            .sensors
+            .iter()
+            .map(|sensor| match sensor {
+                SensorRef::Onboard => self.current_temp_c,
+                SensorRef::Remote(name) => self.remote_sensors.fresh(name),
+            })
+            .collect();
+        let closed = frost_stat.should_close(&readings, self.frost_protecting);
+        if closed != self.frost_protecting {
+            let coldest_c = readings.iter().flatten().copied().reduce(f32::min).unwrap_or_default();
+            if closed {
+                self.raise_alert(Severity::Critical, format!("Near freezing ({:.1}°C), frost protection on", coldest_c));
+            } else {
+                log::info!("Frost protection off at {:.1}°C", coldest_c);
+            }
+        }
+        self.frost_protecting = closed;
+        controller.set_frost_stat(closed)
+    }
+
+    /// Temperature heating stops at (in Celsius), a little early with a heat anticipator
+    /// so the residual heat carries it the rest of the way.
+    pub fn get_heating_stop_temp(&self) -> f32 {
+        self.get_target_temp() - self.settings.comfort().heat_anticipator_c
+    }
+
+    /// Temperature cooling stops at (in Celsius). While dehumidifying the AC may keep running
+    /// up to the overcool limit below the setpoint.
+    pub fn get_cooling_stop_temp(&self) -> f32 {
+        if self.dehumidifying {
+            self.get_target_temp() - self.settings.overcool_limit_c
+        } else {
+            self.get_target_temp()
         }
     }
 
     /// We need to rest for a while after cooling to prevent the compressor from freezing,
     /// since we don't have enough airflow to prevent it.
     pub fn should_rest(&self) -> bool {
-        if let ModeStatus::Cool = self.mode {
-            return match self.rest_mode {
+        if let ModeStatus::Cool = self.settings.mode {
+            return match self.settings.rest_mode {
                 RestStatus::Short => self.total_cooling_duration > Duration::from_mins(60),
                 RestStatus::Medium => self.total_cooling_duration > Duration::from_mins(90),
                 RestStatus::Long => self.total_cooling_duration > Duration::from_mins(120),
@@ -109,180 +935,1578 @@ impl ThermostatState {
         false
     }
 
-    /// Formats the temperature (base unit: Celsius) in the user's preferred unit
-    pub fn format_temp(&self, temp_c: f32) -> String {
-        if self.use_fahrenheit {
-            format!("{:.1}°F", Controller::celsius_to_fahrenheit(temp_c))
-        } else {
-            format!("{:.1}°C", temp_c)
-        }
-    }
-
     pub fn format_time(duration: Duration) -> String {
         let minutes = duration.as_secs() / 60;
         let seconds = duration.as_secs() % 60;
         format!("{}m {}s", minutes, seconds)
     }
 
-    pub fn get_waiting_temp_formatted(&self) -> String {
-        return self.format_temp(self.get_waiting_target_temp());
+    /// Time left in the current rest, zero once it is over
+    pub fn get_remaining_resting_duration(&self) -> Duration {
+        rest_remaining(self.rest_elapsed())
     }
 
-    pub fn get_remaining_resting_duration_formatted(&self) -> String {
-        let elapsed = self.last_resting_start_time.elapsed();
-        let remaining = Duration::from_mins(REST_DURATION_MINS) - elapsed;
-        return Self::format_time(remaining);
-    }
-
-    pub fn get_status_message(&self) -> String {
+    pub fn get_status_message(&self) -> StatusMessage {
         match self.runtime_state {
-            ThermostatRuntimeState::Waiting => format!("Waiting for {}", self.get_waiting_temp_formatted()),
-            ThermostatRuntimeState::Heating => "Heating".to_string(),
-            ThermostatRuntimeState::Cooling => "Cooling".to_string(),
-            ThermostatRuntimeState::Resting => format!("Defrosting for {}", self.get_remaining_resting_duration_formatted()),
-            ThermostatRuntimeState::Idle => "Idling".to_string(),
+            ThermostatRuntimeState::Waiting if self.get_room_temp().is_none() => StatusMessage::NoTemperature,
+            ThermostatRuntimeState::Waiting if self.state_timeout_locked_out() => StatusMessage::TimedOut,
+            ThermostatRuntimeState::Waiting if self.seasonal_lockout() => StatusMessage::SeasonalLockout,
+            ThermostatRuntimeState::Waiting if self.high_temp_cutoff && matches!(self.settings.mode, ModeStatus::Heat) => {
+                StatusMessage::HighTempCutoff
+            }
+            ThermostatRuntimeState::Waiting if self.open_window_paused() => StatusMessage::WindowOpen,
+            ThermostatRuntimeState::Waiting => StatusMessage::Waiting { target_c: self.get_waiting_target_temp() },
+            ThermostatRuntimeState::Heating => StatusMessage::Heating,
+            ThermostatRuntimeState::FanLead => StatusMessage::StartingFan,
+            ThermostatRuntimeState::Cooling if self.dehumidifying => StatusMessage::Dehumidifying,
+            ThermostatRuntimeState::Cooling => StatusMessage::Cooling,
+            ThermostatRuntimeState::Resting => StatusMessage::Resting { remaining_secs: self.get_remaining_resting_duration().as_secs() },
+            ThermostatRuntimeState::Idle => StatusMessage::Idle,
+            ThermostatRuntimeState::Off => StatusMessage::Off,
         }
     }
 
     pub fn set_mode(&mut self, mode: ModeStatus) {
-        self.mode = mode;
+        self.settings.switch_mode(mode);
+        self.settings_changed();
     }
 
     pub fn set_rest_mode(&mut self, rest_mode: RestStatus) {
-        self.rest_mode = rest_mode;
+        self.settings.rest_mode = rest_mode;
+        self.settings_changed();
     }
     
     pub fn set_fan_mode(&mut self, fan_mode: FanStatus) {
-        self.fan_mode = fan_mode;
+        self.settings.fan_mode = fan_mode;
+        self.settings_changed();
     }
 
     /// Set target temperature in Celsius
     pub fn set_target_temp(&mut self, target_temp_c: f32) {
-        self.target_temp_c = target_temp_c;
+        self.settings.set_setpoint(target_temp_c);
+        self.settings_changed();
     }
 
     /// Receives events from the UI thread and updates the state accordingly.
-    pub fn receive_events(&mut self) {
-        if !(self.last_user_interaction_time.elapsed() > Duration::from_secs(5)) {
+    pub fn receive_events(&mut self, controller: &mut Controller) {
+        if !(self.last_user_interaction_time.elapsed() > Duration::from_secs(5)) && !self.refresh_requested {
+            return;
+        }
+        #[cfg(feature = "chaos")]
+        if self.chaos.commands_lost() {
             return;
         }
 
-        while let Ok(event) = self.ui_events_rx.try_recv() {
-            match event {
-                UiEvent::ModeUpdate(mode) => self.mode = mode,
-                UiEvent::UseFahrenheitUpdate(use_fahrenheit) => self.use_fahrenheit = use_fahrenheit,
-                UiEvent::DiffUpdate(diff_mode) => self.diff_mode = diff_mode,
-                UiEvent::RestUpdate(rest_mode) => self.rest_mode = rest_mode,
-                UiEvent::FanUpdate(fan_mode) => self.fan_mode = fan_mode,
-                UiEvent::TargetTempUpdate(target_temp_c) => self.target_temp_c = target_temp_c,
+        while let Some(Message::Command(command)) = self.pending_commands.pop_front().or_else(|| self.commands_rx.try_recv().ok()) {
+            let source = command.source;
+            let id = command.id;
+            let traced = TraceEvent::from_command(&command.event);
+            // Readings from add-on sensors that don't steer control, and diagnostics reads, would
+            // crowd out the rest
+            let read_only = matches!(command.event, UiEvent::DiagnosticsRequest(_));
+            if !(source == CommandSource::Sensor && matches!(traced, TraceEvent::Other(_))) && !read_only {
+                self.trace.record(Some(source), traced);
+            }
+            // Compared with what was applied to tell the source about clamped values
+            let requested = command.event.clone();
+            let command = if source.is_network() {
+                match self.rate_limiter.check(source).and_then(|_| validation::validate(command)) {
+                    Ok(command) => command,
+                    Err(rejection) => {
+                        self.reject(id, source, rejection);
+                        continue;
+                    }
+                }
+            } else {
+                command
+            };
+            let session = command.session.clone();
+            if installer::requires_installer(&command.event) && !self.installer.is_installer(source, session.as_ref()) {
+                self.reject(id, source, CommandRejection::InstallerOnly);
+                continue;
             }
+            let contended = Contended::of(&command.event);
+            if let Some(what) = contended {
+                if let Err(holder) = self.arbiter.check(source, what, self.manual_hold()) {
+                    self.reject(id, source, CommandRejection::HeldBy(holder));
+                    continue;
+                }
+            }
+            // Only commands that changed a setting get them persisted and re-sent
+            let settings_before = self.settings.clone();
+            match command.event.clone() {
+                UiEvent::ModeUpdate(ModeStatus::Cool) if !self.settings.system.has_cooling() => {
+                    self.reject(id, source, CommandRejection::NoCooling);
+                    // Put the ui's mode button back
+                    self.settings_published = false;
+                    continue;
+                }
+                UiEvent::ModeUpdate(ModeStatus::Heat) if !self.settings.system.has_heating() => {
+                    self.reject(id, source, CommandRejection::NoHeating);
+                    self.settings_published = false;
+                    continue;
+                }
+                UiEvent::ModeUpdate(mode) => self.settings.switch_mode(mode),
+                UiEvent::UseFahrenheitUpdate(use_fahrenheit) => {
+                    self.settings.use_fahrenheit = use_fahrenheit;
+                    // Keep the setpoints on a step of the new unit
+                    self.settings.snap_setpoints();
+                }
+                UiEvent::ComfortProfileUpdate(profile) => self.settings.comfort_profile = profile,
+                UiEvent::CustomComfortUpdate(comfort) => {
+                    self.settings.custom_comfort = comfort;
+                    self.settings.comfort_profile = ComfortProfile::Custom;
+                }
+                UiEvent::RestUpdate(rest_mode) => self.settings.rest_mode = rest_mode,
+                UiEvent::FanUpdate(fan_mode) => {
+                    self.settings.fan_mode = fan_mode;
+                    // Switch the fan now rather than with the next state change
+                    if let Err(e) = self.circulate_fan(controller) {
+                        self.controller_fault(controller, e);
+                    }
+                }
+                UiEvent::FanTimer(duration) if !duration.is_zero() && !self.settings.system.has_fan() => {
+                    self.reject(id, source, CommandRejection::NoFan);
+                    continue;
+                }
+                UiEvent::FanTimer(duration) => {
+                    log::info!("Fan timer set to {} minutes", duration.as_secs() / 60);
+                    self.fan_timer_until = (!duration.is_zero()).then(|| Instant::now() + duration);
+                }
+                UiEvent::TargetTempUpdate(target_temp_c) => self.settings.set_setpoint(self.settings.snap_setpoint(target_temp_c)),
+                UiEvent::DisplayPrecisionUpdate(precision) => self.settings.display_precision = precision,
+                UiEvent::BurnInProtectionUpdate(burn_in) => self.settings.burn_in = burn_in,
+                UiEvent::AutoBrightnessUpdate(auto) => self.settings.brightness.auto = auto,
+                UiEvent::ProximityWakeUpdate(proximity_wake) => self.settings.proximity_wake = proximity_wake,
+                UiEvent::ManualBrightnessUpdate(percent) => {
+                    self.settings.brightness.manual_percent = percent;
+                    self.settings.brightness.auto = false;
+                }
+                UiEvent::VacationUpdate(vacation) => self.settings.vacation = vacation,
+                UiEvent::ScheduleProfileUpdate { name, schedule } => {
+                    let result = match schedule {
+                        Some(schedule) => self.schedule_profiles.upsert(&mut self.storage, ScheduleProfile { name: name.clone(), schedule }),
+                        None => self.schedule_profiles.remove(&mut self.storage, &name),
+                    };
+                    if let Err(e) = result {
+                        log::error!("Failed to persist schedule profile {}: {}", name, e);
+                    }
+                    self.schedule_profiles_published = false;
+                    if self.settings.active_schedule.as_ref() == Some(&name) {
+                        if self.schedule_profiles.get(&name).is_none() {
+                            self.settings.active_schedule = None;
+                        }
+                        // Apply the edited schedule's current period right away
+                        self.reapply_schedule();
+                    }
+                }
+                UiEvent::ScheduleExceptionUpdate { name, date, follows } => {
+                    let Some(mut schedule) = self.schedule_profiles.get(&name).cloned() else {
+                        self.reject(id, source, CommandRejection::UnknownScheduleProfile(name));
+                        continue;
+                    };
+                    schedule.set_exception(date, follows, clock::is_set().then(|| clock::local_now().date()));
+                    if schedule.exceptions.len() > MAX_SCHEDULE_EXCEPTIONS {
+                        self.reject(id, source, CommandRejection::TooManyScheduleExceptions);
+                        continue;
+                    }
+                    if let Err(e) = self.schedule_profiles.upsert(&mut self.storage, ScheduleProfile { name: name.clone(), schedule }) {
+                        log::error!("Failed to persist schedule profile {}: {}", name, e);
+                    }
+                    if self.settings.active_schedule.as_ref() == Some(&name) {
+                        // Today may run like another day now
+                        self.reapply_schedule();
+                    }
+                }
+                UiEvent::RenameScheduleProfile { from, to } => {
+                    if self.schedule_profiles.get(&from).is_none() {
+                        self.reject(id, source, CommandRejection::UnknownScheduleProfile(from));
+                        continue;
+                    }
+                    if self.schedule_profiles.get(&to).is_some() {
+                        self.reject(id, source, CommandRejection::ScheduleProfileExists(to));
+                        continue;
+                    }
+                    if let Err(e) = self.schedule_profiles.rename(&mut self.storage, &from, &to) {
+                        log::error!("Failed to rename schedule profile {} to {}: {}", from, to, e);
+                    }
+                    self.schedule_profiles_published = false;
+                    if self.settings.active_schedule.as_ref() == Some(&from) {
+                        self.settings.active_schedule = Some(to);
+                    }
+                }
+                UiEvent::ActivateScheduleProfile(name) => {
+                    if let Some(name) = name.as_ref().filter(|name| self.schedule_profiles.get(name).is_none()) {
+                        self.reject(id, source, CommandRejection::UnknownScheduleProfile(name.clone()));
+                        continue;
+                    }
+                    self.settings.active_schedule = name;
+                    // Apply the new schedule's current period right away
+                    self.reapply_schedule();
+                }
+                UiEvent::Boost(true) => {
+                    let Some(boost) = Boost::start(self.settings.boost, &self.settings.mode) else {
+                        self.reject(id, source, CommandRejection::BoostNeedsMode);
+                        continue;
+                    };
+                    log::info!("Boost for {} minutes", self.settings.boost.duration_mins);
+                    self.boost = Some(boost);
+                }
+                UiEvent::Boost(false) => self.boost = None,
+                UiEvent::DemoTemperature(Some(temp_c)) => {
+                    log::warn!("Demo: pretending the room is at {:.1}°C for {} minutes", temp_c, DEMO_TEMP_MINS);
+                    self.demo_temp = Some((temp_c, Instant::now() + Duration::from_mins(DEMO_TEMP_MINS)));
+                }
+                UiEvent::DemoTemperature(None) => self.demo_temp = None,
+                UiEvent::SleepPreset(Some(wake_time)) => {
+                    if !clock::is_set() {
+                        self.reject(id, source, CommandRejection::ClockNotSet);
+                        continue;
+                    }
+                    let until = sleep::wake_at(clock::local_now(), wake_time);
+                    log::info!("Sleep preset on until {}", until);
+                    self.sleep_until = Some(until);
+                    self.settings.sleep.wake_time = wake_time;
+                }
+                UiEvent::SleepPreset(None) => {
+                    self.sleep_until = None;
+                    self.reapply_schedule();
+                }
+                UiEvent::OpenWindowOverride => {
+                    log::info!("Open window pause overridden");
+                    self.open_window_until = None;
+                    self.open_window.reset();
+                }
+                UiEvent::DemandResponseSignal(duration_mins) => {
+                    log::info!("Demand response signal from {:?}: {:?} minutes", source, duration_mins);
+                    self.demand_response = duration_mins.map(|mins| DemandResponseEvent::new(Duration::from_mins(mins as u64)));
+                }
+                UiEvent::DemandResponseOptOut => {
+                    if let Some(event) = self.demand_response.as_mut() {
+                        log::info!("Opted out of the demand response event");
+                        event.opt_out();
+                    }
+                }
+                UiEvent::OutdoorTempUpdate(outdoor_temp_c) => {
+                    self.outdoor_reading = Some((outdoor_temp_c, Instant::now()));
+                    // A sensor reading, not a setting
+                    continue;
+                }
+                UiEvent::RemoteTempUpdate { sensor, temp_c, battery } => {
+                    self.remote_sensors.record(sensor, temp_c, battery);
+                    // A sensor reading, not a setting
+                    continue;
+                }
+                UiEvent::PairSensor | UiEvent::UnpairSensor(_) if !cfg!(feature = "espnow") => {
+                    self.reject(id, source, CommandRejection::NoEspNow);
+                    continue;
+                }
+                // Pairing needs the button pressed on both ends, so not from the network
+                UiEvent::PairSensor if source != CommandSource::Touch => {
+                    self.reject(id, source, CommandRejection::TouchOnly);
+                    continue;
+                }
+                UiEvent::PairSensor => self.bus.publish_state(BackendEvent::OpenSensorPairing),
+                UiEvent::UnpairSensor(sensor) => {
+                    self.remote_sensors.remove(&sensor);
+                    self.bus.publish_state(BackendEvent::SensorUnpaired(sensor));
+                }
+                UiEvent::BleSensorsUpdate(sensors) => self.settings.ble_sensors = sensors,
+                UiEvent::SensorSelectionUpdate { sensors, periods } => {
+                    self.settings.sensor_selection.sensors = sensors;
+                    if let Some(periods) = periods {
+                        self.settings.sensor_selection.periods = periods;
+                    }
+                }
+                UiEvent::HumidityUpdate(humidity) => {
+                    self.current_humidity = Some(humidity);
+                    // A sensor reading, not a setting
+                    continue;
+                }
+                UiEvent::Co2Update(co2_ppm) => {
+                    self.co2_ppm = Some(co2_ppm);
+                    // A sensor reading, not a setting
+                    continue;
+                }
+                UiEvent::EnclosureTempUpdate(temp_c) => {
+                    match self.enclosure.update(temp_c) {
+                        Some(true) => self.raise_alert(Severity::Warning, format!(
+                            "Thermostat overheating ({:.0}°C inside), display and Wi-Fi off until it cools down",
+                            temp_c
+                        )),
+                        Some(false) => log::info!("Enclosure cooled down to {:.0}°C, display and Wi-Fi back on", temp_c),
+                        None => {}
+                    }
+                    network::set_paused(self.enclosure.overheated());
+                    // A sensor reading, not a setting
+                    continue;
+                }
+                UiEvent::ImportConfig(mut backup) => {
+                    backup.keep_device_settings(&self.settings);
+                    // Homeowners can restore a backup, but the installer settings stay as they are
+                    if !self.installer.is_installer(source, session.as_ref()) {
+                        InstallerSettings::from_settings(&self.settings).apply_to(&mut backup.settings);
+                    }
+                    for name in self.schedule_profiles.names() {
+                        if let Err(e) = self.schedule_profiles.remove(&mut self.storage, &name) {
+                            log::error!("Failed to remove schedule profile {}: {}", name, e);
+                        }
+                    }
+                    for profile in backup.schedule_profiles {
+                        if let Err(e) = self.schedule_profiles.upsert(&mut self.storage, profile) {
+                            log::error!("Failed to persist imported schedule profile: {}", e);
+                        }
+                    }
+                    self.settings = backup.settings;
+                    self.schedule_profiles_published = false;
+                    self.time_format_changed();
+                    self.reapply_schedule();
+                }
+                UiEvent::ExportSettingsRequest => {
+                    let backup = ConfigBackup {
+                        settings: self.settings.clone(),
+                        schedule_profiles: self.schedule_profiles.all().to_vec(),
+                    };
+                    match backup.to_json() {
+                        Ok(json) => self.bus.publish_state(BackendEvent::SettingsExport(json)),
+                        Err(e) => log::error!("Failed to export settings: {}", e),
+                    }
+                    continue;
+                }
+                UiEvent::DiagnosticsRequest(diagnostics) => {
+                    let export = self.diagnostics(diagnostics).map_err(|e| {
+                        log::error!("Failed to export {:?}: {}", diagnostics, e);
+                        e.to_string()
+                    });
+                    self.bus.publish_state(BackendEvent::DiagnosticsExport { id, export });
+                    continue;
+                }
+                UiEvent::ForceRefresh => {
+                    self.refresh_requested = true;
+                    continue;
+                }
+                UiEvent::CheckForUpdates => {
+                    match self.settings.ota_manifest_url.clone() {
+                        Some(url) => self.updater.check(url, self.settings.update_channel),
+                        None => self.bus.publish_state(BackendEvent::UpdateStatus(UpdateStatus::Failed("No update server configured".to_string()))),
+                    }
+                    continue;
+                }
+                UiEvent::InstallUpdate => self.updater.install(),
+                UiEvent::UpdateChannelUpdate(channel) => self.settings.update_channel = channel,
+                UiEvent::NetworkSettingsUpdate(network) => self.settings.network = network,
+                UiEvent::TimeFormatUpdate(time_format) => {
+                    self.settings.time_format = time_format;
+                    self.time_format_changed();
+                }
+                UiEvent::TimezoneUpdate(timezone) => {
+                    self.settings.timezone = timezone;
+                    self.time_format_changed();
+                    // The current schedule period may be a different one in the new local time
+                    self.reapply_schedule();
+                }
+                UiEvent::InstallerLogin(code) => match self.installer.login(source, &code) {
+                    Some(token) => self.bus.publish_state(BackendEvent::InstallerSession { id, token }),
+                    None => {
+                        self.reject(id, source, CommandRejection::WrongInstallerCode);
+                        continue;
+                    }
+                },
+                UiEvent::InstallerLogout => self.installer.logout(source, session.as_ref()),
+                UiEvent::SetInstallerCode(code) => {
+                    if let Err(e) = self.installer.set_code(&mut self.storage, code.as_ref()) {
+                        log::error!("Failed to persist installer code: {}", e);
+                    }
+                }
+                UiEvent::InstallerSettingsUpdate(installer_settings) => installer_settings.apply_to(&mut self.settings),
+                UiEvent::SetSigningSecret(secret) => match signing::store_secret(&mut self.storage, secret.as_ref()) {
+                    Ok(()) => self.bus.publish_state(BackendEvent::SigningSecretChanged),
+                    Err(e) => log::error!("Failed to persist MQTT signing secret: {}", e),
+                },
+                UiEvent::SetApiCredentials(credentials) => match credentials.save(&mut self.storage) {
+                    Ok(()) => {
+                        self.api_credentials = credentials.clone();
+                        self.bus.publish_state(BackendEvent::ApiCredentialsChanged(credentials));
+                    }
+                    Err(e) => log::error!("Failed to persist api credentials: {}", e),
+                },
+                UiEvent::SetTlsMaterial(tls) => {
+                    let stored = match &tls {
+                        Some(tls) => tls.save(&mut self.storage),
+                        None => TlsMaterial::remove(&mut self.storage),
+                    };
+                    match stored {
+                        Ok(()) => log::info!("TLS certificate {}, served from the next restart", if tls.is_some() { "stored" } else { "removed" }),
+                        Err(e) => log::error!("Failed to persist TLS certificate: {}", e),
+                    }
+                }
+                UiEvent::SeasonalLockoutUpdate { heat_above_c, cool_below_c } => {
+                    self.settings.heat_lockout_above_c = heat_above_c;
+                    self.settings.cool_lockout_below_c = cool_below_c;
+                    if (heat_above_c.is_some() || cool_below_c.is_some()) && self.outdoor_temp_c().is_none() {
+                        log::warn!("Seasonal lockout set without an outdoor temperature, it applies once one comes in over MQTT");
+                    }
+                }
+            }
+            if let Some(what) = contended {
+                self.arbiter.record(source, what);
+            }
+            self.audit_log.record(&command);
+            self.audit_log_published = false;
+            if self.settings != settings_before {
+                self.settings_changed();
+            }
+            // The setpoint gets snapped to a step of the display unit on top of any validation
+            let applied = match command.event {
+                UiEvent::TargetTempUpdate(_) => UiEvent::TargetTempUpdate(self.settings.target_temp_c),
+                event => event,
+            };
+            let outcome = if applied == requested {
+                CommandOutcome::Applied
+            } else {
+                // Puts the ui back on the stored value, even when the adjustment left it unchanged
+                self.settings_published = false;
+                CommandOutcome::Adjusted(applied)
+            };
+            self.bus.publish_state(BackendEvent::CommandAck { id, source, outcome });
         }
+        self.audit_log.save_if_dirty(&mut self.storage);
+        self.save_settings_if_dirty();
         self.last_user_interaction_time = Instant::now();
     }
 
-    fn start_heating(&mut self, controller: &mut Controller) {
+    fn reject(&self, id: CommandId, source: CommandSource, rejection: CommandRejection) {
+        log::warn!("Rejected command {} from {:?}: {}", id, source, rejection);
+        self.bus.publish_state(BackendEvent::CommandAck {
+            id,
+            source,
+            outcome: CommandOutcome::Rejected(rejection),
+        });
+    }
+
+    /// Mark the settings to be persisted and re-sent to the ui, and pass on the time zone and
+    /// network config
+    fn settings_changed(&mut self) {
+        self.settings_dirty = true;
+        self.settings_published = false;
+        self.apply_timezone();
+        network::configure(&self.settings.network);
+        wireguard::configure(self.settings.wireguard.as_ref());
+    }
+
+    fn apply_timezone(&self) {
+        // Validated on the way in, only a hand edited blob could get here with anything else
+        clock::set_timezone(timezone::posix(&self.settings.timezone).unwrap_or("UTC0"));
+    }
+
+    fn save_settings_if_dirty(&mut self) {
+        if !self.settings_dirty {
+            return;
+        }
+        match self.settings.save(&mut self.storage) {
+            Ok(()) => self.settings_dirty = false,
+            Err(e) => log::error!("Failed to persist settings: {}", e),
+        }
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let today = self.summary.today();
+        Snapshot {
+            current_temp_c: self.get_room_temp(),
+            control_sensors: self.remote_sensors.in_use(self.active_control_sensors()).into_iter().map(str::to_string).collect(),
+            remote_sensors: self.remote_sensors.statuses(),
+            state: self.runtime_state,
+            fan_running: self.fan_running(),
+            fan_timer_remaining_secs: self.fan_timer_until.map(|until| until.saturating_duration_since(Instant::now()).as_secs()),
+            compressor_lockout_remaining_secs: Some(self.compressor_lockout_remaining().as_secs()).filter(|&secs| secs > 0),
+            rest_remaining_secs: (self.runtime_state == ThermostatRuntimeState::Resting)
+                .then(|| self.get_remaining_resting_duration().as_secs()),
+            rest_progress: (self.runtime_state == ThermostatRuntimeState::Resting).then(|| rest_progress(self.rest_elapsed())),
+            trend: self.trend.trend(),
+            slope_c_per_hour: self.trend.slope_c_per_hour(),
+            setpoint_estimate: self.estimate_time_to_setpoint(),
+            quiet_hours: self.quiet_hours,
+            installer_unlocked: self.installer.is_installer(CommandSource::Touch, None),
+            api_token: self
+                .installer
+                .is_installer(CommandSource::Touch, None)
+                .then(|| self.api_credentials.token.clone())
+                .flatten(),
+            screen_wash: clock::is_set() && self.settings.burn_in.washing(clock::local_now().time()),
+            boost_remaining_secs: self.boost.as_ref().map(|boost| boost.remaining().as_secs()),
+            sleep_until: self.sleep_until.map(|until| until.time()),
+            away_until: self
+                .settings
+                .vacation
+                .as_ref()
+                .filter(|_| self.away_setpoint().is_some())
+                .map(|vacation| vacation.end),
+            schedule_exceptions: match (self.settings.active_schedule.as_deref().and_then(|name| self.schedule_profiles.get(name)), clock::is_set()) {
+                (Some(schedule), true) => schedule.upcoming_exceptions(clock::local_now().date()).cloned().collect(),
+                (Some(schedule), false) => schedule.exceptions.clone(),
+                (None, _) => Vec::new(),
+            },
+            open_window: self.open_window_paused(),
+            demo_temp: self.demo_temp.is_some(),
+            peak: self.peak_phase,
+            demand_response: self.demand_response_active(),
+            outdoor_temp_c: self.outdoor_temp_c(),
+            humidity_percent: self.current_humidity,
+            co2_ppm: self.co2_ppm,
+            ventilating: self.ventilating,
+            ventilation_held: self.ventilation_held,
+            frost_protecting: self.frost_protecting,
+            high_temp_cutoff: self.high_temp_cutoff,
+            capabilities: Capabilities {
+                humidity: self.current_humidity.is_some(),
+                outdoor_temp: self.outdoor_temp_c().is_some(),
+                air_quality: self.co2_ppm.is_some(),
+            },
+            seasonal_lockout: self.seasonal_lockout(),
+            heat_source: self.heat_source.filter(|_| self.runtime_state == ThermostatRuntimeState::Heating),
+            heat_overshoot: self.overshoot.stats(),
+            comfort: today.comfort_metrics(),
+            reduced_power: self.brownouts.reduced_power(),
+            brownouts: self.brownouts.total(),
+            memory: self.memory.stats().clone(),
+            timings: self.timings.stats().to_vec(),
+            online: network::is_online(),
+            ip_address: network::ip_address(),
+            remote_access: wireguard::is_configured().then(wireguard::is_up),
+            enclosure_temp_c: self.enclosure.temp_c(),
+            enclosure_overheated: self.enclosure.overheated(),
+            // Whole minutes, as shown
+            clock_synced_secs_ago: clock::since_last_sync().map(|since| since.as_secs() / 60 * 60),
+            clock_drift_ppm: clock::drift_ppm(),
+        }
+    }
+
+    /// Extrapolate the recent slope to estimate when the running call reaches its target.
+    /// None when idle or when the temperature isn't moving towards the target.
+    pub fn estimate_time_to_setpoint(&self) -> Option<SetpointEstimate> {
+        let target_c = match self.runtime_state {
+            ThermostatRuntimeState::Heating => self.get_heating_stop_temp(),
+            ThermostatRuntimeState::Cooling => self.get_cooling_stop_temp(),
+            _ => return None,
+        };
+        let slope = self.trend.slope_c_per_hour()?;
+        let minutes = (target_c - self.get_control_temp()?) / slope * 60.0;
+        if !(0.0..=MAX_SETPOINT_ESTIMATE_MINS).contains(&minutes) {
+            return None;
+        }
+        Some(SetpointEstimate {
+            target_c,
+            minutes: minutes.round() as u32,
+        })
+    }
+
+    /// Publish any summaries finished by a date change, and POST them to the webhook if configured
+    fn publish_summaries(&mut self) {
+        for summary in self.summary.roll_over(&self.settings) {
+            if let Some(url) = &self.settings.summary_webhook_url {
+                match serde_json::to_string(&summary) {
+                    Ok(body) => webhook::post_json_async(url.clone(), body),
+                    Err(e) => log::error!("Failed to serialize summary: {}", e),
+                }
+            }
+            self.bus.publish_state(BackendEvent::Summary(summary));
+        }
+    }
+
+    /// Publish the compressor start counts when they change and alert on short cycling
+    fn publish_cycle_stats(&mut self) {
+        let counts = self.cycle_stats.counts();
+        if self.published_cycle_counts == Some(counts) {
+            return;
+        }
+        self.published_cycle_counts = Some(counts);
+        self.bus.publish_state(BackendEvent::CycleStatsUpdate(counts));
+
+        let limit = self.settings.max_compressor_starts_per_hour;
+        if counts.starts_last_hour > limit && !self.short_cycling {
+            self.short_cycling = true;
+            self.raise_alert(Severity::Warning, format!(
+                "Compressor started {} times in the last hour, consider a slower differential",
+                counts.starts_last_hour
+            ));
+        } else if counts.starts_last_hour <= limit {
+            self.short_cycling = false;
+        }
+    }
+
+    /// Send the settings to the ui if they changed since they were last sent
+    fn publish_settings(&mut self) {
+        if self.settings_published {
+            return;
+        }
+        self.bus.publish_state(BackendEvent::SettingsUpdate(self.settings.clone()));
+        self.bus.publish_state(BackendEvent::WiringCheck(wiring::check(&self.settings)));
+        self.settings_published = true;
+    }
+
+    /// Send the status message and snapshot to the ui if either changed, or as a keepalive
+    fn publish_status(&mut self) {
+        let message = self.get_status_message();
+        let snapshot = self.snapshot();
+        let unchanged = self.published_status.as_ref().is_some_and(|(published_message, published_snapshot, at)| {
+            *published_message == message && *published_snapshot == snapshot && at.elapsed() < STATUS_KEEPALIVE
+        });
+        if unchanged {
+            return;
+        }
+        self.bus.publish_state(BackendEvent::CurrentStateMessage(message));
+        self.bus.publish_state(BackendEvent::Snapshot(snapshot.clone()));
+        self.published_status = Some((message, snapshot, Instant::now()));
+    }
+
+    /// Send the schedule profile names to the ui if they changed since they were last sent
+    fn publish_schedule_profiles(&mut self) {
+        if self.schedule_profiles_published {
+            return;
+        }
+        self.bus.publish_state(BackendEvent::ScheduleProfilesUpdate(self.schedule_profiles.names()));
+        self.schedule_profiles_published = true;
+    }
+
+    /// Re-send the logs whose summaries show times and dates
+    fn time_format_changed(&mut self) {
+        self.audit_log_published = false;
+        self.transitions_published = false;
+        self.temperature_history_published = false;
+    }
+
+    /// Send the audit log to the ui if it changed since it was last sent
+    fn publish_audit_log(&mut self) {
+        if self.audit_log_published {
+            return;
+        }
+        let entries = self.audit_log.entries().map(|entry| entry.summary(&self.settings.time_format)).collect();
+        self.bus.publish_state(BackendEvent::AuditLogUpdate(entries));
+        self.audit_log_published = true;
+    }
+
+    /// Log the state change of this tick, if there was one
+    fn record_transition(&mut self, previous_state: ThermostatRuntimeState) {
+        let reason = self.transition_reason.take();
+        if previous_state == self.runtime_state {
+            return;
+        }
+        let reason = reason.unwrap_or(TransitionReason::ThresholdCrossed);
+        self.trace.record(None, TraceEvent::Transition { from: previous_state, to: self.runtime_state });
+        #[cfg(feature = "replay")]
+        if let Some(replay) = self.replay.as_mut() {
+            replay.transition(&previous_state, &self.runtime_state);
+        }
+        self.transitions.record(previous_state, self.runtime_state, reason, self.get_room_temp());
+        self.transitions_published = false;
+    }
+
+    /// Send the transition log to the ui if it changed since it was last sent
+    fn publish_transitions(&mut self) {
+        if self.transitions_published {
+            return;
+        }
+        let entries = self.transitions.entries().map(|entry| entry.summary(&self.settings.time_format)).collect();
+        self.bus.publish_state(BackendEvent::TransitionLogUpdate(entries));
+        self.transitions_published = true;
+    }
+
+    /// Send the daily min/max temperatures to the ui if they changed since they were last sent
+    fn publish_temperature_history(&mut self) {
+        if self.temperature_history_published {
+            return;
+        }
+        let days = self.temperature_history.days().map(|day| day.summary(&self.settings.time_format)).collect();
+        self.bus.publish_state(BackendEvent::TemperatureHistoryUpdate(days));
+        self.temperature_history_published = true;
+    }
+
+    /// Daily min/max temperatures as JSON, for the `/history` endpoint of the network api
+    pub fn temperature_history_json(&self) -> anyhow::Result<String> {
+        self.temperature_history.to_json()
+    }
+
+    /// Transition log as JSON, for the `/transitions` endpoint of the network api
+    pub fn transitions_json(&self) -> anyhow::Result<String> {
+        self.transitions.to_json()
+    }
+
+    /// Memory stats, air quality and remote sensors in Prometheus text format, for the `/metrics` endpoint of the network api
+    pub fn metrics_text(&self) -> String {
+        let mut text = self.memory.stats().to_prometheus();
+        text.push_str(&timing::to_prometheus(self.timings.stats()));
+        if let Some(co2_ppm) = self.co2_ppm {
+            let _ = writeln!(text, "thermostat_co2_ppm {}", co2_ppm);
+            let _ = writeln!(text, "thermostat_ventilating {}", self.ventilating as u8);
+        }
+        let comfort = self.summary.today().comfort_metrics();
+        if let Some(score) = comfort.comfort_score {
+            let _ = writeln!(text, "thermostat_comfort_score {}", score);
+        }
+        if let Some(percent) = comfort.percent_in_comfort_band {
+            let _ = writeln!(text, "thermostat_comfort_band_percent {}", percent);
+        }
+        if let Some(secs) = comfort.average_cycle_secs {
+            let _ = writeln!(text, "thermostat_average_cycle_seconds {}", secs);
+        }
+        if let Some(overshoot_c) = comfort.max_overshoot_c {
+            let _ = writeln!(text, "thermostat_max_overshoot_celsius {}", overshoot_c);
+        }
+        // Debug formatting quotes and escapes the names the way label values need
+        for sensor in self.remote_sensors.statuses() {
+            let _ = writeln!(text, "thermostat_remote_temperature_celsius{{sensor={:?}}} {}", sensor.name, sensor.temp_c);
+            if let Some(percent) = sensor.battery.and_then(|battery| battery.percent) {
+                let _ = writeln!(text, "thermostat_remote_battery_percent{{sensor={:?}}} {}", sensor.name, percent);
+            }
+        }
+        text
+    }
+
+    /// Recent inputs and transitions as JSON, for export and replay
+    pub fn trace_json(&self) -> anyhow::Result<String> {
+        self.trace.to_json()
+    }
+
+    /// The trace of the boot before this one as JSON, usually the one that ended in the issue
+    pub fn previous_trace_json(&self) -> Option<&str> {
+        self.previous_trace.as_deref()
+    }
+
+    /// Feed a trace exported by `trace_json` back through the backend from now on
+    #[cfg(feature = "replay")]
+    pub fn start_replay(&mut self, json: &str) -> anyhow::Result<()> {
+        self.replay = Some(crate::trace::TraceReplay::from_json(json)?);
+        Ok(())
+    }
+
+    /// Hand the replayed readings and commands that are due to the backend
+    #[cfg(feature = "replay")]
+    fn replay_due(&mut self) {
+        let Some(replay) = self.replay.as_mut() else {
+            return;
+        };
+        for entry in replay.due() {
+            match (entry.event.to_command(), entry.event) {
+                (Some(event), _) => {
+                    self.bus.publish_command(entry.source.unwrap_or(CommandSource::Sensor), event);
+                }
+                (None, TraceEvent::OnboardTemp(reading)) => self.replay_onboard = Some(reading),
+                (None, _) => {}
+            }
+        }
+    }
+
+    /// Audit log as JSON, for the `/audit` endpoint of the network api
+    pub fn audit_log_json(&self) -> anyhow::Result<String> {
+        self.audit_log.to_json()
+    }
+
+    /// Answer a `DiagnosticsRequest`
+    fn diagnostics(&self, diagnostics: Diagnostics) -> anyhow::Result<String> {
+        match diagnostics {
+            Diagnostics::AuditLog => self.audit_log_json(),
+            Diagnostics::Transitions => self.transitions_json(),
+            Diagnostics::Metrics => Ok(self.metrics_text()),
+            Diagnostics::TemperatureHistory => self.temperature_history_json(),
+        }
+    }
+
+    /// Start a heat call, or switch heat source during one on a dual fuel system.
+    fn start_heating(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if self.runtime_state != ThermostatRuntimeState::Heating {
+            self.summary.record_heating_cycle();
+            self.overshoot.heat_call_started();
+            self.heat_pump_start_time = None;
+        }
+        if self.settings.system.hydronic().is_some() {
+            return self.start_hydronic_heating(controller);
+        }
+        self.runtime_state = ThermostatRuntimeState::Heating;
+        self.heat_source = self.desired_heat_source();
+        match (self.heat_source, self.settings.dual_fuel) {
+            (Some(HeatSource::HeatPump), Some(dual_fuel)) => {
+                if self.heat_pump_start_time.is_none() {
+                    self.heat_pump_start_time = Some(Instant::now());
+                    self.cycle_stats.record_compressor_start();
+                }
+                // The compressor heats through the cool relay, drop the furnace first
+                controller.set_heating(false)?;
+                controller.set_reversing_valve(dual_fuel.reversing_valve.energized(true))?;
+                controller.set_cooling(true)?;
+            }
+            _ => {
+                // Always drop the opposite relay first so the interlock never sees both on
+                controller.set_cooling(false)?;
+                controller.set_heating(true)?;
+            }
+        }
+        self.fan_off_at = None;
+        self.fan_with_call(controller)
+    }
+
+    /// Open the zone valve. The circulator starts once it is open, see `update_hydronic`.
+    fn start_hydronic_heating(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if self.runtime_state != ThermostatRuntimeState::Heating {
+            self.valve_opened_at = Some(Instant::now());
+            self.end_switch_alerted = false;
+        }
         self.runtime_state = ThermostatRuntimeState::Heating;
-        controller.set_heating(true);
-        controller.set_cooling(false);
-        controller.set_fan(true);
+        self.heat_source = None;
+        controller.set_cooling(false)?;
+        controller.set_heating(true)?;
+        self.fan_off_at = None;
+        Ok(())
+    }
+
+    /// Start the circulator pump once the zone valve of the running heat call is open
+    fn update_hydronic(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let Some(hydronic) = self.settings.system.hydronic() else {
+            return Ok(());
+        };
+        let Some(opened_at) = self.valve_opened_at.filter(|_| self.runtime_state == ThermostatRuntimeState::Heating) else {
+            return Ok(());
+        };
+        if hydronic.pump_may_run(opened_at.elapsed(), controller.valve_end_switch()) {
+            return controller.set_fan(true);
+        }
+        if hydronic.end_switch_overdue(opened_at.elapsed()) && !self.end_switch_alerted {
+            self.end_switch_alerted = true;
+            self.raise_alert(Severity::Warning, "Zone valve end switch hasn't closed, the circulator is waiting for it".to_string());
+        }
+        Ok(())
+    }
+
+    /// Heat source a dual fuel system should be using right now, None without dual fuel
+    fn desired_heat_source(&self) -> Option<HeatSource> {
+        let heat_pump_runtime = self.heat_pump_start_time.map(|start| start.elapsed());
+        // Dry contact only ever closes the heat relay for heat
+        self.settings
+            .dual_fuel
+            .filter(|_| self.settings.system.dry_contact().is_none())
+            .map(|dual_fuel| dual_fuel.heat_source(self.outdoor_temp_c(), heat_pump_runtime))
     }
 
-    fn start_cooling(&mut self, controller: &mut Controller) {
+    fn start_cooling(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if self.runtime_state != ThermostatRuntimeState::Cooling {
+            self.summary.record_cooling_cycle();
+            self.cycle_stats.record_compressor_start();
+        }
         self.runtime_state = ThermostatRuntimeState::Cooling;
-        controller.set_cooling(true);
-        controller.set_heating(false);
-        controller.set_fan(true);
+        // Always drop the opposite relay first so the interlock never sees both on
+        controller.set_heating(false)?;
+        if let Some(dual_fuel) = self.settings.dual_fuel {
+            controller.set_reversing_valve(dual_fuel.reversing_valve.energized(false))?;
+        }
+        controller.set_cooling(true)?;
+        self.fan_off_at = None;
+        self.fan_with_call(controller)
+    }
+
+    /// Bring the fan on for a heat/cool call, unless the system profile keeps it out of calls
+    fn fan_with_call(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if !self.settings.system.fan_follows_calls() {
+            return Ok(());
+        }
+        controller.set_fan(true)
     }
 
-    fn start_idle(&mut self, controller: &mut Controller) {
+    /// Start a cool call, running the fan alone for the lead time first if one is configured.
+    fn begin_cooling(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if self.settings.cool_fan_lead_secs == 0 || !self.settings.system.fan_follows_calls() {
+            return self.start_cooling(controller);
+        }
+        self.runtime_state = ThermostatRuntimeState::FanLead;
+        self.fan_lead_start_time = Instant::now();
+        self.fan_off_at = None;
+        controller.set_heating(false)?;
+        controller.set_cooling(false)?;
+        controller.set_fan(true)
+    }
+
+    /// The controller refused a command or couldn't drive a relay. Force everything off,
+    /// tell the UI and go idle until the next tick moves on to Waiting or Off.
+    fn controller_fault(&mut self, controller: &mut Controller, error: ControllerError) {
+        log::error!("Controller fault: {}", error);
+        if let Err(e) = controller.all_off() {
+            log::error!("Failed to reach safe state: {}", e);
+        }
         self.runtime_state = ThermostatRuntimeState::Idle;
-        controller.set_heating(false);
-        controller.set_cooling(false);
-        // Turn fan off if in auto mode. Will always be turned back on when in heating or cooling mode.
-        if self.fan_mode == FanStatus::Auto {
-            controller.set_fan(false);
+        self.transition_reason = Some(TransitionReason::Fault);
+        let message = match error {
+            ControllerError::Interlock(_) => "Heat/cool interlock tripped, all relays off".to_string(),
+            ControllerError::Gpio { relay, .. } => format!("{:?} relay not responding, all relays off", relay),
+        };
+        // A stuck relay fails again every tick, alert until a tick gets through
+        if !self.controller_faulted {
+            self.controller_faulted = true;
+            self.raise_alert(Severity::Critical, message);
+        }
+    }
+
+    /// Show an alert on the ui, and push it to the configured notification target if it is critical.
+    fn raise_alert(&mut self, severity: Severity, message: String) {
+        log::warn!("Alert: {}", message);
+        if let Some(target) = &self.settings.notification_target {
+            self.notifier.notify(target, severity, &message);
+        }
+        self.bus.publish_state(BackendEvent::Alert(message));
+    }
+
+    /// Switch the system off. Everything but a fan asked for by the fan mode or timer goes off,
+    /// a call that was running gets its usual fan run-on.
+    fn start_off(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let previous_state = std::mem::replace(&mut self.runtime_state, ThermostatRuntimeState::Off);
+        controller.set_heating(false)?;
+        controller.set_cooling(false)?;
+        self.release_fan(controller, previous_state)
+    }
+
+    /// Between calls the fan follows the fan mode and timer, unless a run-on is still going
+    fn circulate_fan(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if let Some(on) = circulation_fan(self.runtime_state, self.fan_off_at.is_some(), self.fan_circulating()) {
+            controller.set_fan(on)?;
         }
+        Ok(())
     }
 
-    fn start_resting(&mut self, controller: &mut Controller) {
+    /// No heat or cool call is running or being prepared
+    fn call_inactive(&self) -> bool {
+        self.runtime_state.call_inactive()
+    }
+
+    /// Apply a mode change to a running call right away instead of waiting for the next
+    /// threshold crossing. Returns whether the state changed.
+    fn follow_mode(&mut self, controller: &mut Controller) -> Result<bool, ControllerError> {
+        let off = matches!(self.settings.mode, ModeStatus::Off);
+        let still_wanted = match self.runtime_state {
+            // Leaving Off goes through Waiting so the temperature and lockouts decide what starts
+            ThermostatRuntimeState::Off => off,
+            ThermostatRuntimeState::Heating => matches!(self.settings.mode, ModeStatus::Heat),
+            ThermostatRuntimeState::FanLead | ThermostatRuntimeState::Cooling => matches!(self.settings.mode, ModeStatus::Cool),
+            ThermostatRuntimeState::Waiting | ThermostatRuntimeState::Resting | ThermostatRuntimeState::Idle => !off,
+        };
+        if still_wanted {
+            return Ok(false);
+        }
+        self.transition_reason = Some(TransitionReason::ModeChanged);
+        if off {
+            self.start_off(controller)?;
+        } else {
+            self.start_waiting(controller)?;
+        }
+        Ok(true)
+    }
+
+    fn start_resting(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
         self.runtime_state = ThermostatRuntimeState::Resting;
         self.last_resting_start_time = Instant::now();
-        controller.set_heating(false);
-        controller.set_cooling(false);
+        self.rest_credit = Duration::ZERO;
+        controller.set_heating(false)?;
+        controller.set_cooling(false)?;
         // Fan is always on during resting to make sure compressor thaws
-        controller.set_fan(true);
+        self.fan_with_call(controller)
     }
 
-    fn start_waiting(&mut self, controller: &mut Controller) {
-        self.runtime_state = ThermostatRuntimeState::Waiting;
+    fn start_waiting(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let previous_state = std::mem::replace(&mut self.runtime_state, ThermostatRuntimeState::Waiting);
         self.last_resting_start_time = Instant::now();
+        self.rest_credit = Duration::ZERO;
+
+        controller.set_heating(false)?;
+        controller.set_cooling(false)?;
+        self.release_fan(controller, previous_state)
+    }
+
+    /// Turn fan off if in auto mode. Will always be turned back on when in heating or cooling mode.
+    /// When a heat/cool call just ended the fan keeps running for the run-on time first, to pull
+    /// the residual heat/cold out of the exchanger.
+    fn release_fan(&mut self, controller: &mut Controller, previous_state: ThermostatRuntimeState) -> Result<(), ControllerError> {
+        if self.fan_circulating() {
+            return Ok(());
+        }
+        let run_on_secs = match previous_state {
+            // The fan never came on with the call
+            _ if !self.settings.system.fan_follows_calls() => 0,
+            ThermostatRuntimeState::Heating => match self.settings.system.hydronic() {
+                Some(hydronic) => hydronic.pump_overrun_secs,
+                None => self.settings.comfort().heat_fan_run_on_secs,
+            },
+            ThermostatRuntimeState::Cooling => self.settings.comfort().cool_fan_run_on_secs,
+            // Let a pending run-on finish instead of cutting it short
+            _ if self.fan_off_at.is_some() => return Ok(()),
+            _ => 0,
+        };
+        if run_on_secs == 0 {
+            controller.set_fan(false)
+        } else {
+            self.fan_off_at = Some(Instant::now() + Duration::from_secs(run_on_secs as u64));
+            Ok(())
+        }
+    }
+
+    /// Whether the fan should run continuously, outside heat/cool calls. A fan timer asked for by
+    /// hand runs through quiet hours.
+    fn fan_circulating(&self) -> bool {
+        fan_circulating(self.settings.fan_mode, self.quiet_hours, self.fan_timer_until.is_some()) && self.settings.system.has_fan()
+    }
+
+    /// Whether the fan relay is on: during a call, fan lead or rest, a run-on, or circulation while idle
+    fn fan_running(&self) -> bool {
+        match self.runtime_state {
+            ThermostatRuntimeState::Heating
+            | ThermostatRuntimeState::Cooling
+            | ThermostatRuntimeState::FanLead
+            | ThermostatRuntimeState::Resting if self.settings.system.fan_follows_calls() => true,
+            _ => {
+                self.fan_off_at.is_some()
+                    || self.fan_circulating()
+                    || (self.ventilating && self.settings.ventilation.is_some_and(|ventilation| ventilation.interlock.require_fan))
+            }
+        }
+    }
+
+    /// The outdoor temperature, unless it's missing or too old to go by
+    fn outdoor_temp_c(&self) -> Option<f32> {
+        self.outdoor_reading
+            .filter(|(_, received_at)| received_at.elapsed() < OUTDOOR_TEMP_MAX_AGE)
+            .map(|(temp_c, _)| temp_c)
+    }
+
+    /// No heating while it's warm outside, so a bumped setpoint in July doesn't run the furnace
+    fn heat_locked_out(&self) -> bool {
+        matches!((self.outdoor_temp_c(), self.settings.heat_lockout_above_c), (Some(outdoor), Some(limit)) if outdoor > limit)
+    }
+
+    /// No cooling while it's cold outside
+    fn cool_locked_out(&self) -> bool {
+        matches!((self.outdoor_temp_c(), self.settings.cool_lockout_below_c), (Some(outdoor), Some(limit)) if outdoor < limit)
+    }
+
+    /// Whether the compressor runs: a cool call, or a heat call on the heat pump
+    fn compressor_running(&self) -> bool {
+        match self.runtime_state {
+            ThermostatRuntimeState::Cooling => true,
+            ThermostatRuntimeState::Heating => self.heat_source == Some(HeatSource::HeatPump),
+            _ => false,
+        }
+    }
+
+    /// Note when the compressor stops, whatever stopped it: the end of a call, a mode change,
+    /// a heat source change or a fault
+    fn track_compressor(&mut self) {
+        let running = self.compressor_running();
+        if self.compressor_was_running && !running {
+            self.compressor_lockout_until = Some(Instant::now() + Duration::from_mins(COMPRESSOR_MIN_OFF_MINS));
+        }
+        self.compressor_was_running = running;
+    }
+
+    /// Time left before the compressor may start again
+    fn compressor_lockout_remaining(&self) -> Duration {
+        self.compressor_lockout_until.map_or(Duration::ZERO, |until| until.saturating_duration_since(Instant::now()))
+    }
+
+    fn compressor_locked_out(&self) -> bool {
+        !self.compressor_lockout_remaining().is_zero()
+    }
+
+    /// A heat call would run the heat pump, which is still in its minimum off time
+    fn heat_pump_locked_out(&self) -> bool {
+        self.desired_heat_source() == Some(HeatSource::HeatPump) && self.compressor_locked_out()
+    }
+
+    /// Whether the selected mode is locked out by the outdoor temperature
+    fn seasonal_lockout(&self) -> bool {
+        match self.settings.mode {
+            ModeStatus::Heat => self.heat_locked_out(),
+            ModeStatus::Cool => self.cool_locked_out(),
+            ModeStatus::Off => false,
+        }
+    }
+
+    /// Whether nothing holds off a call in the given mode: the outdoor lockouts, the compressor's
+    /// minimum off time, an open window, the high temperature cutoff or a demand response event.
+    /// Whether the temperature calls for it is up to the caller.
+    fn call_allowed(&mut self, mode: ModeStatus) -> bool {
+        match mode {
+            ModeStatus::Heat => {
+                !self.open_window_paused() && !self.heat_locked_out() && !self.high_temp_cutoff && !self.heat_pump_locked_out()
+            }
+            ModeStatus::Cool => {
+                self.settings.system.has_cooling()
+                    && !self.cool_locked_out()
+                    && !self.compressor_locked_out()
+                    && self.demand_response_allows_cooling()
+            }
+            ModeStatus::Off => false,
+        }
+    }
 
-        controller.set_heating(false);
-        controller.set_cooling(false);
-        // Turn fan off if in auto mode. Will always be turned back on when in heating or cooling mode.
-        if self.fan_mode == FanStatus::Auto {
-            controller.set_fan(false);
+    fn open_window_paused(&self) -> bool {
+        self.open_window_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Watch for an open window while heating, pausing heating for the configured time when one is detected
+    fn update_open_window(&mut self) {
+        if self.open_window_until.is_some_and(|until| Instant::now() >= until) {
+            log::info!("Open window pause over, resuming heating");
+            self.open_window_until = None;
+        }
+        let Some(detection) = self.settings.open_window_detection else {
+            self.open_window_until = None;
+            return;
+        };
+        if self.runtime_state != ThermostatRuntimeState::Heating {
+            self.open_window.reset();
+            return;
+        }
+        let Some(current_temp_c) = self.get_room_temp() else {
+            return;
+        };
+        if self.open_window.record(current_temp_c, detection.sensitivity) {
+            log::warn!("Sustained temperature drop while heating, pausing for {} minutes", detection.pause_mins);
+            self.open_window_until = Some(Instant::now() + Duration::from_mins(detection.pause_mins as u64));
+            self.open_window.reset();
+        }
+    }
+
+    /// Reboot in the weekly maintenance window, if one is set, once no call is running. The
+    /// runtime context is handed over to the next boot so timers and statistics carry on.
+    fn maintenance_reboot_if_due(&mut self, controller: &mut Controller) {
+        let between_calls = self.call_inactive();
+        let due = self
+            .settings
+            .maintenance_reboot
+            .is_some_and(|reboot| clock::is_set() && reboot.due(clock::local_now(), metrics::uptime()));
+        if !between_calls || !due {
+            return;
+        }
+        let memory = self.memory.stats();
+        log::warn!(
+            "Maintenance reboot after {}h up, largest free block {}B (lowest {}B), {} failed allocations",
+            metrics::uptime().as_secs() / 3600,
+            memory.largest_free_block_bytes,
+            memory.min_largest_free_block_bytes,
+            memory.alloc_failures
+        );
+        if let Err(e) = self.handoff().save(&mut self.storage) {
+            log::error!("Failed to hand over the runtime context, rebooting without it: {}", e);
+        }
+        if let Err(e) = controller.all_off() {
+            log::error!("Failed to open the relays before the maintenance reboot: {}", e);
+        }
+        esp_idf_svc::hal::reset::restart();
+    }
+
+    /// Track quiet hours, switching fan circulation off/on as they start and end
+    fn update_quiet_hours(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let quiet_hours = self.sleep_until.is_some()
+            || self
+                .settings
+                .quiet_hours
+                .is_some_and(|window| clock::is_set() && window.contains(clock::local_now().time()));
+        if quiet_hours == self.quiet_hours {
+            return Ok(());
         }
+        log::info!("Quiet hours {}", if quiet_hours { "started" } else { "ended" });
+        self.quiet_hours = quiet_hours;
+        self.circulate_fan(controller)
+    }
+
+    /// Run the fan while the fan timer is running, and hand it back to the fan mode once it's up
+    fn update_fan_timer(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        if self.fan_timer_until.is_some_and(|until| Instant::now() >= until) {
+            log::info!("Fan timer finished");
+            self.fan_timer_until = None;
+        }
+        self.circulate_fan(controller)
+    }
+
+    /// Turn the fan off once its run-on time is over
+    fn update_fan_run_on(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        let Some(fan_off_at) = self.fan_off_at else {
+            return Ok(());
+        };
+        if Instant::now() < fan_off_at {
+            return Ok(());
+        }
+        self.fan_off_at = None;
+        if self.call_inactive() && !self.fan_circulating() {
+            controller.set_fan(false)?;
+        }
+        Ok(())
     }
 
     pub fn run(self: &mut ThermostatState, controller: &mut Controller) {
-        self.receive_events();
+        let tick = timing::measure(Probe::Tick);
+        #[cfg(feature = "chaos")]
+        self.chaos.tick();
+        #[cfg(feature = "replay")]
+        self.replay_due();
+        self.receive_events(controller);
+        self.update_temperature(controller);
+        self.update_remote_sensors();
+        let target_temp_c = self.get_target_temp();
+        // Statistics and history only ever see real temperatures, not a demo's
+        let room_temp_c = self.measured_room_temp();
+        self.summary.record_tick(&self.runtime_state, self.last_run_finished_time.elapsed(), room_temp_c, &self.settings.mode, target_temp_c);
+        if let Some(current_temp_c) = room_temp_c {
+            self.trend.record(current_temp_c);
+            self.overshoot.record(current_temp_c);
+            if self.temperature_history.record(current_temp_c, &mut self.storage) {
+                self.temperature_history_published = false;
+            }
+        }
+        self.publish_summaries();
+        let previous_state = self.runtime_state;
+        let result = self.step(controller);
+        self.faulted = result.is_err();
+        match result {
+            Err(e) => self.controller_fault(controller, e),
+            Ok(()) if self.controller_faulted => {
+                log::info!("Controller recovered");
+                self.controller_faulted = false;
+            }
+            Ok(()) => {}
+        }
+        #[cfg(feature = "chaos")]
+        self.chaos.check(controller, self.get_room_temp().is_some());
+        self.record_transition(previous_state);
+        self.track_compressor();
+        self.checkpoint_cooling();
+        let healthy = self.is_healthy() && network::is_online();
+        if self.boot_health_check.as_mut().is_some_and(|check| check.update(healthy)) {
+            self.boot_health_check = None;
+        }
+        self.brownouts.update(&mut self.storage);
+        // A replay must not overwrite the trace it may have come from
+        #[cfg(feature = "replay")]
+        let save_trace = self.replay.is_none();
+        #[cfg(not(feature = "replay"))]
+        let save_trace = true;
+        if save_trace {
+            self.trace.save_if_due(&mut self.storage);
+        }
+        self.maintenance_reboot_if_due(controller);
+        metrics::record_stack_watermark("backend");
+        self.memory.update();
+        self.timings.update();
+        self.publish_settings();
+        self.publish_schedule_profiles();
+        self.publish_cycle_stats();
+        self.publish_audit_log();
+        self.publish_transitions();
+        self.publish_temperature_history();
+        if let Some(report) = self.self_test_report.take() {
+            self.bus.publish_state(BackendEvent::SelfTestReport(report));
+        }
+        self.publish_status();
+        drop(tick);
+        self.last_run_finished_time = Instant::now();
+    }
+
+    fn state_timeout_locked_out(&self) -> bool {
+        self.state_timeout_lockout_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Force the state machine back to waiting if a state ran far longer than it ever should,
+    /// in case a bad reading or a logic bug keeps equipment running. Returns whether it did.
+    fn check_state_timeout(&mut self, controller: &mut Controller) -> Result<bool, ControllerError> {
+        if self.runtime_state != self.timed_state {
+            self.timed_state = self.runtime_state;
+            self.timed_state_since = Instant::now();
+        }
+        let Some(max) = self.runtime_state.max_duration() else {
+            return Ok(false);
+        };
+        if self.timed_state_since.elapsed() < max {
+            return Ok(false);
+        }
+        let state = self.runtime_state;
+        self.transition_reason = Some(TransitionReason::Timeout);
+        self.start_waiting(controller)?;
+        self.state_timeout_lockout_until = Some(Instant::now() + Duration::from_mins(STATE_TIMEOUT_LOCKOUT_MINS));
+        self.raise_alert(Severity::Critical, format!("{:?} ran for more than {}, stopped as a precaution", state, Self::format_time(max)));
+        Ok(true)
+    }
+
+    /// Time to wait between calls to `run`
+    pub fn loop_interval(&self) -> Duration {
+        self.settings.control_loop_interval()
+    }
+
+    /// Sleep until the next tick is due, or until a ForceRefresh command comes in.
+    /// Commands received meanwhile are kept for `receive_events`.
+    pub fn wait_for_next_tick(&mut self) {
+        let deadline = Instant::now() + self.loop_interval();
+        #[cfg(feature = "chaos")]
+        if self.chaos.commands_lost() {
+            std::thread::sleep(self.loop_interval());
+            return;
+        }
+        while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+            // Held to the bus bound, anything past it stays on the bus where the overflow policy applies
+            if self.pending_commands.len() >= bus::QUEUE_CAPACITY {
+                std::thread::sleep(remaining);
+                return;
+            }
+            match self.commands_rx.recv_timeout(remaining) {
+                Ok(message) => {
+                    let refresh = matches!(&message, Message::Command(Command { event: UiEvent::ForceRefresh, .. }));
+                    self.pending_commands.push_back(message);
+                    if refresh {
+                        self.refresh_requested = true;
+                        return;
+                    }
+                }
+                Err(RecvTimeoutError::Timeout) => return,
+                Err(RecvTimeoutError::Disconnected) => {
+                    std::thread::sleep(remaining);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Hand over the boot self-test results, to be shown on the boot status screen
+    pub fn set_self_test_report(&mut self, report: SelfTestReport) {
+        self.self_test_report = Some(report);
+    }
+
+    /// Whether the control loop is working: the last tick drove the relays without a fault
+    /// and there is a temperature to control to.
+    pub fn is_healthy(&self) -> bool {
+        !self.faulted && !self.sensor_fault
+    }
+
+    /// Advance the state machine by one tick.
+    fn step(&mut self, controller: &mut Controller) -> Result<(), ControllerError> {
+        self.update_dehumidifying();
+        self.update_vacation();
+        self.update_sleep();
+        self.update_schedule();
+        self.update_boost();
+        self.update_demo_temp();
+        self.update_peak_pricing();
+        self.update_demand_response();
+        controller.set_relay_polarity(self.settings.relay_polarity)?;
+        controller.set_unused_relays(self.settings.system.unused_relays())?;
+        self.update_quiet_hours(controller)?;
+        self.update_fan_run_on(controller)?;
+        self.update_fan_timer(controller)?;
+        self.update_hydronic(controller)?;
+        self.update_ventilation(controller)?;
+        self.update_frost_stat(controller)?;
+        self.update_high_temp_cutoff();
+        self.update_open_window();
+        // Mode changes come first, switching off has to work in a timeout lockout or without a sensor
+        if self.follow_mode(controller)? {
+            return Ok(());
+        }
+        if self.check_state_timeout(controller)? || self.state_timeout_locked_out() {
+            return Ok(());
+        }
+        let Some(control_temp_c) = self.get_control_temp() else {
+            // Nothing safe to control to without a temperature, stop any call until the sensor is back
+            if matches!(self.runtime_state, ThermostatRuntimeState::Heating | ThermostatRuntimeState::FanLead | ThermostatRuntimeState::Cooling) {
+                self.transition_reason = Some(TransitionReason::SensorLost);
+                self.start_waiting(controller)?;
+            }
+            return Ok(());
+        };
+        // Unless a branch below says otherwise, states change because a threshold was crossed
+        self.transition_reason.get_or_insert(TransitionReason::ThresholdCrossed);
         match self.runtime_state {
             ThermostatRuntimeState::Waiting => {
                 // Waiting isn't for resting, but if it happens to have rested long enough we don't need to rest again
-                if self.last_resting_start_time.elapsed() > Duration::from_mins(REST_DURATION_MINS) {
+                if self.rest_elapsed() > Duration::from_mins(REST_DURATION_MINS) {
                     self.total_cooling_duration = Duration::from_secs(0);
                 }
-                match self.mode {
+                match self.settings.mode {
                     ModeStatus::Heat => {
-                        if self.current_temp_c < self.get_waiting_target_temp() {
-                            self.start_heating(controller);
+                        if control_temp_c < self.get_waiting_target_temp() && self.call_allowed(ModeStatus::Heat) {
+                            self.start_heating(controller)?;
                         }
                     },
                     ModeStatus::Cool => {
-                        if self.current_temp_c > self.get_waiting_target_temp() {
-                            self.start_cooling(controller);
+                        // While dehumidifying, start as soon as we're above the setpoint so there is room to overcool
+                        let start_temp_c = if self.dehumidifying {
+                            self.get_target_temp()
+                        } else {
+                            self.get_waiting_target_temp()
+                        };
+                        if control_temp_c > start_temp_c && self.call_allowed(ModeStatus::Cool) {
+                            self.begin_cooling(controller)?;
                         }
                     },
-                    ModeStatus::Off => {
-                        self.start_idle(controller);
-                    }
+                    // Already moved to Off by follow_mode
+                    ModeStatus::Off => {}
                 }
             },
             ThermostatRuntimeState::Heating => {
                 self.total_heating_duration += self.last_run_finished_time.elapsed();
-                if self.current_temp_c >= self.target_temp_c {
-                    self.start_waiting(controller);
+                if control_temp_c >= self.get_heating_stop_temp() {
+                    self.overshoot.heat_call_ended(self.get_target_temp(), control_temp_c);
+                    self.start_waiting(controller)?;
+                } else if self.open_window_paused() || self.heat_locked_out() || self.high_temp_cutoff {
+                    self.transition_reason = Some(TransitionReason::Lockout);
+                    self.start_waiting(controller)?;
+                } else if self.desired_heat_source() != self.heat_source && !self.heat_pump_locked_out() {
+                    log::info!("Heat source changing from {:?} to {:?}", self.heat_source, self.desired_heat_source());
+                    self.start_heating(controller)?;
+                }
+            },
+            ThermostatRuntimeState::FanLead => {
+                if self.cool_locked_out() {
+                    self.transition_reason = Some(TransitionReason::Lockout);
+                    self.start_waiting(controller)?;
+                } else if self.fan_lead_start_time.elapsed() >= Duration::from_secs(self.settings.cool_fan_lead_secs as u64)
+                    && !self.compressor_locked_out()
+                {
+                    self.transition_reason = Some(TransitionReason::FanLeadComplete);
+                    self.start_cooling(controller)?;
                 }
             },
             ThermostatRuntimeState::Cooling => {
                 self.total_cooling_duration += self.last_run_finished_time.elapsed();
+                if let Some(event) = self.demand_response.as_mut() {
+                    event.record_cooling(self.last_run_finished_time.elapsed());
+                }
+                // The rest budget is checked first so overcooling never runs past it
                 if self.should_rest() {
-                    self.start_resting(controller);
-                } else if self.current_temp_c <= self.target_temp_c {
-                    self.start_waiting(controller);
+                    self.transition_reason = Some(TransitionReason::RestBudgetExceeded);
+                    self.start_resting(controller)?;
+                } else if control_temp_c <= self.get_cooling_stop_temp() {
+                    self.start_waiting(controller)?;
+                } else if self.cool_locked_out() || !self.demand_response_allows_cooling() {
+                    self.transition_reason = Some(TransitionReason::Lockout);
+                    self.start_waiting(controller)?;
                 }
             },
             ThermostatRuntimeState::Resting => {
-                if self.last_resting_start_time.elapsed() > Duration::from_mins(REST_DURATION_MINS) {
+                // Resting restored after a reboot starts with the relays off
+                self.fan_with_call(controller)?;
+                // A rest carried over a reboot can be over before the compressor's minimum off time
+                if self.rest_elapsed() > Duration::from_mins(REST_DURATION_MINS) && !self.compressor_locked_out() {
                     self.total_cooling_duration = Duration::from_secs(0);
-                    match self.mode {
-                        ModeStatus::Heat => self.start_heating(controller),
-                        ModeStatus::Cool => self.start_cooling(controller),
-                        ModeStatus::Off => self.start_idle(controller)
+                    self.transition_reason = Some(TransitionReason::RestComplete);
+                    let mode = self.settings.mode;
+                    match mode {
+                        ModeStatus::Off => self.start_off(controller)?,
+                        // Waiting picks the call up again once whatever holds it off has cleared
+                        mode if !self.call_allowed(mode) => self.start_waiting(controller)?,
+                        ModeStatus::Heat => self.start_heating(controller)?,
+                        ModeStatus::Cool => self.start_cooling(controller)?,
                     }
                 }
             },
-            ThermostatRuntimeState::Idle => {
-                match self.mode {
-                    ModeStatus::Heat => self.start_heating(controller),
-                    ModeStatus::Cool => self.start_cooling(controller),
-                    ModeStatus::Off => self.start_idle(controller)
-                }
+            // Waiting decides from the temperature and lockouts whether a call may start
+            ThermostatRuntimeState::Idle => self.start_waiting(controller)?,
+            ThermostatRuntimeState::Off => {
+                // Nothing can start a call from here, follow_mode moves on once the mode changes.
+                // Keep forcing the relays off in case something else switched them.
+                controller.set_heating(false)?;
+                controller.set_cooling(false)?;
             }
         }
-        // Update status message to the UI
-        self.actor_events_tx.send(BackendEvent::CurrentStateMessage(self.get_status_message()));
-        self.last_run_finished_time = Instant::now();
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+/// Time left in a rest that has run for `elapsed`, zero once it is over
+fn rest_remaining(elapsed: Duration) -> Duration {
+    Duration::from_mins(REST_DURATION_MINS).saturating_sub(elapsed)
+}
+
+/// How far through a rest that has run for `elapsed` we are, from 0 to 1
+fn rest_progress(elapsed: Duration) -> f32 {
+    (elapsed.as_secs_f32() / Duration::from_mins(REST_DURATION_MINS).as_secs_f32()).min(1.0)
+}
+
+/// Whether the fan mode or a running fan timer asks for the fan outside calls. Quiet hours hold
+/// back the fan mode, not a timer asked for by hand.
+fn fan_circulating(fan_mode: FanStatus, quiet_hours: bool, timer_running: bool) -> bool {
+    (fan_mode == FanStatus::On && !quiet_hours) || timer_running
+}
+
+/// What circulation does with the fan relay in `state`: Some to switch it, None while a call or
+/// a pending run-on has the fan
+fn circulation_fan(state: ThermostatRuntimeState, run_on_pending: bool, circulating: bool) -> Option<bool> {
+    (state.call_inactive() && !run_on_pending).then_some(circulating)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL_STATES: [ThermostatRuntimeState; 7] = [
+        ThermostatRuntimeState::Waiting,
+        ThermostatRuntimeState::Heating,
+        ThermostatRuntimeState::FanLead,
+        ThermostatRuntimeState::Cooling,
+        ThermostatRuntimeState::Resting,
+        ThermostatRuntimeState::Idle,
+        ThermostatRuntimeState::Off,
+    ];
+
+    #[test]
+    fn fan_on_switches_the_fan_between_calls() {
+        for state in [ThermostatRuntimeState::Waiting, ThermostatRuntimeState::Idle, ThermostatRuntimeState::Off] {
+            let circulating = fan_circulating(FanStatus::On, false, false);
+            assert_eq!(circulation_fan(state, false, circulating), Some(true), "{state:?}");
+        }
+    }
+
+    #[test]
+    fn fan_auto_switches_the_fan_off_between_calls() {
+        let circulating = fan_circulating(FanStatus::Auto, false, false);
+        assert_eq!(circulation_fan(ThermostatRuntimeState::Waiting, false, circulating), Some(false));
+    }
+
+    #[test]
+    fn running_calls_keep_the_fan() {
+        for state in ALL_STATES.into_iter().filter(|state| !state.call_inactive()) {
+            assert_eq!(circulation_fan(state, false, true), None, "{state:?}");
+            assert_eq!(circulation_fan(state, false, false), None, "{state:?}");
+        }
+    }
+
+    #[test]
+    fn pending_run_on_keeps_the_fan() {
+        for state in ALL_STATES {
+            assert_eq!(circulation_fan(state, true, false), None, "{state:?}");
+        }
+    }
+
+    #[test]
+    fn quiet_hours_hold_back_the_fan_mode_but_not_the_timer() {
+        assert!(!fan_circulating(FanStatus::On, true, false));
+        assert!(fan_circulating(FanStatus::Auto, true, true));
+        assert!(fan_circulating(FanStatus::On, true, true));
+    }
+
+    #[test]
+    fn rest_countdown_runs_down_to_zero() {
+        let rest = Duration::from_mins(REST_DURATION_MINS);
+        assert_eq!(rest_remaining(Duration::ZERO), rest);
+        assert_eq!(rest_remaining(Duration::from_mins(10)), rest - Duration::from_mins(10));
+        assert_eq!(rest_remaining(rest), Duration::ZERO);
+    }
+
+    #[test]
+    fn rest_countdown_stops_at_zero_past_the_rest() {
+        let rest = Duration::from_mins(REST_DURATION_MINS);
+        assert_eq!(rest_remaining(rest + Duration::from_secs(1)), Duration::ZERO);
+        // A rest credit can push elapsed well past the rest
+        assert_eq!(rest_remaining(Duration::MAX), Duration::ZERO);
+    }
+
+    #[test]
+    fn rest_progress_is_clamped_to_one() {
+        let rest = Duration::from_mins(REST_DURATION_MINS);
+        assert_eq!(rest_progress(Duration::ZERO), 0.0);
+        assert!((rest_progress(rest / 2) - 0.5).abs() < 1e-6);
+        assert_eq!(rest_progress(rest), 1.0);
+        assert_eq!(rest_progress(rest * 3), 1.0);
+    }
+}
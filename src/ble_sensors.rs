@@ -0,0 +1,126 @@
+// Off-the-shelf BLE thermometers as remote room sensors, through a passive NimBLE scan. Only
+// the ones listed in `Settings::ble_sensors` are reported, under the name given there, since
+// the neighbours' thermometers are often in range too. Thermometers heard that aren't listed
+// are logged once with their address, to find the one to add with `PUT /sensors/ble`.
+//
+// Decoded advertisements:
+// - Xiaomi LYWSD03MMC and similar running the ATC or pvvx custom firmware, service data 0x181A.
+//   The stock Xiaomi firmware encrypts its readings and isn't supported.
+// - SwitchBot Meter, Meter Plus and Outdoor Meter, service data 0x0D00 or 0xFD3D.
+
+use std::collections::{BTreeSet, HashMap};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp32_nimble::{BLEDevice, BLEScan};
+use esp_idf_svc::hal::task::block_on;
+
+use crate::bus::{EventBus, Message, Subscription, Topic};
+use crate::events::{BackendEvent, CommandSource};
+use crate::remote_sensors::{Battery, BleSensor};
+use crate::validation;
+
+const UUID_ENVIRONMENTAL_SENSING: u16 = 0x181A;
+const UUID_SWITCHBOT_LEGACY: u16 = 0x0D00;
+const UUID_SWITCHBOT: u16 = 0xFD3D;
+/// SwitchBot device types with a thermometer: Meter, Meter Plus, Outdoor Meter
+const SWITCHBOT_METERS: [u8; 3] = [b'T', b'i', b'w'];
+/// Each scan runs this long before the settings are looked at again
+const SCAN_DURATION_MS: i32 = 10_000;
+/// Thermometers advertise every few seconds, one reading per sensor and interval is plenty
+const REPORT_INTERVAL: Duration = Duration::from_secs(60);
+const STACK_SIZE: usize = 8192;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BleReading {
+    pub temp_c: f32,
+    pub battery: Option<Battery>,
+}
+
+/// Decode the service data of an advertisement, None for anything that isn't a known
+/// thermometer format
+pub fn decode(uuid: u16, data: &[u8]) -> Option<BleReading> {
+    match (uuid, data.len()) {
+        // pvvx: address (reversed), temperature 0.01°C, humidity, battery mV, battery %,
+        // counter, flags, all little endian
+        (UUID_ENVIRONMENTAL_SENSING, 15) => Some(BleReading {
+            temp_c: i16::from_le_bytes([data[6], data[7]]) as f32 / 100.0,
+            battery: Some(Battery { millivolts: u16::from_le_bytes([data[10], data[11]]), percent: Some(data[12].min(100)) }),
+        }),
+        // ATC1441: address, temperature 0.1°C, humidity, battery %, battery mV, counter, big endian
+        (UUID_ENVIRONMENTAL_SENSING, 13) => Some(BleReading {
+            temp_c: i16::from_be_bytes([data[6], data[7]]) as f32 / 10.0,
+            battery: Some(Battery { millivolts: u16::from_be_bytes([data[10], data[11]]), percent: Some(data[9].min(100)) }),
+        }),
+        // Device type, flags, battery %, temperature tenths, temperature degrees with the
+        // sign in the top bit (set for positive), then humidity
+        (UUID_SWITCHBOT_LEGACY | UUID_SWITCHBOT, 6..) if SWITCHBOT_METERS.contains(&(data[0] & 0x7F)) => {
+            let magnitude = (data[4] & 0x7F) as f32 + (data[3] & 0x0F) as f32 / 10.0;
+            Some(BleReading {
+                temp_c: if data[4] & 0x80 != 0 { magnitude } else { -magnitude },
+                // SwitchBot only reports the charge
+                battery: Some(Battery { millivolts: 0, percent: Some((data[2] & 0x7F).min(100)) }),
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Start scanning on its own thread. The BLE thermometers to report come from the settings
+/// the backend publishes.
+pub fn spawn(bus: EventBus) -> anyhow::Result<()> {
+    let settings_rx = bus.subscribe(&[Topic::State]);
+    thread::Builder::new()
+        .name("ble_sensors".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || block_on(scan(bus, settings_rx)))?;
+    Ok(())
+}
+
+async fn scan(bus: EventBus, settings_rx: Subscription) {
+    let device = BLEDevice::take();
+    let mut ble_scan = BLEScan::new();
+    // Passive: thermometers put everything in their advertisements, no need to wake them
+    ble_scan.active_scan(false).interval(100).window(99);
+    let mut sensors: Vec<BleSensor> = Vec::new();
+    let mut last_reported: HashMap<String, Instant> = HashMap::new();
+    let mut unknown_logged = BTreeSet::new();
+    loop {
+        while let Ok(message) = settings_rx.try_recv() {
+            if let Message::State(BackendEvent::SettingsUpdate(settings)) = message {
+                sensors = settings.ble_sensors;
+            }
+        }
+        let result = ble_scan
+            .start(device, SCAN_DURATION_MS, |advertiser, data| {
+                let service_data = data.service_data()?;
+                let esp32_nimble::utilities::BleUuid::Uuid16(uuid) = service_data.uuid else {
+                    return None::<()>;
+                };
+                let reading = decode(uuid, service_data.service_data)?;
+                let address = advertiser.addr().to_string().to_ascii_lowercase();
+                let Some(sensor) = sensors.iter().find(|sensor| sensor.address == address) else {
+                    if unknown_logged.insert(address.clone()) {
+                        log::info!("BLE thermometer {} reads {:.1}°C, add it with PUT /sensors/ble to use it", address, reading.temp_c);
+                    }
+                    return None;
+                };
+                if last_reported.get(&address).is_some_and(|at| at.elapsed() < REPORT_INTERVAL) {
+                    return None;
+                }
+                last_reported.insert(address, Instant::now());
+                match validation::validate_remote_temp(sensor.name.clone(), reading.temp_c, reading.battery) {
+                    Ok(event) => {
+                        bus.publish_command(CommandSource::Sensor, event);
+                    }
+                    Err(rejection) => log::warn!("Ignoring BLE thermometer {}: {}", sensor.name, rejection),
+                }
+                None
+            })
+            .await;
+        if let Err(e) = result {
+            log::warn!("BLE scan failed: {:?}", e);
+            thread::sleep(Duration::from_secs(10));
+        }
+    }
+}
@@ -0,0 +1,74 @@
+// Boost: one touch for a strong heat or cool for a while. It's a temporary setpoint override,
+// the setpoint pushed past the user's in the direction of the mode. It ends on its own once the
+// time is up or the room got that far past the setpoint, whichever comes first, and the normal
+// setpoint takes over again.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::ModeStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BoostSettings {
+    /// How long a boost runs at most (minutes)
+    pub duration_mins: u32,
+    /// How far past the setpoint a boost heats or cools to (Celsius)
+    pub offset_c: f32,
+}
+
+impl Default for BoostSettings {
+    fn default() -> Self {
+        Self {
+            duration_mins: 30,
+            offset_c: 1.5, // ~2.7°F
+        }
+    }
+}
+
+pub struct Boost {
+    until: Instant,
+    /// Whether this boost heats (or cools), it ends when the mode changes
+    heating: bool,
+    offset_c: f32,
+}
+
+impl Boost {
+    /// Start a boost in the given mode, None when the mode is off
+    pub fn start(settings: BoostSettings, mode: &ModeStatus) -> Option<Self> {
+        let heating = match mode {
+            ModeStatus::Heat => true,
+            ModeStatus::Cool => false,
+            ModeStatus::Off => return None,
+        };
+        Some(Self {
+            until: Instant::now() + Duration::from_mins(settings.duration_mins as u64),
+            heating,
+            offset_c: settings.offset_c,
+        })
+    }
+
+    /// Setpoint to control to during the boost
+    pub fn setpoint_c(&self, setpoint_c: f32) -> f32 {
+        if self.heating {
+            setpoint_c + self.offset_c
+        } else {
+            setpoint_c - self.offset_c
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.until.saturating_duration_since(Instant::now())
+    }
+
+    /// Whether the boost ran its course: out of time, reached its setpoint or the mode changed
+    pub fn is_over(&self, setpoint_c: f32, temp_c: Option<f32>, mode: &ModeStatus) -> bool {
+        let mode_changed = !matches!((mode, self.heating), (ModeStatus::Heat, true) | (ModeStatus::Cool, false));
+        let reached = temp_c.is_some_and(|temp_c| match self.heating {
+            true => temp_c >= self.setpoint_c(setpoint_c),
+            false => temp_c <= self.setpoint_c(setpoint_c),
+        });
+        mode_changed || reached || self.remaining().is_zero()
+    }
+}
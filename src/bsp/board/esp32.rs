@@ -0,0 +1,71 @@
+// Plain ESP32 dev boards (WROOM/WROVER modules) as a headless relay box. There is no RGB LCD
+// peripheral, so only `no-ui` builds, and PSRAM is assumed absent as most WROOM modules have none.
+// Strapping and input-only pins (0, 2, 5, 12, 15, 34-39) are avoided for the outputs.
+
+use esp_idf_svc::hal::gpio::{AnyInputPin, AnyOutputPin, Gpio4, Gpio13, Gpio14, Gpio18, Gpio19, Gpio25, Gpio26, Gpio27, Gpio32, Gpio33};
+use esp_idf_svc::hal::i2c::I2cDriver;
+use esp_idf_svc::hal::peripherals::Peripherals;
+
+use super::{i2c_config, BoardPins};
+
+pub const NAME: &str = "ESP32";
+pub const HAS_PSRAM: bool = false;
+
+/// I2C on the usual GPIO 21 (SDA) and 22 (SCL), for add-ons like the RTC
+pub fn take_i2c(peripherals: Peripherals) -> anyhow::Result<I2cDriver<'static>> {
+    Ok(I2cDriver::new(peripherals.i2c0, peripherals.pins.gpio21, peripherals.pins.gpio22, &i2c_config())?)
+}
+
+/// - DS18B20 temperature sensor on GPIO 4
+/// - Heat relay on GPIO 25
+/// - Cool relay on GPIO 26
+/// - Fan relay on GPIO 27
+/// - Reversing valve relay on GPIO 33, only wired up for heat pumps
+///
+/// # Safety
+/// The pins must not be in use anywhere else.
+pub unsafe fn pins() -> BoardPins {
+    BoardPins {
+        temp_sensor: Gpio4::new().into(),
+        heat: Gpio25::new().into(),
+        cool: Gpio26::new().into(),
+        fan: Gpio27::new().into(),
+        valve: Gpio33::new().into(),
+    }
+}
+
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn heartbeat_pin() -> AnyOutputPin {
+    Gpio32::new().into()
+}
+
+/// ERV/HRV relay, used with the `erv` feature
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn ventilation_pin() -> AnyOutputPin {
+    Gpio14::new().into()
+}
+
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn presence_pin() -> AnyInputPin {
+    Gpio13::new().into()
+}
+
+/// Hydronic zone valve end switch, used with the `end-switch` feature
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn end_switch_pin() -> AnyInputPin {
+    Gpio18::new().into()
+}
+
+/// Frost-stat relay, used with the `frost-stat` feature
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn frost_stat_pin() -> AnyOutputPin {
+    Gpio19::new().into()
+}
@@ -0,0 +1,77 @@
+// ESP32-C3 boards as a headless relay box. There is no RGB LCD peripheral, so only `no-ui`
+// builds, and no PSRAM. GPIO 2, 8 and 9 are strapping pins, so they're left alone. The console is
+// on the UART rather than the USB serial JTAG, which frees the USB pins, GPIO 18 and 19, for I/O.
+
+use esp_idf_svc::hal::gpio::{AnyInputPin, AnyOutputPin, Gpio0, Gpio1, Gpio3, Gpio6, Gpio7, Gpio10, Gpio18, Gpio19, Gpio20, Gpio21};
+use esp_idf_svc::hal::i2c::I2cDriver;
+use esp_idf_svc::hal::peripherals::Peripherals;
+
+use super::{i2c_config, BoardPins};
+
+pub const NAME: &str = "ESP32-C3";
+pub const HAS_PSRAM: bool = false;
+
+/// I2C on GPIO 4 (SDA) and 5 (SCL), for add-ons like the RTC
+pub fn take_i2c(peripherals: Peripherals) -> anyhow::Result<I2cDriver<'static>> {
+    Ok(I2cDriver::new(peripherals.i2c0, peripherals.pins.gpio4, peripherals.pins.gpio5, &i2c_config())?)
+}
+
+/// - DS18B20 temperature sensor on GPIO 10
+/// - Heat relay on GPIO 6
+/// - Cool relay on GPIO 7
+/// - Fan relay on GPIO 0
+/// - Reversing valve relay on GPIO 1, only wired up for heat pumps
+///
+/// # Safety
+/// The pins must not be in use anywhere else.
+pub unsafe fn pins() -> BoardPins {
+    BoardPins {
+        temp_sensor: Gpio10::new().into(),
+        heat: Gpio6::new().into(),
+        cool: Gpio7::new().into(),
+        fan: Gpio0::new().into(),
+        valve: Gpio1::new().into(),
+    }
+}
+
+/// # Safety
+/// The pin must not be in use anywhere else. GPIO 21 is the UART0 TX pin, so the heartbeat takes
+/// over from the serial console's output. The boot messages on it before that are only more
+/// pulses to the watchdog.
+pub unsafe fn heartbeat_pin() -> AnyOutputPin {
+    Gpio21::new().into()
+}
+
+/// ERV/HRV relay, used with the `erv` feature. GPIO 18 is USB D-, which nothing pulls up at
+/// reset, so the relay stays off while booting.
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn ventilation_pin() -> AnyOutputPin {
+    Gpio18::new().into()
+}
+
+/// # Safety
+/// The pin must not be in use anywhere else. GPIO 20 is the UART0 RX pin, so the presence
+/// module takes over from the serial console's input.
+pub unsafe fn presence_pin() -> AnyInputPin {
+    Gpio20::new().into()
+}
+
+/// Hydronic zone valve end switch, used with the `end-switch` feature. GPIO 19 is USB D+, whose
+/// pull-up at reset only holds the open switch high like the input's own.
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn end_switch_pin() -> AnyInputPin {
+    Gpio19::new().into()
+}
+
+/// Frost-stat relay, used with the `frost-stat` feature. It takes GPIO 3 from the heartbeat, the
+/// last free pin that is neither strapping nor driven at boot, where a relay would chatter.
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn frost_stat_pin() -> AnyOutputPin {
+    Gpio3::new().into()
+}
@@ -0,0 +1,78 @@
+// Waveshare ESP32-S3 touch panel, the board the firmware was written for. 16 MB flash and
+// octal PSRAM, which the RGB display's frame buffers live in.
+
+use esp_idf_svc::hal::gpio::{AnyInputPin, AnyOutputPin, Gpio2, Gpio3, Gpio4, Gpio6, Gpio11, Gpio12, Gpio13, Gpio14, Gpio15, Gpio16, Gpio21};
+use esp_idf_svc::hal::i2c::I2cDriver;
+use esp_idf_svc::hal::peripherals::Peripherals;
+
+use super::{i2c_config, BoardPins};
+
+pub const NAME: &str = "ESP32-S3 panel";
+pub const HAS_PSRAM: bool = true;
+
+/// I2C on GPIO 8 (SDA) and 9 (SCL), shared with the touch controller and IO expander
+pub fn take_i2c(peripherals: Peripherals) -> anyhow::Result<I2cDriver<'static>> {
+    Ok(I2cDriver::new(peripherals.i2c0, peripherals.pins.gpio8, peripherals.pins.gpio9, &i2c_config())?)
+}
+
+/// - DS18B20 temperature sensor on GPIO 21
+/// - Heat relay on GPIO 2
+/// - Cool relay on GPIO 3
+/// - Fan relay on GPIO 4
+/// - Reversing valve relay on GPIO 6, only wired up for heat pumps
+///
+/// # Safety
+/// The pins must not be in use anywhere else.
+pub unsafe fn pins() -> BoardPins {
+    BoardPins {
+        temp_sensor: Gpio21::new().into(),
+        heat: Gpio2::new().into(),
+        cool: Gpio3::new().into(),
+        fan: Gpio4::new().into(),
+        valve: Gpio6::new().into(),
+    }
+}
+
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn heartbeat_pin() -> AnyOutputPin {
+    Gpio15::new().into()
+}
+
+/// ERV/HRV relay, used with the `erv` feature
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn ventilation_pin() -> AnyOutputPin {
+    Gpio12::new().into()
+}
+
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn presence_pin() -> AnyInputPin {
+    Gpio16::new().into()
+}
+
+/// Only the S3 panel has a display to give touch feedback for
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn buzzer_pin() -> AnyOutputPin {
+    Gpio11::new().into()
+}
+
+/// Hydronic zone valve end switch, used with the `end-switch` feature
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn end_switch_pin() -> AnyInputPin {
+    Gpio13::new().into()
+}
+
+/// Frost-stat relay, used with the `frost-stat` feature
+///
+/// # Safety
+/// The pin must not be in use anywhere else.
+pub unsafe fn frost_stat_pin() -> AnyOutputPin {
+    Gpio14::new().into()
+}
@@ -1 +1,93 @@
+#[cfg(not(feature = "no-ui"))]
 pub mod slint_platform;
+
+// One module per chip with its pin assignments, picked by the build target
+#[cfg_attr(esp32s3, path = "board/esp32s3.rs")]
+#[cfg_attr(esp32, path = "board/esp32.rs")]
+#[cfg_attr(esp32c3, path = "board/esp32c3.rs")]
+pub mod board;
+
+#[cfg(not(any(esp32s3, esp32, esp32c3)))]
+compile_error!("Unsupported chip, the boards are ESP32-S3, ESP32 and ESP32-C3");
+#[cfg(all(not(esp32s3), not(feature = "no-ui")))]
+compile_error!("The display needs the ESP32-S3's RGB LCD peripheral, build other chips with the no-ui feature");
+
+use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "no-ui"))]
+use std::time::Duration;
+
+use esp_idf_svc::hal::gpio::{AnyIOPin, AnyOutputPin};
+use esp_idf_svc::hal::i2c::{config::Config, I2cDriver};
+use esp_idf_svc::hal::prelude::*;
+#[cfg(not(feature = "no-ui"))]
+use esp_idf_svc::sys::gpio_set_level;
+
+/// The I2C bus is shared by the touch controller, the IO expander and
+/// optional add-ons like the RTC
+pub type SharedI2c = Arc<Mutex<I2cDriver<'static>>>;
+
+/// Pulled low while the touch controller is reset
+#[cfg(not(feature = "no-ui"))]
+const TOUCH_RESET_GPIO: i32 = 4;
+/// Output register of the IO expander, and its outputs once the display is set up: touch
+/// reset, backlight and LCD reset all high
+#[cfg(not(feature = "no-ui"))]
+const IO_EXPANDER_OUTPUT: u8 = 0x38;
+#[cfg(not(feature = "no-ui"))]
+const EXIO_OUTPUTS: u8 = 0xE;
+#[cfg(not(feature = "no-ui"))]
+const EXIO_BACKLIGHT: u8 = 0x4;
+
+/// Pins the controller drives, see `board::pins` for where they are on each chip
+pub struct BoardPins {
+    pub temp_sensor: AnyIOPin,
+    pub heat: AnyOutputPin,
+    pub cool: AnyOutputPin,
+    pub fan: AnyOutputPin,
+    pub valve: AnyOutputPin,
+}
+
+fn i2c_config() -> Config {
+    Config::new().baudrate(400_000.Hz())
+}
+
+/// Take the peripherals and return the board's I2C bus. Everything else on the board is
+/// reached through `unsafe` pin handles after this, as the peripherals are consumed here.
+pub fn setup_i2c() -> anyhow::Result<I2cDriver<'static>> {
+    log::info!("Board: {}", board::NAME);
+    board::take_i2c(Peripherals::take()?)
+}
+
+/// Set up the I2C bus and reset the touch controller on it through the IO expander.
+#[cfg(not(feature = "no-ui"))]
+pub fn setup_display() -> anyhow::Result<I2cDriver<'static>> {
+    let mut touch_i2c = setup_i2c()?;
+    
+    // Reset touch screen before using it
+    // DO NOT REMOVE THIS.
+    let _ = touch_i2c.write(0x24, &[0x1], 1000);
+    let mut exio_value = [0xC];
+    let _ = touch_i2c.write(0x38, &exio_value, 1000);
+    std::thread::sleep(Duration::from_millis(100));
+    // SAFETY: plain register write, GPIO 4 isn't driven by anything else yet
+    unsafe {
+        gpio_set_level(TOUCH_RESET_GPIO, 0);
+    }
+    std::thread::sleep(Duration::from_millis(100));
+    exio_value[0] = 0xE;
+    let _ = touch_i2c.write(0x38, &exio_value, 1000);
+    // Not sute why this is needed, probably to give the touch screen time to initialize
+    std::thread::sleep(Duration::from_millis(200));
+    Ok(touch_i2c)
+}
+
+/// Switch the backlight through the IO expander, leaving the other outputs as `setup_display`
+/// left them
+#[cfg(not(feature = "no-ui"))]
+pub fn set_backlight(i2c: &SharedI2c, on: bool) -> anyhow::Result<()> {
+    let outputs = if on { EXIO_OUTPUTS } else { EXIO_OUTPUTS & !EXIO_BACKLIGHT };
+    i2c.lock()
+        .unwrap_or_else(std::sync::PoisonError::into_inner)
+        .write(IO_EXPANDER_OUTPUT, &[outputs], 1000)?;
+    Ok(())
+}
@@ -1,26 +1,48 @@
-use core::cell::RefCell;
+use std::cell::Cell;
 use std::rc::Rc;
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::SharedI2c;
+use crate::timing::{self, Probe};
 
 const DISPLAY_WIDTH: usize = 240;
 const DISPLAY_HEIGHT: usize = 320;
 
+/// Redraws are capped at this rate while something animates
+const MAX_FPS: u32 = 30;
+/// How often the touch screen is polled when nothing is moving
+const IDLE_POLL: Duration = Duration::from_millis(50);
+
+/// Lines gathered before a partial flush, small enough to stay in internal RAM
+const FLUSH_LINES: usize = 8;
+
 type I2C = esp_idf_svc::hal::i2c::I2cDriver<'static>;
 type Gt911 = gt911::Gt911Blocking<I2C>;
 
 struct EspPlatform {
     panel_handle: esp_idf_svc::sys::esp_lcd_panel_handle_t,
     touch: Gt911,
-    i2c: RefCell<I2C>,
+    i2c: SharedI2c,
     window: Rc<slint::platform::software_renderer::MinimalSoftwareWindow>,
     timer: esp_idf_svc::timer::EspTimerService<esp_idf_svc::timer::Task>,
     queue: Arc<Mutex<Vec<Event>>>,
+    /// Two frame buffers with PSRAM, one in internal RAM without
+    frame_buffers: usize,
 }
 
 impl EspPlatform {
-    pub fn new(mut i2c: I2C) -> std::boxed::Box<Self> {
+    pub fn new(i2c: SharedI2c) -> std::boxed::Box<Self> {
         use esp_idf_svc::sys::*;
 
+        // Two full frame buffers only fit in PSRAM, internal RAM gets a single one
+        let psram = crate::memory::psram_available();
+        let frame_buffers = if psram { 2 } else { 1 };
+        if !psram {
+            log::warn!("No PSRAM, using a single frame buffer in internal RAM");
+        }
+
         // Initialize LCD panel and touch
         let mut panel_handle: esp_lcd_panel_handle_t = std::ptr::null_mut();
         let panel_config = esp_lcd_rgb_panel_config_t {
@@ -43,7 +65,7 @@ impl EspPlatform {
             },
             data_width: 18,
             bits_per_pixel: 18,
-            num_fbs: 2,
+            num_fbs: frame_buffers,
             bounce_buffer_size_px: DISPLAY_WIDTH * 10,
             sram_trans_align: 4,
             __bindgen_anon_1: esp_lcd_rgb_panel_config_t__bindgen_ty_1 { dma_burst_size: 64 },
@@ -55,7 +77,7 @@ impl EspPlatform {
             data_gpio_nums: [14, 38, 18, 17, 10, 39, 0, 45, 48, 47, 21, 1, 2, 42, 41, 40],
             flags: {
                 let mut flags = esp_lcd_rgb_panel_config_t__bindgen_ty_2::default();
-                flags.set_fb_in_psram(1);
+                flags.set_fb_in_psram(psram as u32);
                 flags
             },
         };
@@ -80,14 +102,17 @@ impl EspPlatform {
 
         // Setup the touch
         let touch = Gt911::default();
-        if let Err(e) = touch.init(&mut i2c) {
+        if let Err(e) = touch.init(&mut i2c.lock().unwrap_or_else(PoisonError::into_inner)) {
             log::error!("Failed to initialize touch: {:?}", e);
         }
 
         // Setup the window
-        let window = slint::platform::software_renderer::MinimalSoftwareWindow::new(
-            slint::platform::software_renderer::RepaintBufferType::SwappedBuffers,
-        );
+        let repaint = if frame_buffers == 2 {
+            slint::platform::software_renderer::RepaintBufferType::SwappedBuffers
+        } else {
+            slint::platform::software_renderer::RepaintBufferType::ReusedBuffer
+        };
+        let window = slint::platform::software_renderer::MinimalSoftwareWindow::new(repaint);
         window.set_size(slint::PhysicalSize::new(
             DISPLAY_WIDTH as u32,
             DISPLAY_HEIGHT as u32,
@@ -100,20 +125,22 @@ impl EspPlatform {
                 return std::boxed::Box::new(Self {
                     panel_handle,
                     touch,
-                    i2c: i2c.into(),
+                    i2c,
                     window,
                     timer: unsafe { std::mem::zeroed() },
                     queue: Default::default(),
+                    frame_buffers,
                 });
             }
         };
         std::boxed::Box::new(Self {
             panel_handle,
             touch,
-            i2c: i2c.into(),
+            i2c,
             window,
             timer,
             queue: Default::default(),
+            frame_buffers,
         })
     }
 }
@@ -151,22 +178,40 @@ impl slint::platform::Platform for EspPlatform {
         // Create a buffer to draw the scene
         use slint::platform::software_renderer::Rgb565Pixel;
 
+        // The second buffer is None with a single frame buffer, in which case only
+        // the dirty lines are rendered and copied into the scanned out buffer
         let (mut buffer1, mut buffer2) = unsafe {
             let (mut b1, mut b2) = (std::ptr::null_mut(), std::ptr::null_mut());
-            esp_lcd_rgb_panel_get_frame_buffer(self.panel_handle, 2, &mut b1, &mut b2);
+            esp_lcd_rgb_panel_get_frame_buffer(
+                self.panel_handle,
+                self.frame_buffers as u32,
+                &mut b1,
+                &mut b2,
+            );
             (
                 core::slice::from_raw_parts_mut(
                     b1 as *mut Rgb565Pixel,
                     DISPLAY_WIDTH * DISPLAY_HEIGHT,
                 ),
-                core::slice::from_raw_parts_mut(b2, DISPLAY_WIDTH * DISPLAY_HEIGHT),
+                (!b2.is_null()).then(|| {
+                    core::slice::from_raw_parts_mut(
+                        b2 as *mut Rgb565Pixel,
+                        DISPLAY_WIDTH * DISPLAY_HEIGHT,
+                    )
+                }),
             )
         };
 
+        let mut partial = buffer2.is_none().then(|| PartialFlush::new(self.panel_handle));
+
         let mut last_position = slint::LogicalPosition::default();
         let mut touch_down = false;
+        let frame_time = Duration::from_secs(1) / MAX_FPS;
+        let mut frames = 0;
+        let mut fps_since = Instant::now();
 
         loop {
+            let loop_start = Instant::now();
             slint::platform::update_timers_and_animations();
 
             let queue = std::mem::take(&mut *self.queue.lock().unwrap());
@@ -177,11 +222,15 @@ impl slint::platform::Platform for EspPlatform {
                 }
             }
 
-            match self.touch.get_touch(&mut self.i2c.borrow_mut()) {
+            let mut touched = false;
+            let touch = self.touch.get_touch(&mut self.i2c.lock().unwrap_or_else(PoisonError::into_inner));
+            match touch {
                 Ok(Some(point)) => {
                     last_position = slint::PhysicalPosition::new(point.x as _, point.y as _)
                         .to_logical(self.window.scale_factor());
                     if !touch_down {
+                        touched = true;
+                        LAST_TOUCH.set(Some(Instant::now()));
                         self.window
                             .dispatch_event(slint::platform::WindowEvent::PointerPressed {
                                 position: last_position,
@@ -196,6 +245,8 @@ impl slint::platform::Platform for EspPlatform {
                 }
                 Ok(None) => {
                     if touch_down {
+                        touched = true;
+                        LAST_TOUCH.set(Some(Instant::now()));
                         self.window
                             .dispatch_event(slint::platform::WindowEvent::PointerReleased {
                                 position: last_position,
@@ -214,34 +265,75 @@ impl slint::platform::Platform for EspPlatform {
                 }
             }
 
-            // Draw the scene if something needs to be drawn.
-            self.window.draw_if_needed(|renderer| {
+            // Draw the scene only if a property it shows has changed
+            let drawn = self.window.draw_if_needed(|renderer| {
                 while !VSYNC.load(core::sync::atomic::Ordering::SeqCst) {
                     esp_idf_svc::hal::task::do_yield();
                 }
-                renderer.render(buffer1, DISPLAY_WIDTH);
-                unsafe {
-                    esp_lcd_panel_draw_bitmap(
-                        self.panel_handle,
-                        0,
-                        0,
-                        DISPLAY_WIDTH as i32,
-                        DISPLAY_HEIGHT as i32,
-                        buffer1.as_ptr().cast(),
-                    )
-                };
+                let _timing = timing::measure(Probe::Render);
+                match (buffer2.as_mut(), partial.as_mut()) {
+                    (Some(buffer2), _) => {
+                        // Slint only repaints what changed since this buffer was last shown,
+                        // and passing a frame buffer to draw_bitmap makes the DMA scan it
+                        // out from the next frame without copying
+                        renderer.render(buffer1, DISPLAY_WIDTH);
+                        unsafe {
+                            esp_lcd_panel_draw_bitmap(
+                                self.panel_handle,
+                                0,
+                                0,
+                                DISPLAY_WIDTH as i32,
+                                DISPLAY_HEIGHT as i32,
+                                buffer1.as_ptr().cast(),
+                            )
+                        };
+                        core::mem::swap(&mut buffer1, buffer2);
+                    }
+                    (None, Some(partial)) => {
+                        renderer.render_by_line(&mut *partial);
+                        partial.flush();
+                    }
+                    (None, None) => unreachable!(),
+                }
                 VSYNC.store(false, core::sync::atomic::Ordering::SeqCst);
-
-                core::mem::swap(&mut buffer1, &mut buffer2);
             });
 
-            // Try to put the MCU to sleep
-            if !self.window.has_active_animations() {
-                continue;
+            // Slint marks the window dirty while handling the event or the property changes, so
+            // a frame answering either is drawn in this same pass or not at all
+            let response = RESPONSE_PENDING.take();
+            if drawn {
+                frames += 1;
+                if let Some(at) = LAST_TOUCH.get() {
+                    if touched {
+                        record_touch(Probe::TouchFeedback, at.elapsed(), &LAST_FEEDBACK_MS);
+                    }
+                    if response {
+                        record_touch(Probe::TouchResponse, at.elapsed(), &LAST_RESPONSE_MS);
+                    }
+                }
+            }
+            if fps_since.elapsed() >= Duration::from_secs(1) {
+                FPS.store(frames, Ordering::Relaxed);
+                frames = 0;
+                fps_since = Instant::now();
             }
 
-            // FIXME
-            esp_idf_svc::hal::task::do_yield();
+            // Sleep until the next frame while animating or touched, otherwise until the next
+            // touch poll or Slint timer, so the idle UI neither renders nor hogs the I2C bus
+            let mut wait = if self.window.has_active_animations() || touch_down {
+                frame_time
+            } else {
+                IDLE_POLL
+            };
+            if let Some(next_timer) = slint::platform::duration_until_next_timer_update() {
+                wait = wait.min(next_timer);
+            }
+            let wait = wait.saturating_sub(loop_start.elapsed());
+            if wait.is_zero() {
+                esp_idf_svc::hal::task::do_yield();
+            } else {
+                std::thread::sleep(wait);
+            }
         }
     }
 
@@ -260,6 +352,73 @@ enum Event {
     Quit,
     Invoke(Box<dyn FnOnce() + Send>),
 }
+/// Renders the dirty region line by line into a small buffer and copies runs of
+/// lines with the same span into the frame buffer
+struct PartialFlush {
+    panel_handle: esp_idf_svc::sys::esp_lcd_panel_handle_t,
+    lines: Vec<slint::platform::software_renderer::Rgb565Pixel>,
+    first_line: usize,
+    count: usize,
+    span: core::ops::Range<usize>,
+}
+
+impl PartialFlush {
+    fn new(panel_handle: esp_idf_svc::sys::esp_lcd_panel_handle_t) -> Self {
+        Self {
+            panel_handle,
+            lines: vec![Default::default(); DISPLAY_WIDTH * FLUSH_LINES],
+            first_line: 0,
+            count: 0,
+            span: 0..0,
+        }
+    }
+
+    /// Copies the gathered lines into the frame buffer
+    fn flush(&mut self) {
+        if self.count == 0 {
+            return;
+        }
+        unsafe {
+            esp_idf_svc::sys::esp_lcd_panel_draw_bitmap(
+                self.panel_handle,
+                self.span.start as i32,
+                self.first_line as i32,
+                self.span.end as i32,
+                (self.first_line + self.count) as i32,
+                self.lines.as_ptr().cast(),
+            );
+        }
+        self.count = 0;
+    }
+}
+
+impl slint::platform::software_renderer::LineBufferProvider for &mut PartialFlush {
+    type TargetPixel = slint::platform::software_renderer::Rgb565Pixel;
+
+    fn process_line(
+        &mut self,
+        line: usize,
+        range: core::ops::Range<usize>,
+        render_fn: impl FnOnce(&mut [Self::TargetPixel]),
+    ) {
+        if self.count > 0
+            && (self.count == FLUSH_LINES
+                || range != self.span
+                || line != self.first_line + self.count)
+        {
+            self.flush();
+        }
+        if self.count == 0 {
+            self.first_line = line;
+            self.span = range.clone();
+        }
+        let width = range.len();
+        let start = self.count * width;
+        render_fn(&mut self.lines[start..start + width]);
+        self.count += 1;
+    }
+}
+
 struct EspEventLoopProxy {
     queue: Arc<Mutex<Vec<Event>>>,
 }
@@ -286,12 +445,47 @@ impl slint::platform::EventLoopProxy for EspEventLoopProxy {
     }
 }
 
-pub fn init(i2c: I2C) {
+pub fn init(i2c: SharedI2c) {
     if let Err(e) = slint::platform::set_platform(EspPlatform::new(i2c)) {
         log::error!("Failed to set slint platform: {}", e);
     }
 }
 
+/// Frames drawn in the last second
+static FPS: AtomicU32 = AtomicU32::new(0);
+
+/// Frames drawn in the last second, zero while nothing on screen changes
+pub fn fps() -> u32 {
+    FPS.load(Ordering::Relaxed)
+}
+
+/// Latest touch to frame latencies, for the developer overlay
+static LAST_FEEDBACK_MS: AtomicU32 = AtomicU32::new(0);
+static LAST_RESPONSE_MS: AtomicU32 = AtomicU32::new(0);
+
+thread_local! {
+    /// When the screen was last pressed or released
+    static LAST_TOUCH: Cell<Option<Instant>> = const { Cell::new(None) };
+    /// The ui just showed the backend's answer to a touch, the next frame completes the response
+    static RESPONSE_PENDING: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Called by the ui on the thread running the event loop once it set the properties answering
+/// a touch command
+pub fn expect_touch_response() {
+    RESPONSE_PENDING.set(true);
+}
+
+/// Latest touch to first frame, and touch to answered command latencies in ms
+pub fn touch_latency_ms() -> (u32, u32) {
+    (LAST_FEEDBACK_MS.load(Ordering::Relaxed), LAST_RESPONSE_MS.load(Ordering::Relaxed))
+}
+
+fn record_touch(probe: Probe, latency: Duration, last_ms: &AtomicU32) {
+    timing::record(probe, latency);
+    last_ms.store(latency.as_millis() as u32, Ordering::Relaxed);
+}
+
 static VSYNC: core::sync::atomic::AtomicBool = core::sync::atomic::AtomicBool::new(false);
 
 extern "C" fn vsync_callback(
@@ -0,0 +1,36 @@
+// Burn-in mitigation for panels left on around the clock at a fixed brightness. The main
+// layout is nudged around by a few pixels every few minutes so static elements don't sit on
+// the same pixels forever, and an optional nightly wash cycles the whole screen through solid
+// colours to even out wear.
+
+use std::time::Duration;
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeWindow;
+
+/// How long the nightly wash runs
+pub const WASH_DURATION: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BurnInProtection {
+    /// Shift the main layout by a few pixels every few minutes
+    pub pixel_shift: bool,
+    /// Local time the nightly wash starts at, None to disable it
+    pub nightly_wash_at: Option<NaiveTime>,
+}
+
+impl BurnInProtection {
+    /// Whether the wash should be running at the given local time
+    pub fn washing(&self, now: NaiveTime) -> bool {
+        self.nightly_wash_at.is_some_and(|start| {
+            let window = TimeWindow {
+                start,
+                end: start + chrono::Duration::from_std(WASH_DURATION).unwrap_or_default(),
+            };
+            window.contains(now)
+        })
+    }
+}
@@ -0,0 +1,425 @@
+// Small in-process publish/subscribe bus. Every subsystem (ui, backend, and later
+// network, logging, alerts) gets a clone of the bus, subscribes to the topics it
+// cares about and publishes to it, instead of being handed point-to-point channels.
+//
+// Each subscriber has a bounded queue, so a stalled thread can't make the others run out of
+// memory. A status still queued is dropped when a newer version of it comes in, only the latest
+// matters to anyone, and so is an alert when the same alert comes in again. When a queue fills
+// up anyway, a repeated setpoint command is coalesced into the one still queued, and only then
+// something is lost: the oldest status, or the new message. An alert instead pushes out the
+// oldest alert or other backend event. A command lost either way is nacked, so its sender isn't
+// left waiting for an ack.
+
+use std::collections::VecDeque;
+use std::mem::discriminant;
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    mpsc::{RecvTimeoutError, TryRecvError},
+    Arc, Condvar, Mutex, PoisonError, Weak,
+};
+use std::time::{Duration, Instant};
+
+use crate::events::{BackendEvent, Command, CommandId, CommandOutcome, CommandSource, UiEvent};
+use crate::installer::Secret;
+use crate::validation::CommandRejection;
+
+static NEXT_COMMAND_ID: AtomicU32 = AtomicU32::new(1);
+
+/// Messages a subscriber can fall behind by before the overflow policy applies
+pub const QUEUE_CAPACITY: usize = 32;
+
+/// A fresh id for a command, unique for this boot
+pub fn next_command_id() -> CommandId {
+    NEXT_COMMAND_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    /// State changing commands, e.g. from the touch screen
+    Commands,
+    /// State updates published by the backend
+    State,
+    /// Safety alerts published by the backend
+    Alerts,
+}
+
+#[derive(Debug, Clone)]
+pub enum Message {
+    Command(Command),
+    State(BackendEvent),
+}
+
+impl Message {
+    pub fn topic(&self) -> Topic {
+        match self {
+            Message::Command(_) => Topic::Commands,
+            Message::State(BackendEvent::Alert(_)) => Topic::Alerts,
+            Message::State(_) => Topic::State,
+        }
+    }
+
+    /// Status that only matters in its latest version, an older one still queued can go
+    fn is_status(&self) -> bool {
+        matches!(
+            self,
+            Message::State(
+                BackendEvent::CurrentTempCUpdate(_)
+                    | BackendEvent::CurrentStateMessage(_)
+                    | BackendEvent::Snapshot(_)
+                    | BackendEvent::AuditLogUpdate(_)
+                    | BackendEvent::TransitionLogUpdate(_)
+                    | BackendEvent::TemperatureHistoryUpdate(_)
+                    | BackendEvent::SettingsUpdate(_)
+                    | BackendEvent::WiringCheck(_)
+                    | BackendEvent::ScheduleProfilesUpdate(_)
+                    | BackendEvent::CycleStatsUpdate(_)
+                    | BackendEvent::UpdateStatus(_)
+            )
+        )
+    }
+
+    /// Whether this is a newer version of the status in `older`, or the same alert again
+    fn supersedes(&self, older: &Message) -> bool {
+        match (self, older) {
+            (Message::State(BackendEvent::Alert(alert)), Message::State(BackendEvent::Alert(older_alert))) => alert == older_alert,
+            (Message::State(event), Message::State(older_event)) => self.is_status() && discriminant(event) == discriminant(older_event),
+            _ => false,
+        }
+    }
+
+    /// Whether both are setpoint commands from the same source, the later one replaces the other
+    fn coalesces_with(&self, older: &Message) -> bool {
+        matches!(
+            (self, older),
+            (
+                Message::Command(Command { source, event: UiEvent::TargetTempUpdate(_), .. }),
+                Message::Command(Command { source: older_source, event: UiEvent::TargetTempUpdate(_), .. }),
+            ) if source == older_source
+        )
+    }
+}
+
+struct QueueState {
+    messages: VecDeque<Message>,
+    /// Set when something was dropped, until the subscriber catches up, to warn once per stall
+    overflowing: bool,
+}
+
+struct Queue {
+    state: Mutex<QueueState>,
+    ready: Condvar,
+}
+
+/// What became of a message pushed onto a queue
+enum Pushed {
+    Queued,
+    /// The queue was full and the overflow policy applied. Carries the command that was lost, if
+    /// any: the message itself, or the queued one it was coalesced into.
+    Overflowed(Option<(Command, CommandRejection)>),
+}
+
+impl Queue {
+    fn push(&self, message: Message, topics: &[Topic]) -> Pushed {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(index) = state.messages.iter().position(|queued| message.supersedes(queued)) {
+            state.messages.remove(index);
+        }
+        let message = if state.messages.len() >= QUEUE_CAPACITY {
+            if !state.overflowing {
+                state.overflowing = true;
+                log::warn!("Subscriber to {:?} is falling behind, dropping or merging messages", topics);
+            }
+            match Self::make_room(&mut state.messages, message) {
+                Ok(message) => message,
+                Err(lost) => return Pushed::Overflowed(lost),
+            }
+        } else {
+            message
+        };
+        state.messages.push_back(message);
+        drop(state);
+        self.ready.notify_one();
+        Pushed::Queued
+    }
+
+    /// Apply the overflow policy to a full queue, returning the message if it still needs to
+    /// be queued. Commands are never dropped to make room, and alerts get in over anything else.
+    fn make_room(messages: &mut VecDeque<Message>, message: Message) -> Result<Message, Option<(Command, CommandRejection)>> {
+        if let Some(queued) = messages.iter_mut().rev().find(|queued| message.coalesces_with(queued)) {
+            let replaced = std::mem::replace(queued, message);
+            return match replaced {
+                Message::Command(command) => Err(Some((command, CommandRejection::Superseded))),
+                Message::State(_) => Err(None),
+            };
+        }
+        if let Some(index) = messages.iter().position(Message::is_status) {
+            messages.remove(index);
+            return Ok(message);
+        }
+        match message {
+            Message::State(BackendEvent::Alert(_)) => {
+                // The oldest alert goes before any other event, then the oldest event
+                let oldest_alert = messages.iter().position(|queued| matches!(queued, Message::State(BackendEvent::Alert(_))));
+                match oldest_alert.or_else(|| messages.iter().position(|queued| matches!(queued, Message::State(_)))) {
+                    Some(index) => {
+                        messages.remove(index);
+                        Ok(message)
+                    }
+                    None => {
+                        log::error!("Queue full of commands, dropping {:?}", message);
+                        Err(None)
+                    }
+                }
+            }
+            Message::Command(command) => {
+                log::error!("Command queue full, dropping {:?}", command);
+                Err(Some((command, CommandRejection::QueueFull)))
+            }
+            Message::State(_) => Err(None),
+        }
+    }
+}
+
+/// The receiving end of a subscription, with the same interface as an mpsc receiver
+pub struct Subscription {
+    queue: Arc<Queue>,
+}
+
+impl Subscription {
+    pub fn try_recv(&self) -> Result<Message, TryRecvError> {
+        let mut state = self.queue.state.lock().unwrap_or_else(PoisonError::into_inner);
+        match state.messages.pop_front() {
+            Some(message) => {
+                if state.messages.is_empty() {
+                    state.overflowing = false;
+                }
+                Ok(message)
+            }
+            None if self.disconnected() => Err(TryRecvError::Disconnected),
+            None => Err(TryRecvError::Empty),
+        }
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Message, RecvTimeoutError> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.queue.state.lock().unwrap_or_else(PoisonError::into_inner);
+        loop {
+            if let Some(message) = state.messages.pop_front() {
+                if state.messages.is_empty() {
+                    state.overflowing = false;
+                }
+                return Ok(message);
+            }
+            if self.disconnected() {
+                return Err(RecvTimeoutError::Disconnected);
+            }
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(RecvTimeoutError::Timeout);
+            }
+            state = self.queue.ready.wait_timeout(state, remaining).unwrap_or_else(PoisonError::into_inner).0;
+        }
+    }
+
+    /// The bus itself is gone, nothing will be published anymore
+    fn disconnected(&self) -> bool {
+        Arc::weak_count(&self.queue) == 0
+    }
+}
+
+struct Subscriber {
+    topics: Vec<Topic>,
+    queue: Weak<Queue>,
+}
+
+/// Cheap to clone handle to the shared bus.
+#[derive(Clone, Default)]
+pub struct EventBus {
+    subscribers: Arc<Mutex<Vec<Subscriber>>>,
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Subscribe to one or more topics. Every message published on those topics after this
+    /// call is delivered to the returned subscription. Dropping it unsubscribes.
+    pub fn subscribe(&self, topics: &[Topic]) -> Subscription {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState { messages: VecDeque::new(), overflowing: false }),
+            ready: Condvar::new(),
+        });
+        match self.subscribers.lock() {
+            Ok(mut subscribers) => subscribers.push(Subscriber { topics: topics.to_vec(), queue: Arc::downgrade(&queue) }),
+            Err(_) => log::error!("Failed to lock event bus for subscribe"),
+        }
+        Subscription { queue }
+    }
+
+    /// Deliver a message to every subscriber of its topic. A command one of them had no room
+    /// for is nacked to its source.
+    pub fn publish(&self, message: Message) {
+        let topic = message.topic();
+        let Ok(mut subscribers) = self.subscribers.lock() else {
+            log::error!("Failed to lock event bus for publish");
+            return;
+        };
+        let mut lost: Vec<(Command, CommandRejection)> = Vec::new();
+        // Subscribers whose subscription was dropped are removed here
+        subscribers.retain(|subscriber| {
+            if !subscriber.topics.contains(&topic) {
+                return true;
+            }
+            let Some(queue) = subscriber.queue.upgrade() else {
+                return false;
+            };
+            if let Pushed::Overflowed(Some((command, rejection))) = queue.push(message.clone(), &subscriber.topics) {
+                if !lost.iter().any(|(lost_command, _)| lost_command.id == command.id) {
+                    lost.push((command, rejection));
+                }
+            }
+            true
+        });
+        // Published once the lock is released, acks go to the same subscribers
+        drop(subscribers);
+        for (command, rejection) in lost {
+            self.publish_state(BackendEvent::CommandAck {
+                id: command.id,
+                source: command.source,
+                outcome: CommandOutcome::Rejected(rejection),
+            });
+        }
+    }
+
+    /// Publish a command, returning the id its `CommandAck` will carry
+    pub fn publish_command(&self, source: CommandSource, event: UiEvent) -> CommandId {
+        self.publish_command_in_session(source, None, event)
+    }
+
+    /// Publish a command sent with an installer session token, see `installer`
+    pub fn publish_command_in_session(&self, source: CommandSource, session: Option<Secret>, event: UiEvent) -> CommandId {
+        let id = next_command_id();
+        self.publish(Message::Command(Command { id, source, session, event }));
+        id
+    }
+
+    pub fn publish_state(&self, event: BackendEvent) {
+        self.publish(Message::State(event));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(source: CommandSource, event: UiEvent) -> Message {
+        Message::Command(Command { id: next_command_id(), source, session: None, event })
+    }
+
+    fn alert(message: &str) -> Message {
+        Message::State(BackendEvent::Alert(message.to_string()))
+    }
+
+    fn queued(subscription: &Subscription) -> Vec<Message> {
+        std::iter::from_fn(|| subscription.try_recv().ok()).collect()
+    }
+
+    #[test]
+    fn a_newer_status_replaces_the_queued_one() {
+        let bus = EventBus::new();
+        let rx = bus.subscribe(&[Topic::State]);
+        bus.publish_state(BackendEvent::CurrentTempCUpdate(20.0));
+        bus.publish_state(BackendEvent::CurrentTempCUpdate(20.5));
+        let messages = queued(&rx);
+        assert_eq!(messages.len(), 1);
+        assert!(matches!(messages[0], Message::State(BackendEvent::CurrentTempCUpdate(temp_c)) if temp_c == 20.5));
+    }
+
+    #[test]
+    fn a_full_queue_drops_the_oldest_status() {
+        let bus = EventBus::new();
+        let rx = bus.subscribe(&[Topic::State]);
+        bus.publish_state(BackendEvent::CurrentTempCUpdate(20.0));
+        for i in 1..QUEUE_CAPACITY {
+            bus.publish_state(BackendEvent::SettingsExport(i.to_string()));
+        }
+        bus.publish_state(BackendEvent::SettingsExport("last".to_string()));
+        let messages = queued(&rx);
+        assert_eq!(messages.len(), QUEUE_CAPACITY);
+        assert!(!messages.iter().any(|message| matches!(message, Message::State(BackendEvent::CurrentTempCUpdate(_)))));
+    }
+
+    #[test]
+    fn commands_that_dont_fit_are_nacked() {
+        let bus = EventBus::new();
+        let commands_rx = bus.subscribe(&[Topic::Commands]);
+        let acks_rx = bus.subscribe(&[Topic::State]);
+        for _ in 0..QUEUE_CAPACITY {
+            bus.publish_command(CommandSource::Http, UiEvent::ForceRefresh);
+        }
+        let dropped = bus.publish_command(CommandSource::Http, UiEvent::ForceRefresh);
+        assert_eq!(queued(&commands_rx).len(), QUEUE_CAPACITY);
+        let acks = queued(&acks_rx);
+        assert!(matches!(
+            acks.as_slice(),
+            [Message::State(BackendEvent::CommandAck { id, outcome: CommandOutcome::Rejected(CommandRejection::QueueFull), .. })] if *id == dropped
+        ));
+    }
+
+    #[test]
+    fn a_full_queue_coalesces_setpoints_from_the_same_source() {
+        let bus = EventBus::new();
+        let commands_rx = bus.subscribe(&[Topic::Commands]);
+        let acks_rx = bus.subscribe(&[Topic::State]);
+        let superseded = bus.publish_command(CommandSource::Mqtt, UiEvent::TargetTempUpdate(20.0));
+        for _ in 1..QUEUE_CAPACITY {
+            bus.publish_command(CommandSource::Http, UiEvent::ForceRefresh);
+        }
+        bus.publish_command(CommandSource::Mqtt, UiEvent::TargetTempUpdate(21.0));
+        let commands = queued(&commands_rx);
+        assert_eq!(commands.len(), QUEUE_CAPACITY);
+        assert!(matches!(commands[0], Message::Command(Command { event: UiEvent::TargetTempUpdate(temp_c), .. }) if temp_c == 21.0));
+        assert!(matches!(
+            queued(&acks_rx).as_slice(),
+            [Message::State(BackendEvent::CommandAck { id, outcome: CommandOutcome::Rejected(CommandRejection::Superseded), .. })] if *id == superseded
+        ));
+    }
+
+    #[test]
+    fn repeated_alerts_are_coalesced() {
+        let bus = EventBus::new();
+        let rx = bus.subscribe(&[Topic::Alerts]);
+        for _ in 0..3 * QUEUE_CAPACITY {
+            bus.publish(alert("Heating relay not responding"));
+        }
+        assert_eq!(queued(&rx).len(), 1);
+    }
+
+    #[test]
+    fn alerts_stay_bounded_and_push_out_the_oldest() {
+        let bus = EventBus::new();
+        let rx = bus.subscribe(&[Topic::Alerts]);
+        for i in 0..=QUEUE_CAPACITY {
+            bus.publish(alert(&i.to_string()));
+        }
+        let alerts = queued(&rx);
+        assert_eq!(alerts.len(), QUEUE_CAPACITY);
+        assert!(matches!(&alerts[0], Message::State(BackendEvent::Alert(message)) if message == "1"));
+    }
+
+    #[test]
+    fn alerts_push_out_other_events_first_if_there_is_no_alert() {
+        let queue = Queue { state: Mutex::new(QueueState { messages: VecDeque::new(), overflowing: false }), ready: Condvar::new() };
+        for _ in 0..QUEUE_CAPACITY - 1 {
+            queue.push(command(CommandSource::Touch, UiEvent::ForceRefresh), &[]);
+        }
+        queue.push(Message::State(BackendEvent::SettingsExport(String::new())), &[]);
+        assert!(matches!(queue.push(alert("Near freezing"), &[]), Pushed::Queued));
+        let messages = queue.state.lock().unwrap().messages.clone();
+        assert_eq!(messages.len(), QUEUE_CAPACITY);
+        assert!(!messages.iter().any(|message| matches!(message, Message::State(BackendEvent::SettingsExport(_)))));
+        assert!(matches!(queue.push(alert("Sensor not responding"), &[]), Pushed::Queued));
+        assert_eq!(queue.state.lock().unwrap().messages.len(), QUEUE_CAPACITY);
+    }
+}
@@ -0,0 +1,40 @@
+// Piezo buzzer for touch feedback, driven by an LEDC PWM channel behind the `buzzer` feature.
+// A click is a few milliseconds of a 4 kHz tone, short enough to feel like a detent.
+
+use esp_idf_svc::hal::delay::Ets;
+use esp_idf_svc::hal::ledc::{config::TimerConfig, LedcDriver, LedcTimerDriver, CHANNEL0, TIMER0};
+use esp_idf_svc::hal::prelude::*;
+use esp_idf_svc::sys::EspError;
+
+/// How long a click sounds
+const CLICK_US: u32 = 3000;
+
+pub struct Buzzer {
+    /// PWM output to the buzzer, see `bsp::board::buzzer_pin`
+    channel: LedcDriver<'static>,
+}
+
+impl Buzzer {
+    /// # Safety
+    /// LEDC timer 0, channel 0 and the buzzer pin must not be in use anywhere else.
+    pub unsafe fn on_board_pin() -> Result<Self, EspError> {
+        let timer = LedcTimerDriver::new(TIMER0::new(), &TimerConfig::default().frequency(4.kHz().into()))?;
+        let mut channel = LedcDriver::new(CHANNEL0::new(), timer, crate::bsp::board::buzzer_pin())?;
+        channel.set_duty(0)?;
+        log::info!("Buzzer set up");
+        Ok(Self { channel })
+    }
+
+    /// A short tick, e.g. for each step the setpoint dial moves
+    pub fn click(&mut self) {
+        let half = self.channel.get_max_duty() / 2;
+        if let Err(e) = self.channel.set_duty(half) {
+            log::warn!("Failed to sound the buzzer: {}", e);
+            return;
+        }
+        Ets::delay_us(CLICK_US);
+        if let Err(e) = self.channel.set_duty(0) {
+            log::warn!("Failed to silence the buzzer: {}", e);
+        }
+    }
+}
@@ -0,0 +1,97 @@
+// Fault injection for soak testing development builds, with the `chaos` feature. Every so often
+// one fault is injected for a while: the onboard sensor stops answering, it returns garbage
+// (values a DS18B20 really produces on a bad bus), or the backend stops hearing commands as if
+// their senders were gone. After each tick the relays are checked against what the fault
+// handling promises. Faults, recoveries and violations are all logged under the "chaos"
+// target, so a run can be reviewed on the debug screen or the serial log afterwards.
+
+use std::time::{Duration, Instant};
+
+use crate::controller::{Controller, TemperatureReading};
+
+/// Chance of starting a fault on a tick without one, 1 in this many
+const FAULT_ONE_IN: u32 = 60;
+const FAULT_DURATION_MIN: Duration = Duration::from_secs(10);
+const FAULT_DURATION_MAX: Duration = Duration::from_mins(8);
+/// Power on reset value, a read of an all ones scratchpad, and worse
+const GARBAGE_READINGS: [f32; 4] = [85.0, 4095.9375, -127.0, f32::NAN];
+
+#[derive(Debug, Clone, Copy)]
+pub enum Fault {
+    SensorTimeout,
+    GarbageReading(f32),
+    CommandsLost,
+}
+
+#[derive(Default)]
+pub struct Chaos {
+    fault: Option<(Fault, Instant)>,
+    injected: u32,
+    violations: u32,
+    /// Set while the last check failed, so a lasting violation is only counted once
+    violating: bool,
+}
+
+fn random(below: u32) -> u32 {
+    // SAFETY: plain getter with no preconditions
+    unsafe { esp_idf_svc::sys::esp_random() } % below
+}
+
+impl Chaos {
+    /// End the running fault once it's over, or maybe start a new one. Called once per tick.
+    pub fn tick(&mut self) {
+        match self.fault {
+            Some((fault, until)) if Instant::now() >= until => {
+                log::warn!(target: "chaos", "{:?} over ({} faults injected, {} violations)", fault, self.injected, self.violations);
+                self.fault = None;
+            }
+            Some(_) => {}
+            None if random(FAULT_ONE_IN) == 0 => {
+                let fault = match random(3) {
+                    0 => Fault::SensorTimeout,
+                    1 => Fault::GarbageReading(GARBAGE_READINGS[random(GARBAGE_READINGS.len() as u32) as usize]),
+                    _ => Fault::CommandsLost,
+                };
+                let spread = (FAULT_DURATION_MAX - FAULT_DURATION_MIN).as_secs() as u32;
+                let duration = FAULT_DURATION_MIN + Duration::from_secs(random(spread) as u64);
+                log::warn!(target: "chaos", "Injecting {:?} for {}s", fault, duration.as_secs());
+                self.injected += 1;
+                self.fault = Some((fault, Instant::now() + duration));
+            }
+            None => {}
+        }
+    }
+
+    /// The onboard sensor reading as the running fault would have it
+    pub fn temperature(&self, reading: TemperatureReading) -> TemperatureReading {
+        match (self.fault, reading) {
+            (_, TemperatureReading::Pending) => reading,
+            (Some((Fault::SensorTimeout, _)), _) => TemperatureReading::Failed,
+            (Some((Fault::GarbageReading(temp_c), _)), _) => TemperatureReading::Ready(temp_c),
+            _ => reading,
+        }
+    }
+
+    /// Whether commands should be left unread, as with a disconnected channel
+    pub fn commands_lost(&self) -> bool {
+        matches!(self.fault, Some((Fault::CommandsLost, _)))
+    }
+
+    /// Check the relays against the fault handling: never heat and cool at once, and neither
+    /// without a trustworthy temperature.
+    pub fn check(&mut self, controller: &Controller, temp_known: bool) {
+        let (heating, cooling) = (controller.is_heating(), controller.is_cooling());
+        let violation = if heating && cooling {
+            Some("heat and cool relays both closed")
+        } else if !temp_known && (heating || cooling) {
+            Some("heat or cool relay closed without a temperature")
+        } else {
+            None
+        };
+        if let Some(violation) = violation.filter(|_| !self.violating) {
+            self.violations += 1;
+            log::error!(target: "chaos", "Violation during {:?}: {}", self.fault.map(|(fault, _)| fault), violation);
+        }
+        self.violating = violation.is_some();
+    }
+}
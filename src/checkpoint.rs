@@ -0,0 +1,56 @@
+// Compressor runtime bookkeeping checkpointed to NVS, so a reboot in the middle of a long
+// cool call doesn't forget how long the compressor has been running and skip the defrost rest.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "cool_ckpt";
+/// How often the counters are written while they change. Losing this much runtime to a
+/// reboot is harmless, writing every tick would wear the flash.
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CoolingCheckpoint {
+    /// Compressor runtime since the last rest
+    pub total_cooling_secs: u64,
+    /// How far into the rest we were, if resting
+    pub rest_elapsed_secs: Option<u64>,
+    /// Unix seconds when the checkpoint was taken, 0 if the clock wasn't set
+    pub saved_at: u64,
+}
+
+impl CoolingCheckpoint {
+    pub fn new(total_cooling: Duration, rest_elapsed: Option<Duration>) -> Self {
+        Self {
+            total_cooling_secs: total_cooling.as_secs(),
+            rest_elapsed_secs: rest_elapsed.map(|elapsed| elapsed.as_secs()),
+            saved_at: if clock::is_set() { clock::unix_secs() } else { 0 },
+        }
+    }
+
+    pub fn load(storage: &Storage) -> Option<Self> {
+        storage.load(STORAGE_KEY)
+    }
+
+    pub fn save(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save(STORAGE_KEY, self)
+    }
+
+    /// How long the device was down since the checkpoint, None when it can't be known
+    /// because the clock wasn't set when saving or isn't set yet after booting.
+    pub fn downtime(&self) -> Option<Duration> {
+        if self.saved_at == 0 || !clock::is_set() {
+            return None;
+        }
+        Some(Duration::from_secs(clock::unix_secs().saturating_sub(self.saved_at)))
+    }
+
+    /// Whether this differs from `other` in anything but the time it was taken
+    pub fn counters_differ(&self, other: &Self) -> bool {
+        self.total_cooling_secs != other.total_cooling_secs || self.rest_elapsed_secs != other.rest_elapsed_secs
+    }
+}
@@ -0,0 +1,184 @@
+// Wall clock access. Local time follows the TZ configured in the C library (see
+// `set_timezone`), so everything that cares about "what time is it at home" goes through here.
+//
+// Between SNTP syncs the time comes from the crystal, which drifts. Comparing consecutive
+// syncs gives the drift rate, and the time is corrected by it so schedules stay on time
+// through long stretches offline.
+
+use std::ffi::CString;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
+use esp_idf_svc::sys::{localtime_r, setenv, settimeofday, time_t, timeval, tm, tzset};
+use serde::{Deserialize, Serialize};
+
+/// Anything before this means the clock was never set (no SNTP sync or RTC yet)
+const MIN_VALID_UNIX_SECS: u64 = 1_700_000_000;
+
+/// Syncs closer together than this give too noisy a drift rate
+const MIN_DRIFT_MEASUREMENT: Duration = Duration::from_secs(30 * 60);
+/// Anything beyond this is a clock jump (e.g. a manual set), not crystal drift
+const MAX_DRIFT_PPM: f64 = 500.0;
+
+#[derive(Clone, Copy)]
+struct Anchor {
+    at: Instant,
+    unix_secs: f64,
+}
+
+struct SyncState {
+    /// Last time that came from SNTP, what the drift rate is measured against
+    last_sntp: Option<Anchor>,
+    /// Last known good time (SNTP or the RTC), the corrected time counts from here
+    anchor: Option<Anchor>,
+    /// Fraction the crystal runs slow (positive) or fast (negative) by
+    drift_rate: Option<f64>,
+    /// Number of SNTP syncs this boot
+    sntp_syncs: u32,
+}
+
+/// POSIX TZ string in effect, to skip setting it again when nothing changed
+static TIMEZONE: Mutex<String> = Mutex::new(String::new());
+
+static SYNC: Mutex<SyncState> = Mutex::new(SyncState {
+    last_sntp: None,
+    anchor: None,
+    drift_rate: None,
+    sntp_syncs: 0,
+});
+
+pub fn unix_secs() -> u64 {
+    if let Ok(sync) = SYNC.lock() {
+        if let (Some(anchor), Some(rate)) = (sync.anchor, sync.drift_rate) {
+            let elapsed = anchor.at.elapsed().as_secs_f64();
+            return (anchor.unix_secs + elapsed * (1.0 + rate)) as u64;
+        }
+    }
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Called by SNTP with the time it just set
+pub fn record_sntp_sync(synced: Duration) {
+    let Ok(mut sync) = SYNC.lock() else {
+        return;
+    };
+    let now = Anchor {
+        at: Instant::now(),
+        unix_secs: synced.as_secs_f64(),
+    };
+    if let Some(last) = sync.last_sntp {
+        let monotonic = now.at.duration_since(last.at).as_secs_f64();
+        if monotonic >= MIN_DRIFT_MEASUREMENT.as_secs_f64() {
+            let rate = (now.unix_secs - last.unix_secs - monotonic) / monotonic;
+            if rate.abs() * 1e6 <= MAX_DRIFT_PPM {
+                log::info!("Clock drift {:.1} ppm", rate * 1e6);
+                sync.drift_rate = Some(rate);
+            } else {
+                log::warn!("Clock jumped between SNTP syncs, ignoring it for drift");
+            }
+        }
+    }
+    sync.last_sntp = Some(now);
+    sync.anchor = Some(now);
+    sync.sntp_syncs += 1;
+}
+
+/// Set the clock from another time source (the RTC). Doesn't count as a sync for drift.
+pub fn set_unix_secs(secs: u64) {
+    let time = timeval {
+        tv_sec: secs as time_t,
+        tv_usec: 0,
+    };
+    // SAFETY: the pointer is valid for the duration of the call, a null timezone is allowed
+    if unsafe { settimeofday(&time, std::ptr::null()) } != 0 {
+        log::error!("Failed to set the system clock");
+        return;
+    }
+    if let Ok(mut sync) = SYNC.lock() {
+        sync.anchor = Some(Anchor {
+            at: Instant::now(),
+            unix_secs: secs as f64,
+        });
+    }
+}
+
+/// Time since the last SNTP sync, None if there wasn't one this boot
+pub fn since_last_sync() -> Option<Duration> {
+    SYNC.lock().ok()?.last_sntp.map(|last| last.at.elapsed())
+}
+
+/// Number of SNTP syncs so far, to notice new ones by polling
+pub fn sntp_syncs() -> u32 {
+    SYNC.lock().map(|sync| sync.sntp_syncs).unwrap_or(0)
+}
+
+/// Measured crystal drift in parts per million, once there were two syncs far enough apart
+pub fn drift_ppm() -> Option<f32> {
+    SYNC.lock().ok()?.drift_rate.map(|rate| (rate * 1e6) as f32)
+}
+
+/// Whether the clock has been set to a real time
+pub fn is_set() -> bool {
+    unix_secs() >= MIN_VALID_UNIX_SECS
+}
+
+/// Switch local time to a POSIX TZ string, e.g. `EST5EDT,M3.2.0,M11.1.0`
+pub fn set_timezone(posix: &str) {
+    let Ok(mut current) = TIMEZONE.lock() else {
+        return;
+    };
+    if *current == posix {
+        return;
+    }
+    let Ok(value) = CString::new(posix) else {
+        return;
+    };
+    // SAFETY: both strings are valid for the duration of the call. The lock keeps other
+    // callers out, nothing else in the firmware touches the environment.
+    unsafe {
+        if setenv(c"TZ".as_ptr(), value.as_ptr(), 1) != 0 {
+            log::error!("Failed to set the time zone");
+            return;
+        }
+        tzset();
+    }
+    log::info!("Time zone set to {}", posix);
+    *current = posix.to_string();
+}
+
+/// Current local date and time
+pub fn local_now() -> NaiveDateTime {
+    to_local(unix_secs())
+}
+
+/// Local date and time of a unix timestamp
+pub fn to_local(unix_secs: u64) -> NaiveDateTime {
+    let now = unix_secs as time_t;
+    let mut local: tm = unsafe { std::mem::zeroed() };
+    // SAFETY: both pointers are valid for the duration of the call
+    unsafe { localtime_r(&now, &mut local) };
+    NaiveDate::from_ymd_opt(local.tm_year + 1900, (local.tm_mon + 1) as u32, local.tm_mday as u32)
+        .and_then(|date| date.and_hms_opt(local.tm_hour as u32, local.tm_min as u32, local.tm_sec.min(59) as u32))
+        .unwrap_or_else(|| DateTime::from_timestamp(now as i64, 0).unwrap_or_default().naive_utc())
+}
+
+/// Daily window of local time, e.g. 22:00 to 07:00. May wrap past midnight.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: NaiveTime,
+    pub end: NaiveTime,
+}
+
+impl TimeWindow {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
@@ -0,0 +1,30 @@
+// "Feels like" temperature from dry bulb temperature and relative humidity.
+
+use crate::controller::Controller;
+
+/// Heat index (NWS formula) in Celsius for a temperature in Celsius and relative humidity in percent.
+/// Below ~27°C the simple Steadman approximation is used, above it the full Rothfusz regression.
+pub fn heat_index_c(temp_c: f32, relative_humidity: f32) -> f32 {
+    let t = Controller::celsius_to_fahrenheit(temp_c);
+    let rh = relative_humidity.clamp(0.0, 100.0);
+
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    if (simple + t) / 2.0 < 80.0 {
+        return Controller::fahrenheit_to_celsius(simple);
+    }
+
+    let mut hi = -42.379 + 2.049_015_2 * t + 10.143_332 * rh
+        - 0.224_755_4 * t * rh
+        - 0.006_837_83 * t * t
+        - 0.054_817_17 * rh * rh
+        + 0.001_228_74 * t * t * rh
+        + 0.000_852_82 * t * rh * rh
+        - 0.000_001_99 * t * t * rh * rh;
+    // NWS adjustments for very dry and very humid air
+    if rh < 13.0 && (80.0..=112.0).contains(&t) {
+        hi -= ((13.0 - rh) / 4.0) * ((17.0 - (t - 95.0).abs()) / 17.0).sqrt();
+    } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+        hi += ((rh - 85.0) / 10.0) * ((87.0 - t) / 5.0);
+    }
+    Controller::fahrenheit_to_celsius(hi)
+}
@@ -0,0 +1,65 @@
+// Named comfort profiles. Each one bundles the differentials, heat anticipator and fan
+// run-on times, so picking "Eco" or "Comfort" changes everything that trades comfort for
+// energy in one go. Custom uses whatever the user configured.
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::ComfortProfile;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ComfortSettings {
+    /// How far below the setpoint a heat call starts (Celsius)
+    pub heat_differential_c: f32,
+    /// How far above the setpoint a cool call starts (Celsius)
+    pub cool_differential_c: f32,
+    /// How far below the setpoint heat calls stop, to leave room for the residual heat (Celsius)
+    pub heat_anticipator_c: f32,
+    /// How long the fan keeps running after a heat call ends (seconds, 0 to disable)
+    pub heat_fan_run_on_secs: u32,
+    /// How long the fan keeps running after a cool call ends (seconds, 0 to disable)
+    pub cool_fan_run_on_secs: u32,
+}
+
+const COMFORT: ComfortSettings = ComfortSettings {
+    heat_differential_c: 0.3,  // ~0.5°F
+    cool_differential_c: 0.5,  // ~0.9°F
+    heat_anticipator_c: 0.0,
+    // Short run-on so lukewarm air doesn't blow for long
+    heat_fan_run_on_secs: 60,
+    cool_fan_run_on_secs: 30,
+};
+
+const BALANCED: ComfortSettings = ComfortSettings {
+    heat_differential_c: 0.4,  // ~0.75°F
+    cool_differential_c: 0.7,  // ~1.2°F
+    heat_anticipator_c: 0.0,
+    heat_fan_run_on_secs: 90,
+    cool_fan_run_on_secs: 45,
+};
+
+const ECO: ComfortSettings = ComfortSettings {
+    heat_differential_c: 1.0,  // ~1.9°F
+    cool_differential_c: 0.9,  // ~1.7°F
+    heat_anticipator_c: 0.2,   // ~0.4°F
+    // Long run-on to get all the residual heat/cold out of the exchanger
+    heat_fan_run_on_secs: 120,
+    cool_fan_run_on_secs: 60,
+};
+
+impl Default for ComfortSettings {
+    fn default() -> Self {
+        BALANCED
+    }
+}
+
+impl ComfortProfile {
+    /// Settings of a preset profile, None for Custom
+    pub fn preset(self) -> Option<ComfortSettings> {
+        match self {
+            ComfortProfile::Comfort => Some(COMFORT),
+            ComfortProfile::Balanced => Some(BALANCED),
+            ComfortProfile::Eco => Some(ECO),
+            ComfortProfile::Custom => None,
+        }
+    }
+}
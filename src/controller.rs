@@ -1,82 +1,281 @@
 use ds18b20::{Ds18b20, Resolution};
+use embedded_hal::digital::{Error as _, ErrorKind, InputPin, OutputPin, PinState, StatefulOutputPin};
+use embedded_hal_0_2::blocking::delay::{DelayMs, DelayUs};
+use embedded_hal_0_2::digital::v2 as digital_v2;
 use esp_idf_svc::hal::delay::Ets;
-use esp_idf_svc::hal::gpio::{Gpio2, Gpio3, Gpio4, Gpio21, InputOutput, Output, PinDriver};
+use esp_idf_svc::hal::gpio::{AnyIOPin, AnyInputPin, AnyOutputPin, Input, InputOutput, Output, Pin, PinDriver};
 use one_wire_bus::OneWire;
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
 
-/// Used to interface with the relays and thermostat sensor.
-pub struct Controller {
+use crate::air_quality::VentilationInterlock;
+
+/// 12-bit DS18B20 conversions take up to 750ms
+const CONVERSION_TIME: Duration = Duration::from_millis(750);
+
+/// The relays driven by the controller.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Relay {
+    Heat,
+    Cool,
+    Fan,
+    ReversingValve,
+    /// ERV/HRV call, only wired up with the `erv` feature
+    Ventilation,
+    /// Frost protection output, only wired up with the `frost-stat` feature
+    FrostStat,
+}
+
+/// Which pin level closes the relays
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RelayPolarity {
+    /// High closes the relay, as on transistor driven relay modules
+    #[default]
+    ActiveHigh,
+    /// Low closes the relay, as on optocoupler modules with the LED tied to the supply
+    ActiveLow,
+}
+
+impl RelayPolarity {
+    /// The pin level for a closed (true) or open relay
+    fn level(self, closed: bool) -> PinState {
+        PinState::from(closed == (self == RelayPolarity::ActiveHigh))
+    }
+}
+
+/// Result of polling a temperature conversion started with `start_temperature_conversion`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TemperatureReading {
+    /// The conversion is still running
+    Pending,
+    Ready(f32),
+    Failed,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ControllerError {
+    /// Turning this relay on would have energized heating and cooling together.
+    #[error("interlock refused to turn on {0:?} relay")]
+    Interlock(Relay),
+    /// The relay pin could not be driven, even after a retry.
+    #[error("failed to drive {relay:?} relay: {kind}")]
+    Gpio { relay: Relay, kind: ErrorKind },
+}
+
+/// A relay output on the board
+pub type RelayPin = PinDriver<'static, AnyOutputPin, Output>;
+/// The open-drain 1-Wire data line on the board
+pub type OneWirePin = PinDriver<'static, AnyIOPin, InputOutput>;
+/// The zone valve end switch input on the board
+pub type EndSwitchPin = PinDriver<'static, AnyInputPin, Input>;
+
+/// Used to interface with the relays and thermostat sensor. Generic over the relay outputs, the
+/// 1-Wire line and its delay, and the end switch input so the logic doesn't depend on the ESP32
+/// pins, the defaults are the board's.
+pub struct Controller<R = RelayPin, W = OneWirePin, D = Ets, E = EndSwitchPin> {
     is_cooling: bool,
     is_heating: bool,
     is_fan: bool,
-    one_wire: OneWire<PinDriver<'static, Gpio21, InputOutput>>,
+    is_valve_energized: bool,
+    is_ventilating: bool,
+    is_frost_stat: bool,
+    /// Whether the fan was last asked to run, the ventilation interlock may hold it on regardless
+    fan_requested: bool,
+    polarity: RelayPolarity,
+    ventilation_interlock: VentilationInterlock,
+    /// Relays the system profile doesn't use, they are kept open whatever is asked for
+    unused_relays: Vec<Relay>,
+    one_wire: OneWire<W>,
+    /// Delay for the 1-Wire timing and the conversion wait
+    delay: D,
     sensor: Option<Ds18b20>,
-    last_temperature_c: Option<f32>,
-    /// GPIO 2 - Heat relay control
-    heat_pin: PinDriver<'static, Gpio2, Output>,
-    /// GPIO 3 - Cool relay control
-    cool_pin: PinDriver<'static, Gpio3, Output>,
-    /// GPIO 4 - Fan relay control
-    fan_pin: PinDriver<'static, Gpio4, Output>,
+    /// When the running temperature conversion was started, if one is
+    conversion_started: Option<Instant>,
+    /// Heat relay control
+    heat_pin: R,
+    /// Cool relay control
+    cool_pin: R,
+    /// Fan relay control
+    fan_pin: R,
+    /// Heat pump reversing valve (O/B) relay control
+    valve_pin: R,
+    /// ERV/HRV relay control, if one is wired up
+    ventilation_pin: Option<R>,
+    /// Frost-stat relay control, if one is wired up
+    frost_stat_pin: Option<R>,
+    /// Hydronic zone valve end switch, closed (pulled low) once the valve is open
+    end_switch: Option<E>,
 }
 
 impl Controller {
-    /// Create a new controller with:
-    /// - DS18B20 temperature sensor on GPIO 21
-    /// - Heat relay on GPIO 2
-    /// - Cool relay on GPIO 3
-    /// - Fan relay on GPIO 4
+    /// Create a new controller with a DS18B20 temperature sensor on `temp_pin` and the relays
+    /// on the other pins, switched with the given polarity. The reversing valve relay is only
+    /// wired up for heat pumps.
     pub fn new(
-        temp_pin: Gpio21,
-        heat_pin: Gpio2,
-        cool_pin: Gpio3,
-        fan_pin: Gpio4,
-    ) -> Result<Self, esp_idf_svc::sys::EspError> {
+        temp_pin: AnyIOPin,
+        heat_pin: AnyOutputPin,
+        cool_pin: AnyOutputPin,
+        fan_pin: AnyOutputPin,
+        valve_pin: AnyOutputPin,
+        polarity: RelayPolarity,
+    ) -> anyhow::Result<Self> {
+        let temp_gpio = temp_pin.pin();
         // Configure the temperature sensor pin as open-drain for 1-Wire communication
         let pin_driver = PinDriver::input_output_od(temp_pin)?;
-        let mut one_wire = OneWire::new(pin_driver).map_err(|_| {
-            esp_idf_svc::sys::EspError::from_infallible::<{ esp_idf_svc::sys::ESP_ERR_INVALID_STATE }>()
-        })?;
+        let one_wire = OneWire::new(pin_driver).map_err(|e| anyhow::anyhow!("failed to set up 1-Wire on GPIO{}: {:?}", temp_gpio, e))?;
 
-        // Search for DS18B20 sensor on the bus
-        let mut delay = Ets;
-        let sensor = Self::find_ds18b20_sensor(&mut one_wire, &mut delay);
+        let relay_gpios = [heat_pin.pin(), cool_pin.pin(), fan_pin.pin(), valve_pin.pin()];
+        let controller = Self::from_parts(
+            one_wire,
+            Ets,
+            PinDriver::output(heat_pin)?,
+            PinDriver::output(cool_pin)?,
+            PinDriver::output(fan_pin)?,
+            PinDriver::output(valve_pin)?,
+            polarity,
+        )?;
 
-        if sensor.is_none() {
-            log::warn!("No DS18B20 sensor found on GPIO 21");
+        if controller.sensor.is_none() {
+            log::warn!("No DS18B20 sensor found on GPIO{}", temp_gpio);
         } else {
-            log::info!("DS18B20 sensor found on GPIO 21");
+            log::info!("DS18B20 sensor found on GPIO{}", temp_gpio);
         }
+        log::info!(
+            "Controller initialized: Heat=GPIO{}, Cool=GPIO{}, Fan=GPIO{}, Valve=GPIO{}, {:?}",
+            relay_gpios[0],
+            relay_gpios[1],
+            relay_gpios[2],
+            relay_gpios[3],
+            polarity
+        );
+        Ok(controller)
+    }
 
-        // Configure relay control pins as outputs (active low - start with relays off)
-        let mut heat_pin = PinDriver::output(heat_pin)?;
-        let mut cool_pin = PinDriver::output(cool_pin)?;
-        let mut fan_pin = PinDriver::output(fan_pin)?;
+    /// Create a controller on the pins of the board being built for, see `bsp::board::pins`.
+    ///
+    /// # Safety
+    /// The pins must not be in use anywhere else, e.g. after `bsp::setup_i2c` consumed
+    /// the peripherals and nothing else took these pins.
+    pub unsafe fn on_board_pins(polarity: RelayPolarity) -> anyhow::Result<Self> {
+        let pins = crate::bsp::board::pins();
+        let controller = Self::new(pins.temp_sensor, pins.heat, pins.cool, pins.fan, pins.valve, polarity)?;
+        #[cfg(feature = "erv")]
+        let controller = controller.with_ventilation(relay_output(crate::bsp::board::ventilation_pin(), Relay::Ventilation)?)?;
+        #[cfg(feature = "end-switch")]
+        let controller = controller.with_end_switch(end_switch_input(crate::bsp::board::end_switch_pin())?);
+        #[cfg(feature = "frost-stat")]
+        let controller = controller.with_frost_stat(relay_output(crate::bsp::board::frost_stat_pin(), Relay::FrostStat)?)?;
+        Ok(controller)
+    }
 
-        // Initialize all relays to off (low = off for active-high relays)
-        heat_pin.set_low()?;
-        cool_pin.set_low()?;
-        fan_pin.set_low()?;
+    /// Convert Celsius to Fahrenheit: F = C * 9/5 + 32
+    pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
+        celsius * 9.0 / 5.0 + 32.0
+    }
+
+    /// Convert Fahrenheit to Celsius: C = (F - 32) * 5/9
+    pub fn fahrenheit_to_celsius(fahrenheit: f32) -> f32 {
+        (fahrenheit - 32.0) * 5.0 / 9.0
+    }
+}
+
+/// Set up an optional relay output on the board. `with_ventilation` and `with_frost_stat`
+/// open it before anything else happens.
+#[cfg(any(feature = "erv", feature = "frost-stat"))]
+fn relay_output(pin: AnyOutputPin, relay: Relay) -> Result<RelayPin, esp_idf_svc::sys::EspError> {
+    log::info!("{:?} relay on GPIO{}", relay, pin.pin());
+    PinDriver::output(pin)
+}
 
-        log::info!("Controller initialized: Heat=GPIO2, Cool=GPIO3, Fan=GPIO4");
+/// Set up the zone valve end switch input on the board, a dry contact to ground
+#[cfg(feature = "end-switch")]
+fn end_switch_input(pin: AnyInputPin) -> Result<EndSwitchPin, esp_idf_svc::sys::EspError> {
+    let gpio = pin.pin();
+    let mut pin = PinDriver::input(pin)?;
+    pin.set_pull(esp_idf_svc::hal::gpio::Pull::Up)?;
+    log::info!("Zone valve end switch on GPIO{}", gpio);
+    Ok(pin)
+}
+
+impl<R, W, D, E> Controller<R, W, D, E>
+where
+    R: StatefulOutputPin,
+    W: digital_v2::InputPin + digital_v2::OutputPin<Error = <W as digital_v2::InputPin>::Error>,
+    D: DelayUs<u16> + DelayMs<u16>,
+    E: InputPin,
+{
+    /// Create a controller from an already set up 1-Wire bus and relay outputs, opening every
+    /// relay and searching the bus for a DS18B20
+    pub fn from_parts(
+        mut one_wire: OneWire<W>,
+        mut delay: D,
+        mut heat_pin: R,
+        mut cool_pin: R,
+        mut fan_pin: R,
+        mut valve_pin: R,
+        polarity: RelayPolarity,
+    ) -> Result<Self, ControllerError> {
+        // Initialize all relays to off
+        drive_relay(&mut heat_pin, Relay::Heat, polarity, false)?;
+        drive_relay(&mut cool_pin, Relay::Cool, polarity, false)?;
+        drive_relay(&mut fan_pin, Relay::Fan, polarity, false)?;
+        drive_relay(&mut valve_pin, Relay::ReversingValve, polarity, false)?;
 
+        let sensor = Self::find_ds18b20_sensor(&mut one_wire, &mut delay);
         Ok(Self {
             is_cooling: false,
             is_heating: false,
             is_fan: false,
+            is_valve_energized: false,
+            is_ventilating: false,
+            is_frost_stat: false,
+            fan_requested: false,
+            polarity,
+            ventilation_interlock: VentilationInterlock::default(),
+            unused_relays: Vec::new(),
             one_wire,
+            delay,
             sensor,
-            last_temperature_c: None,
+            conversion_started: None,
             heat_pin,
             cool_pin,
             fan_pin,
+            valve_pin,
+            ventilation_pin: None,
+            frost_stat_pin: None,
+            end_switch: None,
         })
     }
 
+    /// Add the zone valve end switch input, closed when it reads low
+    pub fn with_end_switch(mut self, pin: E) -> Self {
+        self.end_switch = Some(pin);
+        self
+    }
+
+    /// Whether the zone valve end switch is closed, None without one. A switch that can't be
+    /// read counts as open.
+    pub fn valve_end_switch(&mut self) -> Option<bool> {
+        self.end_switch.as_mut().map(|pin| pin.is_low().unwrap_or(false))
+    }
+
+    /// Add the ERV/HRV relay on `pin`, opening it
+    pub fn with_ventilation(mut self, mut pin: R) -> Result<Self, ControllerError> {
+        drive_relay(&mut pin, Relay::Ventilation, self.polarity, false)?;
+        self.ventilation_pin = Some(pin);
+        Ok(self)
+    }
+
+    /// Add the frost-stat relay on `pin`, opening it
+    pub fn with_frost_stat(mut self, mut pin: R) -> Result<Self, ControllerError> {
+        drive_relay(&mut pin, Relay::FrostStat, self.polarity, false)?;
+        self.frost_stat_pin = Some(pin);
+        Ok(self)
+    }
+
     /// Search for a DS18B20 sensor on the 1-Wire bus.
-    fn find_ds18b20_sensor(
-        one_wire: &mut OneWire<PinDriver<'static, Gpio21, InputOutput>>,
-        delay: &mut Ets,
-    ) -> Option<Ds18b20> {
+    fn find_ds18b20_sensor(one_wire: &mut OneWire<W>, delay: &mut D) -> Option<Ds18b20> {
         let mut search_state = None;
 
         // Search for devices on the bus
@@ -103,103 +302,529 @@ impl Controller {
         None
     }
 
-    /// Read the temperature from the DS18B20 sensor and update the cached value.
+    /// Read the temperature from the DS18B20 sensor.
     /// Returns the temperature in Celsius if successful.
     fn read_temperature(&mut self) -> Option<f32> {
         let sensor = self.sensor.as_ref()?;
-        let mut delay = Ets;
 
         // Start temperature measurement
-        if sensor.start_temp_measurement(&mut self.one_wire, &mut delay).is_err() {
+        if sensor.start_temp_measurement(&mut self.one_wire, &mut self.delay).is_err() {
             log::error!("Failed to start temperature measurement");
-            return self.last_temperature_c;
+            return None;
         }
 
         // Wait for conversion to complete (750ms for 12-bit resolution)
-        Resolution::Bits12.delay_for_measurement_time(&mut delay);
+        Resolution::Bits12.delay_for_measurement_time(&mut self.delay);
 
         // Read the temperature
-        match sensor.read_data(&mut self.one_wire, &mut delay) {
+        match sensor.read_data(&mut self.one_wire, &mut self.delay) {
             Ok(data) => {
                 let temp_c = data.temperature;
-                self.last_temperature_c = Some(temp_c);
                 log::debug!("Temperature read: {:.2}°C", temp_c);
                 Some(temp_c)
             }
             Err(_) => {
                 log::error!("Failed to read temperature from DS18B20");
-                self.last_temperature_c
+                None
             }
         }
     }
 
-    /// Get the current temperature from the sensor in Celsius (base unit).
-    /// This will trigger a new reading from the sensor.
-    pub fn get_temperature_c(&mut self) -> f32 {
-        self.read_temperature().unwrap_or(25.0) // Default to 25°C if no reading
+    /// Start a temperature conversion without waiting for it to finish.
+    /// Returns false if there is no sensor or it couldn't be started.
+    pub fn start_temperature_conversion(&mut self) -> bool {
+        let Some(sensor) = self.sensor.as_ref() else {
+            return false;
+        };
+        if sensor.start_temp_measurement(&mut self.one_wire, &mut self.delay).is_err() {
+            log::error!("Failed to start temperature measurement");
+            return false;
+        }
+        self.conversion_started = Some(Instant::now());
+        true
     }
 
-    /// Get the current temperature from the sensor in Fahrenheit.
-    /// This converts from the base Celsius reading.
-    pub fn get_temperature_f(&mut self) -> f32 {
-        Self::celsius_to_fahrenheit(self.get_temperature_c())
+    /// Whether a conversion started with `start_temperature_conversion` hasn't been read yet
+    pub fn temperature_conversion_running(&self) -> bool {
+        self.conversion_started.is_some()
     }
 
-    /// Convert Celsius to Fahrenheit: F = C * 9/5 + 32
-    pub fn celsius_to_fahrenheit(celsius: f32) -> f32 {
-        celsius * 9.0 / 5.0 + 32.0
+    /// Whether the running conversion is done, so the next poll reads it off the bus
+    pub fn temperature_conversion_done(&self) -> bool {
+        self.conversion_started.is_some_and(|started| started.elapsed() >= CONVERSION_TIME)
     }
 
-    /// Convert Fahrenheit to Celsius: C = (F - 32) * 5/9
-    pub fn fahrenheit_to_celsius(fahrenheit: f32) -> f32 {
-        (fahrenheit - 32.0) * 5.0 / 9.0
+    /// Read the result of the conversion started with `start_temperature_conversion` once it is done.
+    pub fn poll_temperature_conversion(&mut self) -> TemperatureReading {
+        let Some(started) = self.conversion_started else {
+            return TemperatureReading::Failed;
+        };
+        if started.elapsed() < CONVERSION_TIME {
+            return TemperatureReading::Pending;
+        }
+        self.conversion_started = None;
+        let Some(sensor) = self.sensor.as_ref() else {
+            return TemperatureReading::Failed;
+        };
+        match sensor.read_data(&mut self.one_wire, &mut self.delay) {
+            Ok(data) => {
+                log::debug!("Temperature read: {:.2}°C", data.temperature);
+                TemperatureReading::Ready(data.temperature)
+            }
+            Err(_) => {
+                log::error!("Failed to read temperature from DS18B20");
+                TemperatureReading::Failed
+            }
+        }
+    }
+
+    /// Get the current temperature from the sensor in Celsius (base unit).
+    /// This will trigger a new reading from the sensor, None if there is no sensor or the read failed.
+    pub fn get_temperature_c(&mut self) -> Option<f32> {
+        self.read_temperature()
+    }
+
+    /// Get the current temperature from the sensor in Fahrenheit.
+    /// This converts from the base Celsius reading.
+    pub fn get_temperature_f(&mut self) -> Option<f32> {
+        self.get_temperature_c().map(Controller::celsius_to_fahrenheit)
     }
 
-    /// Control the cooling relay on GPIO 3.
-    /// Active high: high = relay on, low = relay off
-    pub fn set_cooling(&mut self, enabled: bool) {
+    /// Control the cooling relay.
+    /// The pin level that closes the relay follows the relay polarity.
+    /// Refuses to turn cooling on while heating is on.
+    pub fn set_cooling(&mut self, enabled: bool) -> Result<(), ControllerError> {
+        let enabled = enabled && self.in_use(Relay::Cool);
         if self.is_cooling == enabled {
-            return;
+            return Ok(());
         }
-        self.is_cooling = enabled;
-        if enabled {
-            log::info!("Cooling ON");
-            let _ = self.cool_pin.set_high();
-        } else {
-            log::info!("Cooling OFF");
-            let _ = self.cool_pin.set_low();
+        if enabled && self.is_heating {
+            log::error!("Interlock: refusing to turn cooling on while heating is on");
+            return Err(ControllerError::Interlock(Relay::Cool));
         }
+        log::info!("Cooling {}", if enabled { "ON" } else { "OFF" });
+        drive_relay(&mut self.cool_pin, Relay::Cool, self.polarity, enabled)?;
+        self.is_cooling = enabled;
+        Ok(())
     }
 
-    /// Control the heating relay on GPIO 2.
-    /// Active high: high = relay on, low = relay off
-    pub fn set_heating(&mut self, enabled: bool) {
+    /// Control the heating relay.
+    /// The pin level that closes the relay follows the relay polarity.
+    /// Refuses to turn heating on while cooling is on.
+    pub fn set_heating(&mut self, enabled: bool) -> Result<(), ControllerError> {
+        let enabled = enabled && self.in_use(Relay::Heat);
         if self.is_heating == enabled {
-            return;
+            return Ok(());
         }
-        self.is_heating = enabled;
-        if enabled {
-            log::info!("Heating ON");
-            let _ = self.heat_pin.set_high();
-        } else {
-            log::info!("Heating OFF");
-            let _ = self.heat_pin.set_low();
+        if enabled && self.is_cooling {
+            log::error!("Interlock: refusing to turn heating on while cooling is on");
+            return Err(ControllerError::Interlock(Relay::Heat));
         }
+        log::info!("Heating {}", if enabled { "ON" } else { "OFF" });
+        drive_relay(&mut self.heat_pin, Relay::Heat, self.polarity, enabled)?;
+        self.is_heating = enabled;
+        Ok(())
+    }
+
+    /// Control the fan relay. While ventilating with `require_fan` the fan stays on regardless.
+    /// The pin level that closes the relay follows the relay polarity.
+    pub fn set_fan(&mut self, enabled: bool) -> Result<(), ControllerError> {
+        self.fan_requested = enabled;
+        self.drive_fan(enabled || self.ventilation_holds_fan())
+    }
+
+    fn ventilation_holds_fan(&self) -> bool {
+        self.is_ventilating && self.ventilation_interlock.require_fan
     }
 
-    /// Control the fan relay on GPIO 4.
-    /// Active high: high = relay on, low = relay off
-    pub fn set_fan(&mut self, enabled: bool) {
+    fn drive_fan(&mut self, enabled: bool) -> Result<(), ControllerError> {
+        let enabled = enabled && self.in_use(Relay::Fan);
         if self.is_fan == enabled {
-            return;
+            return Ok(());
         }
+        log::info!("Fan {}", if enabled { "ON" } else { "OFF" });
+        drive_relay(&mut self.fan_pin, Relay::Fan, self.polarity, enabled)?;
         self.is_fan = enabled;
-        if enabled {
-            log::info!("Fan ON");
-            let _ = self.fan_pin.set_high();
-        } else {
-            log::info!("Fan OFF");
-            let _ = self.fan_pin.set_low();
+        Ok(())
+    }
+
+    /// Relays that stay open from now on, whatever the backend asks for. Any of them that are
+    /// closed right now are opened.
+    pub fn set_unused_relays(&mut self, relays: Vec<Relay>) -> Result<(), ControllerError> {
+        if self.unused_relays == relays {
+            return Ok(());
+        }
+        self.unused_relays = relays;
+        if !self.in_use(Relay::Heat) {
+            self.set_heating(false)?;
         }
+        if !self.in_use(Relay::Cool) {
+            self.set_cooling(false)?;
+        }
+        if !self.in_use(Relay::Fan) {
+            self.drive_fan(false)?;
+        }
+        if !self.in_use(Relay::ReversingValve) {
+            self.set_reversing_valve(false)?;
+        }
+        Ok(())
+    }
+
+    /// Switch the relays with a different polarity from now on. Every relay is driven again so
+    /// it stays as it was, which also opens relays that the wrong polarity had closed.
+    pub fn set_relay_polarity(&mut self, polarity: RelayPolarity) -> Result<(), ControllerError> {
+        if self.polarity == polarity {
+            return Ok(());
+        }
+        log::warn!("Relay polarity changed to {:?}", polarity);
+        self.polarity = polarity;
+        drive_relay(&mut self.heat_pin, Relay::Heat, polarity, self.is_heating)?;
+        drive_relay(&mut self.cool_pin, Relay::Cool, polarity, self.is_cooling)?;
+        drive_relay(&mut self.fan_pin, Relay::Fan, polarity, self.is_fan)?;
+        drive_relay(&mut self.valve_pin, Relay::ReversingValve, polarity, self.is_valve_energized)?;
+        if let Some(pin) = self.ventilation_pin.as_mut() {
+            drive_relay(pin, Relay::Ventilation, polarity, self.is_ventilating)?;
+        }
+        if let Some(pin) = self.frost_stat_pin.as_mut() {
+            drive_relay(pin, Relay::FrostStat, polarity, self.is_frost_stat)?;
+        }
+        Ok(())
+    }
+
+    fn in_use(&self, relay: Relay) -> bool {
+        !self.unused_relays.contains(&relay)
+    }
+
+    pub fn is_heating(&self) -> bool {
+        self.is_heating
+    }
+
+    pub fn is_cooling(&self) -> bool {
+        self.is_cooling
+    }
+
+    /// Whether an ERV/HRV relay is wired up
+    pub fn has_ventilation(&self) -> bool {
+        self.ventilation_pin.is_some()
+    }
+
+    /// Interlocks `set_ventilation` enforces from now on
+    pub fn set_ventilation_interlock(&mut self, interlock: VentilationInterlock) {
+        self.ventilation_interlock = interlock;
+    }
+
+    /// Control the ERV/HRV relay, within its interlocks: nothing runs outside the outdoor
+    /// temperature limits, and the fan is brought on first if the unit needs it. Returns whether
+    /// it is ventilating now, always false without a ventilation relay.
+    /// The pin level that closes the relay follows the relay polarity.
+    pub fn set_ventilation(&mut self, enabled: bool, outdoor_temp_c: Option<f32>) -> Result<bool, ControllerError> {
+        if self.ventilation_pin.is_none() {
+            return Ok(false);
+        }
+        let enabled = enabled && self.ventilation_interlock.outdoor_allows(outdoor_temp_c);
+        if self.is_ventilating == enabled {
+            // The interlock may have changed since, bring the fan in line with it
+            self.drive_fan(self.fan_requested || self.ventilation_holds_fan())?;
+            return Ok(enabled);
+        }
+        if enabled && self.ventilation_interlock.require_fan {
+            self.drive_fan(true)?;
+        }
+        log::info!("Ventilation {}", if enabled { "ON" } else { "OFF" });
+        if let Some(pin) = self.ventilation_pin.as_mut() {
+            drive_relay(pin, Relay::Ventilation, self.polarity, enabled)?;
+        }
+        self.is_ventilating = enabled;
+        if !enabled {
+            self.drive_fan(self.fan_requested)?;
+        }
+        Ok(enabled)
+    }
+
+    /// Whether a frost-stat relay is wired up
+    pub fn has_frost_stat(&self) -> bool {
+        self.frost_stat_pin.is_some()
+    }
+
+    /// Control the frost-stat relay, a no-op without one. It has nothing to do with the heat
+    /// and cool relays, so no interlock applies.
+    /// The pin level that closes the relay follows the relay polarity.
+    pub fn set_frost_stat(&mut self, enabled: bool) -> Result<(), ControllerError> {
+        let Some(pin) = self.frost_stat_pin.as_mut() else {
+            return Ok(());
+        };
+        if self.is_frost_stat == enabled {
+            return Ok(());
+        }
+        log::info!("Frost-stat {}", if enabled { "ON" } else { "OFF" });
+        drive_relay(pin, Relay::FrostStat, self.polarity, enabled)?;
+        self.is_frost_stat = enabled;
+        Ok(())
+    }
+
+    /// Control the heat pump reversing valve relay.
+    /// The pin level that closes the relay follows the relay polarity.
+    pub fn set_reversing_valve(&mut self, energized: bool) -> Result<(), ControllerError> {
+        let energized = energized && self.in_use(Relay::ReversingValve);
+        if self.is_valve_energized == energized {
+            return Ok(());
+        }
+        log::info!("Reversing valve {}", if energized { "ON" } else { "OFF" });
+        drive_relay(&mut self.valve_pin, Relay::ReversingValve, self.polarity, energized)?;
+        self.is_valve_energized = energized;
+        Ok(())
+    }
+
+    /// Force every relay off regardless of the cached state.
+    /// Used as the safe state when the interlock trips or something else goes wrong.
+    /// Every relay is attempted even if an earlier one fails; the first failure is returned.
+    pub fn all_off(&mut self) -> Result<(), ControllerError> {
+        log::warn!("Forcing all relays OFF");
+        let heat = drive_relay(&mut self.heat_pin, Relay::Heat, self.polarity, false);
+        let cool = drive_relay(&mut self.cool_pin, Relay::Cool, self.polarity, false);
+        let fan = drive_relay(&mut self.fan_pin, Relay::Fan, self.polarity, false);
+        let valve = drive_relay(&mut self.valve_pin, Relay::ReversingValve, self.polarity, false);
+        let ventilation = match self.ventilation_pin.as_mut() {
+            Some(pin) => drive_relay(pin, Relay::Ventilation, self.polarity, false),
+            None => Ok(()),
+        };
+        let frost_stat = match self.frost_stat_pin.as_mut() {
+            Some(pin) => drive_relay(pin, Relay::FrostStat, self.polarity, false),
+            None => Ok(()),
+        };
+        // Only trust the cached state for relays we know went low
+        self.is_heating &= heat.is_err();
+        self.is_cooling &= cool.is_err();
+        self.is_fan &= fan.is_err();
+        self.is_valve_energized &= valve.is_err();
+        self.is_ventilating &= ventilation.is_err();
+        self.is_frost_stat &= frost_stat.is_err();
+        self.fan_requested = false;
+        heat.and(cool).and(fan).and(valve).and(ventilation).and(frost_stat)
+    }
+
+    /// Briefly pulse one relay and check its pin follows, for the boot self-test. The pulse is
+    /// far too short for the equipment to take it as a call, and it's refused (Ok(false)) unless
+    /// every relay is off so nothing can be energized together.
+    pub fn pulse_relay(&mut self, relay: Relay, duration: Duration) -> Result<bool, ControllerError> {
+        if self.is_heating || self.is_cooling || self.is_fan || self.is_valve_energized || self.is_ventilating || self.is_frost_stat {
+            return Ok(false);
+        }
+        let polarity = self.polarity;
+        match relay {
+            Relay::Heat => pulse(&mut self.heat_pin, relay, polarity, duration),
+            Relay::Cool => pulse(&mut self.cool_pin, relay, polarity, duration),
+            Relay::Fan => pulse(&mut self.fan_pin, relay, polarity, duration),
+            Relay::ReversingValve => pulse(&mut self.valve_pin, relay, polarity, duration),
+            Relay::Ventilation => match self.ventilation_pin.as_mut() {
+                Some(pin) => pulse(pin, relay, polarity, duration),
+                None => Ok(false),
+            },
+            Relay::FrostStat => match self.frost_stat_pin.as_mut() {
+                Some(pin) => pulse(pin, relay, polarity, duration),
+                None => Ok(false),
+            },
+        }
+    }
+}
+
+/// Close a relay for `duration` and open it again, returning whether its pin read back both times.
+fn pulse<P: StatefulOutputPin>(pin: &mut P, relay: Relay, polarity: RelayPolarity, duration: Duration) -> Result<bool, ControllerError> {
+    let reads = |pin: &mut P, closed: bool| pin.is_set_high().ok() == Some(polarity.level(closed) == PinState::High);
+    drive_relay(pin, relay, polarity, true)?;
+    let closed = reads(pin, true);
+    std::thread::sleep(duration);
+    drive_relay(pin, relay, polarity, false)?;
+    Ok(closed && reads(pin, false))
+}
+
+/// Drive a relay pin, retrying once before giving up.
+fn drive_relay<P: OutputPin>(pin: &mut P, relay: Relay, polarity: RelayPolarity, enabled: bool) -> Result<(), ControllerError> {
+    let state = polarity.level(enabled);
+    if let Err(e) = pin.set_state(state) {
+        log::warn!("Failed to drive {:?} relay, retrying: {:?}", relay, e);
+        pin.set_state(state).map_err(|source| {
+            log::error!("Failed to drive {:?} relay after retry: {:?}", relay, source);
+            ControllerError::Gpio { relay, kind: source.kind() }
+        })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_hal_mock::eh0::delay::NoopDelay;
+    use embedded_hal_mock::eh0::digital as one_wire_mock;
+    use embedded_hal_mock::eh1::digital::{Mock as PinMock, State, Transaction};
+    use embedded_hal_mock::eh1::MockError;
+
+    type TestController = Controller<PinMock, one_wire_mock::Mock, NoopDelay, PinMock>;
+
+    /// The heat, cool, fan and reversing valve relay mocks, in that order
+    type Relays = [PinMock; 4];
+
+    fn write_error() -> MockError {
+        MockError::Io(std::io::ErrorKind::Other)
+    }
+
+    /// A 1-Wire line nothing answers on: idle high, then one reset without a presence pulse
+    fn empty_bus() -> one_wire_mock::Mock {
+        use one_wire_mock::{State, Transaction};
+        one_wire_mock::Mock::new(&[
+            Transaction::set(State::High),
+            Transaction::get(State::High),
+            Transaction::set(State::Low),
+            Transaction::set(State::High),
+            Transaction::get(State::High),
+        ])
+    }
+
+    /// A controller on an empty 1-Wire bus with relays expecting `relays`, plus the mocks to
+    /// check afterwards
+    fn controller(polarity: RelayPolarity, relays: [&[Transaction]; 4]) -> (TestController, Relays, one_wire_mock::Mock) {
+        let bus = empty_bus();
+        let one_wire = OneWire::new(bus.clone()).unwrap();
+        let pins = relays.map(PinMock::new);
+        let [heat, cool, fan, valve] = pins.clone();
+        let controller = Controller::from_parts(one_wire, NoopDelay::new(), heat, cool, fan, valve, polarity).unwrap();
+        (controller, pins, bus)
+    }
+
+    fn done(mut relays: Relays, mut bus: one_wire_mock::Mock) {
+        relays.iter_mut().for_each(PinMock::done);
+        bus.done();
+    }
+
+    #[test]
+    fn relays_start_open() {
+        let open = [Transaction::set(State::Low)];
+        let (controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&open; 4]);
+        assert!(!controller.is_heating());
+        assert!(!controller.is_cooling());
+        done(relays, bus);
+    }
+
+    #[test]
+    fn active_low_relays_start_high() {
+        let open = [Transaction::set(State::High)];
+        let (_controller, relays, bus) = controller(RelayPolarity::ActiveLow, [&open; 4]);
+        done(relays, bus);
+    }
+
+    #[test]
+    fn heating_is_refused_while_cooling() {
+        let open = [Transaction::set(State::Low)];
+        let cool = [Transaction::set(State::Low), Transaction::set(State::High)];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&open, &cool, &open, &open]);
+        controller.set_cooling(true).unwrap();
+        assert!(matches!(controller.set_heating(true), Err(ControllerError::Interlock(Relay::Heat))));
+        assert!(!controller.is_heating());
+        assert!(controller.is_cooling());
+        done(relays, bus);
+    }
+
+    #[test]
+    fn failed_write_is_retried_once() {
+        let open = [Transaction::set(State::Low)];
+        let heat = [
+            Transaction::set(State::Low),
+            Transaction::set(State::High).with_error(write_error()),
+            Transaction::set(State::High),
+        ];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&heat, &open, &open, &open]);
+        controller.set_heating(true).unwrap();
+        assert!(controller.is_heating());
+        done(relays, bus);
+    }
+
+    #[test]
+    fn relay_failing_twice_reports_the_relay() {
+        let open = [Transaction::set(State::Low)];
+        let heat = [
+            Transaction::set(State::Low),
+            Transaction::set(State::High).with_error(write_error()),
+            Transaction::set(State::High).with_error(write_error()),
+        ];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&heat, &open, &open, &open]);
+        assert!(matches!(controller.set_heating(true), Err(ControllerError::Gpio { relay: Relay::Heat, .. })));
+        assert!(!controller.is_heating());
+        done(relays, bus);
+    }
+
+    #[test]
+    fn unused_relays_stay_open() {
+        let open = [Transaction::set(State::Low)];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&open; 4]);
+        controller.set_unused_relays(vec![Relay::Cool]).unwrap();
+        controller.set_cooling(true).unwrap();
+        assert!(!controller.is_cooling());
+        done(relays, bus);
+    }
+
+    #[test]
+    fn polarity_change_drives_every_relay_again() {
+        let open = [Transaction::set(State::Low), Transaction::set(State::High)];
+        let fan = [Transaction::set(State::Low), Transaction::set(State::High), Transaction::set(State::Low)];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&open, &open, &fan, &open]);
+        controller.set_fan(true).unwrap();
+        controller.set_relay_polarity(RelayPolarity::ActiveLow).unwrap();
+        // Nothing changes when the polarity doesn't
+        controller.set_relay_polarity(RelayPolarity::ActiveLow).unwrap();
+        done(relays, bus);
+    }
+
+    #[test]
+    fn pulse_reads_back_against_the_polarity() {
+        let open = [Transaction::set(State::High)];
+        let heat = [
+            Transaction::set(State::High),
+            Transaction::set(State::Low),
+            Transaction::get_state(State::Low),
+            Transaction::set(State::High),
+            Transaction::get_state(State::High),
+        ];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveLow, [&heat, &open, &open, &open]);
+        assert!(controller.pulse_relay(Relay::Heat, Duration::ZERO).unwrap());
+        done(relays, bus);
+    }
+
+    #[test]
+    fn empty_bus_has_no_sensor() {
+        let open = [Transaction::set(State::Low)];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&open; 4]);
+        assert!(!controller.start_temperature_conversion());
+        assert_eq!(controller.poll_temperature_conversion(), TemperatureReading::Failed);
+        done(relays, bus);
+    }
+
+    #[test]
+    fn bus_held_low_has_no_sensor() {
+        // The search gives up waiting for the line to go high
+        let mut expected = vec![one_wire_mock::Transaction::set(one_wire_mock::State::High)];
+        expected.extend((0..125).map(|_| one_wire_mock::Transaction::get(one_wire_mock::State::Low)));
+        let mut bus = one_wire_mock::Mock::new(&expected);
+        let one_wire = OneWire::new(bus.clone()).unwrap();
+        let mut relays: Relays = std::array::from_fn(|_| PinMock::new(&[Transaction::set(State::Low)]));
+        let [heat, cool, fan, valve] = relays.clone();
+        let mut controller: TestController =
+            Controller::from_parts(one_wire, NoopDelay::new(), heat, cool, fan, valve, RelayPolarity::ActiveHigh).unwrap();
+        assert!(!controller.start_temperature_conversion());
+        relays.iter_mut().for_each(PinMock::done);
+        bus.done();
+    }
+
+    #[test]
+    fn end_switch_is_closed_when_low() {
+        let open = [Transaction::set(State::Low)];
+        let (mut controller, relays, bus) = controller(RelayPolarity::ActiveHigh, [&open; 4]);
+        assert_eq!(controller.valve_end_switch(), None);
+
+        let mut switch = PinMock::new(&[Transaction::get(State::Low), Transaction::get(State::High)]);
+        let mut controller = controller.with_end_switch(switch.clone());
+        assert_eq!(controller.valve_end_switch(), Some(true));
+        assert_eq!(controller.valve_end_switch(), Some(false));
+        switch.done();
+        done(relays, bus);
     }
 }
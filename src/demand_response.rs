@@ -0,0 +1,101 @@
+// Demand response events signalled by the utility (or an aggregator) over the network.
+// While one is active the setpoint is relaxed and cooling may only run part of the time.
+// The user can always opt out of the running event from the screen.
+
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::events::ModeStatus;
+
+/// Cooling duty is limited within periods of this length
+const DUTY_PERIOD: Duration = Duration::from_secs(30 * 60);
+/// Longest event accepted, so a lost "end" signal can't hold the house hostage
+pub const MAX_EVENT_DURATION_MINS: u32 = 24 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DemandResponseSettings {
+    /// How far the setpoint is relaxed during an event (Celsius)
+    pub setpoint_offset_c: f32,
+    /// Share of each half hour cooling may run during an event (percent)
+    pub max_cooling_duty_percent: u8,
+}
+
+/// Decode a signal as sent over MQTT or HTTP: the length of the event in minutes, or `end` to
+/// end the running one
+pub fn parse_signal(payload: &str) -> anyhow::Result<Option<u32>> {
+    match payload.trim() {
+        "end" => Ok(None),
+        mins => mins.parse().map(Some).map_err(|_| anyhow::anyhow!("Invalid demand response signal: {}", mins)),
+    }
+}
+
+impl Default for DemandResponseSettings {
+    fn default() -> Self {
+        Self {
+            setpoint_offset_c: 1.5, // ~2.7°F
+            max_cooling_duty_percent: 50,
+        }
+    }
+}
+
+impl DemandResponseSettings {
+    /// Offset added to the setpoint: cooler when heating, warmer when cooling
+    pub fn setpoint_offset_c(&self, mode: &ModeStatus) -> f32 {
+        match mode {
+            ModeStatus::Heat => -self.setpoint_offset_c,
+            ModeStatus::Cool => self.setpoint_offset_c,
+            ModeStatus::Off => 0.0,
+        }
+    }
+}
+
+pub struct DemandResponseEvent {
+    until: Instant,
+    opted_out: bool,
+    period_start: Instant,
+    cooling_in_period: Duration,
+}
+
+impl DemandResponseEvent {
+    pub fn new(duration: Duration) -> Self {
+        let now = Instant::now();
+        Self {
+            until: now + duration,
+            opted_out: false,
+            period_start: now,
+            cooling_in_period: Duration::ZERO,
+        }
+    }
+
+    pub fn is_over(&self) -> bool {
+        Instant::now() >= self.until
+    }
+
+    /// Whether the event is currently being honoured
+    pub fn is_active(&self) -> bool {
+        !self.opted_out && !self.is_over()
+    }
+
+    pub fn opt_out(&mut self) {
+        self.opted_out = true;
+    }
+
+    pub fn record_cooling(&mut self, elapsed: Duration) {
+        self.roll_period();
+        self.cooling_in_period += elapsed;
+    }
+
+    /// Whether cooling still has duty left in the current period
+    pub fn cooling_allowed(&mut self, max_duty_percent: u8) -> bool {
+        self.roll_period();
+        self.cooling_in_period < DUTY_PERIOD * max_duty_percent.min(100) as u32 / 100
+    }
+
+    fn roll_period(&mut self) {
+        if self.period_start.elapsed() >= DUTY_PERIOD {
+            self.period_start = Instant::now();
+            self.cooling_in_period = Duration::ZERO;
+        }
+    }
+}
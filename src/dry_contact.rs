@@ -0,0 +1,49 @@
+// Dry contact output, for equipment that only wants a contact closure: gas fireplaces, millivolt
+// wall heaters, unit heaters with their own fan control and the like. A heat or cool call closes
+// that one relay and nothing else: the fan relay never follows calls, there is no fan lead, rest
+// or run-on, and relays the installer hasn't marked as used are never closed.
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller::Relay;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DryContact {
+    /// Heat calls close the heat relay
+    pub heat: bool,
+    /// Cool calls close the cool relay
+    pub cool: bool,
+    /// Fan mode On closes the fan relay, it's left alone during heat and cool calls
+    pub fan: bool,
+    /// The heat relay switches a millivolt gas valve (thermopile powered, no 24 VAC)
+    pub millivolt: bool,
+}
+
+impl Default for DryContact {
+    fn default() -> Self {
+        Self {
+            heat: true,
+            cool: false,
+            fan: false,
+            millivolt: false,
+        }
+    }
+}
+
+impl DryContact {
+    /// Relays that must never close. The reversing valve has no meaning here.
+    pub fn unused_relays(&self) -> Vec<Relay> {
+        let mut unused = vec![Relay::ReversingValve];
+        if !self.heat {
+            unused.push(Relay::Heat);
+        }
+        if !self.cool {
+            unused.push(Relay::Cool);
+        }
+        if !self.fan {
+            unused.push(Relay::Fan);
+        }
+        unused
+    }
+}
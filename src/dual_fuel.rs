@@ -0,0 +1,81 @@
+// Dual fuel systems: a heat pump handles heating in mild weather and a gas furnace takes
+// over when it gets too cold outside for the heat pump to be efficient. The compressor runs
+// off the cool relay (Y) and the furnace off the heat relay (W), so the relay interlock
+// already guarantees the two never run together.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+/// Which way the reversing valve output is wired
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum ReversingValve {
+    /// O terminal, energized in cooling (most brands)
+    O,
+    /// B terminal, energized in heating (Rheem, Ruud and some older systems)
+    B,
+}
+
+impl ReversingValve {
+    /// Whether the valve output should be energized when the compressor heats or cools
+    pub fn energized(self, heating: bool) -> bool {
+        match self {
+            ReversingValve::O => !heating,
+            ReversingValve::B => heating,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum HeatSource {
+    HeatPump,
+    Furnace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DualFuel {
+    /// Below this outdoor temperature the furnace heats instead of the heat pump (Celsius)
+    pub balance_point_c: f32,
+    pub reversing_valve: ReversingValve,
+    /// When the furnace is wired as the heat pump's auxiliary stage it also takes over once the
+    /// heat pump has run this long without reaching the setpoint. None when it's only used below
+    /// the balance point.
+    pub furnace_as_aux_after_mins: Option<u32>,
+}
+
+impl Default for DualFuel {
+    fn default() -> Self {
+        Self {
+            balance_point_c: 0.0, // 32°F
+            reversing_valve: ReversingValve::O,
+            furnace_as_aux_after_mins: None,
+        }
+    }
+}
+
+impl DualFuel {
+    /// Pick the heat source for the outdoor temperature and how long the heat pump has been
+    /// running in the current call. Without an outdoor temperature the furnace is used, since it
+    /// can always keep up.
+    pub fn heat_source(&self, outdoor_temp_c: Option<f32>, heat_pump_runtime: Option<Duration>) -> HeatSource {
+        let Some(outdoor_temp_c) = outdoor_temp_c else {
+            return HeatSource::Furnace;
+        };
+        if outdoor_temp_c < self.balance_point_c {
+            return HeatSource::Furnace;
+        }
+        let aux_needed = self
+            .furnace_as_aux_after_mins
+            .zip(heat_pump_runtime)
+            .is_some_and(|(mins, runtime)| runtime >= Duration::from_mins(mins as u64));
+        if aux_needed {
+            HeatSource::Furnace
+        } else {
+            HeatSource::HeatPump
+        }
+    }
+}
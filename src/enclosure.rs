@@ -0,0 +1,99 @@
+// Thermal protection for the enclosure. Thermostats end up in direct sun or above a radiator,
+// and the backlight and Wi-Fi radio add their own heat on top. The chip's internal temperature
+// sensor is read on its own thread; past `OVERHEAT_C` the backend turns the backlight and
+// Wi-Fi off and alerts, and brings them back once the chip has cooled down. The relays keep
+// running meanwhile. The die sits well above the air in the enclosure, hence the high limits.
+// The original ESP32 has no usable internal sensor, there is no protection on it.
+
+#[cfg(not(esp32))]
+pub use sensor::spawn;
+
+/// Chip temperature the backlight and Wi-Fi are turned off at (Celsius)
+pub const OVERHEAT_C: f32 = 85.0;
+/// Chip temperature they come back at (Celsius)
+const RECOVERED_C: f32 = 75.0;
+
+#[derive(Default)]
+pub struct EnclosureMonitor {
+    temp_c: Option<f32>,
+    overheated: bool,
+}
+
+impl EnclosureMonitor {
+    /// Record a reading, returning the new state when it changed
+    pub fn update(&mut self, temp_c: f32) -> Option<bool> {
+        self.temp_c = Some(temp_c);
+        let overheated = match self.overheated {
+            false => temp_c >= OVERHEAT_C,
+            true => temp_c > RECOVERED_C,
+        };
+        (overheated != self.overheated).then(|| {
+            self.overheated = overheated;
+            overheated
+        })
+    }
+
+    pub fn overheated(&self) -> bool {
+        self.overheated
+    }
+
+    /// Latest chip temperature, None before the first reading
+    pub fn temp_c(&self) -> Option<f32> {
+        self.temp_c
+    }
+}
+
+#[cfg(not(esp32))]
+mod sensor {
+    use std::ptr;
+    use std::thread;
+    use std::time::Duration;
+
+    use esp_idf_svc::sys::{
+        esp, soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT, temperature_sensor_config_t,
+        temperature_sensor_enable, temperature_sensor_get_celsius, temperature_sensor_install,
+    };
+
+    use crate::bus::EventBus;
+    use crate::events::{CommandSource, UiEvent};
+
+    /// Measuring range, the sensor is most accurate with a narrow one around the expected values
+    const RANGE_MIN_C: i32 = 20;
+    const RANGE_MAX_C: i32 = 100;
+    const POLL_INTERVAL: Duration = Duration::from_secs(30);
+    const STACK_SIZE: usize = 3072;
+
+    /// Read the internal temperature sensor on its own thread and publish the readings
+    pub fn spawn(bus: EventBus) -> anyhow::Result<()> {
+        thread::Builder::new()
+            .name("enclosure".to_string())
+            .stack_size(STACK_SIZE)
+            .spawn(move || {
+                let config = temperature_sensor_config_t {
+                    range_min: RANGE_MIN_C,
+                    range_max: RANGE_MAX_C,
+                    clk_src: soc_periph_temperature_sensor_clk_src_t_TEMPERATURE_SENSOR_CLK_SRC_DEFAULT,
+                    ..Default::default()
+                };
+                let mut handle = ptr::null_mut();
+                // SAFETY: installed once and never uninstalled, the handle stays valid for this thread
+                let installed = unsafe { esp!(temperature_sensor_install(&config, &mut handle)).and_then(|_| esp!(temperature_sensor_enable(handle))) };
+                if let Err(e) = installed {
+                    log::error!("Failed to start the chip temperature sensor, no enclosure protection: {}", e);
+                    return;
+                }
+                loop {
+                    let mut temp_c = 0.0;
+                    // SAFETY: the sensor was installed and enabled above
+                    match esp!(unsafe { temperature_sensor_get_celsius(handle, &mut temp_c) }) {
+                        Ok(()) => {
+                            bus.publish_command(CommandSource::Sensor, UiEvent::EnclosureTempUpdate(temp_c));
+                        }
+                        Err(e) => log::warn!("Failed to read the chip temperature: {}", e),
+                    }
+                    thread::sleep(POLL_INTERVAL);
+                }
+            })?;
+        Ok(())
+    }
+}
@@ -0,0 +1,279 @@
+// Remote room sensors reporting over ESP-NOW, small battery powered nodes that don't need the
+// access point. Each packet goes on the bus as `UiEvent::RemoteTempUpdate`, the same as a
+// reading from the network api, see `remote_sensors`. ESP-NOW rides on the Wi-Fi radio, so
+// nodes have to send on the channel of the access point the thermostat is connected to.
+//
+// Only paired sensors are listened to, and their packets are encrypted so a neighbour's device
+// can't feed readings into the control loop. Pairing needs a button pressed on both ends: the
+// thermostat listens for a minute after `UiEvent::PairSensor`, and a node whose pairing button
+// is pressed broadcasts a request with its name. The thermostat answers with a random local
+// master key (LMK) for that node, and from then on both ends encrypt with it. The answer itself
+// travels in the clear, so pairing is only as safe as the minute it's open; the primary master
+// key (PMK) shared by all nodes is set at build time through `ESPNOW_PMK` (32 hex digits).
+//
+// Packets, little endian:
+//   Reading, encrypted
+//     0..2      magic "TS"
+//     2         format version, 1
+//     3         name length n, 1 to 16 bytes, ignored since pairing named the node
+//     4..4+n    name, UTF-8
+//     4+n..6+n  temperature in hundredths of a degree Celsius (i16)
+//     6+n..8+n  battery voltage in millivolts (u16), 0 for mains powered nodes
+//     8+n       battery charge in percent (u8), 255 when the node only knows the voltage
+//   Pairing request, broadcast in the clear
+//     0..2      magic "TP"
+//     2         format version, 1
+//     3         name length n, 1 to 16 bytes
+//     4..4+n    name, UTF-8
+//   Pairing answer, to the node in the clear
+//     0..2      magic "TA"
+//     2         format version, 1
+//     3..19     LMK
+
+use std::ffi::c_void;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::espnow::{EspNow, PeerInfo, ReceiveInfo};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{EventBus, Message, Subscription, Topic};
+use crate::events::{BackendEvent, CommandSource};
+use crate::hex;
+use crate::remote_sensors::{Battery, PairingStatus};
+use crate::storage::Storage;
+use crate::validation;
+
+const READING_MAGIC: &[u8; 2] = b"TS";
+const PAIR_REQUEST_MAGIC: &[u8; 2] = b"TP";
+const PAIR_ANSWER_MAGIC: &[u8; 2] = b"TA";
+const VERSION: u8 = 1;
+const PERCENT_UNKNOWN: u8 = 0xFF;
+/// Used when the build doesn't set `ESPNOW_PMK`, the node firmware has to match
+const DEFAULT_PMK: &[u8; 16] = b"esp-thermostat-1";
+/// How long the thermostat listens for a pairing request
+const PAIRING_WINDOW: Duration = Duration::from_secs(60);
+/// ESP-NOW keeps at most 7 encrypted peers with the default sdkconfig
+const MAX_PAIRED: usize = 6;
+const STORAGE_KEY: &str = "espnow_peers";
+const STACK_SIZE: usize = 4096;
+
+type MacAddress = [u8; 6];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SensorPacket {
+    pub name: String,
+    pub temp_c: f32,
+    pub battery: Option<Battery>,
+}
+
+impl SensorPacket {
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (name, rest) = parse_named(data, READING_MAGIC)?;
+        let (&[t0, t1, v0, v1, percent], _) = rest.split_first_chunk::<5>()?;
+        let millivolts = u16::from_le_bytes([v0, v1]);
+        Some(Self {
+            name,
+            temp_c: i16::from_le_bytes([t0, t1]) as f32 / 100.0,
+            battery: (millivolts != 0).then(|| Battery {
+                millivolts,
+                percent: (percent != PERCENT_UNKNOWN).then_some(percent),
+            }),
+        })
+    }
+}
+
+/// Name of the node asking to pair
+pub fn parse_pair_request(data: &[u8]) -> Option<String> {
+    parse_named(data, PAIR_REQUEST_MAGIC).map(|(name, _)| name)
+}
+
+/// Splits the common header (magic, version, name) off a packet
+fn parse_named<'a>(data: &'a [u8], magic: &[u8; 2]) -> Option<(String, &'a [u8])> {
+    let (header, rest) = data.split_first_chunk::<4>()?;
+    if &header[..2] != magic || header[2] != VERSION {
+        return None;
+    }
+    let (name, rest) = rest.split_at_checked(header[3] as usize)?;
+    Some((std::str::from_utf8(name).ok()?.to_string(), rest))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairedSensor {
+    mac: MacAddress,
+    name: String,
+    /// Hex encoded
+    lmk: String,
+}
+
+impl PairedSensor {
+    fn peer_info(&self) -> anyhow::Result<PeerInfo> {
+        let lmk = hex::decode(&self.lmk)?.try_into().map_err(|_| anyhow::anyhow!("LMK must be 16 bytes"))?;
+        Ok(PeerInfo { peer_addr: self.mac, lmk, encrypt: true, ..Default::default() })
+    }
+}
+
+type Paired = Arc<Mutex<Vec<PairedSensor>>>;
+
+fn pmk() -> anyhow::Result<[u8; 16]> {
+    match option_env!("ESPNOW_PMK") {
+        Some(pmk) => hex::decode(pmk)?.try_into().map_err(|_| anyhow::anyhow!("ESPNOW_PMK must be 32 hex digits")),
+        None => Ok(*DEFAULT_PMK),
+    }
+}
+
+fn random_lmk() -> [u8; 16] {
+    let mut lmk = [0; 16];
+    // SAFETY: writes exactly the length given into the buffer, truly random with the radio up
+    unsafe { esp_idf_svc::sys::esp_fill_random(lmk.as_mut_ptr() as *mut c_void, lmk.len()) };
+    lmk
+}
+
+/// Start listening for paired sensors and pairing requests. Needs the Wi-Fi driver started,
+/// see `network::spawn`.
+pub fn spawn(bus: EventBus, nvs: EspDefaultNvsPartition) -> anyhow::Result<()> {
+    let storage = Storage::new(nvs)?;
+    let espnow = EspNow::take()?;
+    espnow.set_pmk(&pmk()?)?;
+    let paired: Paired = Arc::new(Mutex::new(storage.load(STORAGE_KEY).unwrap_or_default()));
+    for sensor in paired.lock().unwrap_or_else(PoisonError::into_inner).iter() {
+        if let Err(e) = sensor.peer_info().and_then(|peer| Ok(espnow.add_peer(peer)?)) {
+            log::error!("Failed to restore ESP-NOW sensor {}: {}", sensor.name, e);
+        }
+    }
+
+    let (requests_tx, requests_rx) = mpsc::channel();
+    let callback_paired = paired.clone();
+    let callback_bus = bus.clone();
+    // Runs in the Wi-Fi task, only parse and hand off
+    espnow.register_recv_cb(move |info: &ReceiveInfo, data: &[u8]| {
+        let mac = *info.src_addr;
+        if let Some(name) = parse_pair_request(data) {
+            let _ = requests_tx.send((mac, name));
+            return;
+        }
+        // ESP-NOW drops unencrypted frames from encrypted peers, knowing the sender is enough
+        let name = callback_paired
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .iter()
+            .find(|sensor| sensor.mac == mac)
+            .map(|sensor| sensor.name.clone());
+        let (Some(name), Some(packet)) = (name, SensorPacket::parse(data)) else {
+            log::debug!("Ignoring ESP-NOW packet from {:02x?}", mac);
+            return;
+        };
+        match validation::validate_remote_temp(name, packet.temp_c, packet.battery) {
+            Ok(event) => {
+                callback_bus.publish_command(CommandSource::Sensor, event);
+            }
+            Err(rejection) => log::warn!("Ignoring ESP-NOW sensor packet from {:02x?}: {}", mac, rejection),
+        }
+    })?;
+
+    let state_rx = bus.subscribe(&[Topic::State]);
+    thread::Builder::new()
+        .name("espnow".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || Pairing { espnow, storage, paired, bus, window_until: None }.run(state_rx, requests_rx))?;
+    Ok(())
+}
+
+struct Pairing {
+    espnow: EspNow<'static>,
+    storage: Storage,
+    paired: Paired,
+    bus: EventBus,
+    /// End of the pairing window while it's open
+    window_until: Option<Instant>,
+}
+
+impl Pairing {
+    fn run(mut self, state_rx: Subscription, requests_rx: Receiver<(MacAddress, String)>) {
+        loop {
+            match state_rx.recv_timeout(Duration::from_secs(1)) {
+                Ok(Message::State(BackendEvent::OpenSensorPairing)) => {
+                    log::info!("Listening for ESP-NOW pairing requests");
+                    self.window_until = Some(Instant::now() + PAIRING_WINDOW);
+                    self.bus.publish_state(BackendEvent::SensorPairing(PairingStatus::Listening));
+                }
+                Ok(Message::State(BackendEvent::SensorUnpaired(name))) => self.unpair(&name),
+                Ok(_) | Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => return,
+            }
+            // Requests outside the window are dropped
+            while let Ok((mac, name)) = requests_rx.try_recv() {
+                if self.window_until.is_some() {
+                    self.pair(mac, name);
+                }
+            }
+            if self.window_until.is_some_and(|until| Instant::now() >= until) {
+                self.window_until = None;
+                self.bus.publish_state(BackendEvent::SensorPairing(PairingStatus::TimedOut));
+            }
+        }
+    }
+
+    fn pair(&mut self, mac: MacAddress, name: String) {
+        let name = match validation::validate_sensor_name(name) {
+            Ok(name) => name,
+            Err(rejection) => {
+                log::warn!("Ignoring ESP-NOW pairing request from {:02x?}: {}", mac, rejection);
+                return;
+            }
+        };
+        let mut paired = self.paired.lock().unwrap_or_else(PoisonError::into_inner);
+        // Pairing again under the same name or address replaces the old pairing, e.g. a new node
+        for replaced in paired.iter().filter(|sensor| sensor.mac == mac || sensor.name == name) {
+            let _ = self.espnow.del_peer(replaced.mac);
+        }
+        paired.retain(|sensor| sensor.mac != mac && sensor.name != name);
+        if paired.len() >= MAX_PAIRED {
+            self.window_until = None;
+            self.bus.publish_state(BackendEvent::SensorPairing(PairingStatus::Full));
+            return;
+        }
+        let lmk = random_lmk();
+        let sensor = PairedSensor { mac, name: name.clone(), lmk: hex::encode(&lmk) };
+        let result = self.answer(mac, &lmk).and_then(|_| Ok(self.espnow.add_peer(sensor.peer_info()?)?));
+        if let Err(e) = result {
+            log::error!("Failed to pair ESP-NOW sensor {}: {}", name, e);
+            return;
+        }
+        paired.push(sensor);
+        if let Err(e) = self.storage.save(STORAGE_KEY, &*paired) {
+            log::error!("Failed to persist ESP-NOW sensors: {}", e);
+        }
+        log::info!("Paired ESP-NOW sensor {} at {:02x?}", name, mac);
+        self.window_until = None;
+        self.bus.publish_state(BackendEvent::SensorPairing(PairingStatus::Paired(name)));
+    }
+
+    /// Hand the node its key, through a temporary unencrypted peer
+    fn answer(&self, mac: MacAddress, lmk: &[u8; 16]) -> anyhow::Result<()> {
+        let mut answer = Vec::with_capacity(19);
+        answer.extend_from_slice(PAIR_ANSWER_MAGIC);
+        answer.push(VERSION);
+        answer.extend_from_slice(lmk);
+        self.espnow.add_peer(PeerInfo { peer_addr: mac, ..Default::default() })?;
+        let sent = self.espnow.send(mac, &answer);
+        self.espnow.del_peer(mac)?;
+        Ok(sent?)
+    }
+
+    fn unpair(&mut self, name: &str) {
+        let mut paired = self.paired.lock().unwrap_or_else(PoisonError::into_inner);
+        let Some(index) = paired.iter().position(|sensor| sensor.name == name) else {
+            return;
+        };
+        let sensor = paired.remove(index);
+        let _ = self.espnow.del_peer(sensor.mac);
+        if let Err(e) = self.storage.save(STORAGE_KEY, &*paired) {
+            log::error!("Failed to persist ESP-NOW sensors: {}", e);
+        }
+        log::info!("Unpaired ESP-NOW sensor {}", name);
+    }
+}
@@ -1,29 +1,412 @@
 
+use chrono::{NaiveDate, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::auth::{ApiCredentials, TlsMaterial};
+use crate::backend::ThermostatRuntimeState;
+use crate::burn_in::BurnInProtection;
+use crate::comfort_profile::ComfortSettings;
+use crate::dual_fuel::HeatSource;
+use crate::installer::{InstallerSettings, Secret};
+use crate::metrics::MemoryStats;
+use crate::network::NetworkSettings;
+use crate::ota::{UpdateChannel, UpdateStatus};
+use crate::overshoot::OvershootStats;
+use crate::peak::PeakPhase;
+use crate::proximity::ProximityWake;
+use crate::remote_sensors::{Battery, BleSensor, PairingStatus, RemoteSensorStatus, SensorPeriod, WeightedSensor};
+use crate::schedule::{ScheduleException, WeeklySchedule};
+use crate::self_test::SelfTestReport;
+use crate::settings::{ConfigBackup, Settings};
+use crate::stats::CycleCounts;
+use crate::summary::{ComfortMetrics, Summary};
+use crate::time_format::TimeFormat;
+use crate::timing::TimingStats;
+use crate::trend::Trend;
+use crate::vacation::Vacation;
+use crate::validation::CommandRejection;
+
+/// Where a state changing command came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandSource {
+    // Aliases read audit logs written before the names were snake_case
+    #[serde(alias = "Touch")]
+    Touch,
+    #[serde(alias = "Mqtt")]
+    Mqtt,
+    #[serde(alias = "Http")]
+    Http,
+    #[serde(alias = "Schedule")]
+    Schedule,
+    #[serde(alias = "Recovery")]
+    Recovery,
+    /// Add-on sensors on the board publishing their readings
+    Sensor,
+}
+
+/// Identifies a command, so the ack for it can be matched up
+pub type CommandId = u32;
+
+/// A state changing event tagged with its source
 #[derive(Debug, Clone)]
+pub struct Command {
+    pub id: CommandId,
+    pub source: CommandSource,
+    /// The installer session token a network client sent the command with, see `installer`
+    pub session: Option<Secret>,
+    pub event: UiEvent,
+}
+
+/// What the backend did with a command
+#[derive(Debug, Clone)]
+pub enum CommandOutcome {
+    Applied,
+    /// Applied with a different value than the one sent, e.g. a clamped or snapped setpoint
+    Adjusted(UiEvent),
+    Rejected(CommandRejection),
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum UiEvent {
     // Event from ui to backend to update the mode
     ModeUpdate(ModeStatus),
     // Event from frontend to backend to update the unit
     UseFahrenheitUpdate(bool),
-    // Event from frontend to backend to update the diff mode
-    DiffUpdate(DiffStatus),
+    // Event from frontend to backend to switch comfort profile
+    ComfortProfileUpdate(ComfortProfile),
+    // Event to backend to edit the custom comfort profile, which also selects it
+    CustomComfortUpdate(ComfortSettings),
     // Event from frontend to backend to update the rest mode
     RestUpdate(RestStatus),
     // Event from frontend to backend to update the fan mode
     FanUpdate(FanStatus),
+    // Event to backend to run the fan for a while whatever the mode, or cancel that with zero.
+    // The fan mode is left alone and takes over again once the time is up.
+    FanTimer(Duration),
     // Event from frontend to backend to update the target temp
     TargetTempUpdate(f32),
+    // Event from frontend to backend to update the temperature display precision
+    DisplayPrecisionUpdate(DisplayPrecision),
+    // Event to backend to configure pixel shift and the nightly screen wash
+    BurnInProtectionUpdate(BurnInProtection),
+    // Event from ui to backend to turn auto brightness on or off
+    AutoBrightnessUpdate(bool),
+    // Event from ui to backend to set the brightness by hand (percent), which turns auto brightness off
+    ManualBrightnessUpdate(u8),
+    // Event to backend to configure waking the screen on proximity
+    ProximityWakeUpdate(ProximityWake),
+    // Event to backend to plan (or cancel with None) an away period
+    VacationUpdate(Option<Vacation>),
+    // Event to backend to create, replace or remove (with None) a named schedule profile
+    ScheduleProfileUpdate { name: String, schedule: Option<WeeklySchedule> },
+    // Event to backend to rename a schedule profile, staying active if it was
+    RenameScheduleProfile { from: String, to: String },
+    // Event to backend to run a day of a schedule profile like another weekday, e.g. a holiday
+    // as a Sunday, or to go back to its own periods with None
+    ScheduleExceptionUpdate { name: String, date: NaiveDate, follows: Option<Weekday> },
+    // Event to backend to switch to a schedule profile by name, or to manual control with None
+    ActivateScheduleProfile(Option<String>),
+    // Event to backend to start (true) or cancel (false) a boost in the current mode
+    Boost(bool),
+    // Event to backend to start the sleep preset until the given wake time, or end it with None
+    SleepPreset(Option<NaiveTime>),
+    // Event to backend to resume heating paused by open window detection
+    OpenWindowOverride,
+    // Event to backend to control to a made up room temperature in Celsius for a while, so a
+    // demo can run through heating and cooling, or to end that with None. Installer only.
+    DemoTemperature(Option<f32>),
+    // Event from the utility to backend to start a demand response event lasting the given minutes, or end it with None
+    DemandResponseSignal(Option<u32>),
+    // Event from ui to backend to opt out of the running demand response event
+    DemandResponseOptOut,
+    // Event from an outdoor sensor or weather service to backend with the outdoor temperature in Celsius
+    OutdoorTempUpdate(f32),
+    // Event from a remote room sensor to backend with its temperature in Celsius, and its
+    // battery level unless it is mains powered
+    RemoteTempUpdate { sensor: String, temp_c: f32, battery: Option<Battery> },
+    // Event from ui to backend to listen for an ESP-NOW sensor pairing request for a minute,
+    // only taken from the touch screen
+    PairSensor,
+    // Event to backend to forget a paired ESP-NOW sensor by name
+    UnpairSensor(String),
+    // Event to backend to replace the BLE thermometers listened to
+    BleSensorsUpdate(Vec<BleSensor>),
+    // Event to backend to choose the sensors driving control outside the time of day periods,
+    // and to replace those periods unless None
+    SensorSelectionUpdate { sensors: Vec<WeightedSensor>, periods: Option<Vec<SensorPeriod>> },
+    // Event from a humidity sensor to backend with the relative humidity in percent
+    HumidityUpdate(f32),
+    // Event from an air quality sensor to backend with the CO2 level in ppm
+    Co2Update(u16),
+    // Event from the chip's internal sensor to backend with its temperature in Celsius
+    EnclosureTempUpdate(f32),
+    // Event to backend to replace all settings and schedule profiles, e.g. restoring a backup
+    ImportConfig(ConfigBackup),
+    // Event to backend asking for the settings and schedule profiles to be published as JSON
+    ExportSettingsRequest,
+    // Event to backend asking for a diagnostics export, answered with DiagnosticsExport
+    DiagnosticsRequest(Diagnostics),
+    // Event to backend to read the sensor and run the state machine now instead of on the next tick
+    ForceRefresh,
+    // Event to backend to check the update manifest for newer firmware
+    CheckForUpdates,
+    // Event to backend to install the update found by the last check
+    InstallUpdate,
+    // Event to backend to switch the release channel updates come from
+    UpdateChannelUpdate(UpdateChannel),
+    // Event to backend to start an installer session for the command's source, answered with
+    // InstallerSession
+    InstallerLogin(Secret),
+    // Event to backend to end the installer session the command came with
+    InstallerLogout,
+    // Event to backend to set or clear (with None) the installer code, installer only
+    SetInstallerCode(Option<Secret>),
+    // Event to backend to replace the installer settings, installer only
+    InstallerSettingsUpdate(InstallerSettings),
+    // Event to backend to set or clear (with None) the hex encoded secret MQTT commands are
+    // signed with, installer only
+    SetSigningSecret(Option<Secret>),
+    // Event to backend to replace the credentials the api takes, installer only
+    SetApiCredentials(ApiCredentials),
+    // Event to backend to store (or with None remove) the api's TLS certificate, served from the
+    // next restart, installer only
+    SetTlsMaterial(Option<TlsMaterial>),
+    // Event to backend to set the outdoor temperature lockouts in Celsius (None to disable), installer only
+    SeasonalLockoutUpdate { heat_above_c: Option<f32>, cool_below_c: Option<f32> },
+    // Event to backend to change the hostname and addressing, applied by reconnecting Wi-Fi
+    NetworkSettingsUpdate(NetworkSettings),
+    // Event to backend to change how times and dates are shown
+    TimeFormatUpdate(TimeFormat),
+    // Event to backend to change the time zone, a name from timezone::ZONES or a POSIX TZ string
+    TimezoneUpdate(String),
+}
+
+/// What the backend keeps for diagnostics, for export over the network api
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Diagnostics {
+    /// The audit log as JSON
+    AuditLog,
+    /// The state transition log with reasons as JSON
+    Transitions,
+    /// Memory, timing and sensor metrics in Prometheus text format
+    Metrics,
+    /// The daily min/max temperatures as JSON
+    TemperatureHistory,
+}
+
+/// What the status line says. Sent as values rather than text so the backend doesn't format a
+/// string every tick, the ui words it in the user's units when it changes.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StatusMessage {
+    NoTemperature,
+    /// Waiting out the lockout after a state ran too long
+    TimedOut,
+    SeasonalLockout,
+    /// Heating is cut off, the measured temperature is above the installer's ceiling
+    HighTempCutoff,
+    WindowOpen,
+    Waiting { target_c: f32 },
+    Heating,
+    StartingFan,
+    Dehumidifying,
+    Cooling,
+    Resting { remaining_secs: u64 },
+    Idle,
+    Off,
+}
+
+/// Structured view of the backend state, sent to the ui when it changes
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Snapshot {
+    /// Temperature of the room in Celsius (base unit) from the sensors driving control, None
+    /// while there is none
+    pub current_temp_c: Option<f32>,
+    /// Remote sensors the current temperature comes from, empty for the onboard sensor
+    pub control_sensors: Vec<String>,
+    /// Remote sensors heard from, with their last reading and how fresh it is
+    pub remote_sensors: Vec<RemoteSensorStatus>,
+    /// What the state machine is doing, for the state icons
+    pub state: ThermostatRuntimeState,
+    /// The fan relay is on, for a call, circulation or run-on
+    pub fan_running: bool,
+    /// Seconds left on the fan timer, if one is running
+    pub fan_timer_remaining_secs: Option<u64>,
+    /// Seconds left of the compressor's minimum off time, while it can't start yet
+    pub compressor_lockout_remaining_secs: Option<u64>,
+    /// Seconds left in the compressor rest, while resting
+    pub rest_remaining_secs: Option<u64>,
+    /// How far through the compressor rest (0-1), while resting
+    pub rest_progress: Option<f32>,
+    pub trend: Trend,
+    /// Rate of change of the temperature in Celsius per hour, None until there is enough history
+    pub slope_c_per_hour: Option<f32>,
+    /// Estimate of when the running heat/cool call reaches its target
+    pub setpoint_estimate: Option<SetpointEstimate>,
+    /// Inside the configured quiet hours, the display stays dimmed
+    pub quiet_hours: bool,
+    /// The nightly burn-in wash is running
+    pub screen_wash: bool,
+    /// The touch screen has installer access
+    pub installer_unlocked: bool,
+    /// The api token, for the installer screen only. Never leaves the device.
+    #[serde(skip)]
+    pub api_token: Option<Secret>,
+    /// Seconds left on the running boost, if boosting
+    pub boost_remaining_secs: Option<u64>,
+    /// When the sleep preset ends, while it's on
+    pub sleep_until: Option<NaiveTime>,
+    /// Last day of the active away period, if away
+    pub away_until: Option<NaiveDate>,
+    /// Exception days coming up in the active schedule profile
+    pub schedule_exceptions: Vec<ScheduleException>,
+    /// Heating is paused because an open window was detected
+    pub open_window: bool,
+    /// The room temperature shown and controlled to is a demo override, not a measurement
+    pub demo_temp: bool,
+    /// Preconditioning for or inside a utility peak pricing window
+    pub peak: Option<PeakPhase>,
+    /// A demand response event is being honoured
+    pub demand_response: bool,
+    /// Outdoor temperature in Celsius, if something reports it
+    pub outdoor_temp_c: Option<f32>,
+    /// Relative humidity in percent, if a humidity sensor reports it
+    pub humidity_percent: Option<f32>,
+    /// CO2 level in ppm, if an air quality sensor reports it
+    pub co2_ppm: Option<u16>,
+    /// The ERV/HRV is running to bring CO2 down
+    pub ventilating: bool,
+    /// CO2 is high but the ventilation interlocks (outdoor temperature) hold the ERV/HRV off
+    pub ventilation_held: bool,
+    /// The frost-stat relay is closed, a watched sensor is near freezing
+    pub frost_protecting: bool,
+    /// Optional sensors and integrations that are reporting, the ui shows a tile for each
+    pub capabilities: Capabilities,
+    /// The selected mode is locked out by the outdoor temperature
+    pub seasonal_lockout: bool,
+    /// Heating is cut off, the measured temperature is above the installer's ceiling
+    pub high_temp_cutoff: bool,
+    /// What is heating on a dual fuel system, while heating
+    pub heat_source: Option<HeatSource>,
+    /// How far heat calls overshoot the setpoint, to tune the heat anticipator with
+    pub heat_overshoot: OvershootStats,
+    /// Today's comfort score, cycle length and overshoot so far
+    #[serde(flatten)]
+    pub comfort: ComfortMetrics,
+    /// Running with a dimmed display after repeated brownouts
+    pub reduced_power: bool,
+    /// Brownout resets counted so far
+    pub brownouts: u32,
+    pub memory: MemoryStats,
+    /// Percentiles of the tick, sensor read and render times
+    pub timings: Vec<TimingStats>,
+    /// Connected to Wi-Fi. Everything but the network features works the same without it.
+    pub online: bool,
+    /// Address of the Wi-Fi station while online, where the web ui is reached
+    pub ip_address: Option<Ipv4Addr>,
+    /// WireGuard tunnel up, None when remote access isn't configured
+    pub remote_access: Option<bool>,
+    /// Chip temperature, None where there is no internal sensor
+    pub enclosure_temp_c: Option<f32>,
+    /// Backlight and Wi-Fi are off until the enclosure cools down
+    pub enclosure_overheated: bool,
+    /// Seconds since the clock was last synced over SNTP, None if it wasn't this boot
+    pub clock_synced_secs_ago: Option<u64>,
+    pub clock_drift_ppm: Option<f32>,
+}
+
+impl Snapshot {
+    /// The measured and target temperatures rounded the way the screen shows them, for the api
+    /// and MQTT. Diagnostics like the enclosure temperature keep their full resolution.
+    pub fn at_display_precision(mut self, use_fahrenheit: bool, precision: DisplayPrecision) -> Self {
+        let round = |temp_c: f32| precision.round_celsius(temp_c, use_fahrenheit);
+        self.current_temp_c = self.current_temp_c.map(round);
+        self.outdoor_temp_c = self.outdoor_temp_c.map(round);
+        for sensor in &mut self.remote_sensors {
+            sensor.temp_c = round(sensor.temp_c);
+        }
+        if let Some(estimate) = &mut self.setpoint_estimate {
+            estimate.target_c = round(estimate.target_c);
+        }
+        self
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct Capabilities {
+    pub humidity: bool,
+    pub outdoor_temp: bool,
+    pub air_quality: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SetpointEstimate {
+    /// Temperature the running call stops at, in Celsius
+    pub target_c: f32,
+    pub minutes: u32,
 }
 
 #[derive(Debug, Clone)]
 pub enum BackendEvent {
     // Event from backend to ui to update the current temperature (in Celsius)
     CurrentTempCUpdate(f32),
-    // Event from backend to ui to update message for current state
-    // Should be one of "Heating", "Cooling", "Resting for <duration>", "Waiting for <target temp>"
-    CurrentStateMessage(String),
+    // Event from backend to ui to update message for current state, worded by the ui
+    CurrentStateMessage(StatusMessage),
+    // Event from backend to ui with the structured state
+    Snapshot(Snapshot),
+    // Event from backend to ui to show a safety alert (e.g. relay interlock tripped)
+    Alert(String),
+    // Event from backend to ui with the audit log, one summary line per entry, oldest first
+    AuditLogUpdate(Vec<String>),
+    // Event from backend to ui with the state transition log, one summary line per entry, oldest first
+    TransitionLogUpdate(Vec<String>),
+    // Event from backend to ui with the daily min/max temperatures, one summary line per day, oldest first
+    TemperatureHistoryUpdate(Vec<String>),
+    // Event from backend to ui with the current settings, sent at boot and whenever they change
+    SettingsUpdate(Settings),
+    // Event from backend with the installer wiring check for the current settings, sent with SettingsUpdate
+    WiringCheck(Vec<String>),
+    // Event from backend to the ESP-NOW thread to listen for a pairing request, see PairSensor
+    OpenSensorPairing,
+    // Event from backend to the ESP-NOW thread to forget a paired sensor
+    SensorUnpaired(String),
+    // Event from the ESP-NOW thread with the progress of pairing a sensor
+    SensorPairing(PairingStatus),
+    // Event from backend with the settings exported as JSON, in reply to ExportSettingsRequest
+    SettingsExport(String),
+    // Event from backend with a diagnostics export, in reply to the DiagnosticsRequest with this id
+    DiagnosticsExport { id: CommandId, export: Result<String, String> },
+    // Event from backend with the names of the schedule profiles, sent at boot and whenever they change
+    ScheduleProfilesUpdate(Vec<String>),
+    // Event from backend with a finished daily or weekly summary
+    Summary(Summary),
+    // Event from backend with compressor start counts, sent whenever they change
+    CycleStatsUpdate(CycleCounts),
+    // Event from backend with the results of the boot self-test, if it ran
+    SelfTestReport(SelfTestReport),
+    // Event from backend with the progress of an update check or install
+    UpdateStatus(UpdateStatus),
+    // Event from backend to the MQTT thread once a new signing secret was stored
+    SigningSecretChanged,
+    // Event from backend to the source of an InstallerLogin with the code right, with the token
+    // network clients send their installer commands with
+    InstallerSession { id: CommandId, token: Secret },
+    // Event from backend to the api once new credentials were stored
+    ApiCredentialsChanged(ApiCredentials),
+    // Event from backend to the command's source once a state changing command was handled.
+    // Requests answered with their own event (exports, update checks, refreshes) aren't acked.
+    CommandAck {
+        id: CommandId,
+        source: CommandSource,
+        outcome: CommandOutcome,
+    },
 }
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i32)]
 pub enum ModeStatus {
     Heat = 0,
@@ -31,15 +414,18 @@ pub enum ModeStatus {
     Off = 2,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i32)]
-pub enum DiffStatus {
-    Slow,
-    Normal,
-    Fast,
+pub enum ComfortProfile {
+    Comfort,
+    Balanced,
+    Eco,
+    Custom,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i32)]
 pub enum RestStatus {
     Short,
@@ -48,13 +434,172 @@ pub enum RestStatus {
     Off,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 #[repr(i32)]
 pub enum FanStatus {
     Auto,
     On,
 }
 
+/// Resolution temperatures are shown with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum DisplayPrecision {
+    Whole,
+    Half,
+    Tenth,
+}
+
+impl DisplayPrecision {
+    /// Round to the display resolution. Halves always round away from zero so
+    /// 21.0°C (69.8°F) shows as 70°F in whole degrees.
+    pub fn round(self, value: f32) -> f32 {
+        match self {
+            DisplayPrecision::Whole => value.round(),
+            DisplayPrecision::Half => (value * 2.0).round() / 2.0,
+            DisplayPrecision::Tenth => (value * 10.0).round() / 10.0,
+        }
+    }
+
+    /// Round a temperature to the display resolution in the unit it's shown in, keeping it in
+    /// Celsius. In whole Fahrenheit degrees 21.0°C comes out as 21.11°C, which is exactly 70°F.
+    pub fn round_celsius(self, temp_c: f32, use_fahrenheit: bool) -> f32 {
+        if use_fahrenheit {
+            crate::Controller::fahrenheit_to_celsius(self.round(crate::Controller::celsius_to_fahrenheit(temp_c)))
+        } else {
+            self.round(temp_c)
+        }
+    }
+
+    /// Number of decimals to print a rounded value with
+    pub fn decimals(self) -> usize {
+        match self {
+            DisplayPrecision::Whole => 0,
+            DisplayPrecision::Half | DisplayPrecision::Tenth => 1,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DisplayPrecision::Whole => "whole",
+            DisplayPrecision::Half => "half",
+            DisplayPrecision::Tenth => "tenth",
+        }
+    }
+}
+
+
+// The string forms are the serde names, so what MQTT and HTTP send matches what is stored in NVS.
+// The i32 forms are the indices the Slint ui uses.
+
+impl ModeStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ModeStatus::Heat => "heat",
+            ModeStatus::Cool => "cool",
+            ModeStatus::Off => "off",
+        }
+    }
+}
+
+impl FromStr for ModeStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "heat" => Ok(ModeStatus::Heat),
+            "cool" => Ok(ModeStatus::Cool),
+            "off" => Ok(ModeStatus::Off),
+            _ => Err(anyhow::anyhow!("Invalid mode status: {}", value)),
+        }
+    }
+}
+
+impl ComfortProfile {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ComfortProfile::Comfort => "comfort",
+            ComfortProfile::Balanced => "balanced",
+            ComfortProfile::Eco => "eco",
+            ComfortProfile::Custom => "custom",
+        }
+    }
+}
+
+impl FromStr for ComfortProfile {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "comfort" => Ok(ComfortProfile::Comfort),
+            "balanced" => Ok(ComfortProfile::Balanced),
+            "eco" => Ok(ComfortProfile::Eco),
+            "custom" => Ok(ComfortProfile::Custom),
+            _ => Err(anyhow::anyhow!("Invalid comfort profile: {}", value)),
+        }
+    }
+}
+
+impl RestStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RestStatus::Short => "short",
+            RestStatus::Medium => "medium",
+            RestStatus::Long => "long",
+            RestStatus::Off => "off",
+        }
+    }
+}
+
+impl FromStr for RestStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "short" => Ok(RestStatus::Short),
+            "medium" => Ok(RestStatus::Medium),
+            "long" => Ok(RestStatus::Long),
+            "off" => Ok(RestStatus::Off),
+            _ => Err(anyhow::anyhow!("Invalid rest status: {}", value)),
+        }
+    }
+}
+
+impl FanStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FanStatus::Auto => "auto",
+            FanStatus::On => "on",
+        }
+    }
+}
+
+impl FromStr for FanStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "auto" => Ok(FanStatus::Auto),
+            "on" => Ok(FanStatus::On),
+            _ => Err(anyhow::anyhow!("Invalid fan status: {}", value)),
+        }
+    }
+}
+
+impl FromStr for DisplayPrecision {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "whole" => Ok(DisplayPrecision::Whole),
+            "half" => Ok(DisplayPrecision::Half),
+            "tenth" => Ok(DisplayPrecision::Tenth),
+            _ => Err(anyhow::anyhow!("Invalid display precision: {}", value)),
+        }
+    }
+}
 
 impl TryFrom<i32> for ModeStatus {
     type Error = anyhow::Error;
@@ -69,15 +614,16 @@ impl TryFrom<i32> for ModeStatus {
     }
 }
 
-impl TryFrom<i32> for DiffStatus {
+impl TryFrom<i32> for ComfortProfile {
     type Error = anyhow::Error;
 
     fn try_from(value: i32) -> Result<Self, Self::Error> {
         match value {
-            0 => Ok(DiffStatus::Slow),
-            1 => Ok(DiffStatus::Normal),
-            2 => Ok(DiffStatus::Fast),
-            _ => Err(anyhow::anyhow!("Invalid diff status: {}", value)),
+            0 => Ok(ComfortProfile::Comfort),
+            1 => Ok(ComfortProfile::Balanced),
+            2 => Ok(ComfortProfile::Eco),
+            3 => Ok(ComfortProfile::Custom),
+            _ => Err(anyhow::anyhow!("Invalid comfort profile: {}", value)),
         }
     }
 }
@@ -106,4 +652,120 @@ impl TryFrom<i32> for FanStatus {
             _ => Err(anyhow::anyhow!("Invalid fan status: {}", value)),
         }
     }
-}
\ No newline at end of file
+}
+
+impl TryFrom<i32> for DisplayPrecision {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DisplayPrecision::Whole),
+            1 => Ok(DisplayPrecision::Half),
+            2 => Ok(DisplayPrecision::Tenth),
+            _ => Err(anyhow::anyhow!("Invalid display precision: {}", value)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fmt::Debug;
+
+    /// Lists every variant as `ALL`. The match stops compiling when a variant is added without
+    /// adding it here, so the round trips below can't miss one.
+    macro_rules! all_variants {
+        ($enum:ident { $($variant:ident),+ $(,)? }) => {
+            impl $enum {
+                const ALL: &'static [$enum] = &[$($enum::$variant),+];
+            }
+            const _: fn($enum) = |value| match value {
+                $($enum::$variant => {})+
+            };
+        };
+    }
+
+    all_variants!(ModeStatus { Heat, Cool, Off });
+    all_variants!(ComfortProfile { Comfort, Balanced, Eco, Custom });
+    all_variants!(RestStatus { Short, Medium, Long, Off });
+    all_variants!(FanStatus { Auto, On });
+    all_variants!(DisplayPrecision { Whole, Half, Tenth });
+    all_variants!(ThermostatRuntimeState { Waiting, Heating, FanLead, Cooling, Resting, Idle, Off });
+
+    /// Every variant parses back from its string form, which is also its serde name
+    fn assert_round_trips<T>(variants: &[T], as_str: fn(T) -> &'static str)
+    where
+        T: Copy + Debug + PartialEq + FromStr + Serialize,
+        T::Err: Debug,
+    {
+        for &variant in variants {
+            let name = as_str(variant);
+            assert_eq!(name.parse::<T>().unwrap(), variant);
+            assert_eq!(serde_json::to_string(&variant).unwrap(), format!("\"{}\"", name));
+        }
+    }
+
+    /// Every variant comes back from its Slint index, and the indices past the ends don't
+    fn assert_indices_round_trip<T>(variants: &[T], index: fn(T) -> i32)
+    where
+        T: Copy + Debug + PartialEq + TryFrom<i32>,
+        T::Error: Debug,
+    {
+        for &variant in variants {
+            assert_eq!(T::try_from(index(variant)).unwrap(), variant);
+        }
+        assert!(T::try_from(-1).is_err());
+        assert!(T::try_from(variants.len() as i32).is_err());
+    }
+
+    #[test]
+    fn mode_status_round_trips() {
+        assert_round_trips(ModeStatus::ALL, ModeStatus::as_str);
+        assert_indices_round_trip(ModeStatus::ALL, |mode| mode as i32);
+    }
+
+    #[test]
+    fn comfort_profile_round_trips() {
+        assert_round_trips(ComfortProfile::ALL, ComfortProfile::as_str);
+        assert_indices_round_trip(ComfortProfile::ALL, |profile| profile as i32);
+    }
+
+    #[test]
+    fn rest_status_round_trips() {
+        assert_round_trips(RestStatus::ALL, RestStatus::as_str);
+        assert_indices_round_trip(RestStatus::ALL, |rest| rest as i32);
+    }
+
+    #[test]
+    fn fan_status_round_trips() {
+        assert_round_trips(FanStatus::ALL, FanStatus::as_str);
+        assert_indices_round_trip(FanStatus::ALL, |fan| fan as i32);
+    }
+
+    #[test]
+    fn display_precision_round_trips() {
+        assert_round_trips(DisplayPrecision::ALL, DisplayPrecision::as_str);
+        assert_indices_round_trip(DisplayPrecision::ALL, |precision| precision as i32);
+    }
+
+    #[test]
+    fn temperatures_round_to_the_display_precision_in_the_display_unit() {
+        // 21.0°C is 69.8°F, shown as 70°F in whole degrees
+        let rounded = DisplayPrecision::Whole.round_celsius(21.0, true);
+        assert!((crate::Controller::celsius_to_fahrenheit(rounded) - 70.0).abs() < 1e-4);
+        assert_eq!(DisplayPrecision::Half.round_celsius(21.3, false), 21.5);
+        assert_eq!(DisplayPrecision::Tenth.round_celsius(21.04, false), 21.0);
+    }
+
+    #[test]
+    fn runtime_state_round_trips() {
+        assert_round_trips(ThermostatRuntimeState::ALL, ThermostatRuntimeState::as_str);
+    }
+
+    #[test]
+    fn unknown_names_are_rejected() {
+        assert!("Heat".parse::<ModeStatus>().is_err());
+        assert!("".parse::<FanStatus>().is_err());
+        assert!("fanlead".parse::<ThermostatRuntimeState>().is_err());
+    }
+}
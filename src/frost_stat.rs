@@ -0,0 +1,43 @@
+// Frost-stat output for unheated spaces: crawlspaces, garages, pump houses. An auxiliary relay
+// closes when any of the watched sensors drops below the frost threshold, whatever the mode and
+// the state machine are doing, to drive pipe trace heating or an alarm. It opens again once every
+// one of them is back above the threshold plus the hysteresis.
+
+use serde::{Deserialize, Serialize};
+
+use crate::remote_sensors::SensorRef;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct FrostStat {
+    /// Sensors watched, any one of them being cold enough closes the output
+    pub sensors: Vec<SensorRef>,
+    /// The output closes below this (Celsius)
+    pub threshold_c: f32,
+    /// And opens again once every sensor is this far above the threshold (Celsius)
+    pub hysteresis_c: f32,
+}
+
+impl Default for FrostStat {
+    fn default() -> Self {
+        Self {
+            sensors: vec![SensorRef::Onboard],
+            threshold_c: 4.0,
+            hysteresis_c: 1.0,
+        }
+    }
+}
+
+impl FrostStat {
+    /// Whether the output should be closed, given the readings of the watched sensors (None for
+    /// one that isn't reporting) and whether it's closed now. Sensors that aren't reporting are
+    /// left out, and with none of them reporting the output stays as it is.
+    pub fn should_close(&self, readings: &[Option<f32>], closed: bool) -> bool {
+        let mut reporting = readings.iter().flatten().peekable();
+        if reporting.peek().is_none() {
+            return closed;
+        }
+        let release_c = self.threshold_c + self.hysteresis_c;
+        reporting.any(|&temp_c| temp_c < self.threshold_c || (closed && temp_c < release_c))
+    }
+}
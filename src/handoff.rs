@@ -0,0 +1,73 @@
+// Runtime context handed over across a planned reboot, the weekly maintenance one. Written
+// right before restarting and taken (read and removed) at the next boot, it brings back what
+// the periodic checkpoints only cover roughly or not at all: the runtime state, the compressor
+// protection timers and the statistics. Timers are stored as time left or time elapsed, with
+// the downtime accounted for when the clock tells it. Only written between calls, so the relays
+// are off across the reboot and the state restored is Waiting, Idle or Off.
+//
+// An unplanned reboot leaves no handoff and falls back to the cooling checkpoint. A handoff
+// that is somehow older than `MAX_AGE` is ignored, too much may have happened since.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ThermostatRuntimeState;
+use crate::clock;
+use crate::storage::Storage;
+use crate::summary::Summary;
+
+const STORAGE_KEY: &str = "handoff";
+const MAX_AGE: Duration = Duration::from_mins(10);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Handoff {
+    pub runtime_state: ThermostatRuntimeState,
+    pub total_cooling_secs: u64,
+    pub total_heating_secs: u64,
+    /// Time since the compressor last stopped, which counts as rest
+    pub rest_elapsed_secs: u64,
+    /// Time left on the lockout after a state timeout, if one was running
+    pub state_timeout_lockout_secs: Option<u64>,
+    pub fan_timer_secs: Option<u64>,
+    /// Time left on the open window heating pause, if paused
+    pub open_window_secs: Option<u64>,
+    /// Time left of the compressor's minimum off time, if it stopped only just before
+    #[serde(default)]
+    pub compressor_lockout_secs: Option<u64>,
+    /// How long ago each compressor start of the last day was
+    pub compressor_start_ages_secs: Vec<u64>,
+    pub compressor_starts_total: u32,
+    pub today: Summary,
+    pub week: Summary,
+    /// Unix seconds when the handoff was written, 0 if the clock wasn't set
+    pub saved_at: u64,
+}
+
+impl Handoff {
+    pub fn save(&mut self, storage: &mut Storage) -> anyhow::Result<()> {
+        self.saved_at = if clock::is_set() { clock::unix_secs() } else { 0 };
+        storage.save(STORAGE_KEY, self)
+    }
+
+    /// Read and remove the handoff left by the last boot, if it planned the reboot
+    pub fn take(storage: &mut Storage) -> Option<Self> {
+        let handoff: Self = storage.load(STORAGE_KEY)?;
+        if let Err(e) = storage.remove(STORAGE_KEY) {
+            log::error!("Failed to remove the reboot handoff: {}", e);
+        }
+        if handoff.downtime().is_some_and(|downtime| downtime > MAX_AGE) {
+            log::warn!("Ignoring a reboot handoff from {}s ago", handoff.downtime().unwrap_or_default().as_secs());
+            return None;
+        }
+        Some(handoff)
+    }
+
+    /// How long the reboot took, if the clock was set on both sides of it
+    pub fn downtime(&self) -> Option<Duration> {
+        if self.saved_at == 0 || !clock::is_set() {
+            return None;
+        }
+        Some(Duration::from_secs(clock::unix_secs().saturating_sub(self.saved_at)))
+    }
+}
@@ -0,0 +1,32 @@
+// Heartbeat output for external watchdog relays. The pin toggles once per control loop tick
+// while the loop is healthy. If the firmware locks up or keeps faulting the square wave stops
+// and the watchdog hardware drops the HVAC calls on its own.
+
+use esp_idf_svc::hal::gpio::{AnyOutputPin, Output, Pin, PinDriver};
+use esp_idf_svc::sys::EspError;
+
+pub struct Heartbeat {
+    /// Heartbeat output to the external watchdog, see `bsp::board::heartbeat_pin`
+    pin: PinDriver<'static, AnyOutputPin, Output>,
+}
+
+impl Heartbeat {
+    pub fn new(pin: AnyOutputPin) -> Result<Self, EspError> {
+        let gpio = pin.pin();
+        let mut pin = PinDriver::output(pin)?;
+        pin.set_low()?;
+        log::info!("Heartbeat output on GPIO{}", gpio);
+        Ok(Self { pin })
+    }
+
+    /// Call once per control loop tick. Holding the pin steady is what tells the watchdog
+    /// something is wrong, so an unhealthy tick just leaves it where it is.
+    pub fn tick(&mut self, healthy: bool) {
+        if !healthy {
+            return;
+        }
+        if let Err(e) = self.pin.toggle() {
+            log::error!("Failed to toggle heartbeat output: {}", e);
+        }
+    }
+}
@@ -0,0 +1,15 @@
+// Hex encoding for keys, hashes and signatures that travel as JSON strings.
+
+pub fn decode(hex: &str) -> anyhow::Result<Vec<u8>> {
+    if !hex.is_ascii() || hex.len() % 2 != 0 {
+        anyhow::bail!("invalid hex");
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| anyhow::anyhow!("invalid hex")))
+        .collect()
+}
+
+pub fn encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
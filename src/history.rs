@@ -0,0 +1,131 @@
+// Daily minimum and maximum temperature for the last month, persisted so trend dashboards
+// keep their history across reboots. Only recorded once the clock is set, before that there
+// is no day to file a reading under.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::storage::Storage;
+use crate::time_format::TimeFormat;
+
+const STORAGE_KEY: &str = "temp_history";
+/// Days kept before the oldest ones are dropped
+pub const HISTORY_DAYS: usize = 30;
+/// New extremes are written at most this often to spare the flash, day changes always are
+const SAVE_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct DailyExtremes {
+    pub date: NaiveDate,
+    /// Celsius
+    pub min_c: f32,
+    /// Unix seconds of the minimum reading
+    pub min_at: u64,
+    /// Celsius
+    pub max_c: f32,
+    /// Unix seconds of the maximum reading
+    pub max_at: u64,
+}
+
+impl DailyExtremes {
+    fn new(date: NaiveDate, temp_c: f32, now: u64) -> Self {
+        Self {
+            date,
+            min_c: temp_c,
+            min_at: now,
+            max_c: temp_c,
+            max_at: now,
+        }
+    }
+
+    /// Single line summary, used by the diagnostics screen
+    pub fn summary(&self, format: &TimeFormat) -> String {
+        let time_of_day = |unix_secs| format.time(clock::to_local(unix_secs).time());
+        format!(
+            "{} low {:.1}°C at {}, high {:.1}°C at {}",
+            format.day(self.date),
+            self.min_c,
+            time_of_day(self.min_at),
+            self.max_c,
+            time_of_day(self.max_at)
+        )
+    }
+}
+
+pub struct TemperatureHistory {
+    /// Oldest day first, the last entry is today
+    days: VecDeque<DailyExtremes>,
+    /// Changed since last persisted
+    dirty: bool,
+    last_saved: Instant,
+}
+
+impl TemperatureHistory {
+    pub fn load(storage: &Storage) -> Self {
+        Self {
+            days: storage.load(STORAGE_KEY).unwrap_or_default(),
+            dirty: false,
+            last_saved: Instant::now(),
+        }
+    }
+
+    /// Record a reading, returns true when it set a new extreme or started a new day
+    pub fn record(&mut self, temp_c: f32, storage: &mut Storage) -> bool {
+        if !clock::is_set() {
+            return false;
+        }
+        let now = clock::unix_secs();
+        let date = clock::local_now().date();
+        let changed = match self.days.back_mut() {
+            Some(today) if today.date == date => {
+                if temp_c < today.min_c {
+                    today.min_c = temp_c;
+                    today.min_at = now;
+                    true
+                } else if temp_c > today.max_c {
+                    today.max_c = temp_c;
+                    today.max_at = now;
+                    true
+                } else {
+                    false
+                }
+            }
+            _ => {
+                if self.days.len() == HISTORY_DAYS {
+                    self.days.pop_front();
+                }
+                self.days.push_back(DailyExtremes::new(date, temp_c, now));
+                // Don't lose the finished day to a reboot
+                self.save(storage);
+                return true;
+            }
+        };
+        self.dirty |= changed;
+        if self.dirty && self.last_saved.elapsed() >= SAVE_INTERVAL {
+            self.save(storage);
+        }
+        changed
+    }
+
+    fn save(&mut self, storage: &mut Storage) {
+        if let Err(e) = storage.save(STORAGE_KEY, &self.days) {
+            log::error!("Failed to persist temperature history: {}", e);
+        }
+        self.dirty = false;
+        self.last_saved = Instant::now();
+    }
+
+    /// Oldest day first
+    pub fn days(&self) -> impl Iterator<Item = &DailyExtremes> {
+        self.days.iter()
+    }
+
+    /// Export the history as JSON, oldest day first
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&self.days)?)
+    }
+}
@@ -0,0 +1,52 @@
+// Hydronic (boiler) zones. The heat relay opens the zone valve and the fan relay runs the
+// circulator pump. The pump only starts once the valve is open, so it never pushes against a
+// closed valve: when the valve has an end switch wired to the end switch input the pump waits
+// for it, otherwise it waits for the valve's opening time. After the call the pump keeps
+// running for a while to carry the heat left in the boiler out to the zone.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Hydronic {
+    /// The zone valve's end switch is wired to the end switch input (`end-switch` feature)
+    pub end_switch: bool,
+    /// How long the valve takes to open, the pump waits this long without an end switch (seconds)
+    pub valve_open_secs: u32,
+    /// How long the pump keeps running after the valve closes (seconds, 0 to disable)
+    pub pump_overrun_secs: u32,
+}
+
+impl Default for Hydronic {
+    fn default() -> Self {
+        Self {
+            end_switch: false,
+            // Thermal actuators take a couple of minutes, motorized valves far less
+            valve_open_secs: 120,
+            pump_overrun_secs: 180,
+        }
+    }
+}
+
+impl Hydronic {
+    /// Whether the pump may run, the valve having been told to open `since_opened` ago.
+    /// `end_switch_closed` is the end switch input, None when it can't be read.
+    pub fn pump_may_run(&self, since_opened: Duration, end_switch_closed: Option<bool>) -> bool {
+        if self.end_switch {
+            end_switch_closed == Some(true)
+        } else {
+            since_opened >= self.valve_open_time()
+        }
+    }
+
+    pub fn valve_open_time(&self) -> Duration {
+        Duration::from_secs(self.valve_open_secs as u64)
+    }
+
+    /// The end switch should have closed well before this, something is stuck or miswired
+    pub fn end_switch_overdue(&self, since_opened: Duration) -> bool {
+        self.end_switch && since_opened >= self.valve_open_time() * 2
+    }
+}
@@ -0,0 +1,265 @@
+// Installer vs homeowner access. Homeowner settings (setpoints, schedules, units, comfort) can
+// be changed by anyone at the screen or with api credentials. Installer settings describe how
+// the equipment is wired and protected (staging, lockouts, timing) and getting them wrong can
+// damage it, so changing them needs a separate installer code. The touch screen has a session of
+// its own. A network client gets a random token at login and sends it along with its commands
+// (the api's `X-Installer-Session` header), so one client logging in doesn't unlock the others.
+// Sessions time out. Until an installer code is set, everyone is an installer, so a fresh device
+// can be set up.
+//
+// Pin mapping is fixed in the firmware for now; it'll belong here too.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::air_quality::VentilationSettings;
+use crate::controller::RelayPolarity;
+use crate::dual_fuel::DualFuel;
+use crate::events::{CommandSource, UiEvent};
+use crate::frost_stat::FrostStat;
+use crate::hex;
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::system_profile::SystemProfile;
+
+const STORAGE_KEY: &str = "installer_code";
+/// An installer session ends after this long without being renewed by a login
+const SESSION_DURATION: Duration = Duration::from_secs(15 * 60);
+/// Wrong codes in a row before logins from that source are refused for a while
+const MAX_FAILED_LOGINS: u32 = 5;
+const FAILED_LOGIN_LOCKOUT: Duration = Duration::from_secs(5 * 60);
+/// Random bytes in a session token
+const SESSION_TOKEN_BYTES: usize = 16;
+/// Installer codes are entered on the keypad
+pub const MIN_CODE_LEN: usize = 4;
+pub const MAX_CODE_LEN: usize = 8;
+
+/// A code entered by a user. Never shows up in logs or the audit log.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct Secret(pub String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+/// Settings only an installer can change
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct InstallerSettings {
+    pub heat_lockout_above_c: Option<f32>,
+    pub cool_lockout_below_c: Option<f32>,
+    pub high_temp_cutoff_c: Option<f32>,
+    pub system: SystemProfile,
+    pub dual_fuel: Option<DualFuel>,
+    pub ventilation: Option<VentilationSettings>,
+    pub frost_stat: Option<FrostStat>,
+    pub max_compressor_starts_per_hour: u32,
+    pub cool_fan_lead_secs: u32,
+    pub control_loop_interval_ms: u32,
+    pub sensor_poll_interval_secs: u32,
+    pub ota_health_check_mins: u32,
+    pub boot_self_test: bool,
+    #[serde(default)]
+    pub relay_polarity: RelayPolarity,
+}
+
+impl InstallerSettings {
+    pub fn from_settings(settings: &Settings) -> Self {
+        Self {
+            heat_lockout_above_c: settings.heat_lockout_above_c,
+            cool_lockout_below_c: settings.cool_lockout_below_c,
+            high_temp_cutoff_c: settings.high_temp_cutoff_c,
+            system: settings.system,
+            dual_fuel: settings.dual_fuel,
+            ventilation: settings.ventilation,
+            frost_stat: settings.frost_stat.clone(),
+            max_compressor_starts_per_hour: settings.max_compressor_starts_per_hour,
+            cool_fan_lead_secs: settings.cool_fan_lead_secs,
+            control_loop_interval_ms: settings.control_loop_interval_ms,
+            sensor_poll_interval_secs: settings.sensor_poll_interval_secs,
+            ota_health_check_mins: settings.ota_health_check_mins,
+            boot_self_test: settings.boot_self_test,
+            relay_polarity: settings.relay_polarity,
+        }
+    }
+
+    pub fn apply_to(self, settings: &mut Settings) {
+        settings.heat_lockout_above_c = self.heat_lockout_above_c;
+        settings.cool_lockout_below_c = self.cool_lockout_below_c;
+        settings.high_temp_cutoff_c = self.high_temp_cutoff_c;
+        settings.system = self.system;
+        settings.dual_fuel = self.dual_fuel;
+        settings.ventilation = self.ventilation;
+        settings.frost_stat = self.frost_stat;
+        settings.max_compressor_starts_per_hour = self.max_compressor_starts_per_hour;
+        settings.cool_fan_lead_secs = self.cool_fan_lead_secs;
+        settings.control_loop_interval_ms = self.control_loop_interval_ms;
+        settings.sensor_poll_interval_secs = self.sensor_poll_interval_secs;
+        settings.ota_health_check_mins = self.ota_health_check_mins;
+        settings.boot_self_test = self.boot_self_test;
+        settings.relay_polarity = self.relay_polarity;
+    }
+}
+
+/// Commands that only an installer may send. Imports are allowed for homeowners but keep the
+/// installer settings as they are (see `InstallerSettings::apply_to`).
+pub fn requires_installer(event: &UiEvent) -> bool {
+    matches!(
+        event,
+        UiEvent::InstallerSettingsUpdate(_)
+            | UiEvent::SeasonalLockoutUpdate { .. }
+            | UiEvent::SetInstallerCode(_)
+            | UiEvent::SetSigningSecret(_)
+            | UiEvent::SetApiCredentials(_)
+            | UiEvent::SetTlsMaterial(_)
+            | UiEvent::DemoTemperature(Some(_))
+    )
+}
+
+/// Salted hash of the installer code, as stored
+#[derive(Serialize, Deserialize)]
+struct StoredCode {
+    salt: String,
+    hash: String,
+}
+
+impl StoredCode {
+    fn new(code: &str) -> Self {
+        // SAFETY: plain getter with no preconditions, random once the radio is up and pseudo random before
+        let salt: Vec<u8> = (0..4).flat_map(|_| unsafe { esp_idf_svc::sys::esp_random() }.to_le_bytes()).collect();
+        Self {
+            hash: hash(&salt, code),
+            salt: hex::encode(&salt),
+        }
+    }
+
+    fn matches(&self, code: &str) -> bool {
+        let Ok(salt) = hex::decode(&self.salt) else {
+            return false;
+        };
+        let given = hash(&salt, code);
+        // Compare without returning early so the time taken doesn't leak how much matched
+        given.len() == self.hash.len() && given.bytes().zip(self.hash.bytes()).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+    }
+}
+
+fn hash(salt: &[u8], code: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(code.as_bytes());
+    hex::encode(&hasher.finalize())
+}
+
+/// Whose session a command runs in
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum SessionKey {
+    Touch,
+    /// A network client, by the token it got at login
+    Token(String),
+}
+
+impl SessionKey {
+    /// Touch commands are in the screen's session, others only in the one their token is for
+    fn of(source: CommandSource, session: Option<&Secret>) -> Option<Self> {
+        match (source, session) {
+            (CommandSource::Touch, _) => Some(Self::Touch),
+            (_, Some(token)) => Some(Self::Token(token.0.clone())),
+            (_, None) => None,
+        }
+    }
+}
+
+#[derive(Default)]
+struct FailedLogins {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+pub struct InstallerAccess {
+    code: Option<StoredCode>,
+    /// When each session ends
+    sessions: HashMap<SessionKey, Instant>,
+    /// By source, a client guessing codes can't be told apart from the others before it logged in
+    failed: HashMap<CommandSource, FailedLogins>,
+}
+
+impl InstallerAccess {
+    pub fn load(storage: &Storage) -> Self {
+        Self {
+            code: storage.load(STORAGE_KEY),
+            sessions: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    /// Whether commands from `source`, with the session token they came with, have installer
+    /// access right now
+    pub fn is_installer(&self, source: CommandSource, session: Option<&Secret>) -> bool {
+        self.code.is_none()
+            || SessionKey::of(source, session)
+                .and_then(|key| self.sessions.get(&key))
+                .is_some_and(|until| Instant::now() < *until)
+    }
+
+    /// Start an installer session for `source`. Returns the token for it if the code was right,
+    /// which network clients send along with their commands and the touch screen doesn't need.
+    pub fn login(&mut self, source: CommandSource, code: &Secret) -> Option<Secret> {
+        let failed = self.failed.entry(source).or_default();
+        if failed.locked_until.is_some_and(|until| Instant::now() < until) {
+            log::warn!("Installer login from {:?} refused, too many wrong codes", source);
+            return None;
+        }
+        if self.code.as_ref().is_some_and(|stored| !stored.matches(&code.0)) {
+            failed.count += 1;
+            log::warn!("Wrong installer code from {:?}", source);
+            if failed.count >= MAX_FAILED_LOGINS {
+                failed.count = 0;
+                failed.locked_until = Some(Instant::now() + FAILED_LOGIN_LOCKOUT);
+            }
+            return None;
+        }
+        *failed = FailedLogins::default();
+        let now = Instant::now();
+        self.sessions.retain(|_, until| now < *until);
+        let token = new_session_token();
+        log::info!("Installer session started for {:?}", source);
+        if let Some(key) = SessionKey::of(source, Some(&token)) {
+            self.sessions.insert(key, now + SESSION_DURATION);
+        }
+        Some(token)
+    }
+
+    pub fn logout(&mut self, source: CommandSource, session: Option<&Secret>) {
+        if let Some(key) = SessionKey::of(source, session) {
+            self.sessions.remove(&key);
+        }
+    }
+
+    /// Set or clear the installer code. Ends every session since the old code no longer applies.
+    pub fn set_code(&mut self, storage: &mut Storage, code: Option<&Secret>) -> anyhow::Result<()> {
+        match code {
+            Some(code) => {
+                let stored = StoredCode::new(&code.0);
+                storage.save(STORAGE_KEY, &stored)?;
+                self.code = Some(stored);
+            }
+            None => {
+                storage.remove(STORAGE_KEY)?;
+                self.code = None;
+            }
+        }
+        self.sessions.clear();
+        Ok(())
+    }
+}
+
+fn new_session_token() -> Secret {
+    // SAFETY: plain getter with no preconditions, random once the radio is up and pseudo random before
+    let bytes: Vec<u8> = (0..SESSION_TOKEN_BYTES / 4).flat_map(|_| unsafe { esp_idf_svc::sys::esp_random() }.to_le_bytes()).collect();
+    Secret(hex::encode(&bytes))
+}
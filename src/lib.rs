@@ -1,6 +1,94 @@
+//! Thermostat firmware for the ESP32-S3 touch display board, usable as a library for other
+//! ESP HVAC projects.
+//!
+//! The pieces most variants build on:
+//! - [`backend`]: the control state machine, [`ThermostatState`], run once per tick
+//! - [`controller`]: relay outputs and the DS18B20, [`Controller`]
+//! - [`sensors`]: drivers for the optional sensors on the shared I2C bus
+//! - [`events`] and [`bus`]: the commands and state updates everything else talks through
+//!
+//! A headless controller or a different display only needs to publish [`events::UiEvent`]s
+//! on the [`EventBus`] and subscribe to the [`events::BackendEvent`]s it cares about, the
+//! binary in this crate is one such consumer. Optional hardware is behind cargo features:
+//! `rtc`, `heartbeat`, `buzzer`, `mmwave`, `espnow` and `ble-sensors`. `no-ui` builds a headless relay box without the display.
 #![feature(duration_constructors_lite)]
 pub mod events;
+pub mod bus;
+#[cfg(not(feature = "no-ui"))]
 pub mod ui;
 pub mod backend;
 pub mod controller;
-pub mod bsp;
\ No newline at end of file
+pub mod sensors;
+pub mod bsp;
+pub mod storage;
+pub mod audit;
+pub mod validation;
+pub mod arbitration;
+pub mod auth;
+pub mod api;
+pub mod installer;
+pub mod hex;
+pub mod signing;
+pub mod settings;
+pub mod summary;
+pub mod webhook;
+pub mod notify;
+pub mod comfort;
+pub mod comfort_profile;
+pub mod stats;
+pub mod history;
+pub mod trend;
+pub mod clock;
+pub mod timezone;
+pub mod time_format;
+pub mod burn_in;
+pub mod air_quality;
+pub mod remote_sensors;
+pub mod ambient_light;
+pub mod proximity;
+pub mod vacation;
+pub mod boost;
+pub mod sleep;
+pub mod schedule;
+pub mod open_window;
+pub mod frost_stat;
+pub mod peak;
+pub mod demand_response;
+pub mod dual_fuel;
+pub mod hydronic;
+pub mod dry_contact;
+pub mod system_profile;
+pub mod wiring;
+pub mod checkpoint;
+pub mod handoff;
+pub mod overshoot;
+pub mod transitions;
+pub mod trace;
+pub mod power;
+pub mod enclosure;
+pub mod metrics;
+pub mod memory;
+pub mod maintenance;
+pub mod timing;
+pub mod log_tail;
+pub mod network;
+pub mod mqtt;
+pub mod wireguard;
+#[cfg(feature = "espnow")]
+pub mod espnow_sensors;
+#[cfg(feature = "ble-sensors")]
+pub mod ble_sensors;
+#[cfg(feature = "rtc")]
+pub mod rtc;
+pub mod ota;
+pub mod self_test;
+#[cfg(feature = "heartbeat")]
+pub mod heartbeat;
+#[cfg(feature = "chaos")]
+pub mod chaos;
+#[cfg(all(feature = "buzzer", not(feature = "no-ui")))]
+pub mod buzzer;
+
+pub use backend::ThermostatState;
+pub use bus::EventBus;
+pub use controller::Controller;
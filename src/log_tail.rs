@@ -0,0 +1,213 @@
+// The last few hundred log lines kept in RAM, so a thermostat in the field can be debugged from
+// its own debug screen or over the network api instead of a laptop on the serial port.
+// Everything still goes to the ESP-IDF logger as before, this only keeps a copy.
+//
+// Lines are kept as fixed size records in one `LargeBuffer`, so the whole tail sits in PSRAM
+// instead of hundreds of small strings in internal RAM.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+use esp_idf_svc::log::EspLogger;
+use log::{Level, Log, Metadata, Record};
+use serde::Serialize;
+
+use crate::memory::LargeBuffer;
+
+/// Number of lines kept before the oldest ones are dropped
+pub const LOG_TAIL_CAPACITY: usize = 300;
+/// Longer messages are cut, so the buffer has a bounded size
+const MAX_MESSAGE_LEN: usize = 160;
+const MAX_TARGET_LEN: usize = 32;
+
+static ESP_LOGGER: EspLogger = EspLogger::new();
+/// None until `init`, or if there was no memory for it
+static TAIL: Mutex<Option<Ring>> = Mutex::new(None);
+/// Bumped on every line, so readers can tell whether anything changed
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Milliseconds since boot, the wall clock may not be set yet
+    pub uptime_ms: u64,
+    #[serde(serialize_with = "serialize_level")]
+    pub level: Level,
+    pub target: String,
+    pub message: String,
+}
+
+fn serialize_level<S: serde::Serializer>(level: &Level, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(level.as_str())
+}
+
+impl LogLine {
+    /// Single line summary, used by the debug screen
+    pub fn summary(&self) -> String {
+        let secs = self.uptime_ms / 1000;
+        format!("{:>3}:{:02}:{:02} {:<5} {}", secs / 3600, secs / 60 % 60, secs % 60, self.level, self.message)
+    }
+}
+
+/// A line as stored in the ring
+#[derive(Clone, Copy)]
+struct StoredLine {
+    uptime_secs: u32,
+    uptime_millis: u16,
+    level: u8,
+    target_len: u8,
+    message_len: u8,
+    target: [u8; MAX_TARGET_LEN],
+    message: [u8; MAX_MESSAGE_LEN],
+}
+
+const EMPTY_LINE: StoredLine = StoredLine {
+    uptime_secs: 0,
+    uptime_millis: 0,
+    level: 0,
+    target_len: 0,
+    message_len: 0,
+    target: [0; MAX_TARGET_LEN],
+    message: [0; MAX_MESSAGE_LEN],
+};
+
+impl StoredLine {
+    fn new(record: &Record) -> Self {
+        let mut line = EMPTY_LINE;
+        let uptime = crate::metrics::uptime();
+        line.uptime_secs = uptime.as_secs() as u32;
+        line.uptime_millis = uptime.subsec_millis() as u16;
+        line.level = record.level() as u8;
+        line.target_len = copy_truncated(record.target(), &mut line.target);
+        line.message_len = copy_truncated(&record.args().to_string(), &mut line.message);
+        line
+    }
+
+    fn to_log_line(self) -> LogLine {
+        let text = |bytes: &[u8]| String::from_utf8_lossy(bytes).into_owned();
+        LogLine {
+            uptime_ms: self.uptime_secs as u64 * 1000 + self.uptime_millis as u64,
+            level: level_from_u8(self.level),
+            target: text(&self.target[..self.target_len as usize]),
+            message: text(&self.message[..self.message_len as usize]),
+        }
+    }
+}
+
+/// Copy as much of `text` as fits, cut on a character boundary. Returns the length copied.
+fn copy_truncated(text: &str, into: &mut [u8]) -> u8 {
+    let mut end = text.len().min(into.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    into[..end].copy_from_slice(&text.as_bytes()[..end]);
+    end as u8
+}
+
+fn level_from_u8(level: u8) -> Level {
+    match level {
+        1 => Level::Error,
+        2 => Level::Warn,
+        3 => Level::Info,
+        4 => Level::Debug,
+        _ => Level::Trace,
+    }
+}
+
+/// Oldest line at `start`, overwritten once full
+struct Ring {
+    lines: LargeBuffer<StoredLine>,
+    start: usize,
+    len: usize,
+}
+
+impl Ring {
+    fn new() -> Option<Self> {
+        Some(Self {
+            lines: LargeBuffer::new(LOG_TAIL_CAPACITY, EMPTY_LINE)?,
+            start: 0,
+            len: 0,
+        })
+    }
+
+    fn push(&mut self, line: StoredLine) {
+        let capacity = self.lines.len();
+        if self.len < capacity {
+            self.lines[(self.start + self.len) % capacity] = line;
+            self.len += 1;
+        } else {
+            self.lines[self.start] = line;
+            self.start = (self.start + 1) % capacity;
+        }
+    }
+
+    /// Oldest first
+    fn iter(&self) -> impl Iterator<Item = &StoredLine> {
+        (0..self.len).map(|i| &self.lines[(self.start + i) % self.lines.len()])
+    }
+}
+
+struct TailLogger;
+
+impl Log for TailLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        ESP_LOGGER.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        ESP_LOGGER.log(record);
+        let line = StoredLine::new(record);
+        if let Some(tail) = TAIL.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+            tail.push(line);
+            GENERATION.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn flush(&self) {
+        ESP_LOGGER.flush();
+    }
+}
+
+static LOGGER: TailLogger = TailLogger;
+
+/// Install the logger, in place of `EspLogger::initialize_default`
+pub fn init() {
+    // Allocated before logging starts, nothing may log while the tail is locked
+    let ring = Ring::new();
+    *TAIL.lock().unwrap_or_else(PoisonError::into_inner) = ring;
+    if log::set_logger(&LOGGER).is_ok() {
+        ESP_LOGGER.initialize();
+    }
+}
+
+/// Changes whenever a line is logged
+pub fn generation() -> u64 {
+    GENERATION.load(Ordering::Relaxed)
+}
+
+/// Buffered lines at `min_level` or more severe, oldest first
+pub fn lines(min_level: Level) -> Vec<LogLine> {
+    TAIL.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .as_ref()
+        .map(|tail| {
+            tail.iter()
+                .filter(|line| level_from_u8(line.level) <= min_level)
+                .map(|line| line.to_log_line())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// Buffered lines as JSON, for the `/logs` endpoint of the network api. `level` is an
+/// optional filter such as "warn", everything is returned without one.
+pub fn to_json(level: Option<&str>) -> anyhow::Result<String> {
+    let min_level = match level {
+        Some(level) => Level::from_str(level).map_err(|_| anyhow::anyhow!("Unknown log level {:?}", level))?,
+        None => Level::Trace,
+    };
+    Ok(serde_json::to_string(&lines(min_level))?)
+}
@@ -1,39 +1,60 @@
-use esp_idf_svc::hal::gpio::{Gpio2, Gpio3, Gpio4, Gpio21, Pin};
-use esp_idf_svc::hal::i2c::I2cDriver;
-use esp_idf_svc::sys::{self as idf_sys, gpio_set_level};
+use esp_idf_svc::hal::modem::Modem;
 use esp_idf_svc::{
     eventloop::EspSystemEventLoop,
-    hal::{
-        prelude::*,
-        task::{block_on, thread::ThreadSpawnConfiguration},
-    },
+    hal::task::thread::ThreadSpawnConfiguration,
     nvs::EspDefaultNvsPartition,
-    timer::EspTaskTimerService,
 };
-use esp_thermostat::backend::ThermostatState;
-use esp_thermostat::controller::Controller;
-use esp_thermostat::events::{BackendEvent, UiEvent};
+use esp_thermostat::air_quality::{self, Scd40};
+use esp_thermostat::bsp;
+#[cfg(feature = "rtc")]
+use esp_thermostat::rtc::{self, Ds3231};
+use esp_thermostat::self_test;
+use esp_thermostat::settings::Settings;
+use esp_thermostat::storage::Storage;
+#[cfg(not(feature = "no-ui"))]
 use esp_thermostat::ui::window::Window;
-use std::ffi::CString;
-use std::{
-    sync::mpsc::{self, Receiver, Sender, SyncSender},
-    sync::Arc,
-    thread,
-};
+use esp_thermostat::{Controller, EventBus, ThermostatState};
+use std::sync::{Arc, Mutex};
+#[cfg(not(feature = "no-ui"))]
+use std::thread;
 
 fn main() -> anyhow::Result<()> {
     esp_idf_svc::sys::link_patches();
-    esp_idf_svc::log::EspLogger::initialize_default();
+    // Logs to the console as usual, and keeps the tail for the debug screen
+    esp_thermostat::log_tail::init();
     log::info!("Booting up...");
+    esp_thermostat::metrics::watch_alloc_failures();
 
     
 
-    let touch_i2c = setup_display()?;
+    #[cfg(not(feature = "no-ui"))]
+    let touch_i2c = Arc::new(Mutex::new(bsp::setup_display()?));
+    // Headless there is no touch controller to reset, only add-ons on the bus
+    #[cfg(feature = "no-ui")]
+    let touch_i2c = Arc::new(Mutex::new(bsp::setup_i2c()?));
+
+    // Set the clock before anything looks at it so schedules are right from the first tick
+    #[cfg(feature = "rtc")]
+    if let Some(rtc) = Ds3231::probe(touch_i2c.clone()) {
+        rtc.restore_clock();
+        if let Err(e) = rtc::spawn(rtc) {
+            log::error!("Failed to start RTC sync: {}", e);
+        }
+    }
 
-    // UI Updates Channel is used to send events to the UI thread.
-    let (ui_updates_tx, ui_updates_rx): (Sender<UiEvent>, Receiver<UiEvent>) = mpsc::channel();
-    // Actor would take action on events typically from the UI thread. (e.g. when a button is pressed)
-    let (actor_tx, actor_rx): (Sender<BackendEvent>, Receiver<BackendEvent>) = mpsc::channel();
+    // Every thread shares the same bus: the UI publishes commands and subscribes to state,
+    // the backend does the opposite.
+    let bus = EventBus::new();
+    if let Some(scd40) = Scd40::probe(touch_i2c.clone()) {
+        if let Err(e) = air_quality::spawn(scd40, bus.clone()) {
+            log::error!("Failed to start air quality polling: {}", e);
+        }
+    }
+    #[cfg(not(esp32))]
+    if let Err(e) = esp_thermostat::enclosure::spawn(bus.clone()) {
+        log::error!("Failed to start enclosure temperature monitoring: {}", e);
+    }
+    let self_test_i2c = touch_i2c.clone();
     
     // Need more stack space since we use stack based allocator
     ThreadSpawnConfiguration {
@@ -46,56 +67,67 @@ fn main() -> anyhow::Result<()> {
         log::error!("Failed to set thread spawn configuration: {}", e);
     }
 
-    let window_thread = thread::spawn(move || {
-        Window::init(
-            touch_i2c,
-            actor_rx,
-            ui_updates_tx,
-        ).unwrap();
-    });
+    #[cfg(not(feature = "no-ui"))]
+    let window_thread = {
+        let window_bus = bus.clone();
+        thread::spawn(move || {
+            Window::init(
+                touch_i2c,
+                window_bus,
+            ).unwrap();
+        })
+    };
 
+    let nvs = EspDefaultNvsPartition::take()?;
+    let mut storage = Storage::new(nvs.clone())?;
+    let settings = Settings::load(&storage);
     // SAFETY: We only create these once, after peripherals are consumed by setup_display
-    let gpio21 = unsafe { Gpio21::new() };  // Temperature sensor
-    let gpio2 = unsafe { Gpio2::new() };    // Heat relay
-    let gpio3 = unsafe { Gpio3::new() };    // Cool relay
-    let gpio4 = unsafe { Gpio4::new() };    // Fan relay
-    let mut controller = Controller::new(gpio21, gpio2, gpio3, gpio4)?;
-    let mut thermostat_state = ThermostatState::new(ui_updates_rx, actor_tx);
+    let mut controller = unsafe { Controller::on_board_pins(settings.relay_polarity) }?;
+    // Runs on its own thread, the thermostat works the same offline if this fails
+    // SAFETY: the modem isn't used anywhere else
+    if let Err(e) = esp_thermostat::network::spawn(unsafe { Modem::new() }, EspSystemEventLoop::take()?, nvs.clone()) {
+        log::error!("Failed to start Wi-Fi, running offline: {}", e);
+    }
+    if let Err(e) = esp_thermostat::mqtt::spawn(bus.clone(), nvs.clone(), settings.network.hostname.clone()) {
+        log::error!("Failed to start MQTT: {}", e);
+    }
+    // Kept for as long as the firmware runs, dropping it stops the server
+    let _api = match esp_thermostat::api::spawn(bus.clone(), &mut storage) {
+        Ok(server) => Some(server),
+        Err(e) => {
+            log::error!("Failed to start the network api: {}", e);
+            None
+        }
+    };
+    #[cfg(feature = "espnow")]
+    if let Err(e) = esp_thermostat::espnow_sensors::spawn(bus.clone(), nvs) {
+        log::error!("Failed to start ESP-NOW sensors: {}", e);
+    }
+    #[cfg(feature = "ble-sensors")]
+    if let Err(e) = esp_thermostat::ble_sensors::spawn(bus.clone()) {
+        log::error!("Failed to start BLE thermometer scan: {}", e);
+    }
+    let self_test = settings
+        .boot_self_test
+        .then(|| self_test::run(&self_test_i2c, &mut controller, &mut storage));
+    let mut thermostat_state = ThermostatState::new(bus, storage);
+    if let Some(report) = self_test {
+        thermostat_state.set_self_test_report(report);
+    }
+    #[cfg(feature = "replay")]
+    thermostat_state.start_replay(include_str!(env!("REPLAY_TRACE")))?;
+    #[cfg(feature = "heartbeat")]
+    let mut heartbeat = esp_thermostat::heartbeat::Heartbeat::new(unsafe { bsp::board::heartbeat_pin() })?;
     loop {
-        // 1 second interval between backend runs to not burn CPU
-        std::thread::sleep(std::time::Duration::from_secs(1));
+        // Configurable interval between backend runs to not burn CPU, cut short by a ForceRefresh
+        thermostat_state.wait_for_next_tick();
         thermostat_state.run(&mut controller);
+        #[cfg(feature = "heartbeat")]
+        heartbeat.tick(thermostat_state.is_healthy());
     }
 
+    #[cfg(not(feature = "no-ui"))]
     let _ = window_thread.join().unwrap();
 
     Ok(())
 }
-
-/// Sets up the touch display and returns the I2cDriver for it.
-fn setup_display() -> Result<I2cDriver<'static>, anyhow::Error> {
-    let peripherals = Peripherals::take()?;
-
-    let mut touch_i2c = esp_idf_svc::hal::i2c::I2cDriver::new(
-        peripherals.i2c0,
-        peripherals.pins.gpio8,
-        peripherals.pins.gpio9,
-        &esp_idf_svc::hal::i2c::config::Config::new().baudrate(400_000.Hz()),
-    )?;
-    
-    // Reset touch screen before using it
-    // DO NOT REMOVE THIS.
-    let _ = touch_i2c.write(0x24, &[0x1], 1000);
-    let mut exio_value = [0xC];
-    let _ = touch_i2c.write(0x38, &exio_value, 1000);
-    std::thread::sleep(std::time::Duration::from_millis(100));
-    unsafe {
-        gpio_set_level(peripherals.pins.gpio4.pin(), 0);
-    }
-    std::thread::sleep(std::time::Duration::from_millis(100));
-    exio_value[0] = 0xE;
-    let _ = touch_i2c.write(0x38, &exio_value, 1000);
-    // Not sute why this is needed, probably to give the touch screen time to initialize
-    std::thread::sleep(std::time::Duration::from_millis(200));
-    Ok(touch_i2c)
-}
\ No newline at end of file
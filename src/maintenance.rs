@@ -0,0 +1,40 @@
+// Optional weekly maintenance reboot. Weeks of uptime fragment the heap until a large
+// allocation (a TLS handshake, an OTA buffer) fails even with plenty free in total; rebooting
+// in a quiet hour of the week starts over with a clean heap. Only done once the thermostat
+// has been up for a day, so a unit that just rebooted inside the window doesn't loop, and only
+// between calls.
+
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// How long after the configured time the reboot may still happen, if a call was running then
+const WINDOW: Duration = Duration::from_hours(1);
+/// Uptime needed before rebooting again
+const MIN_UPTIME: Duration = Duration::from_hours(24);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct MaintenanceReboot {
+    pub weekday: Weekday,
+    /// Local time the reboot window opens
+    pub time: NaiveTime,
+}
+
+impl Default for MaintenanceReboot {
+    /// Sunday 3am
+    fn default() -> Self {
+        Self { weekday: Weekday::Sun, time: NaiveTime::from_hms_opt(3, 0, 0).unwrap_or_default() }
+    }
+}
+
+impl MaintenanceReboot {
+    /// Whether `now` (local time) is inside the window and the last boot is long enough ago
+    pub fn due(&self, now: NaiveDateTime, uptime: Duration) -> bool {
+        let Ok(window) = chrono::Duration::from_std(WINDOW) else {
+            return false;
+        };
+        let start = now.date().and_time(self.time);
+        now.weekday() == self.weekday && start <= now && now < start + window && uptime >= MIN_UPTIME
+    }
+}
@@ -0,0 +1,92 @@
+// Where big allocations go. Internal RAM is what Wi-Fi, DMA and the stacks need, so anything
+// large and long-lived (frame buffers, history rings) goes to PSRAM when the board has it and
+// only falls back to internal RAM when it doesn't.
+//
+// ESP-IDF already sends general allocations above `CONFIG_SPIRAM_MALLOC_ALWAYSINTERNAL` to
+// PSRAM. This is for buffers made of many small records that would otherwise stay internal.
+
+use std::ptr::NonNull;
+
+use esp_idf_svc::sys::{heap_caps_free, heap_caps_get_free_size, heap_caps_get_total_size, heap_caps_malloc, MALLOC_CAP_8BIT, MALLOC_CAP_INTERNAL, MALLOC_CAP_SPIRAM};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Psram,
+    Internal,
+}
+
+/// Whether the board has PSRAM and ESP-IDF brought it up
+pub fn psram_available() -> bool {
+    // SAFETY: plain getter with no preconditions
+    unsafe { heap_caps_get_total_size(MALLOC_CAP_SPIRAM) > 0 }
+}
+
+/// Free internal RAM, in bytes
+pub fn free_internal_bytes() -> usize {
+    // SAFETY: plain getter with no preconditions
+    unsafe { heap_caps_get_free_size(MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT) }
+}
+
+/// Free PSRAM, in bytes. Zero without PSRAM.
+pub fn free_psram_bytes() -> usize {
+    // SAFETY: plain getter with no preconditions
+    unsafe { heap_caps_get_free_size(MALLOC_CAP_SPIRAM) }
+}
+
+/// Fixed size buffer of plain records, in PSRAM when there is some
+pub struct LargeBuffer<T: Copy> {
+    ptr: NonNull<T>,
+    len: usize,
+    region: Region,
+}
+
+// SAFETY: the buffer owns its allocation like a Box<[T]> does
+unsafe impl<T: Copy + Send> Send for LargeBuffer<T> {}
+unsafe impl<T: Copy + Sync> Sync for LargeBuffer<T> {}
+
+impl<T: Copy> LargeBuffer<T> {
+    /// `len` copies of `value`, None when neither region has room
+    pub fn new(len: usize, value: T) -> Option<Self> {
+        const { assert!(std::mem::align_of::<T>() <= 4, "heap_caps_malloc only guarantees 4 byte alignment") };
+        let size = len.checked_mul(std::mem::size_of::<T>())?.max(1);
+        let (ptr, region) = [(MALLOC_CAP_SPIRAM, Region::Psram), (MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT, Region::Internal)]
+            .into_iter()
+            // SAFETY: plain allocation, checked for null. Alignment is checked above.
+            .find_map(|(caps, region)| NonNull::new(unsafe { heap_caps_malloc(size, caps) }.cast::<T>()).map(|ptr| (ptr, region)))?;
+        for i in 0..len {
+            // SAFETY: in bounds of the allocation, and T is Copy so nothing needs dropping
+            unsafe { ptr.as_ptr().add(i).write(value) };
+        }
+        if region == Region::Internal {
+            log::warn!("No PSRAM for a {} byte buffer, using internal RAM", size);
+        }
+        Some(Self { ptr, len, region })
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+}
+
+impl<T: Copy> std::ops::Deref for LargeBuffer<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        // SAFETY: initialized in `new` and owned by self
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> std::ops::DerefMut for LargeBuffer<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        // SAFETY: initialized in `new` and owned by self
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T: Copy> Drop for LargeBuffer<T> {
+    fn drop(&mut self) {
+        // SAFETY: allocated with heap_caps_malloc in `new`
+        unsafe { heap_caps_free(self.ptr.as_ptr().cast()) };
+    }
+}
@@ -0,0 +1,156 @@
+// Memory diagnostics: free heap, the lowest it has ever been, and how close each thread came
+// to overflowing its stack. Threads report their own stack high-water mark since FreeRTOS only
+// cheaply gives it for the calling task.
+//
+// Fragmentation shows as the largest free block shrinking while the free total holds, so that
+// and the lowest it has been are tracked too, with the allocations that failed outright.
+
+use std::collections::BTreeMap;
+use std::ffi::c_char;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::sys::{
+    esp_get_free_heap_size, esp_get_minimum_free_heap_size, esp_timer_get_time, heap_caps_get_largest_free_block,
+    heap_caps_register_failed_alloc_callback, uxTaskGetStackHighWaterMark, MALLOC_CAP_8BIT, MALLOC_CAP_INTERNAL,
+};
+use serde::Serialize;
+
+use crate::memory;
+
+/// How often the backend collects the numbers
+pub const COLLECT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// Lowest free stack (bytes) each named thread has had, filled in by the threads themselves
+static STACK_WATERMARKS: Mutex<BTreeMap<&'static str, u32>> = Mutex::new(BTreeMap::new());
+
+/// Allocations that failed since boot
+static ALLOC_FAILURES: AtomicU32 = AtomicU32::new(0);
+
+unsafe extern "C" fn alloc_failed(_size: usize, _caps: u32, _function_name: *const c_char) {
+    // Runs inside the failed allocation, nothing here may allocate (that includes logging)
+    ALLOC_FAILURES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Count failed allocations from now on, called once at boot
+pub fn watch_alloc_failures() {
+    // SAFETY: the callback only touches an atomic
+    if let Err(e) = esp_idf_svc::sys::esp!(unsafe { heap_caps_register_failed_alloc_callback(Some(alloc_failed)) }) {
+        log::warn!("Failed to watch for allocation failures: {}", e);
+    }
+}
+
+/// Time since boot
+pub fn uptime() -> Duration {
+    // SAFETY: plain getter with no preconditions
+    Duration::from_micros(unsafe { esp_timer_get_time() }.max(0) as u64)
+}
+
+/// Record the stack high-water mark of the calling thread under `name`.
+/// Cheap enough to call every tick.
+pub fn record_stack_watermark(name: &'static str) {
+    // SAFETY: a null handle means the calling task
+    let free_bytes = unsafe { uxTaskGetStackHighWaterMark(std::ptr::null_mut()) };
+    if let Ok(mut watermarks) = STACK_WATERMARKS.lock() {
+        watermarks.insert(name, free_bytes);
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MemoryStats {
+    pub free_heap_bytes: u32,
+    /// Lowest free heap since boot
+    pub min_free_heap_bytes: u32,
+    /// Lowest free stack per thread, in bytes
+    pub stack_free_bytes: Vec<(String, u32)>,
+    /// Free internal RAM, what Wi-Fi and DMA need, in bytes
+    pub free_internal_bytes: usize,
+    /// Free PSRAM in bytes, zero on boards without it
+    pub free_psram_bytes: usize,
+    /// Largest block of internal RAM that can still be allocated in one piece, in bytes
+    pub largest_free_block_bytes: usize,
+    /// Lowest `largest_free_block_bytes` since boot
+    pub min_largest_free_block_bytes: usize,
+    /// Allocations that failed since boot
+    pub alloc_failures: u32,
+}
+
+impl MemoryStats {
+    pub fn collect() -> Self {
+        // SAFETY: plain getters with no preconditions
+        let (free_heap_bytes, min_free_heap_bytes) = unsafe { (esp_get_free_heap_size(), esp_get_minimum_free_heap_size()) };
+        let stack_free_bytes = STACK_WATERMARKS
+            .lock()
+            .map(|watermarks| watermarks.iter().map(|(name, bytes)| (name.to_string(), *bytes)).collect())
+            .unwrap_or_default();
+        Self {
+            free_heap_bytes,
+            min_free_heap_bytes,
+            stack_free_bytes,
+            free_internal_bytes: memory::free_internal_bytes(),
+            free_psram_bytes: memory::free_psram_bytes(),
+            // SAFETY: plain getter with no preconditions
+            largest_free_block_bytes: unsafe { heap_caps_get_largest_free_block(MALLOC_CAP_INTERNAL | MALLOC_CAP_8BIT) },
+            min_largest_free_block_bytes: 0,
+            alloc_failures: ALLOC_FAILURES.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Prometheus text format, for `ThermostatState::metrics_text`
+    pub fn to_prometheus(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "thermostat_uptime_seconds {}", uptime().as_secs());
+        let _ = writeln!(out, "thermostat_free_heap_bytes {}", self.free_heap_bytes);
+        let _ = writeln!(out, "thermostat_min_free_heap_bytes {}", self.min_free_heap_bytes);
+        let _ = writeln!(out, "thermostat_free_internal_bytes {}", self.free_internal_bytes);
+        let _ = writeln!(out, "thermostat_free_psram_bytes {}", self.free_psram_bytes);
+        let _ = writeln!(out, "thermostat_largest_free_block_bytes {}", self.largest_free_block_bytes);
+        let _ = writeln!(out, "thermostat_min_largest_free_block_bytes {}", self.min_largest_free_block_bytes);
+        let _ = writeln!(out, "thermostat_alloc_failures_total {}", self.alloc_failures);
+        for (thread, bytes) in &self.stack_free_bytes {
+            let _ = writeln!(out, "thermostat_stack_free_bytes{{thread=\"{}\"}} {}", thread, bytes);
+        }
+        out
+    }
+}
+
+/// Collects the stats every `COLLECT_INTERVAL`
+pub struct MemoryMonitor {
+    stats: MemoryStats,
+    last_collected: Option<Instant>,
+}
+
+impl Default for MemoryMonitor {
+    fn default() -> Self {
+        Self {
+            stats: MemoryStats::default(),
+            last_collected: None,
+        }
+    }
+}
+
+impl MemoryMonitor {
+    pub fn update(&mut self) {
+        if self.last_collected.is_some_and(|at| at.elapsed() < COLLECT_INTERVAL) {
+            return;
+        }
+        self.last_collected = Some(Instant::now());
+        let mut stats = MemoryStats::collect();
+        // Zero until the first collection
+        stats.min_largest_free_block_bytes = match self.stats.min_largest_free_block_bytes {
+            0 => stats.largest_free_block_bytes,
+            min => min.min(stats.largest_free_block_bytes),
+        };
+        if stats.alloc_failures > self.stats.alloc_failures {
+            log::warn!("{} allocations failed so far", stats.alloc_failures);
+        }
+        self.stats = stats;
+        log::debug!("Memory: {:?}", self.stats);
+    }
+
+    pub fn stats(&self) -> &MemoryStats {
+        &self.stats
+    }
+}
@@ -0,0 +1,312 @@
+// MQTT client. Commands come in on `<hostname>/<setting>/set` topics, e.g. `thermostat/mode/set`
+// with `heat`, using the same names as the settings JSON. The hostname is read at boot. A backup
+// goes to `<hostname>/config/set` to restore it, and anything sent to `<hostname>/export/set`
+// has the current one published on `<hostname>/config`. An outdoor sensor or weather integration
+// sends the outdoor temperature in Celsius to `<hostname>/outdoor_temp_c/set`, which the seasonal
+// lockouts, dual fuel and the ventilation interlock go by.
+//
+// The daily and weekly summaries are published retained on `<hostname>/summary/day` and
+// `<hostname>/summary/week` as they finish, and the time to reach the setpoint on
+// `<hostname>/estimate` whenever it changes, `null` while there is none. The peak pricing phase
+// goes retained to `<hostname>/peak` as it changes: `"precondition"` while pre-heating or
+// pre-cooling ahead of a window, `"peak"` inside one, `null` otherwise. The compressor start
+// counts go retained to `<hostname>/cycles` whenever they change, and today's comfort score,
+// time in the comfort band, cycle length and overshoot to `<hostname>/comfort`. Remote sensors
+// that report their battery have it published retained on `<hostname>/sensors/<name>/battery`,
+// for battery dashboards. Temperatures are in Celsius, rounded to the display precision in the
+// unit the screen shows.
+//
+// Every command payload goes through `signing::CommandVerifier` first, so with a shared secret
+// set only signed commands get through. The broker is stored on its own like the Wi-Fi
+// credentials, so it doesn't end up in exported settings.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+use embedded_svc::mqtt::client::{Details, EventPayload, QoS};
+use esp_idf_svc::mqtt::client::{EspMqttClient, MqttClientConfiguration};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use serde::{Deserialize, Serialize};
+
+use crate::bus::{EventBus, Message, Subscription, Topic};
+use crate::demand_response;
+use crate::events::{BackendEvent, CommandSource, DisplayPrecision, UiEvent};
+use crate::remote_sensors::Battery;
+use crate::settings::ConfigBackup;
+use crate::signing::CommandVerifier;
+use crate::summary::SummaryPeriod;
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "mqtt";
+const STACK_SIZE: usize = 8192;
+/// How often the client thread checks whether it has to subscribe again
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+/// Largest message sent or taken whole, a config backup has to fit
+const BUFFER_SIZE: usize = 16 * 1024;
+
+/// Set on every (re)connect, the broker may have forgotten the subscription
+static SUBSCRIBE_PENDING: AtomicBool = AtomicBool::new(false);
+/// Set when the broker asked for an export, so exports asked for elsewhere don't get published
+static EXPORT_PENDING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MqttConfig {
+    /// `mqtt://host:1883`, or `mqtts://host:8883` for TLS
+    pub url: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+impl MqttConfig {
+    /// Stored config, or the broker baked in at build time through `MQTT_URL`/`MQTT_USER`/`MQTT_PASS`
+    pub fn load(storage: &Storage) -> Option<Self> {
+        storage.load(STORAGE_KEY).or_else(|| {
+            option_env!("MQTT_URL").map(|url| Self {
+                url: url.to_string(),
+                username: option_env!("MQTT_USER").map(str::to_string),
+                password: option_env!("MQTT_PASS").map(str::to_string),
+            })
+        })
+    }
+
+    pub fn save(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save(STORAGE_KEY, self)
+    }
+}
+
+/// Connect to the configured broker, topics start with `hostname`. Without a broker configured
+/// this does nothing. The client reconnects by itself whenever the network comes back.
+pub fn spawn(bus: EventBus, nvs: EspDefaultNvsPartition, hostname: String) -> anyhow::Result<()> {
+    let storage = Storage::new(nvs)?;
+    let Some(config) = MqttConfig::load(&storage) else {
+        log::info!("No MQTT broker configured");
+        return Ok(());
+    };
+    let verifier = CommandVerifier::load(storage);
+    if !verifier.is_required() {
+        log::warn!("No MQTT signing secret, commands from the broker are taken unsigned");
+    }
+    // Also reloaded by the client thread when an installer sets a new secret
+    let verifier = Arc::new(Mutex::new(verifier));
+    let callback_verifier = verifier.clone();
+    let prefix = format!("{}/", hostname);
+    let client = EspMqttClient::new_cb(
+        &config.url,
+        &MqttClientConfiguration {
+            client_id: Some(&hostname),
+            username: config.username.as_deref(),
+            password: config.password.as_deref(),
+            crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+            buffer_size: BUFFER_SIZE,
+            out_buffer_size: BUFFER_SIZE,
+            ..Default::default()
+        },
+        move |event| match event.payload() {
+            EventPayload::Connected(_) => {
+                log::info!("MQTT connected");
+                SUBSCRIBE_PENDING.store(true, Ordering::Relaxed);
+            }
+            EventPayload::Disconnected => log::warn!("MQTT disconnected"),
+            EventPayload::Received { topic: Some(topic), data, details: Details::Complete, .. } => {
+                if let Some(setting) = topic.strip_prefix(&prefix).and_then(|topic| topic.strip_suffix("/set")) {
+                    let mut verifier = callback_verifier.lock().unwrap_or_else(PoisonError::into_inner);
+                    received(&bus, &mut verifier, topic, setting, data);
+                }
+            }
+            EventPayload::Error(e) => log::error!("MQTT error: {:?}", e),
+            _ => {}
+        },
+    )?;
+    let state_rx = bus.subscribe(&[Topic::State]);
+    thread::Builder::new()
+        .name("mqtt".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || run(client, state_rx, verifier, hostname))?;
+    Ok(())
+}
+
+/// Keeps the client alive and subscribed, and publishes what the backend has for the broker
+fn run(mut client: EspMqttClient<'static>, state_rx: Subscription, verifier: Arc<Mutex<CommandVerifier>>, hostname: String) {
+    let commands = format!("{}/+/set", hostname);
+    // Temperatures go out rounded like the screen shows them
+    let mut units = (false, DisplayPrecision::Tenth);
+    let mut published_estimate = None;
+    let mut published_peak = None;
+    let mut published_comfort = None;
+    let mut published_batteries: BTreeMap<String, Battery> = BTreeMap::new();
+    loop {
+        if SUBSCRIBE_PENDING.swap(false, Ordering::Relaxed) {
+            if let Err(e) = client.subscribe(&commands, QoS::AtLeastOnce) {
+                log::error!("Failed to subscribe to {}: {}", commands, e);
+                SUBSCRIBE_PENDING.store(true, Ordering::Relaxed);
+            }
+        }
+        if let Ok(Message::State(event)) = state_rx.recv_timeout(POLL_INTERVAL) {
+            match event {
+                BackendEvent::SigningSecretChanged => {
+                    log::info!("MQTT signing secret changed");
+                    verifier.lock().unwrap_or_else(PoisonError::into_inner).reload();
+                }
+                BackendEvent::SettingsExport(json) if EXPORT_PENDING.swap(false, Ordering::Relaxed) => {
+                    publish(&mut client, &format!("{}/config", hostname), false, json.as_bytes());
+                }
+                BackendEvent::SettingsUpdate(settings) => units = (settings.use_fahrenheit, settings.display_precision),
+                BackendEvent::Snapshot(snapshot) => {
+                    let snapshot = snapshot.at_display_precision(units.0, units.1);
+                    if published_estimate != Some(snapshot.setpoint_estimate) {
+                        match serde_json::to_string(&snapshot.setpoint_estimate) {
+                            Ok(json) => publish(&mut client, &format!("{}/estimate", hostname), true, json.as_bytes()),
+                            Err(e) => log::error!("Failed to serialize setpoint estimate: {}", e),
+                        }
+                        published_estimate = Some(snapshot.setpoint_estimate);
+                    }
+                    if published_peak != Some(snapshot.peak) {
+                        match serde_json::to_string(&snapshot.peak) {
+                            Ok(json) => publish(&mut client, &format!("{}/peak", hostname), true, json.as_bytes()),
+                            Err(e) => log::error!("Failed to serialize peak phase: {}", e),
+                        }
+                        published_peak = Some(snapshot.peak);
+                    }
+                    if published_comfort != Some(snapshot.comfort) {
+                        match serde_json::to_string(&snapshot.comfort) {
+                            Ok(json) => publish(&mut client, &format!("{}/comfort", hostname), true, json.as_bytes()),
+                            Err(e) => log::error!("Failed to serialize comfort metrics: {}", e),
+                        }
+                        published_comfort = Some(snapshot.comfort);
+                    }
+                    for sensor in &snapshot.remote_sensors {
+                        let Some(battery) = sensor.battery else {
+                            continue;
+                        };
+                        if published_batteries.get(&sensor.name) == Some(&battery) {
+                            continue;
+                        }
+                        match serde_json::to_string(&battery) {
+                            Ok(json) => publish(&mut client, &format!("{}/sensors/{}/battery", hostname, topic_level(&sensor.name)), true, json.as_bytes()),
+                            Err(e) => log::error!("Failed to serialize battery: {}", e),
+                        }
+                        published_batteries.insert(sensor.name.clone(), battery);
+                    }
+                }
+                BackendEvent::CycleStatsUpdate(counts) => match serde_json::to_string(&counts) {
+                    Ok(json) => publish(&mut client, &format!("{}/cycles", hostname), true, json.as_bytes()),
+                    Err(e) => log::error!("Failed to serialize cycle counts: {}", e),
+                },
+                BackendEvent::Summary(summary) => {
+                    let period = match summary.period {
+                        SummaryPeriod::Day => "day",
+                        SummaryPeriod::Week => "week",
+                    };
+                    match serde_json::to_string(&summary.at_display_precision(units.0, units.1)) {
+                        Ok(json) => publish(&mut client, &format!("{}/summary/{}", hostname, period), true, json.as_bytes()),
+                        Err(e) => log::error!("Failed to serialize summary: {}", e),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn publish(client: &mut EspMqttClient<'static>, topic: &str, retain: bool, payload: &[u8]) {
+    if let Err(e) = client.publish(topic, QoS::AtLeastOnce, retain, payload) {
+        log::error!("Failed to publish {}: {}", topic, e);
+    }
+}
+
+/// `name` as a single topic level, with the separator and the wildcards replaced
+fn topic_level(name: &str) -> String {
+    name.replace(['/', '+', '#'], "_")
+}
+
+/// Check and decode a command for `setting`, received on `topic`, and put it on the bus
+fn received(bus: &EventBus, verifier: &mut CommandVerifier, topic: &str, setting: &str, data: &[u8]) {
+    let Ok(message) = std::str::from_utf8(data) else {
+        log::warn!("Ignoring MQTT {} command that isn't UTF-8", setting);
+        return;
+    };
+    let payload = match verifier.verify(topic, message) {
+        Ok(payload) => payload,
+        Err(rejection) => {
+            log::warn!("Ignoring MQTT {} command: {}", setting, rejection);
+            return;
+        }
+    };
+    match parse_command(setting, &payload) {
+        Ok(event) => {
+            if matches!(event, UiEvent::ExportSettingsRequest) {
+                EXPORT_PENDING.store(true, Ordering::Relaxed);
+            }
+            bus.publish_command(CommandSource::Mqtt, event);
+        }
+        Err(e) => log::warn!("Ignoring MQTT {} command: {}", setting, e),
+    }
+}
+
+/// The command for a payload sent to `<hostname>/<setting>/set`
+fn parse_command(setting: &str, payload: &str) -> anyhow::Result<UiEvent> {
+    let payload = payload.trim();
+    Ok(match setting {
+        "mode" => UiEvent::ModeUpdate(payload.parse()?),
+        "fan_mode" => UiEvent::FanUpdate(payload.parse()?),
+        "rest_mode" => UiEvent::RestUpdate(payload.parse()?),
+        "comfort_profile" => UiEvent::ComfortProfileUpdate(payload.parse()?),
+        // Celsius, like everything else sent to the thermostat
+        "target_temp_c" => UiEvent::TargetTempUpdate(payload.parse().map_err(|_| anyhow!("Invalid temperature: {}", payload))?),
+        // From an outdoor sensor or a weather integration, forgotten after an hour without updates
+        "outdoor_temp_c" => UiEvent::OutdoorTempUpdate(payload.parse().map_err(|_| anyhow!("Invalid temperature: {}", payload))?),
+        // Minutes, or `end`
+        "demand_response" => UiEvent::DemandResponseSignal(demand_response::parse_signal(payload)?),
+        // A backup as exported, homeowners can't change the installer settings with it
+        "config" => UiEvent::ImportConfig(ConfigBackup::from_json(payload)?),
+        // The payload doesn't matter, the export goes to `<hostname>/config`
+        "export" => UiEvent::ExportSettingsRequest,
+        _ => bail!("Unknown setting {:?}", setting),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{FanStatus, ModeStatus};
+
+    #[test]
+    fn settings_parse_by_their_serde_names() {
+        assert!(matches!(parse_command("mode", "heat"), Ok(UiEvent::ModeUpdate(ModeStatus::Heat))));
+        assert!(matches!(parse_command("fan_mode", " on\n"), Ok(UiEvent::FanUpdate(FanStatus::On))));
+        assert!(matches!(parse_command("target_temp_c", "21.5"), Ok(UiEvent::TargetTempUpdate(temp_c)) if temp_c == 21.5));
+        assert!(matches!(parse_command("outdoor_temp_c", "-7.5"), Ok(UiEvent::OutdoorTempUpdate(temp_c)) if temp_c == -7.5));
+    }
+
+    #[test]
+    fn demand_response_takes_minutes_or_end() {
+        assert!(matches!(parse_command("demand_response", "90"), Ok(UiEvent::DemandResponseSignal(Some(90)))));
+        assert!(matches!(parse_command("demand_response", "end"), Ok(UiEvent::DemandResponseSignal(None))));
+        assert!(parse_command("demand_response", "-5").is_err());
+    }
+
+    #[test]
+    fn config_takes_a_backup() {
+        let backup = ConfigBackup { settings: Default::default(), schedule_profiles: Vec::new() }.to_json().unwrap();
+        assert!(matches!(parse_command("config", &backup), Ok(UiEvent::ImportConfig(_))));
+        assert!(parse_command("config", "{\"version\":1").is_err());
+        assert!(matches!(parse_command("export", ""), Ok(UiEvent::ExportSettingsRequest)));
+    }
+
+    #[test]
+    fn unknown_settings_and_values_are_refused() {
+        assert!(parse_command("mode", "auto").is_err());
+        assert!(parse_command("target_temp_c", "warm").is_err());
+        assert!(parse_command("relay_polarity", "active_low").is_err());
+    }
+
+    #[test]
+    fn sensor_names_make_a_single_topic_level() {
+        assert_eq!(topic_level("Living room"), "Living room");
+        assert_eq!(topic_level("Up/down #2+"), "Up_down _2_");
+    }
+}
@@ -0,0 +1,277 @@
+// Wi-Fi station, SNTP and the WireGuard tunnel. Nothing else waits on this thread: the control loop, schedules and
+// ui keep running the same with no network, they only read `is_online` to show it. When the
+// connection drops it is retried with exponential backoff, forever.
+//
+// The hostname and DHCP or static addressing come from `Settings::network`. They are read at
+// boot, later changes are handed over with `configure` and applied by reconnecting.
+
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use esp_idf_svc::eventloop::EspSystemEventLoop;
+use esp_idf_svc::hal::modem::Modem;
+use esp_idf_svc::ipv4::{self, DHCPClientSettings, Mask, Subnet};
+use esp_idf_svc::netif::{EspNetif, NetifConfiguration, NetifStack};
+use esp_idf_svc::nvs::EspDefaultNvsPartition;
+use esp_idf_svc::sntp::{EspSntp, SntpConf};
+use esp_idf_svc::wifi::{AuthMethod, BlockingWifi, ClientConfiguration, Configuration, EspWifi, WifiDriver};
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+use crate::settings::Settings;
+use crate::storage::Storage;
+use crate::wireguard::Tunnel;
+
+const STORAGE_KEY: &str = "wifi";
+const BACKOFF_MIN: Duration = Duration::from_secs(1);
+const BACKOFF_MAX: Duration = Duration::from_secs(5 * 60);
+/// How often the link is checked while connected
+const CHECK_INTERVAL: Duration = Duration::from_secs(10);
+const STACK_SIZE: usize = 8192;
+/// Longest hostname DHCP servers are sure to take, and what the netif allows
+pub const MAX_HOSTNAME_LEN: usize = 30;
+
+static ONLINE: AtomicBool = AtomicBool::new(false);
+static PAUSED: AtomicBool = AtomicBool::new(false);
+/// Station address while online, 0 otherwise
+static IP_ADDRESS: AtomicU32 = AtomicU32::new(0);
+/// Network settings changed since the network thread last looked
+static PENDING: Mutex<Option<NetworkSettings>> = Mutex::new(None);
+
+/// Whether the station is connected and has an IP address
+pub fn is_online() -> bool {
+    ONLINE.load(Ordering::Relaxed)
+}
+
+/// Turn the radio off (and back on), e.g. to cut heat while the enclosure is too hot
+pub fn set_paused(paused: bool) {
+    PAUSED.store(paused, Ordering::Relaxed);
+}
+
+/// The station's address, while online
+pub fn ip_address() -> Option<Ipv4Addr> {
+    match IP_ADDRESS.load(Ordering::Relaxed) {
+        0 => None,
+        ip => Some(Ipv4Addr::from(ip)),
+    }
+}
+
+/// Hand changed network settings to the network thread, which reconnects with them
+pub fn configure(settings: &NetworkSettings) {
+    if let Ok(mut pending) = PENDING.lock() {
+        *pending = Some(settings.clone());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct NetworkSettings {
+    /// Sent to the DHCP server, so the router lists the thermostat by name
+    pub hostname: String,
+    /// Fixed address instead of DHCP, None to use DHCP
+    pub static_ip: Option<StaticIp>,
+}
+
+impl Default for NetworkSettings {
+    fn default() -> Self {
+        Self { hostname: "thermostat".to_string(), static_ip: None }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct StaticIp {
+    pub address: Ipv4Addr,
+    /// Netmask as a prefix length, 24 for 255.255.255.0
+    pub prefix_len: u8,
+    pub gateway: Ipv4Addr,
+    /// The gateway is used when None
+    pub dns: Option<Ipv4Addr>,
+}
+
+impl StaticIp {
+    /// Whether the address and gateway are usable host addresses on the same subnet
+    pub fn is_valid(&self) -> bool {
+        if !(1..=30).contains(&self.prefix_len) {
+            return false;
+        }
+        let mask = u32::MAX << (32 - self.prefix_len);
+        let (address, gateway) = (u32::from(self.address), u32::from(self.gateway));
+        let host = |ip: u32| ip & !mask != 0 && ip & !mask != !mask;
+        address != gateway && address & mask == gateway & mask && host(address) && host(gateway)
+    }
+}
+
+impl NetworkSettings {
+    fn sta_netif(&self) -> anyhow::Result<EspNetif> {
+        let ip_configuration = match self.static_ip {
+            None => ipv4::ClientConfiguration::DHCP(DHCPClientSettings::default()),
+            Some(static_ip) => ipv4::ClientConfiguration::Fixed(ipv4::ClientSettings {
+                ip: static_ip.address,
+                subnet: Subnet { gateway: static_ip.gateway, mask: Mask(static_ip.prefix_len) },
+                dns: Some(static_ip.dns.unwrap_or(static_ip.gateway)),
+                secondary_dns: None,
+            }),
+        };
+        let mut netif = EspNetif::new_with_conf(&NetifConfiguration {
+            ip_configuration: Some(ipv4::Configuration::Client(ip_configuration)),
+            ..NetifConfiguration::wifi_default_client()
+        })?;
+        // Set before DHCP starts so it goes out with the first request
+        netif.set_hostname(&self.hostname)?;
+        Ok(netif)
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WifiCredentials {
+    pub ssid: String,
+    /// Empty for open networks
+    pub password: String,
+}
+
+impl WifiCredentials {
+    /// Stored credentials, or the ones baked in at build time through `WIFI_SSID`/`WIFI_PASS`
+    pub fn load(storage: &Storage) -> Option<Self> {
+        storage.load(STORAGE_KEY).or_else(|| {
+            option_env!("WIFI_SSID").map(|ssid| Self {
+                ssid: ssid.to_string(),
+                password: option_env!("WIFI_PASS").unwrap_or_default().to_string(),
+            })
+        })
+    }
+
+    pub fn save(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save(STORAGE_KEY, self)
+    }
+
+    fn configuration(&self) -> anyhow::Result<Configuration> {
+        Ok(Configuration::Client(ClientConfiguration {
+            ssid: self.ssid.as_str().try_into().map_err(|_| anyhow!("SSID too long"))?,
+            password: self.password.as_str().try_into().map_err(|_| anyhow!("Wi-Fi password too long"))?,
+            auth_method: if self.password.is_empty() { AuthMethod::None } else { AuthMethod::WPA2Personal },
+            ..Default::default()
+        }))
+    }
+}
+
+/// Start the Wi-Fi thread. Without credentials the thermostat simply stays offline, with the
+/// `espnow` feature the radio is still started for the sensors but never connects.
+pub fn spawn(modem: Modem, sysloop: EspSystemEventLoop, nvs: EspDefaultNvsPartition) -> anyhow::Result<()> {
+    let storage = Storage::new(nvs.clone())?;
+    let credentials = WifiCredentials::load(&storage);
+    if credentials.is_none() && !cfg!(feature = "espnow") {
+        log::info!("No Wi-Fi credentials, running offline");
+        return Ok(());
+    }
+    let settings = Settings::load(&storage).network;
+    let driver = WifiDriver::new(modem, sysloop.clone(), Some(nvs))?;
+    let wifi = EspWifi::wrap_all(driver, settings.sta_netif()?, EspNetif::new(NetifStack::Ap)?)?;
+    let mut wifi = BlockingWifi::wrap(wifi, sysloop)?;
+    let configuration = match &credentials {
+        Some(credentials) => credentials.configuration()?,
+        None => Configuration::Client(ClientConfiguration::default()),
+    };
+    wifi.set_configuration(&configuration)?;
+    // Started here rather than on the first connect, ESP-NOW needs the radio up once this returns
+    wifi.start()?;
+    thread::Builder::new()
+        .name("network".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || match credentials {
+            Some(_) => run(wifi, settings),
+            None => {
+                log::info!("No Wi-Fi credentials, radio only up for ESP-NOW");
+                // Keeps the driver alive
+                loop {
+                    thread::park();
+                }
+            }
+        })?;
+    Ok(())
+}
+
+fn run(mut wifi: BlockingWifi<EspWifi<'static>>, mut settings: NetworkSettings) {
+    // SNTP keeps retrying by itself while offline, and resyncs hourly once it gets through
+    let _sntp = match EspSntp::new_with_callback(&SntpConf::default(), clock::record_sntp_sync) {
+        Ok(sntp) => Some(sntp),
+        Err(e) => {
+            log::error!("Failed to start SNTP: {}", e);
+            None
+        }
+    };
+    let mut tunnel = Tunnel::default();
+    let mut backoff = BACKOFF_MIN;
+    loop {
+        let changed = PENDING.lock().ok().and_then(|mut pending| pending.take()).filter(|pending| *pending != settings);
+        if let Some(changed) = changed {
+            log::info!("Network settings changed, reconnecting");
+            match apply(&mut wifi, &changed) {
+                Ok(()) => settings = changed,
+                Err(e) => log::error!("Failed to apply the network settings: {}", e),
+            }
+            ONLINE.store(false, Ordering::Relaxed);
+            IP_ADDRESS.store(0, Ordering::Relaxed);
+            tunnel.stop();
+        }
+        if PAUSED.load(Ordering::Relaxed) {
+            if wifi.is_started().unwrap_or(false) {
+                log::warn!("Wi-Fi paused");
+                ONLINE.store(false, Ordering::Relaxed);
+                IP_ADDRESS.store(0, Ordering::Relaxed);
+                tunnel.stop();
+                let _ = wifi.disconnect();
+                if let Err(e) = wifi.stop() {
+                    log::error!("Failed to stop Wi-Fi: {}", e);
+                }
+            }
+            thread::sleep(CHECK_INTERVAL);
+            continue;
+        }
+        if wifi.is_up().unwrap_or(false) {
+            ONLINE.store(true, Ordering::Relaxed);
+            backoff = BACKOFF_MIN;
+            tunnel.update();
+            thread::sleep(CHECK_INTERVAL);
+            continue;
+        }
+        if ONLINE.swap(false, Ordering::Relaxed) {
+            log::warn!("Wi-Fi connection lost");
+            IP_ADDRESS.store(0, Ordering::Relaxed);
+            tunnel.stop();
+        }
+        match connect(&mut wifi) {
+            Ok(ip) => {
+                log::info!("Wi-Fi connected as {} ({})", ip, settings.hostname);
+                IP_ADDRESS.store(u32::from(ip), Ordering::Relaxed);
+            }
+            Err(e) => {
+                log::warn!("Wi-Fi connect failed, retrying in {}s: {}", backoff.as_secs(), e);
+                thread::sleep(backoff);
+                backoff = (backoff * 2).min(BACKOFF_MAX);
+            }
+        }
+    }
+}
+
+/// Connect and return the address the station got
+fn connect(wifi: &mut BlockingWifi<EspWifi<'static>>) -> anyhow::Result<Ipv4Addr> {
+    if !wifi.is_started()? {
+        wifi.start()?;
+    }
+    // A half open association from the last attempt makes connect fail straight away
+    let _ = wifi.disconnect();
+    wifi.connect()?;
+    wifi.wait_netif_up()?;
+    Ok(wifi.wifi().sta_netif().get_ip_info()?.ip)
+}
+
+/// Swap in a station interface with the new settings. The next pass of the loop reconnects.
+fn apply(wifi: &mut BlockingWifi<EspWifi<'static>>, settings: &NetworkSettings) -> anyhow::Result<()> {
+    let _ = wifi.disconnect();
+    wifi.wifi_mut().swap_netif_sta(settings.sta_netif()?)?;
+    Ok(())
+}
@@ -0,0 +1,149 @@
+// Push notifications for critical alerts, sent straight to ntfy or Pushover over
+// HTTPS so they work without any smart home hub.
+//
+// Only critical alerts are pushed, the rest only show on the thermostat. The same alert is
+// pushed at most once per `RESEND_INTERVAL`, and pushes go out one at a time from a single
+// thread with a short queue, so a flapping alert can't eat the heap with TLS connections.
+
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::OnceLock;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::webhook;
+
+const PUSHOVER_URL: &str = "https://api.pushover.net/1/messages.json";
+/// TLS needs a lot more stack than the default pthread size
+const STACK_SIZE: usize = 8192;
+/// Pushes waiting for the sender thread, more than this are dropped
+const QUEUE_LEN: usize = 4;
+/// How long before the same alert is pushed again
+const RESEND_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Pushes waiting to be sent, the sender thread is started with the first one
+static QUEUE: OnceLock<SyncSender<(NotificationTarget, String)>> = OnceLock::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    /// Shown on the thermostat only
+    Warning,
+    /// Needs someone to act soon, e.g. a failed sensor, a stuck relay or freezing pipes. Pushed too.
+    Critical,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationTarget {
+    /// `server` is e.g. "https://ntfy.sh"
+    Ntfy { server: String, topic: String },
+    Pushover { app_token: String, user_key: String },
+}
+
+impl NotificationTarget {
+    /// Send a critical alert, blocking until the server answered.
+    fn send(&self, message: &str) -> anyhow::Result<()> {
+        match self {
+            NotificationTarget::Ntfy { server, topic } => {
+                let url = format!("{}/{}", server.trim_end_matches('/'), topic);
+                let headers = [
+                    ("content-type".to_string(), "text/plain".to_string()),
+                    ("title".to_string(), "Thermostat alert".to_string()),
+                    ("priority".to_string(), "urgent".to_string()),
+                ];
+                webhook::post(&url, &headers, message)
+            }
+            NotificationTarget::Pushover { app_token, user_key } => {
+                let body = serde_json::json!({
+                    "token": app_token,
+                    "user": user_key,
+                    "title": "Thermostat alert",
+                    "message": message,
+                    "priority": 1,
+                });
+                let headers = [("content-type".to_string(), "application/json".to_string())];
+                webhook::post(PUSHOVER_URL, &headers, &body.to_string())
+            }
+        }
+    }
+}
+
+/// Decides which alerts are pushed and hands them to the sender thread
+#[derive(Default)]
+pub struct Notifier {
+    /// When each alert was last pushed, forgotten after `RESEND_INTERVAL`
+    last_sent: HashMap<String, Instant>,
+}
+
+impl Notifier {
+    /// Push `message` to `target` in the background if it is critical and wasn't pushed recently.
+    pub fn notify(&mut self, target: &NotificationTarget, severity: Severity, message: &str) {
+        if !self.should_send(severity, message, Instant::now()) {
+            return;
+        }
+        let queue = QUEUE.get_or_init(spawn_sender);
+        match queue.try_send((target.clone(), message.to_string())) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => log::warn!("Push queue full, dropping alert: {}", message),
+            Err(TrySendError::Disconnected(_)) => log::error!("Push sender not running, dropping alert: {}", message),
+        }
+    }
+
+    fn should_send(&mut self, severity: Severity, message: &str, now: Instant) -> bool {
+        if severity != Severity::Critical {
+            return false;
+        }
+        self.last_sent.retain(|_, sent_at| now.duration_since(*sent_at) < RESEND_INTERVAL);
+        if self.last_sent.contains_key(message) {
+            return false;
+        }
+        self.last_sent.insert(message.to_string(), now);
+        true
+    }
+}
+
+fn spawn_sender() -> SyncSender<(NotificationTarget, String)> {
+    let (queue, pushes) = mpsc::sync_channel(QUEUE_LEN);
+    let spawned = thread::Builder::new()
+        .name("notify".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || send_all(pushes));
+    if let Err(e) = spawned {
+        // The receiver is gone with the closure, every push after this is dropped with an error
+        log::error!("Failed to spawn push sender thread: {}", e);
+    }
+    queue
+}
+
+fn send_all(pushes: Receiver<(NotificationTarget, String)>) {
+    for (target, message) in pushes {
+        if let Err(e) = target.send(&message) {
+            log::error!("Failed to push alert: {}", e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_critical_alerts_are_pushed() {
+        let mut notifier = Notifier::default();
+        let now = Instant::now();
+        assert!(!notifier.should_send(Severity::Warning, "Compressor started 7 times in the last hour", now));
+        assert!(notifier.should_send(Severity::Critical, "Temperature sensor not responding", now));
+    }
+
+    #[test]
+    fn repeats_wait_for_the_resend_interval() {
+        let mut notifier = Notifier::default();
+        let now = Instant::now();
+        assert!(notifier.should_send(Severity::Critical, "Heating relay not responding", now));
+        assert!(!notifier.should_send(Severity::Critical, "Heating relay not responding", now + Duration::from_secs(60)));
+        assert!(notifier.should_send(Severity::Critical, "Cooling relay not responding", now + Duration::from_secs(60)));
+        assert!(notifier.should_send(Severity::Critical, "Heating relay not responding", now + RESEND_INTERVAL));
+    }
+}
@@ -0,0 +1,81 @@
+// Open window detection from the temperature signature alone, no contact sensors needed.
+// While heating, a sudden drop that doesn't recover within a couple of minutes is almost
+// always a window or door left open, and heating into it is wasted energy.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How far back the peak temperature is looked for
+const DROP_WINDOW: Duration = Duration::from_secs(5 * 60);
+/// The drop has to last this long, so a draft from a door opening and closing doesn't count
+const SUSTAIN: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum OpenWindowSensitivity {
+    Low,
+    Medium,
+    High,
+}
+
+impl OpenWindowSensitivity {
+    /// Drop below the recent peak that counts as an open window (Celsius)
+    fn drop_threshold_c(self) -> f32 {
+        match self {
+            OpenWindowSensitivity::Low => 1.5,    // ~2.7°F
+            OpenWindowSensitivity::Medium => 1.0, // ~1.8°F
+            OpenWindowSensitivity::High => 0.6,   // ~1.1°F
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct OpenWindowDetection {
+    pub sensitivity: OpenWindowSensitivity,
+    /// How long heating stays paused once an open window is detected
+    pub pause_mins: u32,
+}
+
+impl Default for OpenWindowDetection {
+    fn default() -> Self {
+        Self {
+            sensitivity: OpenWindowSensitivity::Medium,
+            pause_mins: 15,
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct OpenWindowDetector {
+    samples: VecDeque<(Instant, f32)>,
+    /// When the temperature first fell past the threshold, if it still is
+    dropped_since: Option<Instant>,
+}
+
+impl OpenWindowDetector {
+    /// Forget the history, e.g. when a heat call ends or the user overrides a detection.
+    pub fn reset(&mut self) {
+        self.samples.clear();
+        self.dropped_since = None;
+    }
+
+    /// Feed a reading taken while heating.
+    /// Returns true once the temperature has stayed far enough below its recent peak for long enough.
+    pub fn record(&mut self, temp_c: f32, sensitivity: OpenWindowSensitivity) -> bool {
+        let now = Instant::now();
+        self.samples.push_back((now, temp_c));
+        while self.samples.front().is_some_and(|(at, _)| now.duration_since(*at) > DROP_WINDOW) {
+            self.samples.pop_front();
+        }
+        let peak_c = self.samples.iter().map(|(_, temp_c)| *temp_c).fold(f32::MIN, f32::max);
+        if peak_c - temp_c < sensitivity.drop_threshold_c() {
+            self.dropped_since = None;
+            return false;
+        }
+        let dropped_since = *self.dropped_since.get_or_insert(now);
+        now.duration_since(dropped_since) >= SUSTAIN
+    }
+}
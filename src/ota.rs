@@ -0,0 +1,374 @@
+// Over the air firmware updates. A signed JSON manifest at a user configured url describes the
+// latest firmware on each release channel:
+//
+//     {"manifest": "<json below, as a string>", "signature": "<hex ed25519 signature of it>"}
+//     {"stable": {"version": "0.2.0", "url": "https://...", "sha256": "<hex>", "changelog": "..."},
+//      "beta": {...}}
+//
+// The signature is checked against the public key baked in at build time through
+// `OTA_PUBLIC_KEY` (hex), and the image against the manifest's hash, so a compromised server
+// or download can't install anything. Without a key, updates are refused.
+//
+// Checking and installing run on their own thread and report back over the bus, so the
+// control loop never waits on the network. The image goes into the inactive OTA slot and the
+// device restarts into it once it's written completely. The bootloader then boots it on
+// probation: unless `BootHealthCheck` sees it healthy within the configured time, it rolls
+// back to the previous slot.
+
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use embedded_svc::http::client::Client;
+use embedded_svc::io::{Read, Write};
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+use ed25519_dalek::{Signature, Verifier, VerifyingKey};
+use esp_idf_svc::ota::{EspOta, SlotState};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::bus::EventBus;
+use crate::events::BackendEvent;
+use crate::hex;
+
+pub const FIRMWARE_VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Short git hash of the source the firmware was built from
+pub const BUILD_HASH: &str = env!("BUILD_HASH");
+/// TLS plus the download buffer need more stack than the default pthread size
+const OTA_STACK_SIZE: usize = 12 * 1024;
+const DOWNLOAD_CHUNK: usize = 4096;
+/// The manifest is a few hundred bytes, anything this big is not a manifest
+const MAX_MANIFEST_BYTES: usize = 16 * 1024;
+/// A new image has to stay healthy this long before it's kept
+const HEALTHY_FOR: Duration = Duration::from_secs(2 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum UpdateChannel {
+    Stable = 0,
+    /// Gets releases before they go out to everyone
+    Beta = 1,
+}
+
+impl TryFrom<i32> for UpdateChannel {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(UpdateChannel::Stable),
+            1 => Ok(UpdateChannel::Beta),
+            _ => Err(anyhow::anyhow!("Invalid update channel: {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct OtaManifest {
+    pub version: String,
+    /// Where the firmware image is downloaded from
+    pub url: String,
+    /// Hex SHA-256 of the image
+    pub sha256: String,
+    #[serde(default)]
+    pub changelog: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct ChannelManifests {
+    stable: Option<OtaManifest>,
+    beta: Option<OtaManifest>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SignedManifest {
+    /// Kept as the exact string that was signed
+    manifest: String,
+    signature: String,
+}
+
+fn verifying_key() -> anyhow::Result<VerifyingKey> {
+    let Some(key_hex) = option_env!("OTA_PUBLIC_KEY") else {
+        anyhow::bail!("firmware built without an update signing key");
+    };
+    let bytes: [u8; 32] = hex::decode(key_hex)?.try_into().map_err(|_| anyhow::anyhow!("signing key must be 32 bytes"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Check the signature and pick the release for `channel`
+fn verify_manifest(signed: &SignedManifest, channel: UpdateChannel) -> anyhow::Result<Option<OtaManifest>> {
+    let signature: [u8; 64] = hex::decode(&signed.signature)?.try_into().map_err(|_| anyhow::anyhow!("signature must be 64 bytes"))?;
+    verifying_key()?
+        .verify(signed.manifest.as_bytes(), &Signature::from_bytes(&signature))
+        .map_err(|_| anyhow::anyhow!("manifest signature doesn't match"))?;
+    let channels: ChannelManifests = serde_json::from_str(&signed.manifest)?;
+    Ok(match channel {
+        UpdateChannel::Stable => channels.stable,
+        // Beta follows stable when stable is newer
+        UpdateChannel::Beta => match (channels.beta, channels.stable) {
+            (Some(beta), Some(stable)) if is_newer(&beta.version, &stable.version) => Some(stable),
+            (beta, stable) => beta.or(stable),
+        },
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum UpdateStatus {
+    Checking,
+    UpToDate,
+    Available { version: String, changelog: String },
+    /// Downloading and writing the image, percent done if the size is known
+    Installing(Option<u8>),
+    Failed(String),
+}
+
+/// Whether `candidate` is a newer dotted version than `current`. Anything that isn't a
+/// number (e.g. a "-beta" suffix) is ignored.
+pub fn is_newer(current: &str, candidate: &str) -> bool {
+    let parse = |version: &str| -> Vec<u64> {
+        version
+            .trim_start_matches('v')
+            .split(['.', '-', '+'])
+            .map_while(|part| part.parse().ok())
+            .collect()
+    };
+    parse(candidate) > parse(current)
+}
+
+fn http_client() -> anyhow::Result<Client<EspHttpConnection>> {
+    let connection = EspHttpConnection::new(&Configuration {
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    Ok(Client::wrap(connection))
+}
+
+/// Fetch and verify the manifest, and return the release on `channel`, if it has one
+pub fn fetch_manifest(url: &str, channel: UpdateChannel) -> anyhow::Result<Option<OtaManifest>> {
+    let mut client = http_client()?;
+    let mut response = client.get(url)?.submit()?;
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        anyhow::bail!("unexpected status {}", status);
+    }
+    let mut body = Vec::new();
+    let mut chunk = [0u8; 512];
+    loop {
+        let read = response.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        body.extend_from_slice(&chunk[..read]);
+        if body.len() > MAX_MANIFEST_BYTES {
+            anyhow::bail!("manifest too large");
+        }
+    }
+    let signed: SignedManifest = serde_json::from_slice(&body)?;
+    verify_manifest(&signed, channel)
+}
+
+/// Download the image into the inactive slot and restart into it. Only returns on failure.
+fn install(manifest: &OtaManifest, bus: &EventBus) -> anyhow::Result<()> {
+    let mut client = http_client()?;
+    let mut response = client.get(&manifest.url)?.submit()?;
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        anyhow::bail!("unexpected status {}", status);
+    }
+    let total = response
+        .header("content-length")
+        .and_then(|length| length.parse::<usize>().ok())
+        .filter(|length| *length > 0);
+    let expected_sha256 = hex::decode(&manifest.sha256)?;
+    let mut hasher = Sha256::new();
+    let mut ota = EspOta::new()?;
+    let mut update = ota.initiate_update()?;
+    let mut chunk = vec![0u8; DOWNLOAD_CHUNK];
+    let mut written = 0;
+    let mut last_percent = None;
+    loop {
+        let read = match response.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) => {
+                let _ = update.abort();
+                return Err(e.into());
+            }
+        };
+        if read == 0 {
+            break;
+        }
+        if let Err(e) = update.write_all(&chunk[..read]) {
+            let _ = update.abort();
+            return Err(e.into());
+        }
+        hasher.update(&chunk[..read]);
+        written += read;
+        let percent = total.map(|total| (written * 100 / total).min(100) as u8);
+        if percent != last_percent {
+            last_percent = percent;
+            bus.publish_state(BackendEvent::UpdateStatus(UpdateStatus::Installing(percent)));
+        }
+    }
+    if hasher.finalize().as_slice() != expected_sha256.as_slice() {
+        let _ = update.abort();
+        anyhow::bail!("downloaded image doesn't match the manifest's hash");
+    }
+    update.complete()?;
+    log::info!("Firmware {} written ({} bytes), restarting", manifest.version, written);
+    esp_idf_svc::hal::reset::restart();
+}
+
+/// Runs update checks and installs in the background and publishes their progress
+pub struct Updater {
+    bus: EventBus,
+    /// Manifest of the update found by the last check, if it was newer
+    available: Arc<Mutex<Option<OtaManifest>>>,
+    /// Set while a check or install is running
+    busy: Arc<Mutex<bool>>,
+}
+
+impl Updater {
+    pub fn new(bus: EventBus) -> Self {
+        Self {
+            bus,
+            available: Arc::default(),
+            busy: Arc::default(),
+        }
+    }
+
+    fn start(&self) -> bool {
+        match self.busy.lock() {
+            Ok(mut busy) if !*busy => {
+                *busy = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn spawn(&self, task: impl FnOnce(&EventBus, &Mutex<Option<OtaManifest>>) + Send + 'static) {
+        if !self.start() {
+            log::warn!("Update check or install already running");
+            return;
+        }
+        let bus = self.bus.clone();
+        let available = self.available.clone();
+        let busy = self.busy.clone();
+        let spawned = thread::Builder::new().name("ota".to_string()).stack_size(OTA_STACK_SIZE).spawn(move || {
+            task(&bus, &available);
+            if let Ok(mut busy) = busy.lock() {
+                *busy = false;
+            }
+        });
+        if let Err(e) = spawned {
+            log::error!("Failed to spawn OTA thread: {}", e);
+            if let Ok(mut busy) = self.busy.lock() {
+                *busy = false;
+            }
+        }
+    }
+
+    /// Fetch the manifest and publish whether there is a newer firmware on `channel`
+    pub fn check(&self, manifest_url: String, channel: UpdateChannel) {
+        self.spawn(move |bus, available| {
+            bus.publish_state(BackendEvent::UpdateStatus(UpdateStatus::Checking));
+            let status = match fetch_manifest(&manifest_url, channel) {
+                Ok(Some(manifest)) if is_newer(FIRMWARE_VERSION, &manifest.version) => {
+                    log::info!("Firmware {} available", manifest.version);
+                    let status = UpdateStatus::Available {
+                        version: manifest.version.clone(),
+                        changelog: manifest.changelog.clone(),
+                    };
+                    if let Ok(mut available) = available.lock() {
+                        *available = Some(manifest);
+                    }
+                    status
+                }
+                Ok(_) => {
+                    if let Ok(mut available) = available.lock() {
+                        *available = None;
+                    }
+                    UpdateStatus::UpToDate
+                }
+                Err(e) => {
+                    log::error!("Update check failed: {}", e);
+                    UpdateStatus::Failed(e.to_string())
+                }
+            };
+            bus.publish_state(BackendEvent::UpdateStatus(status));
+        });
+    }
+
+    /// Install the update found by the last check
+    pub fn install(&self) {
+        let Some(manifest) = self.available.lock().ok().and_then(|available| available.clone()) else {
+            log::warn!("No update to install, check for updates first");
+            return;
+        };
+        self.spawn(move |bus, _| {
+            bus.publish_state(BackendEvent::UpdateStatus(UpdateStatus::Installing(None)));
+            if let Err(e) = install(&manifest, bus) {
+                log::error!("Firmware update failed: {}", e);
+                bus.publish_state(BackendEvent::UpdateStatus(UpdateStatus::Failed(e.to_string())));
+            }
+        });
+    }
+}
+
+/// Decides whether a freshly installed image is kept. Only exists while the running image is
+/// on probation after an update.
+pub struct BootHealthCheck {
+    ota: EspOta,
+    deadline: Instant,
+    healthy_since: Option<Instant>,
+}
+
+impl BootHealthCheck {
+    /// None unless the running image still has to prove itself
+    pub fn start(timeout: Duration) -> Option<Self> {
+        let ota = match EspOta::new() {
+            Ok(ota) => ota,
+            Err(e) => {
+                log::error!("Failed to open OTA: {}", e);
+                return None;
+            }
+        };
+        match ota.get_running_slot() {
+            Ok(slot) if slot.state == SlotState::Unverified => {
+                log::info!("Running a new firmware, keeping it if it's healthy within {}s", timeout.as_secs());
+                Some(Self {
+                    ota,
+                    deadline: Instant::now() + timeout,
+                    healthy_since: None,
+                })
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::error!("Failed to read the running OTA slot: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Call every tick with whether the device is healthy (Wi-Fi up and the control loop
+    /// working). Returns true once the check is over and it can be dropped. Rolls back and
+    /// restarts when the deadline passes first.
+    pub fn update(&mut self, healthy: bool) -> bool {
+        if !healthy {
+            self.healthy_since = None;
+        } else if self.healthy_since.get_or_insert_with(Instant::now).elapsed() >= HEALTHY_FOR {
+            log::info!("New firmware is healthy, keeping it");
+            if let Err(e) = self.ota.mark_running_slot_valid() {
+                log::error!("Failed to mark the running firmware valid: {}", e);
+            }
+            return true;
+        }
+        if Instant::now() >= self.deadline {
+            log::error!("New firmware didn't get healthy in time, rolling back");
+            let e = self.ota.mark_running_slot_invalid_and_reboot();
+            log::error!("Failed to roll back: {}", e);
+            return true;
+        }
+        false
+    }
+}
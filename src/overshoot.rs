@@ -0,0 +1,80 @@
+// Overshoot after heat calls. Forced air systems keep heating for a while after the burner
+// stops, so the temperature peaks some time after the call ends. How far past the setpoint it
+// goes is what the heat anticipator should be tuned to.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+/// Stop looking for the peak after this long
+const SETTLE: Duration = Duration::from_secs(15 * 60);
+/// The peak is over once the temperature is this far below it (Celsius)
+const FALLING_THRESHOLD_C: f32 = 0.2;
+/// Number of heat calls the average is taken over
+const HISTORY: usize = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct OvershootStats {
+    /// How far the last heat call went past the setpoint (Celsius), negative if it stopped short
+    pub last_c: Option<f32>,
+    /// Average over the last few heat calls (Celsius)
+    pub average_c: Option<f32>,
+}
+
+struct PendingCall {
+    ended: Instant,
+    target_c: f32,
+    peak_c: f32,
+}
+
+#[derive(Default)]
+pub struct OvershootTracker {
+    pending: Option<PendingCall>,
+    recent: VecDeque<f32>,
+}
+
+impl OvershootTracker {
+    /// A heat call reached its stop temperature, start watching for the peak
+    pub fn heat_call_ended(&mut self, target_c: f32, temp_c: f32) {
+        self.finish();
+        self.pending = Some(PendingCall {
+            ended: Instant::now(),
+            target_c,
+            peak_c: temp_c,
+        });
+    }
+
+    /// A new heat call started, whatever peak was seen so far is the one
+    pub fn heat_call_started(&mut self) {
+        self.finish();
+    }
+
+    pub fn record(&mut self, temp_c: f32) {
+        let Some(pending) = self.pending.as_mut() else {
+            return;
+        };
+        pending.peak_c = pending.peak_c.max(temp_c);
+        if temp_c <= pending.peak_c - FALLING_THRESHOLD_C || pending.ended.elapsed() >= SETTLE {
+            self.finish();
+        }
+    }
+
+    pub fn stats(&self) -> OvershootStats {
+        let average_c = (!self.recent.is_empty()).then(|| self.recent.iter().sum::<f32>() / self.recent.len() as f32);
+        OvershootStats {
+            last_c: self.recent.back().copied(),
+            average_c,
+        }
+    }
+
+    fn finish(&mut self) {
+        let Some(pending) = self.pending.take() else {
+            return;
+        };
+        if self.recent.len() == HISTORY {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(pending.peak_c - pending.target_c);
+    }
+}
@@ -0,0 +1,65 @@
+// Utility peak pricing windows. Ahead of a window the house is pre-heated or pre-cooled a
+// little, then during the window the deadband is widened so the system runs as little as
+// possible while electricity is expensive.
+
+use chrono::{NaiveTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeWindow;
+use crate::events::ModeStatus;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PeakPricing {
+    /// Daily peak rate windows in local time
+    pub windows: Vec<TimeWindow>,
+    /// How long before a window preconditioning starts
+    pub precondition_mins: u32,
+    /// How far past the setpoint to precondition (Celsius)
+    pub precondition_offset_c: f32,
+    /// How much wider the deadband is during a window (Celsius)
+    pub deadband_widening_c: f32,
+}
+
+impl Default for PeakPricing {
+    fn default() -> Self {
+        Self {
+            windows: Vec::new(),
+            precondition_mins: 60,
+            precondition_offset_c: 1.0, // ~1.8°F
+            deadband_widening_c: 1.5,   // ~2.7°F
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum PeakPhase {
+    /// Getting ahead of an upcoming window
+    Precondition = 1,
+    /// Inside a window, shown as "Peak savings"
+    Peak = 2,
+}
+
+impl PeakPricing {
+    pub fn phase(&self, now: NaiveTime) -> Option<PeakPhase> {
+        if self.windows.iter().any(|window| window.contains(now)) {
+            return Some(PeakPhase::Peak);
+        }
+        let lead = TimeDelta::minutes(self.precondition_mins as i64);
+        let preconditioning = self.windows.iter().any(|window| {
+            // Subtracting wraps around midnight, which TimeWindow handles
+            TimeWindow { start: window.start - lead, end: window.start }.contains(now)
+        });
+        preconditioning.then_some(PeakPhase::Precondition)
+    }
+
+    /// Offset added to the setpoint while preconditioning: warmer before heating, cooler before cooling
+    pub fn precondition_offset_c(&self, mode: &ModeStatus) -> f32 {
+        match mode {
+            ModeStatus::Heat => self.precondition_offset_c,
+            ModeStatus::Cool => -self.precondition_offset_c,
+            ModeStatus::Off => 0.0,
+        }
+    }
+}
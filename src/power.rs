@@ -0,0 +1,77 @@
+// Brownout tracking. Cheap 24 VAC to USB supplies sag when the HVAC equipment kicks in,
+// and the chip resets. After a few brownouts in a row the thermostat drops into a reduced
+// power mode (dimmed display) to take some load off the supply until it has been stable again.
+
+use std::time::{Duration, Instant};
+
+use esp_idf_svc::sys::{esp_reset_reason, esp_reset_reason_t_ESP_RST_BROWNOUT};
+use serde::{Deserialize, Serialize};
+
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "brownouts";
+/// Brownout resets in a row before reduced power mode kicks in
+const REDUCED_POWER_AFTER: u32 = 3;
+/// Running this long without a brownout means the supply is fine again
+const STABLE_AFTER: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+struct BrownoutCounts {
+    /// Every brownout reset since the counts were first stored
+    total: u32,
+    /// Brownout resets without a stable stretch in between
+    consecutive: u32,
+}
+
+pub struct BrownoutMonitor {
+    counts: BrownoutCounts,
+    boot_time: Instant,
+    /// Set once this boot ran long enough to clear the consecutive count
+    stable: bool,
+}
+
+impl BrownoutMonitor {
+    /// Check why the chip last reset and update the persisted counts.
+    pub fn on_boot(storage: &mut Storage) -> Self {
+        let mut counts: BrownoutCounts = storage.load(STORAGE_KEY).unwrap_or_default();
+        // SAFETY: plain getter with no preconditions
+        let brownout = unsafe { esp_reset_reason() } == esp_reset_reason_t_ESP_RST_BROWNOUT;
+        if brownout {
+            counts.total += 1;
+            counts.consecutive += 1;
+            log::warn!("Reset by brownout ({} in a row, {} total)", counts.consecutive, counts.total);
+            if let Err(e) = storage.save(STORAGE_KEY, &counts) {
+                log::error!("Failed to persist brownout counts: {}", e);
+            }
+        }
+        Self {
+            counts,
+            boot_time: Instant::now(),
+            stable: false,
+        }
+    }
+
+    /// Clear the consecutive count once this boot has been running long enough
+    pub fn update(&mut self, storage: &mut Storage) {
+        if self.stable || self.boot_time.elapsed() < STABLE_AFTER {
+            return;
+        }
+        self.stable = true;
+        if self.counts.consecutive == 0 {
+            return;
+        }
+        log::info!("Power stable again, leaving reduced power mode");
+        self.counts.consecutive = 0;
+        if let Err(e) = storage.save(STORAGE_KEY, &self.counts) {
+            log::error!("Failed to persist brownout counts: {}", e);
+        }
+    }
+
+    pub fn reduced_power(&self) -> bool {
+        self.counts.consecutive >= REDUCED_POWER_AFTER
+    }
+
+    pub fn total(&self) -> u32 {
+        self.counts.total
+    }
+}
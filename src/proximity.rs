@@ -0,0 +1,175 @@
+// Proximity wake: brightens the screen out of quiet hours / reduced power dimming when someone
+// walks up to it, instead of needing a touch first. Works with an APDS-9930 on the shared I2C
+// bus, found at boot, or with an mmWave presence module's output pin (LD2410 and the like) on
+// the board's presence pin behind the `mmwave` feature. The mmWave module's range is set on the module itself.
+
+use std::sync::PoisonError;
+
+use serde::{Deserialize, Serialize};
+
+use crate::bsp::SharedI2c;
+
+const APDS9930_ADDRESS: u8 = 0x39;
+/// Command bit, repeated byte access
+const APDS9930_COMMAND: u8 = 0x80;
+/// Command bits, auto-incrementing access
+const APDS9930_COMMAND_AUTO_INCREMENT: u8 = 0xA0;
+const APDS9930_REG_ENABLE: u8 = 0x00;
+const APDS9930_REG_PTIME: u8 = 0x02;
+const APDS9930_REG_PPULSE: u8 = 0x0E;
+const APDS9930_REG_CONTROL: u8 = 0x0F;
+const APDS9930_REG_PDATA: u8 = 0x18;
+/// Power on and proximity enabled
+const APDS9930_ENABLE_PON_PEN: u8 = 0x05;
+/// 100mA LED drive, channel 1 diode, 1x gain
+const APDS9930_CONTROL: u8 = 0x20;
+const I2C_TIMEOUT: u32 = 1000;
+
+/// How close someone has to come before the screen wakes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum ProximitySensitivity {
+    /// Right in front of the screen, a few centimetres
+    Near = 1,
+    Medium = 2,
+    /// Walking up to it, a few tens of centimetres
+    Far = 3,
+}
+
+impl ProximitySensitivity {
+    /// Raw proximity count (0-1023) that counts as someone being there
+    fn threshold(self) -> u16 {
+        match self {
+            ProximitySensitivity::Near => 600,
+            ProximitySensitivity::Medium => 300,
+            ProximitySensitivity::Far => 120,
+        }
+    }
+}
+
+impl TryFrom<i32> for ProximitySensitivity {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            1 => Ok(ProximitySensitivity::Near),
+            2 => Ok(ProximitySensitivity::Medium),
+            3 => Ok(ProximitySensitivity::Far),
+            _ => Err(anyhow::anyhow!("Invalid proximity sensitivity: {}", value)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ProximityWake {
+    pub enabled: bool,
+    /// Only used by the APDS-9930
+    pub sensitivity: ProximitySensitivity,
+}
+
+impl Default for ProximityWake {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            sensitivity: ProximitySensitivity::Medium,
+        }
+    }
+}
+
+struct Apds9930 {
+    i2c: SharedI2c,
+}
+
+impl Apds9930 {
+    fn probe(i2c: SharedI2c) -> Option<Self> {
+        let sensor = Self { i2c };
+        let init = [
+            (APDS9930_REG_PTIME, 0xFF),
+            (APDS9930_REG_PPULSE, 8),
+            (APDS9930_REG_CONTROL, APDS9930_CONTROL),
+            (APDS9930_REG_ENABLE, APDS9930_ENABLE_PON_PEN),
+        ];
+        for (register, value) in init {
+            sensor.write(register, value).ok()?;
+        }
+        log::info!("APDS-9930 proximity sensor found");
+        Some(sensor)
+    }
+
+    fn write(&self, register: u8, value: u8) -> anyhow::Result<()> {
+        let mut bus = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+        bus.write(APDS9930_ADDRESS, &[APDS9930_COMMAND | register, value], I2C_TIMEOUT)?;
+        Ok(())
+    }
+
+    fn read_proximity(&self) -> anyhow::Result<u16> {
+        let mut raw = [0u8; 2];
+        let mut bus = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+        bus.write_read(APDS9930_ADDRESS, &[APDS9930_COMMAND_AUTO_INCREMENT | APDS9930_REG_PDATA], &mut raw, I2C_TIMEOUT)?;
+        Ok(u16::from_le_bytes(raw))
+    }
+}
+
+#[cfg(feature = "mmwave")]
+type PresencePin = esp_idf_svc::hal::gpio::PinDriver<'static, esp_idf_svc::hal::gpio::AnyInputPin, esp_idf_svc::hal::gpio::Input>;
+
+pub struct ProximityDetector {
+    apds9930: Option<Apds9930>,
+    /// Presence output of an mmWave module, high while someone is there. See `bsp::board::presence_pin`
+    #[cfg(feature = "mmwave")]
+    presence: Option<PresencePin>,
+    settings: ProximityWake,
+}
+
+impl ProximityDetector {
+    pub fn probe(i2c: SharedI2c) -> Self {
+        #[cfg(feature = "mmwave")]
+        // SAFETY: the presence pin isn't used anywhere else when the mmwave feature is on
+        let presence = match esp_idf_svc::hal::gpio::PinDriver::input(unsafe { crate::bsp::board::presence_pin() }) {
+            Ok(pin) => Some(pin),
+            Err(e) => {
+                log::error!("Failed to set up mmWave presence input: {}", e);
+                None
+            }
+        };
+        Self {
+            apds9930: Apds9930::probe(i2c),
+            #[cfg(feature = "mmwave")]
+            presence,
+            settings: ProximityWake::default(),
+        }
+    }
+
+    pub fn has_sensor(&self) -> bool {
+        #[cfg(feature = "mmwave")]
+        if self.presence.is_some() {
+            return true;
+        }
+        self.apds9930.is_some()
+    }
+
+    pub fn configure(&mut self, settings: ProximityWake) {
+        self.settings = settings;
+    }
+
+    /// Whether someone is in front of the screen right now
+    pub fn someone_near(&self) -> bool {
+        if !self.settings.enabled {
+            return false;
+        }
+        #[cfg(feature = "mmwave")]
+        if self.presence.as_ref().is_some_and(|pin| pin.is_high()) {
+            return true;
+        }
+        match self.apds9930.as_ref().map(Apds9930::read_proximity) {
+            Some(Ok(proximity)) => proximity >= self.settings.sensitivity.threshold(),
+            Some(Err(e)) => {
+                log::warn!("Failed to read proximity: {}", e);
+                false
+            }
+            None => false,
+        }
+    }
+}
@@ -0,0 +1,249 @@
+// Remote room temperature sensors. They report over whatever transport they use (ESP-NOW, BLE, HTTP)
+// as `UiEvent::RemoteTempUpdate`, keyed by name. Which of them drives control can change with
+// the time of day, e.g. the bedroom at night and the living room by day, or a weighted average
+// of several. A sensor that hasn't reported within the stale window is left out, and with none
+// left the onboard sensor takes over. Battery powered ones also report their charge, which is
+// alerted about once when it runs low.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::time::{Duration, Instant};
+
+use chrono::NaiveTime;
+use serde::{Deserialize, Serialize};
+
+use crate::clock::TimeWindow;
+
+/// Battery charge alerted about as low
+pub const LOW_BATTERY_PERCENT: u8 = 15;
+/// Charge a sensor has to be back above to be alerted about again, so a battery hovering around
+/// the threshold doesn't alert on every report
+const LOW_BATTERY_CLEAR_PERCENT: u8 = 25;
+
+/// Readings from further sensors are dropped, so a misbehaving client can't fill the memory
+pub const MAX_REMOTE_SENSORS: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SensorRef {
+    Onboard,
+    Remote(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WeightedSensor {
+    pub sensor: SensorRef,
+    /// Share of this sensor in the average, relative to the others
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+/// Sensors driving control during a window of the day
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SensorPeriod {
+    pub window: TimeWindow,
+    pub sensors: Vec<WeightedSensor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SensorSelection {
+    /// Sensors driving control outside the periods, empty for the onboard sensor alone
+    pub sensors: Vec<WeightedSensor>,
+    /// Time of day overrides, the first one containing the current time is used
+    pub periods: Vec<SensorPeriod>,
+}
+
+impl SensorSelection {
+    /// Sensors driving control at `time`, the default ones while the clock isn't set
+    pub fn active(&self, time: Option<NaiveTime>) -> &[WeightedSensor] {
+        time.and_then(|time| self.periods.iter().find(|period| period.window.contains(time)))
+            .map_or(&self.sensors, |period| &period.sensors)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Battery {
+    /// 0 when the sensor only knows the charge
+    pub millivolts: u16,
+    /// None when the sensor only knows the voltage
+    pub percent: Option<u8>,
+}
+
+/// An off-the-shelf BLE thermometer to listen to, any others nearby are ignored
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BleSensor {
+    /// Bluetooth address, e.g. "a4:c1:38:12:34:56"
+    pub address: String,
+    /// Name it reports under, as used in the sensor selection
+    pub name: String,
+}
+
+/// Progress of pairing a sensor, for transports that pair (ESP-NOW)
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PairingStatus {
+    /// Waiting for the sensor's pairing button
+    Listening,
+    Paired(String),
+    TimedOut,
+    /// No room for another paired sensor
+    Full,
+}
+
+struct RemoteReading {
+    temp_c: f32,
+    battery: Option<Battery>,
+    at: Instant,
+}
+
+/// A remote sensor as shown on the diagnostics screen
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RemoteSensorStatus {
+    pub name: String,
+    pub temp_c: f32,
+    /// Seconds since it last reported, in steps of 10 under a minute and whole minutes after,
+    /// only as precise as the screen shows it so the snapshot doesn't change every second
+    pub age_secs: u64,
+    pub stale: bool,
+    /// None for mains powered sensors
+    pub battery: Option<Battery>,
+}
+
+pub struct RemoteSensors {
+    readings: BTreeMap<String, RemoteReading>,
+    /// Readings older than this are stale and not used for control
+    stale_after: Duration,
+    /// Sensors already alerted about as stale, until they report again
+    stale_alerted: BTreeSet<String>,
+    /// Sensors already alerted about as low on battery, until the charge is back up
+    low_battery_alerted: BTreeSet<String>,
+}
+
+impl Default for RemoteSensors {
+    fn default() -> Self {
+        Self {
+            readings: BTreeMap::new(),
+            stale_after: Duration::from_secs(10 * 60),
+            stale_alerted: BTreeSet::new(),
+            low_battery_alerted: BTreeSet::new(),
+        }
+    }
+}
+
+impl RemoteSensors {
+    pub fn set_stale_after(&mut self, stale_after: Duration) {
+        self.stale_after = stale_after;
+    }
+
+    pub fn record(&mut self, name: String, temp_c: f32, battery: Option<Battery>) {
+        if !self.readings.contains_key(&name) && self.readings.len() >= MAX_REMOTE_SENSORS {
+            log::warn!("Ignoring remote sensor {}, already tracking {}", name, MAX_REMOTE_SENSORS);
+            return;
+        }
+        if self.stale_alerted.remove(&name) {
+            log::info!("Remote sensor {} reporting again", name);
+        }
+        if battery.and_then(|battery| battery.percent).is_none_or(|percent| percent >= LOW_BATTERY_CLEAR_PERCENT) {
+            self.low_battery_alerted.remove(&name);
+        }
+        self.readings.insert(name, RemoteReading { temp_c, battery, at: Instant::now() });
+    }
+
+    /// Drop a sensor's readings, e.g. once it is unpaired
+    pub fn remove(&mut self, name: &str) {
+        self.readings.remove(name);
+        self.stale_alerted.remove(name);
+        self.low_battery_alerted.remove(name);
+    }
+
+    fn is_stale(&self, reading: &RemoteReading) -> bool {
+        reading.at.elapsed() >= self.stale_after
+    }
+
+    /// Latest reading of a sensor, unless it's stale
+    pub fn fresh(&self, name: &str) -> Option<f32> {
+        self.readings
+            .get(name)
+            .filter(|reading| !self.is_stale(reading))
+            .map(|reading| reading.temp_c)
+    }
+
+    /// Every sensor heard from, stale or not
+    pub fn statuses(&self) -> Vec<RemoteSensorStatus> {
+        self.readings
+            .iter()
+            .map(|(name, reading)| RemoteSensorStatus {
+                name: name.clone(),
+                temp_c: reading.temp_c,
+                age_secs: match reading.at.elapsed().as_secs() {
+                    secs if secs < 60 => secs / 10 * 10,
+                    secs => secs / 60 * 60,
+                },
+                stale: self.is_stale(reading),
+                battery: reading.battery,
+            })
+            .collect()
+    }
+
+    /// Remote sensors in `sensors` that went stale since the last call, each reported once until
+    /// it reports again. Sensors never heard from don't count, there is nothing to miss.
+    pub fn newly_stale(&mut self, sensors: &[WeightedSensor]) -> Vec<String> {
+        let mut newly_stale = Vec::new();
+        for weighted in sensors {
+            let SensorRef::Remote(name) = &weighted.sensor else {
+                continue;
+            };
+            let stale = self.readings.get(name).is_some_and(|reading| self.is_stale(reading));
+            if stale && self.stale_alerted.insert(name.clone()) {
+                newly_stale.push(name.clone());
+            }
+        }
+        newly_stale
+    }
+
+    /// Sensors whose battery ran low since the last call with their charge, each reported once
+    /// until it is replaced or recharged
+    pub fn newly_low_battery(&mut self) -> Vec<(String, u8)> {
+        let mut newly_low = Vec::new();
+        for (name, reading) in &self.readings {
+            let Some(percent) = reading.battery.and_then(|battery| battery.percent) else {
+                continue;
+            };
+            if percent <= LOW_BATTERY_PERCENT && self.low_battery_alerted.insert(name.clone()) {
+                newly_low.push((name.clone(), percent));
+            }
+        }
+        newly_low
+    }
+
+    /// Weighted average of the sensors that have a value, None when none of them has one
+    pub fn blend(&self, sensors: &[WeightedSensor], onboard_c: Option<f32>) -> Option<f32> {
+        let (sum, weights) = sensors
+            .iter()
+            .filter_map(|weighted| {
+                let temp_c = match &weighted.sensor {
+                    SensorRef::Onboard => onboard_c,
+                    SensorRef::Remote(name) => self.fresh(name),
+                }?;
+                Some((temp_c * weighted.weight, weighted.weight))
+            })
+            .fold((0.0, 0.0), |(sum, weights), (temp, weight)| (sum + temp, weights + weight));
+        (weights > 0.0).then(|| sum / weights)
+    }
+
+    /// Names of the remote sensors in `sensors` that currently have a value
+    pub fn in_use<'a>(&self, sensors: &'a [WeightedSensor]) -> Vec<&'a str> {
+        sensors
+            .iter()
+            .filter(|weighted| weighted.weight > 0.0)
+            .filter_map(|weighted| match &weighted.sensor {
+                SensorRef::Remote(name) if self.fresh(name).is_some() => Some(name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,224 @@
+// Optional DS3231 real time clock on the shared I2C bus. Keeps the time across power cycles
+// in homes without Wi-Fi so schedules still run on time. When SNTP is available the RTC is set
+// from it, and the error it built up since is trimmed out through its aging offset register.
+// Without SNTP the system clock is periodically reset from the RTC, which drifts far less
+// than the ESP32's crystal.
+
+use std::sync::PoisonError;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use chrono::{DateTime, Datelike, NaiveDate, Timelike, Utc};
+
+use crate::bsp::SharedI2c;
+use crate::clock;
+
+const ADDRESS: u8 = 0x68;
+const REG_TIME: u8 = 0x00;
+const REG_STATUS: u8 = 0x0F;
+const REG_AGING: u8 = 0x10;
+/// Set when the oscillator stopped (e.g. dead backup battery), the time is garbage then
+const STATUS_OSF: u8 = 0x80;
+const I2C_TIMEOUT: u32 = 1000;
+/// Roughly how much one step of the aging offset trims at room temperature
+const AGING_PPM_PER_STEP: f64 = 0.1;
+/// The RTC only keeps whole seconds, so its error needs this long to mean anything
+const MIN_TRIM_MEASUREMENT: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+const CHECK_INTERVAL: Duration = Duration::from_secs(10 * 60);
+/// Without an SNTP sync this recent the system clock is reset from the RTC
+const SNTP_FRESH: Duration = Duration::from_secs(60 * 60);
+const STACK_SIZE: usize = 4096;
+
+fn from_bcd(value: u8) -> u32 {
+    ((value >> 4) * 10 + (value & 0x0F)) as u32
+}
+
+fn to_bcd(value: u32) -> u8 {
+    (((value / 10) << 4) | (value % 10)) as u8
+}
+
+pub struct Ds3231 {
+    i2c: SharedI2c,
+}
+
+impl Ds3231 {
+    /// Returns None when there is no RTC on the bus
+    pub fn probe(i2c: SharedI2c) -> Option<Self> {
+        let rtc = Self { i2c };
+        match rtc.read_register(REG_STATUS) {
+            Ok(_) => {
+                log::info!("DS3231 RTC found");
+                Some(rtc)
+            }
+            Err(_) => None,
+        }
+    }
+
+    fn read_register(&self, register: u8) -> anyhow::Result<u8> {
+        let mut value = [0u8];
+        self.read(register, &mut value)?;
+        Ok(value[0])
+    }
+
+    fn read(&self, register: u8, buf: &mut [u8]) -> anyhow::Result<()> {
+        let mut i2c = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+        i2c.write_read(ADDRESS, &[register], buf, I2C_TIMEOUT)?;
+        Ok(())
+    }
+
+    fn write(&self, register: u8, bytes: &[u8]) -> anyhow::Result<()> {
+        let mut frame = Vec::with_capacity(bytes.len() + 1);
+        frame.push(register);
+        frame.extend_from_slice(bytes);
+        let mut i2c = self.i2c.lock().unwrap_or_else(PoisonError::into_inner);
+        i2c.write(ADDRESS, &frame, I2C_TIMEOUT)?;
+        Ok(())
+    }
+
+    /// Time kept by the RTC (UTC), None if its oscillator stopped since it was last set
+    pub fn now(&self) -> anyhow::Result<Option<DateTime<Utc>>> {
+        if self.read_register(REG_STATUS)? & STATUS_OSF != 0 {
+            return Ok(None);
+        }
+        let mut regs = [0u8; 7];
+        self.read(REG_TIME, &mut regs)?;
+        let hour = if regs[2] & 0x40 != 0 {
+            // 12 hour mode, bit 5 is PM
+            from_bcd(regs[2] & 0x1F) % 12 + if regs[2] & 0x20 != 0 { 12 } else { 0 }
+        } else {
+            from_bcd(regs[2] & 0x3F)
+        };
+        let century = if regs[5] & 0x80 != 0 { 100 } else { 0 };
+        let year = 2000 + century + from_bcd(regs[6]) as i32;
+        NaiveDate::from_ymd_opt(year, from_bcd(regs[5] & 0x1F), from_bcd(regs[4] & 0x3F))
+            .and_then(|date| date.and_hms_opt(hour, from_bcd(regs[1] & 0x7F), from_bcd(regs[0] & 0x7F)))
+            .map(|time| Some(time.and_utc()))
+            .ok_or_else(|| anyhow!("RTC holds an invalid date"))
+    }
+
+    /// Set the RTC (24 hour mode) and clear the oscillator stopped flag
+    pub fn set(&self, time: DateTime<Utc>) -> anyhow::Result<()> {
+        let year = time.year() - 2000;
+        if !(0..200).contains(&year) {
+            return Err(anyhow!("Year {} out of range for the RTC", time.year()));
+        }
+        let century = if year >= 100 { 0x80 } else { 0 };
+        self.write(
+            REG_TIME,
+            &[
+                to_bcd(time.second()),
+                to_bcd(time.minute()),
+                to_bcd(time.hour()),
+                to_bcd(time.weekday().number_from_monday()),
+                to_bcd(time.day()),
+                to_bcd(time.month()) | century,
+                to_bcd(year as u32 % 100),
+            ],
+        )?;
+        let status = self.read_register(REG_STATUS)?;
+        self.write(REG_STATUS, &[status & !STATUS_OSF])
+    }
+
+    fn aging(&self) -> anyhow::Result<i8> {
+        Ok(self.read_register(REG_AGING)? as i8)
+    }
+
+    fn set_aging(&self, aging: i8) -> anyhow::Result<()> {
+        self.write(REG_AGING, &[aging as u8])
+    }
+
+    /// Set the system clock from the RTC. Returns whether it held a valid time.
+    pub fn restore_clock(&self) -> bool {
+        match self.now() {
+            Ok(Some(time)) => {
+                log::info!("Clock set from RTC: {}", time);
+                clock::set_unix_secs(time.timestamp().max(0) as u64);
+                true
+            }
+            Ok(None) => {
+                log::warn!("RTC lost its time, waiting for SNTP");
+                false
+            }
+            Err(e) => {
+                log::error!("Failed to read RTC: {}", e);
+                false
+            }
+        }
+    }
+}
+
+/// Keeps the RTC and the system clock in line with each other
+struct RtcSync {
+    rtc: Ds3231,
+    /// SNTP syncs already copied to the RTC
+    seen_syncs: u32,
+    /// Unix seconds when the RTC was last set from SNTP
+    set_at: Option<u64>,
+}
+
+impl RtcSync {
+    fn tick(&mut self) {
+        let syncs = clock::sntp_syncs();
+        if syncs != self.seen_syncs {
+            self.seen_syncs = syncs;
+            self.update_from_sntp();
+        } else if clock::since_last_sync().is_none_or(|since| since >= SNTP_FRESH) {
+            self.rtc.restore_clock();
+        }
+    }
+
+    /// Trim the aging offset by the error built up since it was last set, then set it
+    fn update_from_sntp(&mut self) {
+        let now = clock::unix_secs();
+        if let (Some(set_at), Ok(Some(rtc_time))) = (self.set_at, self.rtc.now()) {
+            let elapsed = now.saturating_sub(set_at);
+            if elapsed >= MIN_TRIM_MEASUREMENT.as_secs() {
+                let error_secs = rtc_time.timestamp() - now as i64;
+                let ppm = error_secs as f64 / elapsed as f64 * 1e6;
+                // A higher aging offset slows the oscillator down
+                let steps = (ppm / AGING_PPM_PER_STEP).round() as i32;
+                if steps != 0 {
+                    match self.rtc.aging() {
+                        Ok(aging) => {
+                            let aging = (aging as i32 + steps).clamp(i8::MIN as i32, i8::MAX as i32) as i8;
+                            log::info!("RTC drifted {:.2} ppm, aging offset now {}", ppm, aging);
+                            if let Err(e) = self.rtc.set_aging(aging) {
+                                log::error!("Failed to set RTC aging offset: {}", e);
+                            }
+                        }
+                        Err(e) => log::error!("Failed to read RTC aging offset: {}", e),
+                    }
+                }
+            } else {
+                // Too early to tell the drift apart from rounding, keep measuring from the old set
+                return;
+            }
+        }
+        let Some(time) = DateTime::from_timestamp(now as i64, 0) else {
+            return;
+        };
+        match self.rtc.set(time) {
+            Ok(()) => self.set_at = Some(now),
+            Err(e) => log::error!("Failed to set RTC: {}", e),
+        }
+    }
+}
+
+/// Keep the RTC synced from SNTP, and the system clock from the RTC while SNTP is unavailable
+pub fn spawn(rtc: Ds3231) -> anyhow::Result<()> {
+    let mut sync = RtcSync {
+        rtc,
+        seen_syncs: clock::sntp_syncs(),
+        set_at: None,
+    };
+    thread::Builder::new()
+        .name("rtc".to_string())
+        .stack_size(STACK_SIZE)
+        .spawn(move || loop {
+            thread::sleep(CHECK_INTERVAL);
+            sync.tick();
+        })?;
+    Ok(())
+}
@@ -0,0 +1,208 @@
+// Weekly setpoint schedule. Each day is a list of periods; a period's setpoints
+// apply from its start time until the next period starts (carrying over midnight).
+// Exception days follow another weekday's schedule, e.g. a holiday run as a Sunday.
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::events::ModeStatus;
+use crate::storage::Storage;
+
+/// Name given to the schedule migrated from before profiles existed
+pub const DEFAULT_SCHEDULE_PROFILE: &str = "Default";
+const PROFILE_NAMES_KEY: &str = "sched_names";
+/// Exception days kept per profile, holidays for a year or so
+pub const MAX_SCHEDULE_EXCEPTIONS: usize = 32;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SchedulePeriod {
+    pub start: NaiveTime,
+    /// Setpoints in Celsius
+    pub heat_setpoint_c: f32,
+    pub cool_setpoint_c: f32,
+}
+
+impl SchedulePeriod {
+    pub fn setpoint_c(&self, mode: &ModeStatus) -> Option<f32> {
+        match mode {
+            ModeStatus::Heat => Some(self.heat_setpoint_c),
+            ModeStatus::Cool => Some(self.cool_setpoint_c),
+            ModeStatus::Off => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleException {
+    pub date: NaiveDate,
+    /// The weekday whose periods are used on `date`
+    pub follows: Weekday,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WeeklySchedule {
+    /// Periods for each day, Monday first, each sorted by start time
+    pub days: [Vec<SchedulePeriod>; 7],
+    pub exceptions: Vec<ScheduleException>,
+}
+
+impl WeeklySchedule {
+    /// Periods used on `date`, taking exceptions into account
+    fn periods_on(&self, date: NaiveDate) -> &[SchedulePeriod] {
+        let weekday = self
+            .exceptions
+            .iter()
+            .find(|exception| exception.date == date)
+            .map(|exception| exception.follows)
+            .unwrap_or(date.weekday());
+        &self.days[weekday.num_days_from_monday() as usize]
+    }
+
+    /// Make `date` follow another weekday's periods, or with None go back to its own. Exceptions
+    /// before `today` are dropped while at it, they won't come round again.
+    pub fn set_exception(&mut self, date: NaiveDate, follows: Option<Weekday>, today: Option<NaiveDate>) {
+        self.exceptions.retain(|exception| exception.date != date && today.is_none_or(|today| exception.date >= today));
+        if let Some(follows) = follows {
+            self.exceptions.push(ScheduleException { date, follows });
+            self.exceptions.sort_by_key(|exception| exception.date);
+        }
+    }
+
+    /// Exception days from `today` on
+    pub fn upcoming_exceptions(&self, today: NaiveDate) -> impl Iterator<Item = &ScheduleException> {
+        self.exceptions.iter().filter(move |exception| exception.date >= today)
+    }
+
+    /// The period active at `now` and when it started. Walks back up to a week to
+    /// find the last period that started, so an empty morning carries over the previous evening.
+    pub fn active_period(&self, now: NaiveDateTime) -> Option<(NaiveDateTime, &SchedulePeriod)> {
+        for days_back in 0..7 {
+            let date = now.date() - chrono::Duration::days(days_back);
+            let started = self
+                .periods_on(date)
+                .iter()
+                .rev()
+                .map(|period| (date.and_time(period.start), period))
+                .find(|(start, _)| *start <= now);
+            if started.is_some() {
+                return started;
+            }
+        }
+        None
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ScheduleProfile {
+    pub name: String,
+    pub schedule: WeeklySchedule,
+}
+
+/// Named weekly schedules (Home, Summer, ...). Each profile is stored under its own
+/// NVS key so editing one doesn't rewrite all of them.
+#[derive(Default)]
+pub struct ScheduleProfiles {
+    profiles: Vec<ScheduleProfile>,
+}
+
+impl ScheduleProfiles {
+    pub fn load(storage: &Storage) -> Self {
+        let names: Vec<String> = storage.load(PROFILE_NAMES_KEY).unwrap_or_default();
+        let profiles = names
+            .into_iter()
+            .filter_map(|name| {
+                let schedule = storage.load(&profile_key(&name));
+                if schedule.is_none() {
+                    log::error!("Schedule profile {} is missing from storage", name);
+                }
+                Some(ScheduleProfile { schedule: schedule?, name })
+            })
+            .collect();
+        Self { profiles }
+    }
+
+    pub fn get(&self, name: &str) -> Option<&WeeklySchedule> {
+        self.profiles.iter().find(|profile| profile.name == name).map(|profile| &profile.schedule)
+    }
+
+    pub fn names(&self) -> Vec<String> {
+        self.profiles.iter().map(|profile| profile.name.clone()).collect()
+    }
+
+    pub fn all(&self) -> &[ScheduleProfile] {
+        &self.profiles
+    }
+
+    /// Add a profile or replace the one with the same name
+    pub fn upsert(&mut self, storage: &mut Storage, profile: ScheduleProfile) -> anyhow::Result<()> {
+        storage.save(&profile_key(&profile.name), &profile.schedule)?;
+        match self.profiles.iter_mut().find(|existing| existing.name == profile.name) {
+            Some(existing) => *existing = profile,
+            None => {
+                self.profiles.push(profile);
+                self.save_names(storage)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn remove(&mut self, storage: &mut Storage, name: &str) -> anyhow::Result<()> {
+        self.profiles.retain(|profile| profile.name != name);
+        self.save_names(storage)?;
+        storage.remove(&profile_key(name))
+    }
+
+    /// Rename a profile, keeping its place in the list. The caller makes sure `to` isn't taken.
+    pub fn rename(&mut self, storage: &mut Storage, from: &str, to: &str) -> anyhow::Result<()> {
+        let Some(profile) = self.profiles.iter_mut().find(|profile| profile.name == from) else {
+            return Ok(());
+        };
+        storage.save(&profile_key(to), &profile.schedule)?;
+        profile.name = to.to_string();
+        self.save_names(storage)?;
+        storage.remove(&profile_key(from))
+    }
+
+    fn save_names(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save(PROFILE_NAMES_KEY, &self.names())
+    }
+}
+
+/// NVS keys are limited to 15 characters, so profiles are keyed by a hash of their name
+fn profile_key(name: &str) -> String {
+    // 32 bit FNV-1a
+    let hash = name.bytes().fold(0x811c_9dc5u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+    format!("sch_{:08x}", hash)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 12, day).unwrap()
+    }
+
+    #[test]
+    fn an_exception_day_runs_like_the_weekday_it_follows() {
+        let mut schedule = WeeklySchedule::default();
+        let sunday = SchedulePeriod { start: NaiveTime::from_hms_opt(8, 0, 0).unwrap(), heat_setpoint_c: 20.0, cool_setpoint_c: 25.0 };
+        schedule.days[Weekday::Sun.num_days_from_monday() as usize].push(sunday.clone());
+        // Christmas 2026 is a Friday
+        schedule.set_exception(date(25), Some(Weekday::Sun), None);
+        let (_, period) = schedule.active_period(date(25).and_hms_opt(9, 0, 0).unwrap()).unwrap();
+        assert_eq!(*period, sunday);
+    }
+
+    #[test]
+    fn setting_an_exception_replaces_the_one_on_that_date_and_drops_past_ones() {
+        let mut schedule = WeeklySchedule::default();
+        schedule.set_exception(date(1), Some(Weekday::Sat), None);
+        schedule.set_exception(date(25), Some(Weekday::Sat), None);
+        schedule.set_exception(date(25), Some(Weekday::Sun), Some(date(10)));
+        assert_eq!(schedule.exceptions, vec![ScheduleException { date: date(25), follows: Weekday::Sun }]);
+        schedule.set_exception(date(25), None, Some(date(10)));
+        assert!(schedule.exceptions.is_empty());
+    }
+}
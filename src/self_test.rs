@@ -0,0 +1,156 @@
+// Optional power-on self-test: checks the hardware the thermostat depends on before the control
+// loop starts, so a loose sensor or a dead relay driver shows up on the boot status screen
+// instead of as a cold house. Failures are reported, they don't stop the boot.
+
+#[cfg(not(feature = "no-ui"))]
+use std::sync::PoisonError;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::bsp::SharedI2c;
+use crate::controller::{Controller, Relay, TemperatureReading};
+use crate::storage::Storage;
+
+/// GT911 touch controller and its product id register
+#[cfg(not(feature = "no-ui"))]
+const GT911_ADDRESS: u8 = 0x5D;
+#[cfg(not(feature = "no-ui"))]
+const GT911_REG_PRODUCT_ID: [u8; 2] = [0x81, 0x40];
+/// IO expander mode register, written with the same value as at boot
+#[cfg(not(feature = "no-ui"))]
+const IO_EXPANDER_MODE: u8 = 0x24;
+#[cfg(not(feature = "no-ui"))]
+const I2C_TIMEOUT: u32 = 1000;
+/// Indoor readings outside this range mean a bad sensor, not a cold house (Celsius)
+const PLAUSIBLE_TEMP_C: std::ops::RangeInclusive<f32> = -10.0..=50.0;
+const CONVERSION_TIMEOUT: Duration = Duration::from_millis(1500);
+const NVS_TEST_KEY: &str = "selftest";
+/// Long enough for the pin to settle, far too short for any HVAC control to react to
+const RELAY_PULSE: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SelfTestResult {
+    pub name: &'static str,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SelfTestReport {
+    pub results: Vec<SelfTestResult>,
+}
+
+impl SelfTestReport {
+    fn record(&mut self, name: &'static str, result: anyhow::Result<String>) {
+        let (passed, detail) = match result {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, e.to_string()),
+        };
+        if passed {
+            log::info!("Self-test {}: passed ({})", name, detail);
+        } else {
+            log::error!("Self-test {}: FAILED ({})", name, detail);
+        }
+        self.results.push(SelfTestResult { name, passed, detail });
+    }
+
+    pub fn failures(&self) -> usize {
+        self.results.iter().filter(|result| !result.passed).count()
+    }
+
+    /// One line per check, used by the boot status screen
+    pub fn summary_lines(&self) -> Vec<String> {
+        self.results
+            .iter()
+            .map(|result| format!("{} {}: {}", if result.passed { "OK  " } else { "FAIL" }, result.name, result.detail))
+            .collect()
+    }
+}
+
+/// Run every check. Expects the relays to be off, as they are at boot.
+pub fn run(i2c: &SharedI2c, controller: &mut Controller, storage: &mut Storage) -> SelfTestReport {
+    let mut report = SelfTestReport::default();
+    #[cfg(not(feature = "no-ui"))]
+    {
+        report.record("touch controller", check_touch(i2c));
+        report.record("IO expander", check_io_expander(i2c));
+    }
+    #[cfg(feature = "no-ui")]
+    let _ = i2c;
+    report.record("temperature sensor", check_temperature(controller));
+    report.record("NVS", check_nvs(storage));
+    for relay in [Relay::Heat, Relay::Cool, Relay::Fan, Relay::ReversingValve, Relay::Ventilation, Relay::FrostStat] {
+        if relay == Relay::Ventilation && !controller.has_ventilation() {
+            continue;
+        }
+        if relay == Relay::FrostStat && !controller.has_frost_stat() {
+            continue;
+        }
+        let name = match relay {
+            Relay::Heat => "heat relay",
+            Relay::Cool => "cool relay",
+            Relay::Fan => "fan relay",
+            Relay::ReversingValve => "reversing valve relay",
+            Relay::Ventilation => "ventilation relay",
+            Relay::FrostStat => "frost-stat relay",
+        };
+        report.record(name, check_relay(controller, relay));
+    }
+    report
+}
+
+#[cfg(not(feature = "no-ui"))]
+fn check_touch(i2c: &SharedI2c) -> anyhow::Result<String> {
+    let mut product_id = [0u8; 4];
+    i2c.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .write_read(GT911_ADDRESS, &GT911_REG_PRODUCT_ID, &mut product_id, I2C_TIMEOUT)?;
+    let product_id = String::from_utf8_lossy(&product_id).trim_end_matches('\0').to_string();
+    if product_id != "911" {
+        anyhow::bail!("unexpected product id {:?}", product_id);
+    }
+    Ok("GT911".to_string())
+}
+
+#[cfg(not(feature = "no-ui"))]
+fn check_io_expander(i2c: &SharedI2c) -> anyhow::Result<String> {
+    // Same mode the display setup put it in, so this is harmless
+    i2c.lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .write(IO_EXPANDER_MODE, &[0x1], I2C_TIMEOUT)?;
+    Ok("responding".to_string())
+}
+
+fn check_temperature(controller: &mut Controller) -> anyhow::Result<String> {
+    if !controller.start_temperature_conversion() {
+        anyhow::bail!("no DS18B20 found");
+    }
+    let started = Instant::now();
+    loop {
+        match controller.poll_temperature_conversion() {
+            TemperatureReading::Ready(temp_c) if PLAUSIBLE_TEMP_C.contains(&temp_c) => return Ok(format!("{:.1}°C", temp_c)),
+            TemperatureReading::Ready(temp_c) => anyhow::bail!("implausible reading {:.1}°C", temp_c),
+            TemperatureReading::Failed => anyhow::bail!("reading failed"),
+            TemperatureReading::Pending if started.elapsed() > CONVERSION_TIMEOUT => anyhow::bail!("conversion timed out"),
+            TemperatureReading::Pending => std::thread::sleep(Duration::from_millis(50)),
+        }
+    }
+}
+
+fn check_nvs(storage: &mut Storage) -> anyhow::Result<String> {
+    // SAFETY: plain getter with no preconditions
+    let value = unsafe { esp_idf_svc::sys::esp_random() };
+    storage.save(NVS_TEST_KEY, &value)?;
+    match storage.load::<u32>(NVS_TEST_KEY) {
+        Some(read) if read == value => Ok("read back".to_string()),
+        _ => anyhow::bail!("value didn't read back"),
+    }
+}
+
+fn check_relay(controller: &mut Controller, relay: Relay) -> anyhow::Result<String> {
+    match controller.pulse_relay(relay, RELAY_PULSE)? {
+        true => Ok("toggled".to_string()),
+        false => anyhow::bail!("pin didn't follow, or another relay was on"),
+    }
+}
@@ -0,0 +1,11 @@
+// The sensor drivers in one place, for projects that only want the hardware side. Each lives
+// in the module of the feature that uses it, this only gathers them.
+//
+// The DS18B20 temperature sensor is read through `Controller`, as it shares the relay board.
+
+pub use crate::air_quality::Scd40;
+pub use crate::ambient_light::AmbientLightSensor;
+pub use crate::controller::TemperatureReading;
+pub use crate::proximity::ProximityDetector;
+#[cfg(feature = "rtc")]
+pub use crate::rtc::Ds3231;
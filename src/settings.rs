@@ -0,0 +1,509 @@
+// User settings persisted to NVS as a versioned JSON blob.
+//
+// The blob is stored as `{"version": N, "settings": {...}}`. When the shape of `Settings`
+// changes in a way serde defaults can't handle (renamed or re-interpreted fields), bump
+// `SETTINGS_VERSION` and add a migration from the previous version to `MIGRATIONS`.
+// Simply adding a field only needs a `#[serde(default)]`.
+
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::air_quality::VentilationSettings;
+use crate::ambient_light::BrightnessSettings;
+use crate::boost::BoostSettings;
+use crate::burn_in::BurnInProtection;
+use crate::clock::TimeWindow;
+use crate::controller::{Controller, RelayPolarity};
+use crate::demand_response::DemandResponseSettings;
+use crate::dual_fuel::DualFuel;
+use crate::maintenance::MaintenanceReboot;
+use crate::network::NetworkSettings;
+use crate::comfort_profile::ComfortSettings;
+use crate::events::{ComfortProfile, DisplayPrecision, FanStatus, ModeStatus, RestStatus};
+use crate::frost_stat::FrostStat;
+use crate::notify::NotificationTarget;
+use crate::open_window::OpenWindowDetection;
+use crate::peak::PeakPricing;
+use crate::ota::UpdateChannel;
+use crate::proximity::ProximityWake;
+use crate::sleep::SleepSettings;
+use crate::remote_sensors::{BleSensor, SensorSelection};
+use crate::schedule::{ScheduleProfile, WeeklySchedule, DEFAULT_SCHEDULE_PROFILE};
+use crate::storage::Storage;
+use crate::system_profile::SystemProfile;
+use crate::time_format::TimeFormat;
+use crate::timezone::DEFAULT_TIMEZONE;
+use crate::vacation::Vacation;
+use crate::wireguard::WireguardSettings;
+
+const STORAGE_KEY: &str = "settings";
+/// Faster ticks starve the ui thread, slower ones make the relays sluggish to respond
+const CONTROL_LOOP_INTERVAL_MIN_MS: u32 = 250;
+const CONTROL_LOOP_INTERVAL_MAX_MS: u32 = 10_000;
+/// A conversion takes 750ms, and readings older than a few minutes aren't trusted anyway
+const SENSOR_POLL_INTERVAL_MIN_SECS: u32 = 1;
+const SENSOR_POLL_INTERVAL_MAX_SECS: u32 = 120;
+pub const SETTINGS_VERSION: u32 = 5;
+
+/// Migration from version `n` to `n + 1` lives at index `n - 1`.
+/// Each one takes the settings object of the old version and returns the new one.
+const MIGRATIONS: &[fn(Value) -> Value] = &[migrate_v1_to_v2, migrate_v2_to_v3, migrate_v3_to_v4, migrate_v4_to_v5];
+
+/// v2 moved the single weekly schedule out of the settings into named profiles stored on their own.
+/// The old schedule is handed over as `legacy_schedule` and becomes the "Default" profile.
+fn migrate_v1_to_v2(mut value: Value) -> Value {
+    if let Some(object) = value.as_object_mut() {
+        match object.remove("schedule") {
+            Some(schedule) if !schedule.is_null() => {
+                object.insert("legacy_schedule".to_string(), schedule);
+                object.insert("active_schedule".to_string(), Value::from(DEFAULT_SCHEDULE_PROFILE));
+            }
+            _ => {}
+        }
+    }
+    value
+}
+
+/// v3 replaced the Slow/Normal/Fast differential with comfort profiles that also cover the heat
+/// anticipator and fan run-on. Settings matching a preset keep it, anything else becomes Custom.
+fn migrate_v2_to_v3(mut value: Value) -> Value {
+    let Some(object) = value.as_object_mut() else {
+        return value;
+    };
+    let (profile, heat_differential_c, cool_differential_c) = match object.remove("diff_mode").as_ref().and_then(Value::as_str) {
+        Some("Slow") => (ComfortProfile::Eco, 1.0, 0.9),
+        Some("Fast") => (ComfortProfile::Comfort, 0.3, 0.5),
+        _ => (ComfortProfile::Balanced, 0.4, 0.7),
+    };
+    let mut custom = ComfortSettings {
+        heat_differential_c,
+        cool_differential_c,
+        ..ComfortSettings::default()
+    };
+    if let Some(anticipator) = object.remove("heat_anticipator_c").as_ref().and_then(Value::as_f64) {
+        custom.heat_anticipator_c = anticipator as f32;
+    }
+    if let Some(secs) = object.remove("heat_fan_run_on_secs").as_ref().and_then(Value::as_u64) {
+        custom.heat_fan_run_on_secs = secs as u32;
+    }
+    if let Some(secs) = object.remove("cool_fan_run_on_secs").as_ref().and_then(Value::as_u64) {
+        custom.cool_fan_run_on_secs = secs as u32;
+    }
+    let profile = if profile.preset() == Some(custom) { profile } else { ComfortProfile::Custom };
+    object.insert("comfort_profile".to_string(), serde_json::to_value(profile).unwrap_or_default());
+    object.insert("custom_comfort".to_string(), serde_json::to_value(custom).unwrap_or_default());
+    value
+}
+
+/// v4 switched every enum to snake_case names ("HeatPump" became "heat_pump"), so all
+/// transports share one encoding that doesn't follow renames in the code.
+fn migrate_v3_to_v4(mut value: Value) -> Value {
+    const ENUM_PATHS: &[&[&str]] = &[
+        &["mode"],
+        &["comfort_profile"],
+        &["rest_mode"],
+        &["fan_mode"],
+        &["display_precision"],
+        &["update_channel"],
+        &["proximity_wake", "sensitivity"],
+        &["open_window_detection", "sensitivity"],
+        &["dual_fuel", "reversing_valve"],
+    ];
+    for path in ENUM_PATHS {
+        let Some(Value::String(name)) = path.iter().try_fold(&mut value, |value, key| value.get_mut(*key)) else {
+            continue;
+        };
+        *name = to_snake_case(name);
+    }
+    // Variants with data are stored as {"Ntfy": {...}}
+    if let Some(Value::Object(target)) = value.get_mut("notification_target") {
+        *target = std::mem::take(target).into_iter().map(|(name, fields)| (to_snake_case(&name), fields)).collect();
+    }
+    value
+}
+
+/// v5 remembers a setpoint per mode. Both start out at the one shared setpoint of v4.
+fn migrate_v4_to_v5(mut value: Value) -> Value {
+    let Some(object) = value.as_object_mut() else {
+        return value;
+    };
+    if let Some(target) = object.get("target_temp_c").cloned() {
+        object.insert("heat_setpoint_c".to_string(), target.clone());
+        object.insert("cool_setpoint_c".to_string(), target);
+    }
+    value
+}
+
+fn to_snake_case(name: &str) -> String {
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (i, c) in name.chars().enumerate() {
+        if c.is_ascii_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    snake
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// Target temperature in Celsius (base unit), the setpoint of the current mode
+    pub target_temp_c: f32,
+    /// Last setpoint used in heat and in cool mode, restored when switching to it (Celsius)
+    pub heat_setpoint_c: f32,
+    pub cool_setpoint_c: f32,
+    pub mode: ModeStatus,
+    pub comfort_profile: ComfortProfile,
+    /// Used while the comfort profile is Custom
+    pub custom_comfort: ComfortSettings,
+    pub rest_mode: RestStatus,
+    pub fan_mode: FanStatus,
+    pub use_fahrenheit: bool,
+    pub display_precision: DisplayPrecision,
+    /// Pixel shift and nightly screen wash for displays that are always on
+    pub burn_in: BurnInProtection,
+    /// Auto brightness from the ambient light sensor, and the manual brightness
+    pub brightness: BrightnessSettings,
+    /// Wake the dimmed screen when someone walks up to it, if there is a proximity sensor
+    pub proximity_wake: ProximityWake,
+    /// Which sensors drive control at which time of day, the onboard one by default
+    pub sensor_selection: SensorSelection,
+    /// BLE thermometers reporting as remote sensors, with the `ble-sensors` feature
+    pub ble_sensors: Vec<BleSensor>,
+    /// Remote sensors that haven't reported for this long are left out of control (minutes)
+    pub remote_sensor_stale_mins: u32,
+    /// Cool to the "feels like" temperature (heat index) instead of the dry bulb temperature
+    /// when humidity is known
+    pub feels_like_control: bool,
+    /// Relative humidity (percent) above which cooling overcools to dehumidify
+    pub max_humidity: Option<f32>,
+    /// How far below the cool setpoint cooling may run while dehumidifying (Celsius)
+    pub overcool_limit_c: f32,
+    /// ERV/HRV on the ventilation relay run by the CO2 level, None when there is none
+    pub ventilation: Option<VentilationSettings>,
+    /// Frost protection on the frost-stat relay, None when it isn't used
+    pub frost_stat: Option<FrostStat>,
+    /// How long the fan runs alone before the compressor starts (seconds, 0 to disable)
+    pub cool_fan_lead_secs: u32,
+    /// Time between control loop ticks (milliseconds)
+    pub control_loop_interval_ms: u32,
+    /// Time between temperature sensor reads (seconds)
+    pub sensor_poll_interval_secs: u32,
+    /// Raise an alert when the compressor starts more often than this in an hour
+    pub max_compressor_starts_per_hour: u32,
+    /// Local time window where fan circulation is suppressed and the display dims
+    pub quiet_hours: Option<TimeWindow>,
+    /// Pause heating when the temperature signature looks like an open window, None to disable
+    pub open_window_detection: Option<OpenWindowDetection>,
+    /// Heat calls are locked out while it is warmer than this outside (Celsius), None to disable
+    pub heat_lockout_above_c: Option<f32>,
+    /// Cool calls are locked out while it is colder than this outside (Celsius), None to disable
+    pub cool_lockout_below_c: Option<f32>,
+    /// Heat calls are cut off while the measured temperature is above this (Celsius), for
+    /// attics, garages and other spaces where a runaway heater can go unnoticed. None to disable.
+    pub high_temp_cutoff_c: Option<f32>,
+    /// What the relays drive: forced air, a hydronic zone valve and pump, or dry contacts
+    pub system: SystemProfile,
+    /// Heat pump plus furnace configuration, None for a single heat source on the heat relay
+    pub dual_fuel: Option<DualFuel>,
+    /// Utility peak rate windows to precondition ahead of and save energy during, if any
+    pub peak_pricing: Option<PeakPricing>,
+    /// How the thermostat responds to demand response events from the utility
+    pub demand_response: DemandResponseSettings,
+    /// How long and how hard the boost button heats or cools
+    pub boost: BoostSettings,
+    /// Night setpoints and wake time for the sleep preset
+    pub sleep: SleepSettings,
+    /// Planned away period, cleared automatically once it is over
+    pub vacation: Option<Vacation>,
+    /// How long a setpoint changed by hand holds before the schedule may change it (minutes)
+    pub manual_hold_mins: u32,
+    /// Name of the schedule profile in use, None to only use the manual setpoint
+    pub active_schedule: Option<String>,
+    /// Schedule carried over from a v1 settings blob, moved into the profiles at boot
+    #[serde(skip_serializing)]
+    pub legacy_schedule: Option<WeeklySchedule>,
+    /// Where the firmware update manifest is fetched from, None to disable update checks
+    pub ota_manifest_url: Option<String>,
+    pub update_channel: UpdateChannel,
+    /// How long a new firmware gets to come up healthy before rolling back (minutes)
+    pub ota_health_check_mins: u32,
+    /// Check the sensor, I2C devices, NVS and relay drivers at power on
+    pub boot_self_test: bool,
+    /// Which pin level closes the relays, depends on the relay board fitted
+    pub relay_polarity: RelayPolarity,
+    /// Where daily/weekly summaries are POSTed as JSON, if anywhere
+    pub summary_webhook_url: Option<String>,
+    /// Used to estimate running costs in the summaries, in the user's currency
+    pub heating_cost_per_hour: f32,
+    pub cooling_cost_per_hour: f32,
+    /// Where critical alerts are pushed to, if anywhere
+    pub notification_target: Option<NotificationTarget>,
+    /// Weekly reboot between calls to start over with an unfragmented heap, None to disable
+    pub maintenance_reboot: Option<MaintenanceReboot>,
+    /// 12 or 24 hour clock and the order of day and month, wherever times and dates are shown
+    pub time_format: TimeFormat,
+    /// Zone name from `timezone::ZONES`, or a POSIX TZ string for anywhere else
+    pub timezone: String,
+    /// Hostname and DHCP or static addressing of the Wi-Fi station
+    pub network: NetworkSettings,
+    /// WireGuard tunnel for remote access from outside the LAN, None to disable
+    pub wireguard: Option<WireguardSettings>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            target_temp_c: 21.0, // ~70°F
+            heat_setpoint_c: 21.0,
+            cool_setpoint_c: 24.0, // ~75°F
+            mode: ModeStatus::Off,
+            comfort_profile: ComfortProfile::Balanced,
+            custom_comfort: ComfortSettings::default(),
+            rest_mode: RestStatus::Off,
+            fan_mode: FanStatus::Auto,
+            use_fahrenheit: true,
+            display_precision: DisplayPrecision::Tenth,
+            burn_in: BurnInProtection::default(),
+            brightness: BrightnessSettings::default(),
+            proximity_wake: ProximityWake::default(),
+            sensor_selection: SensorSelection::default(),
+            ble_sensors: Vec::new(),
+            remote_sensor_stale_mins: 10,
+            feels_like_control: false,
+            max_humidity: None,
+            ventilation: None,
+            frost_stat: None,
+            overcool_limit_c: 1.5, // ~2.7°F
+            cool_fan_lead_secs: 0,
+            control_loop_interval_ms: 1000,
+            sensor_poll_interval_secs: 1,
+            max_compressor_starts_per_hour: 6,
+            quiet_hours: None,
+            open_window_detection: None,
+            heat_lockout_above_c: None,
+            high_temp_cutoff_c: None,
+            cool_lockout_below_c: None,
+            system: SystemProfile::default(),
+            dual_fuel: None,
+            peak_pricing: None,
+            demand_response: DemandResponseSettings::default(),
+            boost: BoostSettings::default(),
+            sleep: SleepSettings::default(),
+            vacation: None,
+            manual_hold_mins: 60,
+            active_schedule: None,
+            legacy_schedule: None,
+            ota_manifest_url: None,
+            update_channel: UpdateChannel::Stable,
+            ota_health_check_mins: 10,
+            boot_self_test: false,
+            relay_polarity: RelayPolarity::ActiveHigh,
+            summary_webhook_url: None,
+            heating_cost_per_hour: 0.0,
+            cooling_cost_per_hour: 0.0,
+            notification_target: None,
+            maintenance_reboot: None,
+            time_format: TimeFormat::default(),
+            timezone: DEFAULT_TIMEZONE.to_string(),
+            network: NetworkSettings::default(),
+            wireguard: None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct VersionedSettings {
+    version: u32,
+    settings: Value,
+    /// Only present in exported backups, the profiles are stored separately on the device
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    schedule_profiles: Vec<ScheduleProfile>,
+}
+
+/// Settings that belong to the unit rather than to its configuration: its network identity, and
+/// the keys and tokens in it. Left out of exported backups, see `ConfigBackup::keep_device_settings`.
+const DEVICE_SETTINGS: &[&str] = &["network", "wireguard", "notification_target", "summary_webhook_url"];
+
+/// Everything exported and imported as one JSON document
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConfigBackup {
+    pub settings: Settings,
+    pub schedule_profiles: Vec<ScheduleProfile>,
+}
+
+impl ConfigBackup {
+    /// Export as versioned JSON, for backups or cloning to another thermostat. Secrets and the
+    /// unit's identity are left out.
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        let mut versioned = VersionedSettings {
+            schedule_profiles: self.schedule_profiles.clone(),
+            ..self.settings.to_versioned()?
+        };
+        if let Value::Object(settings) = &mut versioned.settings {
+            for name in DEVICE_SETTINGS {
+                settings.remove(*name);
+            }
+        }
+        Ok(serde_json::to_string(&versioned)?)
+    }
+
+    /// Keep what the unit has for the settings left out of exports, a backup doesn't carry them
+    /// and one from another thermostat mustn't clone its identity. Covers `DEVICE_SETTINGS`.
+    pub fn keep_device_settings(&mut self, current: &Settings) {
+        self.settings.network = current.network.clone();
+        self.settings.wireguard = current.wireguard.clone();
+        self.settings.notification_target = current.notification_target.clone();
+        self.settings.summary_webhook_url = current.summary_webhook_url.clone();
+    }
+
+    /// Import a backup made by `to_json`, possibly from older firmware.
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let mut stored: VersionedSettings = serde_json::from_str(json)?;
+        let mut schedule_profiles = std::mem::take(&mut stored.schedule_profiles);
+        let mut settings = Settings::from_versioned(stored)?;
+        // A v1 backup carries its schedule inside the settings
+        if let Some(schedule) = settings.legacy_schedule.take() {
+            schedule_profiles.push(ScheduleProfile {
+                name: DEFAULT_SCHEDULE_PROFILE.to_string(),
+                schedule,
+            });
+        }
+        Ok(Self {
+            settings,
+            schedule_profiles,
+        })
+    }
+}
+
+impl Settings {
+    /// Snap a setpoint to the step of the display unit: whole degrees in Fahrenheit,
+    /// half degrees in Celsius. Setpoints are always stored in Celsius.
+    pub fn snap_setpoint(&self, target_temp_c: f32) -> f32 {
+        if self.use_fahrenheit {
+            Controller::fahrenheit_to_celsius(Controller::celsius_to_fahrenheit(target_temp_c).round())
+        } else {
+            (target_temp_c * 2.0).round() / 2.0
+        }
+    }
+
+    /// Snap the setpoint of every mode to the step of the display unit
+    pub fn snap_setpoints(&mut self) {
+        self.target_temp_c = self.snap_setpoint(self.target_temp_c);
+        self.heat_setpoint_c = self.snap_setpoint(self.heat_setpoint_c);
+        self.cool_setpoint_c = self.snap_setpoint(self.cool_setpoint_c);
+    }
+
+    /// Switch mode, restoring the setpoint last used in the new one. Off keeps the current setpoint.
+    pub fn switch_mode(&mut self, mode: ModeStatus) {
+        match mode {
+            ModeStatus::Heat => self.target_temp_c = self.heat_setpoint_c,
+            ModeStatus::Cool => self.target_temp_c = self.cool_setpoint_c,
+            ModeStatus::Off => {}
+        }
+        self.mode = mode;
+    }
+
+    /// Change the setpoint, remembering it for the current mode
+    pub fn set_setpoint(&mut self, target_temp_c: f32) {
+        self.target_temp_c = target_temp_c;
+        match self.mode {
+            ModeStatus::Heat => self.heat_setpoint_c = target_temp_c,
+            ModeStatus::Cool => self.cool_setpoint_c = target_temp_c,
+            ModeStatus::Off => {}
+        }
+    }
+
+    /// Differentials, anticipator and fan run-on of the selected comfort profile
+    pub fn comfort(&self) -> ComfortSettings {
+        self.comfort_profile.preset().unwrap_or(self.custom_comfort)
+    }
+
+    /// Time between control loop ticks, kept within what the rest of the firmware copes with
+    pub fn control_loop_interval(&self) -> Duration {
+        Duration::from_millis(self.control_loop_interval_ms.clamp(CONTROL_LOOP_INTERVAL_MIN_MS, CONTROL_LOOP_INTERVAL_MAX_MS) as u64)
+    }
+
+    /// Time between temperature sensor reads, never longer than the sensor hold time
+    pub fn sensor_poll_interval(&self) -> Duration {
+        Duration::from_secs(self.sensor_poll_interval_secs.clamp(SENSOR_POLL_INTERVAL_MIN_SECS, SENSOR_POLL_INTERVAL_MAX_SECS) as u64)
+    }
+
+    /// Load the settings, migrating them from an older version if needed.
+    /// Falls back to defaults if nothing was stored or the blob can't be understood.
+    pub fn load(storage: &Storage) -> Self {
+        let Some(stored) = storage.load::<VersionedSettings>(STORAGE_KEY) else {
+            log::info!("No stored settings, using defaults");
+            return Self::default();
+        };
+        match Self::from_versioned(stored) {
+            Ok(settings) => settings,
+            Err(e) => {
+                log::error!("Failed to load stored settings, using defaults: {}", e);
+                Self::default()
+            }
+        }
+    }
+
+    fn from_versioned(stored: VersionedSettings) -> anyhow::Result<Self> {
+        if stored.version == 0 || stored.version > SETTINGS_VERSION {
+            // Probably written by newer firmware, refuse to guess what it means
+            anyhow::bail!("unsupported settings version {}", stored.version);
+        }
+        let mut value = stored.settings;
+        for version in stored.version..SETTINGS_VERSION {
+            log::info!("Migrating settings from version {} to {}", version, version + 1);
+            value = MIGRATIONS[(version - 1) as usize](value);
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+
+    pub fn save(&self, storage: &mut Storage) -> anyhow::Result<()> {
+        storage.save(STORAGE_KEY, &self.to_versioned()?)
+    }
+
+    fn to_versioned(&self) -> anyhow::Result<VersionedSettings> {
+        Ok(VersionedSettings {
+            version: SETTINGS_VERSION,
+            settings: serde_json::to_value(self)?,
+            schedule_profiles: Vec::new(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device_settings() -> Settings {
+        Settings {
+            network: NetworkSettings { hostname: "attic".to_string(), ..Default::default() },
+            notification_target: Some(NotificationTarget::Pushover { app_token: "app-token".to_string(), user_key: "user-key".to_string() }),
+            summary_webhook_url: Some("https://example.com/hook/secret".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn exports_leave_secrets_and_identity_out() {
+        let backup = ConfigBackup { settings: device_settings(), schedule_profiles: Vec::new() };
+        let json = backup.to_json().unwrap();
+        for secret in ["attic", "app-token", "user-key", "secret"] {
+            assert!(!json.contains(secret), "{} exported", secret);
+        }
+    }
+
+    #[test]
+    fn imports_keep_the_device_settings() {
+        let current = device_settings();
+        let exported = ConfigBackup { settings: Settings::default(), schedule_profiles: Vec::new() }.to_json().unwrap();
+        let mut backup = ConfigBackup::from_json(&exported).unwrap();
+        backup.keep_device_settings(&current);
+        assert_eq!(backup.settings.network.hostname, "attic");
+        assert!(backup.settings.notification_target.is_some());
+        assert_eq!(backup.settings.summary_webhook_url, current.summary_webhook_url);
+    }
+}
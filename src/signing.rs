@@ -0,0 +1,179 @@
+// HMAC signing of commands and configuration pushed over MQTT (see `mqtt`): the shared secret
+// and the check of signed payloads. With a secret configured, only payloads wrapped like this
+// are accepted:
+//
+//     {"payload": "<command or config json, as a string>", "nonce": "<random>", "timestamp": 1700000000,
+//      "hmac": "<hex HMAC-SHA256 of topic + \n + nonce + \n + timestamp + \n + payload>"}
+//
+// so a compromised broker or anyone else on the network can't forge or replay commands, nor
+// move a signed command to another topic. The secret is set by an installer through the api.
+//
+// Replays are caught by the nonce within a boot and by the clock window once the clock is set.
+// The newest timestamp taken is stored, and anything signed before it is refused after a reboot,
+// so captured messages can't be replayed while the clock is still unset.
+
+use std::collections::VecDeque;
+
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::clock;
+use crate::hex;
+use crate::installer::Secret;
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "mqtt_hmac";
+const NEWEST_KEY: &str = "mqtt_hmac_ts";
+/// Shortest secret taken, anything shorter is too easy to guess (bytes)
+pub const MIN_SECRET_LEN: usize = 16;
+/// Messages further off than this from the device clock are refused (seconds)
+const MAX_CLOCK_SKEW_SECS: u64 = 5 * 60;
+/// Nonces remembered for replay protection. Anything older is already out of the time window
+/// unless someone sends more than this many commands in it.
+const NONCE_CACHE: usize = 128;
+
+type HmacSha256 = Hmac<Sha256>;
+
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum SignatureRejection {
+    #[error("payload is not signed")]
+    Unsigned,
+    #[error("signature doesn't match")]
+    BadSignature,
+    #[error("timestamp is too far from the device clock")]
+    Stale,
+    #[error("nonce was already used")]
+    Replayed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SignedPayload {
+    pub payload: String,
+    pub nonce: String,
+    /// Unix seconds when it was signed
+    pub timestamp: u64,
+    pub hmac: String,
+}
+
+/// The shared secret as stored, hex encoded
+#[derive(Serialize, Deserialize)]
+struct StoredSecret {
+    secret: String,
+}
+
+/// Store a new shared secret, hex encoded. None turns signing off. Verifiers pick it up with
+/// `CommandVerifier::reload`.
+pub fn store_secret(storage: &mut Storage, secret: Option<&Secret>) -> anyhow::Result<()> {
+    match secret {
+        Some(secret) => storage.save(STORAGE_KEY, &StoredSecret { secret: secret.0.clone() }),
+        None => storage.remove(STORAGE_KEY),
+    }
+}
+
+pub struct CommandVerifier {
+    storage: Storage,
+    secret: Option<Vec<u8>>,
+    seen_nonces: VecDeque<String>,
+    /// Newest timestamp taken before this boot, anything up to it is a replay
+    newest_before_boot: u64,
+    /// Newest timestamp taken, stored for the next boot
+    newest: u64,
+}
+
+impl CommandVerifier {
+    pub fn load(storage: Storage) -> Self {
+        let newest = storage.load(NEWEST_KEY).unwrap_or(0);
+        let mut verifier = Self {
+            storage,
+            secret: None,
+            seen_nonces: VecDeque::new(),
+            newest_before_boot: newest,
+            newest,
+        };
+        verifier.reload();
+        verifier
+    }
+
+    /// Read the secret again after `store_secret`
+    pub fn reload(&mut self) {
+        self.secret = self
+            .storage
+            .load::<StoredSecret>(STORAGE_KEY)
+            .and_then(|stored| match hex::decode(&stored.secret) {
+                Ok(secret) => Some(secret),
+                Err(e) => {
+                    log::error!("Stored MQTT signing secret is invalid: {}", e);
+                    None
+                }
+            });
+        self.seen_nonces.clear();
+    }
+
+    /// Whether payloads have to be signed
+    pub fn is_required(&self) -> bool {
+        self.secret.is_some()
+    }
+
+    /// Check a message as received from the broker on `topic` and return the payload to decode.
+    /// Without a secret configured the message is the payload itself.
+    pub fn verify(&mut self, topic: &str, message: &str) -> Result<String, SignatureRejection> {
+        let Some(secret) = &self.secret else {
+            return Ok(message.to_string());
+        };
+        let signed: SignedPayload = serde_json::from_str(message).map_err(|_| SignatureRejection::Unsigned)?;
+        let expected = hex::decode(&signed.hmac).map_err(|_| SignatureRejection::BadSignature)?;
+        let mac = mac(secret, topic, &signed).map_err(|_| SignatureRejection::BadSignature)?;
+        // Constant time comparison
+        mac.verify_slice(&expected).map_err(|_| SignatureRejection::BadSignature)?;
+        let too_old = if clock::is_set() {
+            clock::unix_secs().abs_diff(signed.timestamp) > MAX_CLOCK_SKEW_SECS
+        } else {
+            // Nothing to compare against, but the window can't move back from what was taken
+            signed.timestamp <= self.newest_before_boot || signed.timestamp + MAX_CLOCK_SKEW_SECS < self.newest
+        };
+        if too_old {
+            return Err(SignatureRejection::Stale);
+        }
+        if self.seen_nonces.contains(&signed.nonce) {
+            return Err(SignatureRejection::Replayed);
+        }
+        if self.seen_nonces.len() == NONCE_CACHE {
+            self.seen_nonces.pop_front();
+        }
+        self.seen_nonces.push_back(signed.nonce);
+        if signed.timestamp > self.newest {
+            self.newest = signed.timestamp;
+            if let Err(e) = self.storage.save(NEWEST_KEY, &self.newest) {
+                log::error!("Failed to store the newest signed command time: {}", e);
+            }
+        }
+        Ok(signed.payload)
+    }
+}
+
+/// The HMAC of a signed message received on `topic`
+fn mac(secret: &[u8], topic: &str, signed: &SignedPayload) -> Result<HmacSha256, hmac::digest::InvalidLength> {
+    let mut mac = HmacSha256::new_from_slice(secret)?;
+    mac.update(topic.as_bytes());
+    mac.update(b"\n");
+    mac.update(signed.nonce.as_bytes());
+    mac.update(b"\n");
+    mac.update(signed.timestamp.to_string().as_bytes());
+    mac.update(b"\n");
+    mac.update(signed.payload.as_bytes());
+    Ok(mac)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_topic_is_signed_too() {
+        let signed = SignedPayload { payload: "off".to_string(), nonce: "1".to_string(), timestamp: 1_700_000_000, hmac: String::new() };
+        let tag = mac(b"0123456789abcdef", "thermostat/rest_mode/set", &signed).unwrap().finalize().into_bytes();
+        assert!(mac(b"0123456789abcdef", "thermostat/rest_mode/set", &signed).unwrap().verify_slice(&tag).is_ok());
+        assert!(mac(b"0123456789abcdef", "thermostat/mode/set", &signed).unwrap().verify_slice(&tag).is_err());
+    }
+}
@@ -0,0 +1,49 @@
+// Sleep preset: one touch at bedtime switches to the night setpoints, dims the display and
+// holds quiet hours until the chosen wake time. Then the normal setpoint and schedule take over
+// again, as if the current schedule period had just started.
+
+use chrono::{NaiveDateTime, NaiveTime, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::events::ModeStatus;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SleepSettings {
+    /// Setpoints while asleep, in Celsius
+    pub heat_setpoint_c: f32,
+    pub cool_setpoint_c: f32,
+    /// Wake time offered when the preset is started, the last one chosen
+    pub wake_time: NaiveTime,
+}
+
+impl Default for SleepSettings {
+    fn default() -> Self {
+        Self {
+            heat_setpoint_c: 18.0, // ~64°F
+            cool_setpoint_c: 25.0, // ~77°F
+            wake_time: NaiveTime::from_hms_opt(7, 0, 0).unwrap_or_default(),
+        }
+    }
+}
+
+impl SleepSettings {
+    /// Setpoint to use while asleep for the given mode, None when off
+    pub fn setpoint_c(&self, mode: &ModeStatus) -> Option<f32> {
+        match mode {
+            ModeStatus::Heat => Some(self.heat_setpoint_c),
+            ModeStatus::Cool => Some(self.cool_setpoint_c),
+            ModeStatus::Off => None,
+        }
+    }
+}
+
+/// The next time the clock reads `wake_time` after `now`
+pub fn wake_at(now: NaiveDateTime, wake_time: NaiveTime) -> NaiveDateTime {
+    let today = now.date().and_time(wake_time);
+    if today > now {
+        today
+    } else {
+        today + TimeDelta::days(1)
+    }
+}
@@ -0,0 +1,74 @@
+// Compressor start statistics. Frequent starts are the main sign that the
+// differentials are set too tight for the equipment.
+
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use serde::Serialize;
+
+use crate::metrics;
+
+const HOUR: Duration = Duration::from_secs(60 * 60);
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct CycleCounts {
+    pub starts_last_hour: u32,
+    pub starts_last_day: u32,
+    /// Every compressor start since boot
+    pub starts_total: u32,
+}
+
+#[derive(Default)]
+pub struct CycleStats {
+    /// Start times within the last day in seconds since boot, oldest first. Starts handed over
+    /// from before a reboot are negative.
+    starts: VecDeque<i64>,
+    starts_total: u32,
+}
+
+impl CycleStats {
+    pub fn record_compressor_start(&mut self) {
+        self.starts.push_back(now_secs());
+        self.starts_total += 1;
+        self.prune();
+    }
+
+    pub fn counts(&mut self) -> CycleCounts {
+        self.prune();
+        let now = now_secs();
+        let starts_last_hour = self.starts.iter().filter(|&&start| now - start <= HOUR.as_secs() as i64).count();
+        CycleCounts {
+            starts_last_hour: starts_last_hour as u32,
+            starts_last_day: self.starts.len() as u32,
+            starts_total: self.starts_total,
+        }
+    }
+
+    /// How long ago each start of the last day was (seconds), and the total, to carry over a reboot
+    pub fn handoff(&self) -> (Vec<u64>, u32) {
+        let now = now_secs();
+        (self.starts.iter().map(|start| (now - start) as u64).collect(), self.starts_total)
+    }
+
+    /// Pick up the starts from `handoff` before a reboot `downtime` ago
+    pub fn restore(&mut self, ages_secs: &[u64], starts_total: u32, downtime: Duration) {
+        let now = now_secs();
+        let downtime = downtime.as_secs() as i64;
+        self.starts = ages_secs.iter().map(|&age| now - age as i64 - downtime).chain(self.starts.drain(..)).collect();
+        self.starts_total += starts_total;
+        self.prune();
+    }
+
+    /// Drop starts older than a day
+    fn prune(&mut self) {
+        let now = now_secs();
+        while self.starts.front().is_some_and(|&start| now - start > DAY.as_secs() as i64) {
+            self.starts.pop_front();
+        }
+    }
+}
+
+fn now_secs() -> i64 {
+    metrics::uptime().as_secs() as i64
+}
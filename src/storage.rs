@@ -0,0 +1,73 @@
+// Persistent key/value storage on top of the default NVS partition.
+// Values are stored as JSON blobs so any serde type can be persisted.
+
+use esp_idf_svc::nvs::{EspDefaultNvsPartition, EspNvs, NvsDefault};
+use esp_idf_svc::sys::EspError;
+use serde::{de::DeserializeOwned, Serialize};
+
+const NAMESPACE: &str = "thermostat";
+
+pub struct Storage {
+    nvs: EspNvs<NvsDefault>,
+}
+
+impl Storage {
+    pub fn new(partition: EspDefaultNvsPartition) -> Result<Self, EspError> {
+        Ok(Self {
+            nvs: EspNvs::new(partition, NAMESPACE, true)?,
+        })
+    }
+
+    /// Load and deserialize the value stored under `key`.
+    /// Returns None if nothing is stored or the stored value can't be decoded.
+    /// Keys are limited to 15 characters by NVS.
+    pub fn load<T: DeserializeOwned>(&self, key: &str) -> Option<T> {
+        let bytes = self.load_raw(key)?;
+        match serde_json::from_slice(&bytes) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                log::error!("Failed to decode stored value for {}: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Load the raw bytes stored under `key`.
+    pub fn load_raw(&self, key: &str) -> Option<Vec<u8>> {
+        let len = match self.nvs.blob_len(key) {
+            Ok(Some(len)) => len,
+            Ok(None) => return None,
+            Err(e) => {
+                log::error!("Failed to read length of {} from NVS: {}", key, e);
+                return None;
+            }
+        };
+        let mut buf = vec![0u8; len];
+        match self.nvs.get_blob(key, &mut buf) {
+            Ok(Some(bytes)) => Some(bytes.to_vec()),
+            Ok(None) => None,
+            Err(e) => {
+                log::error!("Failed to read {} from NVS: {}", key, e);
+                None
+            }
+        }
+    }
+
+    /// Serialize and store `value` under `key`.
+    pub fn save<T: Serialize>(&mut self, key: &str, value: &T) -> anyhow::Result<()> {
+        let bytes = serde_json::to_vec(value)?;
+        self.save_raw(key, &bytes)
+    }
+
+    /// Store raw bytes under `key`.
+    pub fn save_raw(&mut self, key: &str, bytes: &[u8]) -> anyhow::Result<()> {
+        self.nvs.set_blob(key, bytes)?;
+        Ok(())
+    }
+
+    /// Remove whatever is stored under `key`, if anything.
+    pub fn remove(&mut self, key: &str) -> anyhow::Result<()> {
+        self.nvs.remove(key)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+// Daily and weekly digests of what the thermostat did: runtime per mode, cycles,
+// temperature extremes, an estimated cost, and how well it held the setpoint.
+
+use std::time::Duration;
+
+use chrono::{Datelike, NaiveDate, Weekday};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ThermostatRuntimeState;
+use crate::clock;
+use crate::events::{DisplayPrecision, ModeStatus};
+use crate::settings::Settings;
+
+/// Within this of the setpoint counts as comfortable (Celsius)
+const COMFORT_BAND_C: f32 = 0.5;
+/// Cycles shorter than this on average cost comfort score points (seconds)
+const SHORT_CYCLE_SECS: u64 = 10 * 60;
+/// Most points short cycling can cost
+const SHORT_CYCLE_PENALTY: f32 = 20.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryPeriod {
+    Day,
+    Week,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub period: SummaryPeriod,
+    /// First day covered by the summary
+    pub start_date: NaiveDate,
+    pub heating_runtime_secs: u64,
+    pub cooling_runtime_secs: u64,
+    pub heating_cycles: u32,
+    pub cooling_cycles: u32,
+    pub min_temp_c: Option<f32>,
+    pub max_temp_c: Option<f32>,
+    /// Runtime multiplied by the configured hourly costs
+    pub estimated_cost: f32,
+    /// Time spent within half a degree of the setpoint
+    pub secs_in_comfort_band: u64,
+    /// Time with a setpoint to hold (mode not off, sensor working)
+    pub secs_controlled: u64,
+    /// Furthest past the setpoint in the direction of the mode, e.g. above it while heating (Celsius)
+    pub max_overshoot_c: Option<f32>,
+    pub average_cycle_secs: Option<u64>,
+    /// 0-100: share of time in the comfort band, less up to 20 points for short cycling
+    pub comfort_score: Option<u8>,
+}
+
+/// How well the setpoint is held, published as telemetry for tuning the differentials and rest
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct ComfortMetrics {
+    /// See `Summary::comfort_score`, None until there was a setpoint to hold
+    pub comfort_score: Option<u8>,
+    /// Share of the controlled time within half a degree of the setpoint, in whole percent so
+    /// the snapshot doesn't change every tick
+    pub percent_in_comfort_band: Option<u8>,
+    pub average_cycle_secs: Option<u64>,
+    /// Furthest past the setpoint (Celsius)
+    pub max_overshoot_c: Option<f32>,
+}
+
+impl Summary {
+    fn new(period: SummaryPeriod, start_date: NaiveDate) -> Self {
+        Self {
+            period,
+            start_date,
+            heating_runtime_secs: 0,
+            cooling_runtime_secs: 0,
+            heating_cycles: 0,
+            cooling_cycles: 0,
+            min_temp_c: None,
+            max_temp_c: None,
+            estimated_cost: 0.0,
+            secs_in_comfort_band: 0,
+            secs_controlled: 0,
+            max_overshoot_c: None,
+            average_cycle_secs: None,
+            comfort_score: None,
+        }
+    }
+
+    fn add(&mut self, other: &Summary) {
+        self.heating_runtime_secs += other.heating_runtime_secs;
+        self.cooling_runtime_secs += other.cooling_runtime_secs;
+        self.heating_cycles += other.heating_cycles;
+        self.cooling_cycles += other.cooling_cycles;
+        self.min_temp_c = min_option(self.min_temp_c, other.min_temp_c);
+        self.max_temp_c = max_option(self.max_temp_c, other.max_temp_c);
+        self.estimated_cost += other.estimated_cost;
+        self.secs_in_comfort_band += other.secs_in_comfort_band;
+        self.secs_controlled += other.secs_controlled;
+        self.max_overshoot_c = max_option(self.max_overshoot_c, other.max_overshoot_c);
+    }
+
+    fn finish(&mut self, settings: &Settings) {
+        self.estimated_cost = self.heating_runtime_secs as f32 / 3600.0 * settings.heating_cost_per_hour
+            + self.cooling_runtime_secs as f32 / 3600.0 * settings.cooling_cost_per_hour;
+        self.update_comfort();
+    }
+
+    /// The temperature extremes rounded the way the screen shows them, for MQTT
+    pub fn at_display_precision(mut self, use_fahrenheit: bool, precision: DisplayPrecision) -> Self {
+        self.min_temp_c = self.min_temp_c.map(|temp_c| precision.round_celsius(temp_c, use_fahrenheit));
+        self.max_temp_c = self.max_temp_c.map(|temp_c| precision.round_celsius(temp_c, use_fahrenheit));
+        self
+    }
+
+    pub fn comfort_metrics(&self) -> ComfortMetrics {
+        ComfortMetrics {
+            comfort_score: self.comfort_score,
+            percent_in_comfort_band: (self.secs_controlled > 0)
+                .then(|| (self.secs_in_comfort_band as f32 / self.secs_controlled as f32 * 100.0).round() as u8),
+            average_cycle_secs: self.average_cycle_secs,
+            max_overshoot_c: self.max_overshoot_c,
+        }
+    }
+
+    /// Work out the cycle length and comfort score from the counters
+    fn update_comfort(&mut self) {
+        let cycles = (self.heating_cycles + self.cooling_cycles) as u64;
+        self.average_cycle_secs = (cycles > 0).then(|| (self.heating_runtime_secs + self.cooling_runtime_secs) / cycles);
+        if self.secs_controlled == 0 {
+            self.comfort_score = None;
+            return;
+        }
+        let in_band = self.secs_in_comfort_band as f32 / self.secs_controlled as f32 * 100.0;
+        let short_cycling = self
+            .average_cycle_secs
+            .filter(|&secs| secs < SHORT_CYCLE_SECS)
+            .map(|secs| (1.0 - secs as f32 / SHORT_CYCLE_SECS as f32) * SHORT_CYCLE_PENALTY)
+            .unwrap_or(0.0);
+        self.comfort_score = Some((in_band - short_cycling).clamp(0.0, 100.0).round() as u8);
+    }
+}
+
+/// Accumulates the current day and week, handing back finished summaries on rollover.
+pub struct SummaryTracker {
+    today: Summary,
+    week: Summary,
+}
+
+impl Default for SummaryTracker {
+    fn default() -> Self {
+        let today = current_date();
+        Self {
+            today: Summary::new(SummaryPeriod::Day, today),
+            week: Summary::new(SummaryPeriod::Week, today),
+        }
+    }
+}
+
+impl SummaryTracker {
+    /// Account for the time spent in `state` since the last tick.
+    pub fn record_tick(&mut self, state: &ThermostatRuntimeState, elapsed: Duration, temp_c: Option<f32>, mode: &ModeStatus, target_c: f32) {
+        match state {
+            ThermostatRuntimeState::Heating => self.today.heating_runtime_secs += elapsed.as_secs(),
+            ThermostatRuntimeState::Cooling => self.today.cooling_runtime_secs += elapsed.as_secs(),
+            _ => {}
+        }
+        self.today.min_temp_c = min_option(self.today.min_temp_c, temp_c);
+        self.today.max_temp_c = max_option(self.today.max_temp_c, temp_c);
+        let Some(temp_c) = temp_c else {
+            return;
+        };
+        let overshoot_c = match mode {
+            ModeStatus::Heat => temp_c - target_c,
+            ModeStatus::Cool => target_c - temp_c,
+            ModeStatus::Off => return,
+        };
+        self.today.secs_controlled += elapsed.as_secs();
+        if (temp_c - target_c).abs() <= COMFORT_BAND_C {
+            self.today.secs_in_comfort_band += elapsed.as_secs();
+        }
+        self.today.max_overshoot_c = max_option(self.today.max_overshoot_c, Some(overshoot_c.max(0.0)));
+    }
+
+    /// Comfort metrics of the day so far, for the diagnostics screen
+    pub fn today(&self) -> Summary {
+        let mut today = self.today.clone();
+        today.update_comfort();
+        today
+    }
+
+    pub fn record_heating_cycle(&mut self) {
+        self.today.heating_cycles += 1;
+    }
+
+    pub fn record_cooling_cycle(&mut self) {
+        self.today.cooling_cycles += 1;
+    }
+
+    /// The day and week so far, to carry over a reboot
+    pub fn handoff(&self) -> (Summary, Summary) {
+        (self.today.clone(), self.week.clone())
+    }
+
+    /// Pick up the day and week from `handoff`, adding whatever was counted since boot
+    pub fn restore(&mut self, today: Summary, week: Summary) {
+        let since_boot = std::mem::replace(&mut self.today, today);
+        self.today.add(&since_boot);
+        self.week = week;
+    }
+
+    /// If the date changed, returns the finished day summary, followed by the finished
+    /// week summary when a new week (starting Monday) began.
+    pub fn roll_over(&mut self, settings: &Settings) -> Vec<Summary> {
+        let today = current_date();
+        if today == self.today.start_date {
+            return Vec::new();
+        }
+        let mut finished = Vec::new();
+        let mut day = std::mem::replace(&mut self.today, Summary::new(SummaryPeriod::Day, today));
+        day.finish(settings);
+        self.week.add(&day);
+        finished.push(day);
+        if today.weekday() == Weekday::Mon || today.signed_duration_since(self.week.start_date).num_days() >= 7 {
+            let mut week = std::mem::replace(&mut self.week, Summary::new(SummaryPeriod::Week, today));
+            week.update_comfort();
+            finished.push(week);
+        }
+        finished
+    }
+}
+
+fn current_date() -> NaiveDate {
+    clock::local_now().date()
+}
+
+fn min_option(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.min(b)),
+        (a, b) => a.or(b),
+    }
+}
+
+fn max_option(a: Option<f32>, b: Option<f32>) -> Option<f32> {
+    match (a, b) {
+        (Some(a), Some(b)) => Some(a.max(b)),
+        (a, b) => a.or(b),
+    }
+}
@@ -0,0 +1,73 @@
+// What kind of equipment the relays drive. Forced air is the default: heat and cool calls bring
+// the blower on through the fan relay. Hydronic zones use the heat relay for the zone valve and
+// the fan relay for the circulator pump, and have no cooling. Dry contact closes exactly the
+// relay of the call, for fireplaces and other equipment that runs its own fan.
+
+use serde::{Deserialize, Serialize};
+
+use crate::controller::Relay;
+use crate::dry_contact::DryContact;
+use crate::hydronic::Hydronic;
+
+#[derive(Debug, Clone, Copy, PartialEq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SystemProfile {
+    #[default]
+    ForcedAir,
+    Hydronic(Hydronic),
+    DryContact(DryContact),
+}
+
+impl SystemProfile {
+    pub fn hydronic(&self) -> Option<Hydronic> {
+        match self {
+            SystemProfile::Hydronic(hydronic) => Some(*hydronic),
+            _ => None,
+        }
+    }
+
+    pub fn dry_contact(&self) -> Option<DryContact> {
+        match self {
+            SystemProfile::DryContact(dry_contact) => Some(*dry_contact),
+            _ => None,
+        }
+    }
+
+    pub fn has_heating(&self) -> bool {
+        match self {
+            SystemProfile::DryContact(dry_contact) => dry_contact.heat,
+            _ => true,
+        }
+    }
+
+    pub fn has_cooling(&self) -> bool {
+        match self {
+            SystemProfile::ForcedAir => true,
+            SystemProfile::Hydronic(_) => false,
+            SystemProfile::DryContact(dry_contact) => dry_contact.cool,
+        }
+    }
+
+    /// Whether fan mode On means anything: there is no blower to circulate air with on hydronic
+    pub fn has_fan(&self) -> bool {
+        match self {
+            SystemProfile::ForcedAir => true,
+            SystemProfile::Hydronic(_) => false,
+            SystemProfile::DryContact(dry_contact) => dry_contact.fan,
+        }
+    }
+
+    /// Whether the fan relay runs with heat and cool calls (the blower, or the circulator once the
+    /// zone valve is open). Never on dry contact.
+    pub fn fan_follows_calls(&self) -> bool {
+        !matches!(self, SystemProfile::DryContact(_))
+    }
+
+    /// Relays the controller must keep open whatever the backend asks for
+    pub fn unused_relays(&self) -> Vec<Relay> {
+        match self {
+            SystemProfile::DryContact(dry_contact) => dry_contact.unused_relays(),
+            _ => Vec::new(),
+        }
+    }
+}
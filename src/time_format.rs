@@ -0,0 +1,95 @@
+// How times and dates are shown: 12 or 24 hour clock, month or day first. Only the display
+// changes, everything stored or sent over the network keeps unix seconds and ISO dates.
+
+use chrono::{NaiveDate, NaiveTime, Timelike};
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum ClockFormat {
+    #[default]
+    H24,
+    H12,
+}
+
+/// Order of the day and month in short dates
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum DateOrder {
+    #[default]
+    MonthDay,
+    DayMonth,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TimeFormat {
+    pub clock: ClockFormat,
+    pub date_order: DateOrder,
+}
+
+impl TimeFormat {
+    /// 19:05, or 7:05 PM
+    pub fn time(&self, time: NaiveTime) -> String {
+        match self.clock {
+            ClockFormat::H24 => time.format("%-H:%M").to_string(),
+            ClockFormat::H12 => {
+                let (pm, hour) = time.hour12();
+                format!("{}:{:02} {}", hour, time.minute(), if pm { "PM" } else { "AM" })
+            }
+        }
+    }
+
+    /// Numeric day and month: 10/16, or 16/10
+    pub fn day(&self, date: NaiveDate) -> String {
+        match self.date_order {
+            DateOrder::MonthDay => date.format("%m/%d").to_string(),
+            DateOrder::DayMonth => date.format("%d/%m").to_string(),
+        }
+    }
+
+    /// Day with the month spelled out: Oct 16, or 16 Oct
+    pub fn date(&self, date: NaiveDate) -> String {
+        match self.date_order {
+            DateOrder::MonthDay => date.format("%b %-d").to_string(),
+            DateOrder::DayMonth => date.format("%-d %b").to_string(),
+        }
+    }
+
+    /// Local day and time of a unix timestamp, for log entries
+    pub fn timestamp(&self, unix_secs: u64) -> String {
+        if unix_secs == 0 {
+            return "--".to_string();
+        }
+        let local = clock::to_local(unix_secs);
+        format!("{} {}", self.day(local.date()), self.time(local.time()))
+    }
+}
+
+impl TryFrom<i32> for ClockFormat {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ClockFormat::H24),
+            1 => Ok(ClockFormat::H12),
+            _ => Err(anyhow::anyhow!("Invalid clock format: {}", value)),
+        }
+    }
+}
+
+impl TryFrom<i32> for DateOrder {
+    type Error = anyhow::Error;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DateOrder::MonthDay),
+            1 => Ok(DateOrder::DayMonth),
+            _ => Err(anyhow::anyhow!("Invalid date order: {}", value)),
+        }
+    }
+}
@@ -0,0 +1,81 @@
+// Named time zones mapped to the POSIX TZ strings the C library understands. The library has
+// no zone database, a POSIX string carries the offsets and DST rules itself, so local time
+// (schedules, quiet hours, the maintenance window) switches with DST without the thermostat
+// knowing about it. Zones not in the list can still be set as a raw POSIX string.
+
+/// Zone names in the order the settings screen cycles through them, with their POSIX strings
+pub const ZONES: &[(&str, &str)] = &[
+    ("UTC", "UTC0"),
+    ("Pacific/Honolulu", "HST10"),
+    ("America/Anchorage", "AKST9AKDT,M3.2.0,M11.1.0"),
+    ("America/Vancouver", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Los_Angeles", "PST8PDT,M3.2.0,M11.1.0"),
+    ("America/Phoenix", "MST7"),
+    ("America/Edmonton", "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Denver", "MST7MDT,M3.2.0,M11.1.0"),
+    ("America/Regina", "CST6"),
+    ("America/Mexico_City", "CST6"),
+    ("America/Winnipeg", "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Chicago", "CST6CDT,M3.2.0,M11.1.0"),
+    ("America/Toronto", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/New_York", "EST5EDT,M3.2.0,M11.1.0"),
+    ("America/Halifax", "AST4ADT,M3.2.0,M11.1.0"),
+    ("America/St_Johns", "NST3:30NDT,M3.2.0,M11.1.0"),
+    ("America/Sao_Paulo", "<-03>3"),
+    ("Europe/London", "GMT0BST,M3.5.0/1,M10.5.0"),
+    ("Europe/Dublin", "GMT0IST,M3.5.0/1,M10.5.0"),
+    ("Europe/Lisbon", "WET0WEST,M3.5.0/1,M10.5.0"),
+    ("Europe/Paris", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Berlin", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Madrid", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Rome", "CET-1CEST,M3.5.0,M10.5.0/3"),
+    ("Europe/Athens", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Helsinki", "EET-2EEST,M3.5.0/3,M10.5.0/4"),
+    ("Europe/Moscow", "MSK-3"),
+    ("Asia/Dubai", "<+04>-4"),
+    ("Asia/Kolkata", "IST-5:30"),
+    ("Asia/Singapore", "<+08>-8"),
+    ("Asia/Shanghai", "CST-8"),
+    ("Asia/Tokyo", "JST-9"),
+    ("Australia/Perth", "AWST-8"),
+    ("Australia/Adelaide", "ACST-9:30ACDT,M10.1.0,M4.1.0/3"),
+    ("Australia/Brisbane", "AEST-10"),
+    ("Australia/Sydney", "AEST-10AEDT,M10.1.0,M4.1.0/3"),
+    ("Pacific/Auckland", "NZST-12NZDT,M9.5.0,M4.1.0/3"),
+];
+
+pub const DEFAULT_TIMEZONE: &str = "UTC";
+/// Longer than any real POSIX TZ string
+const MAX_POSIX_LEN: usize = 64;
+
+/// The POSIX string for a zone name from the list, or the setting itself if it is a POSIX
+/// string already. None if it is neither.
+pub fn posix(timezone: &str) -> Option<&str> {
+    if let Some((_, posix)) = ZONES.iter().find(|(name, _)| *name == timezone) {
+        return Some(posix);
+    }
+    looks_like_posix(timezone).then_some(timezone)
+}
+
+/// The zone after `timezone` in the list, wrapping around. A custom POSIX string goes to the start.
+pub fn next(timezone: &str) -> &'static str {
+    ZONES
+        .iter()
+        .position(|(name, _)| *name == timezone)
+        .map_or(ZONES[0].0, |index| ZONES[(index + 1) % ZONES.len()].0)
+}
+
+/// A standard time name (letters, or anything quoted in <>) followed by an offset. The C library
+/// falls back to UTC on anything it can't parse, this only catches what clearly isn't one.
+fn looks_like_posix(timezone: &str) -> bool {
+    let name_len = if let Some(quoted) = timezone.strip_prefix('<') {
+        quoted.find('>').map_or(0, |end| end + 2)
+    } else {
+        timezone.chars().take_while(char::is_ascii_alphabetic).count()
+    };
+    let offset = timezone.get(name_len..).unwrap_or_default();
+    timezone.len() <= MAX_POSIX_LEN
+        && timezone.is_ascii()
+        && name_len >= 3
+        && offset.trim_start_matches(['+', '-']).starts_with(|c: char| c.is_ascii_digit())
+}
@@ -0,0 +1,177 @@
+// Timing of the hot paths: the backend tick, the onboard sensor read, the Slint render and the
+// touch latency. Each run is measured with the microsecond timer and the last samples are kept
+// per probe, so the diagnostics screen and the metrics can show percentiles and a feature that
+// slows one of them down stands out. Probes run on different threads, the samples sit behind a
+// mutex that is only held to push one sample or copy them out.
+
+use std::fmt::Write;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::metrics;
+
+/// Samples kept per probe, the percentiles cover roughly the last minute of ticks
+const SAMPLES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Probe {
+    /// One pass of the backend loop, without the sleep until the next
+    Tick,
+    /// Starting or reading a DS18B20 conversion on the 1-Wire bus
+    SensorRead,
+    /// Rendering and flushing one frame, without the wait for vsync
+    Render,
+    /// From a touch to the first frame drawn after it, the pressed look of a button
+    TouchFeedback,
+    /// From a touch to the frame showing the backend's answer to the command it sent
+    TouchResponse,
+}
+
+impl Probe {
+    const ALL: [Probe; 5] = [Probe::Tick, Probe::SensorRead, Probe::Render, Probe::TouchFeedback, Probe::TouchResponse];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Probe::Tick => "tick",
+            Probe::SensorRead => "sensor_read",
+            Probe::Render => "render",
+            Probe::TouchFeedback => "touch_feedback",
+            Probe::TouchResponse => "touch_response",
+        }
+    }
+}
+
+struct Ring {
+    samples: [u32; SAMPLES],
+    len: usize,
+    next: usize,
+}
+
+impl Ring {
+    const fn new() -> Self {
+        Self { samples: [0; SAMPLES], len: 0, next: 0 }
+    }
+
+    fn push(&mut self, micros: u32) {
+        self.samples[self.next] = micros;
+        self.next = (self.next + 1) % SAMPLES;
+        self.len = (self.len + 1).min(SAMPLES);
+    }
+}
+
+static RINGS: Mutex<[Ring; Probe::ALL.len()]> = Mutex::new([const { Ring::new() }; Probe::ALL.len()]);
+
+/// Record one run of `probe`
+pub fn record(probe: Probe, duration: Duration) {
+    let micros = duration.as_micros().min(u32::MAX as u128) as u32;
+    if let Ok(mut rings) = RINGS.lock() {
+        rings[probe as usize].push(micros);
+    }
+}
+
+/// Records the time until it is dropped
+pub struct Measure {
+    probe: Probe,
+    started: Duration,
+}
+
+/// Measure `probe` until the returned guard goes out of scope
+pub fn measure(probe: Probe) -> Measure {
+    Measure { probe, started: metrics::uptime() }
+}
+
+impl Drop for Measure {
+    fn drop(&mut self) {
+        record(self.probe, metrics::uptime().saturating_sub(self.started));
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct TimingStats {
+    pub probe: &'static str,
+    pub samples: usize,
+    pub p50_us: u32,
+    pub p95_us: u32,
+    pub p99_us: u32,
+    pub max_us: u32,
+}
+
+impl TimingStats {
+    /// One line for the diagnostics screen
+    pub fn summary(&self) -> String {
+        format!(
+            "{} {:.1}/{:.1}/{:.1}ms",
+            self.probe,
+            self.p50_us as f32 / 1000.0,
+            self.p95_us as f32 / 1000.0,
+            self.max_us as f32 / 1000.0
+        )
+    }
+}
+
+/// Percentiles of every probe with samples
+pub fn collect() -> Vec<TimingStats> {
+    let Ok(rings) = RINGS.lock() else {
+        return Vec::new();
+    };
+    let copies: Vec<(Probe, Vec<u32>)> = Probe::ALL
+        .iter()
+        .map(|probe| {
+            let ring = &rings[*probe as usize];
+            (*probe, ring.samples[..ring.len].to_vec())
+        })
+        .collect();
+    drop(rings);
+    copies
+        .into_iter()
+        .filter(|(_, samples)| !samples.is_empty())
+        .map(|(probe, mut samples)| {
+            samples.sort_unstable();
+            let percentile = |p: usize| samples[(samples.len() - 1) * p / 100];
+            TimingStats {
+                probe: probe.name(),
+                samples: samples.len(),
+                p50_us: percentile(50),
+                p95_us: percentile(95),
+                p99_us: percentile(99),
+                max_us: samples[samples.len() - 1],
+            }
+        })
+        .collect()
+}
+
+/// Prometheus text format, for the `/metrics` endpoint
+pub fn to_prometheus(stats: &[TimingStats]) -> String {
+    let mut out = String::new();
+    for stat in stats {
+        for (quantile, micros) in [("0.5", stat.p50_us), ("0.95", stat.p95_us), ("0.99", stat.p99_us), ("1", stat.max_us)] {
+            let _ = writeln!(out, "thermostat_duration_seconds{{path=\"{}\",quantile=\"{}\"}} {}", stat.probe, quantile, micros as f64 / 1e6);
+        }
+    }
+    out
+}
+
+/// Collects the percentiles every `metrics::COLLECT_INTERVAL`
+#[derive(Default)]
+pub struct TimingMonitor {
+    stats: Vec<TimingStats>,
+    last_collected: Option<Instant>,
+}
+
+impl TimingMonitor {
+    pub fn update(&mut self) {
+        if self.last_collected.is_some_and(|at| at.elapsed() < metrics::COLLECT_INTERVAL) {
+            return;
+        }
+        self.last_collected = Some(Instant::now());
+        self.stats = collect();
+        log::debug!("Timing: {:?}", self.stats);
+    }
+
+    pub fn stats(&self) -> &[TimingStats] {
+        &self.stats
+    }
+}
@@ -0,0 +1,214 @@
+// Event trace for reproducing field issues. The recorder keeps the last inputs to the control
+// loop (commands, sensor readings) and the state transitions they led to, timestamped, and
+// persists them to NVS every few minutes so they survive the reboot that usually follows a
+// report. Built with the `replay` feature, the firmware instead feeds an exported trace back
+// through the backend at the recorded times, taking the onboard readings from it instead of
+// the sensor, and logs every transition that differs from the recorded one.
+//
+// Only the commands that steer control are replayable, the rest are recorded by name so the
+// trace still shows that they happened.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ThermostatRuntimeState;
+use crate::clock;
+use crate::events::{ComfortProfile, CommandSource, FanStatus, ModeStatus, UiEvent};
+use crate::storage::Storage;
+
+const STORAGE_KEY: &str = "trace";
+/// Entries kept before the oldest ones are dropped, sized to fit NVS next to the settings
+pub const TRACE_CAPACITY: usize = 128;
+/// How often a changed trace is written to flash
+const SAVE_INTERVAL: Duration = Duration::from_mins(5);
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TraceEvent {
+    /// A read of the onboard sensor, None when it failed
+    OnboardTemp(Option<f32>),
+    RemoteTemp { sensor: String, temp_c: f32 },
+    OutdoorTemp(f32),
+    Humidity(f32),
+    Mode(ModeStatus),
+    Setpoint(f32),
+    Fan(FanStatus),
+    Comfort(ComfortProfile),
+    /// Any other command, by variant name
+    Other(String),
+    Transition { from: ThermostatRuntimeState, to: ThermostatRuntimeState },
+}
+
+impl TraceEvent {
+    pub fn from_command(event: &UiEvent) -> Self {
+        match event {
+            UiEvent::RemoteTempUpdate { sensor, temp_c, .. } => Self::RemoteTemp { sensor: sensor.clone(), temp_c: *temp_c },
+            UiEvent::OutdoorTempUpdate(temp_c) => Self::OutdoorTemp(*temp_c),
+            UiEvent::HumidityUpdate(humidity) => Self::Humidity(*humidity),
+            UiEvent::ModeUpdate(mode) => Self::Mode(*mode),
+            UiEvent::TargetTempUpdate(temp_c) => Self::Setpoint(*temp_c),
+            UiEvent::FanUpdate(fan) => Self::Fan(*fan),
+            UiEvent::ComfortProfileUpdate(profile) => Self::Comfort(*profile),
+            event => {
+                let name = format!("{:?}", event);
+                Self::Other(name.split(['(', ' ']).next().unwrap_or_default().to_string())
+            }
+        }
+    }
+
+    /// The command to replay, None for readings, transitions and commands only known by name
+    pub fn to_command(&self) -> Option<UiEvent> {
+        Some(match self {
+            Self::RemoteTemp { sensor, temp_c } => UiEvent::RemoteTempUpdate { sensor: sensor.clone(), temp_c: *temp_c, battery: None },
+            Self::OutdoorTemp(temp_c) => UiEvent::OutdoorTempUpdate(*temp_c),
+            Self::Humidity(humidity) => UiEvent::HumidityUpdate(*humidity),
+            Self::Mode(mode) => UiEvent::ModeUpdate(*mode),
+            Self::Setpoint(temp_c) => UiEvent::TargetTempUpdate(*temp_c),
+            Self::Fan(fan) => UiEvent::FanUpdate(*fan),
+            Self::Comfort(profile) => UiEvent::ComfortProfileUpdate(*profile),
+            Self::OnboardTemp(_) | Self::Other(_) | Self::Transition { .. } => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Milliseconds since boot
+    pub at_ms: u64,
+    /// Seconds since the unix epoch (only meaningful once the clock has been set)
+    pub timestamp: u64,
+    /// Where a command came from, None for readings and transitions
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source: Option<CommandSource>,
+    pub event: TraceEvent,
+}
+
+pub struct TraceRecorder {
+    entries: VecDeque<TraceEntry>,
+    boot: Instant,
+    dirty: bool,
+    last_save: Instant,
+}
+
+impl TraceRecorder {
+    /// Start a new trace. The one persisted by the last boot stays in storage until the first
+    /// save, export it with `persisted_json` before then.
+    pub fn new() -> Self {
+        Self { entries: VecDeque::new(), boot: Instant::now(), dirty: false, last_save: Instant::now() }
+    }
+
+    pub fn record(&mut self, source: Option<CommandSource>, event: TraceEvent) {
+        if self.entries.len() == TRACE_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(TraceEntry {
+            at_ms: self.boot.elapsed().as_millis() as u64,
+            timestamp: clock::unix_secs(),
+            source,
+            event,
+        });
+        self.dirty = true;
+    }
+
+    /// Export the trace as JSON, oldest entry first
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&self.entries)?)
+    }
+
+    /// The trace persisted before the last reboot, as JSON, until this boot's trace replaces it
+    pub fn persisted_json(storage: &Storage) -> Option<String> {
+        storage.load_raw(STORAGE_KEY).and_then(|bytes| String::from_utf8(bytes).ok())
+    }
+
+    /// Write the trace to storage at most every few minutes, to spare the flash
+    pub fn save_if_due(&mut self, storage: &mut Storage) {
+        if !self.dirty || self.last_save.elapsed() < SAVE_INTERVAL {
+            return;
+        }
+        self.last_save = Instant::now();
+        match storage.save(STORAGE_KEY, &self.entries) {
+            Ok(()) => self.dirty = false,
+            Err(e) => log::error!("Failed to persist trace: {}", e),
+        }
+    }
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Feeds a recorded trace back at the recorded pace, starting from its first entry
+#[cfg(feature = "replay")]
+pub struct TraceReplay {
+    entries: VecDeque<TraceEntry>,
+    started: Instant,
+    /// Offset of the first entry
+    first_ms: u64,
+    /// Recorded transitions the backend hasn't made yet, in order. Compared by order rather
+    /// than time, a transition a tick early or late isn't a divergence.
+    expected: VecDeque<(ThermostatRuntimeState, ThermostatRuntimeState)>,
+    divergences: u32,
+    finished: bool,
+}
+
+#[cfg(feature = "replay")]
+impl TraceReplay {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let entries: VecDeque<TraceEntry> = serde_json::from_str(json)?;
+        let expected = entries
+            .iter()
+            .filter_map(|entry| match &entry.event {
+                TraceEvent::Transition { from, to } => Some((*from, *to)),
+                _ => None,
+            })
+            .collect();
+        log::info!("Replaying {} trace entries", entries.len());
+        Ok(Self {
+            first_ms: entries.front().map_or(0, |entry| entry.at_ms),
+            entries,
+            started: Instant::now(),
+            expected,
+            divergences: 0,
+            finished: false,
+        })
+    }
+
+    /// Readings and commands whose time has come
+    pub fn due(&mut self) -> Vec<TraceEntry> {
+        let now_ms = self.first_ms + self.started.elapsed().as_millis() as u64;
+        let mut due = Vec::new();
+        while let Some(entry) = self.entries.pop_front() {
+            if entry.at_ms > now_ms {
+                self.entries.push_front(entry);
+                break;
+            }
+            match &entry.event {
+                TraceEvent::Transition { .. } => {}
+                TraceEvent::Other(name) => log::info!("Replay: skipping {} from {:?}, not replayable", name, entry.source),
+                _ => due.push(entry),
+            }
+        }
+        if self.entries.is_empty() && !self.finished {
+            self.finished = true;
+            log::info!("Replay finished, {} divergences so far", self.divergences);
+        }
+        due
+    }
+
+    /// Check a live transition against the next recorded one
+    pub fn transition(&mut self, from: &ThermostatRuntimeState, to: &ThermostatRuntimeState) {
+        match self.expected.pop_front() {
+            Some((expected_from, expected_to)) if expected_from == *from && expected_to == *to => {
+                log::info!("Replay: {:?} -> {:?} as recorded", from, to);
+            }
+            expected => {
+                self.divergences += 1;
+                log::error!("Replay diverged: {:?} -> {:?}, recorded {:?}", from, to, expected);
+            }
+        }
+    }
+}
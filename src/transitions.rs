@@ -0,0 +1,84 @@
+// In-memory log of runtime state transitions and why they happened. Mostly useful for
+// tuning differentials: a wall of threshold crossings a few minutes apart means they're too tight.
+
+use std::collections::VecDeque;
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ThermostatRuntimeState;
+use crate::clock;
+use crate::time_format::TimeFormat;
+
+/// Number of transitions kept before the oldest ones are dropped
+pub const TRANSITION_LOG_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransitionReason {
+    /// The temperature crossed a start or stop threshold
+    ThresholdCrossed,
+    /// The compressor ran long enough that it has to rest
+    RestBudgetExceeded,
+    RestComplete,
+    FanLeadComplete,
+    /// The user (or a schedule) changed the mode
+    ModeChanged,
+    /// Outdoor temperature, open window or demand response lockout
+    Lockout,
+    SensorLost,
+    /// The state ran into its timeout guard
+    Timeout,
+    /// The controller refused a command or a relay didn't respond
+    Fault,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Transition {
+    /// Seconds since the unix epoch (only meaningful once the clock has been set)
+    pub timestamp: u64,
+    pub from: ThermostatRuntimeState,
+    pub to: ThermostatRuntimeState,
+    pub reason: TransitionReason,
+    /// Temperature at the time, in Celsius
+    pub temp_c: Option<f32>,
+}
+
+impl Transition {
+    /// Single line summary, used by the diagnostics screen
+    pub fn summary(&self, format: &TimeFormat) -> String {
+        let time = format.timestamp(self.timestamp);
+        let temp = self.temp_c.map(|temp_c| format!(" at {:.1}°C", temp_c)).unwrap_or_default();
+        format!("{} {:?} -> {:?}: {:?}{}", time, self.from, self.to, self.reason, temp)
+    }
+}
+
+#[derive(Default)]
+pub struct TransitionLog {
+    entries: VecDeque<Transition>,
+}
+
+impl TransitionLog {
+    pub fn record(&mut self, from: ThermostatRuntimeState, to: ThermostatRuntimeState, reason: TransitionReason, temp_c: Option<f32>) {
+        log::info!("Transition {:?} -> {:?}: {:?}", from, to, reason);
+        if self.entries.len() == TRANSITION_LOG_CAPACITY {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(Transition {
+            timestamp: clock::unix_secs(),
+            from,
+            to,
+            reason,
+            temp_c,
+        });
+    }
+
+    /// Oldest entry first
+    pub fn entries(&self) -> impl Iterator<Item = &Transition> {
+        self.entries.iter()
+    }
+
+    /// Export the log as JSON, oldest entry first
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string(&self.entries)?)
+    }
+}
@@ -0,0 +1,70 @@
+// Rate of change of the measured temperature over the last few minutes.
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use serde::{Deserialize, Serialize};
+
+/// How far back the slope is computed over
+const WINDOW: Duration = Duration::from_secs(15 * 60);
+/// Readings are only sampled this often, so the window stays small
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+/// Slopes smaller than this are shown as steady (Celsius per hour)
+const STEADY_THRESHOLD_C_PER_HOUR: f32 = 0.3;
+/// Need at least this much history before reporting a trend
+const MIN_SPAN: Duration = Duration::from_secs(5 * 60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+#[repr(i32)]
+pub enum Trend {
+    Falling = -1,
+    Steady = 0,
+    Rising = 1,
+}
+
+#[derive(Default)]
+pub struct TemperatureTrend {
+    samples: VecDeque<(Instant, f32)>,
+}
+
+impl TemperatureTrend {
+    pub fn record(&mut self, temp_c: f32) {
+        let now = Instant::now();
+        if self.samples.back().is_some_and(|(at, _)| now.duration_since(*at) < SAMPLE_INTERVAL) {
+            return;
+        }
+        self.samples.push_back((now, temp_c));
+        while self.samples.front().is_some_and(|(at, _)| now.duration_since(*at) > WINDOW) {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Least squares slope of the samples in the window, in Celsius per hour.
+    /// None until there is enough history.
+    pub fn slope_c_per_hour(&self) -> Option<f32> {
+        let (first, _) = self.samples.front()?;
+        let (last, _) = self.samples.back()?;
+        if last.duration_since(*first) < MIN_SPAN {
+            return None;
+        }
+        let n = self.samples.len() as f32;
+        let points = self.samples.iter().map(|(at, temp_c)| (at.duration_since(*first).as_secs_f32() / 3600.0, *temp_c));
+        let (sum_x, sum_y, sum_xy, sum_xx) = points.fold((0.0, 0.0, 0.0, 0.0), |(sx, sy, sxy, sxx), (x, y)| {
+            (sx + x, sy + y, sxy + x * y, sxx + x * x)
+        });
+        let denominator = n * sum_xx - sum_x * sum_x;
+        if denominator == 0.0 {
+            return None;
+        }
+        Some((n * sum_xy - sum_x * sum_y) / denominator)
+    }
+
+    pub fn trend(&self) -> Trend {
+        match self.slope_c_per_hour() {
+            Some(slope) if slope >= STEADY_THRESHOLD_C_PER_HOUR => Trend::Rising,
+            Some(slope) if slope <= -STEADY_THRESHOLD_C_PER_HOUR => Trend::Falling,
+            _ => Trend::Steady,
+        }
+    }
+}
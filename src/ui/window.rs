@@ -1,16 +1,15 @@
 use anyhow::Result;
-use esp_idf_svc::hal::i2c::I2cDriver;
-use slint::{Color, SharedString, Weak};
+use chrono::{NaiveDate, NaiveTime, Timelike, Weekday};
+use slint::{Color, Model, SharedString, Weak};
 use std::{
+    cell::RefCell,
     collections::HashMap,
-    sync::{
-        mpsc::{Receiver, Sender},
-        Arc,
-    },
+    rc::Rc,
+    sync::Arc,
     time::Duration,
 };
 
-use crate::{bsp::slint_platform, events::{BackendEvent, DiffStatus, FanStatus, ModeStatus, RestStatus, UiEvent}};
+use crate::{ambient_light::{AmbientLightSensor, AutoBrightness}, burn_in::BurnInProtection, comfort_profile::ComfortSettings, backend::ThermostatRuntimeState, clock, installer::{InstallerSettings, Secret}, schedule::{ScheduleException, WeeklySchedule}, vacation::Vacation, network::{NetworkSettings, StaticIp}, ota::{self, UpdateChannel, UpdateStatus}, time_format::{ClockFormat, DateOrder, TimeFormat}, timezone, remote_sensors::{Battery, PairingStatus, SensorRef, WeightedSensor, LOW_BATTERY_PERCENT}, proximity::{ProximityDetector, ProximitySensitivity, ProximityWake}, bsp::{slint_platform, SharedI2c}, bus::{EventBus, Message, Subscription, Topic}, events::{BackendEvent, ComfortProfile, CommandOutcome, CommandSource, DisplayPrecision, FanStatus, ModeStatus, RestStatus, StatusMessage, UiEvent}};
 
 
 slint::include_modules!();
@@ -18,16 +17,27 @@ pub struct Window;
 
 impl Window {
     pub fn init(
-        touch_i2c: I2cDriver<'static>,
-        rx: Receiver<BackendEvent>,
-        actor_tx: Sender<UiEvent>,
+        touch_i2c: SharedI2c,
+        bus: EventBus,
     ) -> Result<()> {
+        let light_sensor = AmbientLightSensor::probe(touch_i2c.clone());
+        let proximity = ProximityDetector::probe(touch_i2c.clone());
+        let backlight_i2c = touch_i2c.clone();
         slint_platform::init(touch_i2c);
         let window = MainWindow::new()
             .map_err(|e| anyhow::anyhow!("Failed to create main window: {}", e))?;
 
-        install_callbacks(&window, actor_tx);
-        let timer = regiser_event_receiver_timer(&window, rx);
+        let rx = bus.subscribe(&[Topic::State, Topic::Alerts]);
+        // The installer settings as last received, the screen only edits some of them
+        let installer_settings = Rc::new(RefCell::new(None));
+        install_callbacks(&window, bus, installer_settings.clone());
+        #[cfg(feature = "buzzer")]
+        // SAFETY: LEDC and the buzzer pin aren't used anywhere else
+        match unsafe { crate::buzzer::Buzzer::on_board_pin() } {
+            Ok(mut buzzer) => window.on_dial_tick(move || buzzer.click()),
+            Err(e) => log::error!("Failed to set up the buzzer: {}", e),
+        }
+        let timer = regiser_event_receiver_timer(&window, rx, AutoBrightness::new(light_sensor), proximity, backlight_i2c, installer_settings);
 
         window
             .run()
@@ -37,43 +47,731 @@ impl Window {
     }
 }
 
-fn install_callbacks(window: &MainWindow, actor_tx: Sender<UiEvent>) {
+fn install_callbacks(window: &MainWindow, bus: EventBus, installer_settings: Rc<RefCell<Option<InstallerSettings>>>) {
     let _ = window.as_weak();
-    let diff_mode_tx = actor_tx.clone();
-    let rest_mode_tx = actor_tx.clone();
-    let fan_mode_tx = actor_tx.clone();
-    let hvac_mode_tx = actor_tx.clone();
-    let target_temp_tx = actor_tx.clone();
-    window.on_diff_mode_changed(move |e| {
-        diff_mode_tx.send(UiEvent::DiffUpdate(DiffStatus::try_from(e).unwrap())).unwrap();
+    let comfort_profile_bus = bus.clone();
+    let custom_comfort_bus = bus.clone();
+    let rest_mode_bus = bus.clone();
+    let fan_mode_bus = bus.clone();
+    let fan_timer_bus = bus.clone();
+    let boost_bus = bus.clone();
+    let sleep_bus = bus.clone();
+    let vacation_bus = bus.clone();
+    let hvac_mode_bus = bus.clone();
+    let target_temp_bus = bus.clone();
+    let refresh_bus = bus.clone();
+    let display_precision_bus = bus.clone();
+    let use_fahrenheit_bus = bus.clone();
+    let schedule_profile_bus = bus.clone();
+    let create_schedule_bus = bus.clone();
+    let rename_schedule_bus = bus.clone();
+    let remove_schedule_bus = bus.clone();
+    let schedule_exception_bus = bus.clone();
+    let schedule_exception_window = window.as_weak();
+    let create_schedule_window = window.as_weak();
+    let rename_schedule_window = window.as_weak();
+    let open_window_bus = bus.clone();
+    let demo_temp_bus = bus.clone();
+    let demand_response_bus = bus.clone();
+    let auto_brightness_bus = bus.clone();
+    let manual_brightness_bus = bus.clone();
+    let proximity_wake_bus = bus.clone();
+    let burn_in_bus = bus.clone();
+    let check_updates_bus = bus.clone();
+    let install_update_bus = bus.clone();
+    let update_channel_bus = bus.clone();
+    let installer_login_bus = bus.clone();
+    let installer_logout_bus = bus.clone();
+    let installer_code_bus = bus.clone();
+    let installer_settings_bus = bus.clone();
+    let seasonal_lockout_bus = bus.clone();
+    let network_bus = bus.clone();
+    let timezone_bus = bus.clone();
+    let time_format_bus = bus.clone();
+    let timezone_window = window.as_weak();
+    let network_window = window.as_weak();
+    let control_sensor_bus = bus.clone();
+    let pair_sensor_bus = bus.clone();
+    let unpair_sensor_bus = bus.clone();
+    let window_weak = window.as_weak();
+    let control_sensor_window = window.as_weak();
+    window.on_comfort_profile_changed(move |e| {
+        comfort_profile_bus.publish_command(CommandSource::Touch, UiEvent::ComfortProfileUpdate(ComfortProfile::try_from(e).unwrap()));
+    });
+    window.on_custom_comfort_changed(move |heat_differential_c, cool_differential_c, heat_anticipator_c, heat_run_on_secs, cool_run_on_secs| {
+        let comfort = ComfortSettings {
+            heat_differential_c,
+            cool_differential_c,
+            heat_anticipator_c,
+            heat_fan_run_on_secs: heat_run_on_secs.max(0) as u32,
+            cool_fan_run_on_secs: cool_run_on_secs.max(0) as u32,
+        };
+        custom_comfort_bus.publish_command(CommandSource::Touch, UiEvent::CustomComfortUpdate(comfort));
     });
     window.on_rest_mode_changed(move |e| {
-        rest_mode_tx.send(UiEvent::RestUpdate(RestStatus::try_from(e).unwrap())).unwrap();
+        rest_mode_bus.publish_command(CommandSource::Touch, UiEvent::RestUpdate(RestStatus::try_from(e).unwrap()));
     });
     window.on_fan_mode_changed(move |e| {
-        fan_mode_tx.send(UiEvent::FanUpdate(FanStatus::try_from(e).unwrap())).unwrap();
+        fan_mode_bus.publish_command(CommandSource::Touch, UiEvent::FanUpdate(FanStatus::try_from(e).unwrap()));
+    });
+    window.on_fan_timer(move |minutes| {
+        fan_timer_bus.publish_command(CommandSource::Touch, UiEvent::FanTimer(Duration::from_mins(minutes.max(0) as u64)));
+    });
+    window.on_boost(move |start| {
+        boost_bus.publish_command(CommandSource::Touch, UiEvent::Boost(start));
+    });
+    window.on_sleep_preset(move |wake_mins| {
+        let wake_time = (wake_mins >= 0).then(|| NaiveTime::from_hms_opt((wake_mins / 60) as u32 % 24, (wake_mins % 60) as u32, 0)).flatten();
+        sleep_bus.publish_command(CommandSource::Touch, UiEvent::SleepPreset(wake_time));
+    });
+    window.on_vacation_changed(move |away, starts_in_days, days, heat_setpoint_c, cool_setpoint_c| {
+        let vacation = if away {
+            // The dates are picked relative to today
+            if !clock::is_set() {
+                log::warn!("Can't set an away period before the clock is set");
+                return;
+            }
+            let start = clock::local_now().date() + chrono::Days::new(starts_in_days.max(0) as u64);
+            let end = start + chrono::Days::new(days.max(1) as u64 - 1);
+            Some(Vacation { start, end, heat_setpoint_c, cool_setpoint_c })
+        } else {
+            None
+        };
+        vacation_bus.publish_command(CommandSource::Touch, UiEvent::VacationUpdate(vacation));
     });
     window.on_hvac_mode_changed(move |e| {
-        hvac_mode_tx.send(UiEvent::ModeUpdate(ModeStatus::try_from(e).unwrap())).unwrap();
+        hvac_mode_bus.publish_command(CommandSource::Touch, UiEvent::ModeUpdate(ModeStatus::try_from(e).unwrap()));
+    });
+    window.on_display_precision_changed(move |e| {
+        display_precision_bus.publish_command(CommandSource::Touch, UiEvent::DisplayPrecisionUpdate(DisplayPrecision::try_from(e).unwrap()));
+    });
+    window.on_use_fahrenheit_changed(move |e| {
+        use_fahrenheit_bus.publish_command(CommandSource::Touch, UiEvent::UseFahrenheitUpdate(e));
     });
     window.on_target_temp_changed(move |e| {
-        target_temp_tx.send(UiEvent::TargetTempUpdate(e)).unwrap();
+        target_temp_bus.publish_command(CommandSource::Touch, UiEvent::TargetTempUpdate(e));
+    });
+    // Re-evaluate right away rather than on the next tick, so the new setpoint takes effect instantly
+    window.on_target_temp_settled(move || {
+        refresh_bus.publish_command(CommandSource::Touch, UiEvent::ForceRefresh);
+    });
+    window.on_pair_sensor(move || {
+        pair_sensor_bus.publish_command(CommandSource::Touch, UiEvent::PairSensor);
+    });
+    window.on_unpair_sensor(move |name| {
+        unpair_sensor_bus.publish_command(CommandSource::Touch, UiEvent::UnpairSensor(name.to_string()));
+    });
+    window.on_open_window_override(move || {
+        open_window_bus.publish_command(CommandSource::Touch, UiEvent::OpenWindowOverride);
+    });
+    window.on_demo_temperature(move |start, temp_c| {
+        demo_temp_bus.publish_command(CommandSource::Touch, UiEvent::DemoTemperature(start.then_some(temp_c)));
+    });
+    window.on_demand_response_opt_out(move || {
+        demand_response_bus.publish_command(CommandSource::Touch, UiEvent::DemandResponseOptOut);
+    });
+    window.on_auto_brightness_changed(move |e| {
+        auto_brightness_bus.publish_command(CommandSource::Touch, UiEvent::AutoBrightnessUpdate(e));
+    });
+    window.on_manual_brightness_changed(move |e| {
+        manual_brightness_bus.publish_command(CommandSource::Touch, UiEvent::ManualBrightnessUpdate(e.clamp(0, 100) as u8));
+    });
+    window.on_check_for_updates(move || {
+        check_updates_bus.publish_command(CommandSource::Touch, UiEvent::CheckForUpdates);
+    });
+    window.on_install_update(move || {
+        install_update_bus.publish_command(CommandSource::Touch, UiEvent::InstallUpdate);
+    });
+    window.on_update_channel_changed(move |e| {
+        update_channel_bus.publish_command(CommandSource::Touch, UiEvent::UpdateChannelUpdate(UpdateChannel::try_from(e).unwrap()));
+    });
+    window.on_installer_login(move |code| {
+        installer_login_bus.publish_command(CommandSource::Touch, UiEvent::InstallerLogin(Secret(code.to_string())));
+    });
+    window.on_installer_logout(move || {
+        installer_logout_bus.publish_command(CommandSource::Touch, UiEvent::InstallerLogout);
+    });
+    window.on_set_installer_code(move |code| {
+        let code = (!code.is_empty()).then(|| Secret(code.to_string()));
+        installer_code_bus.publish_command(CommandSource::Touch, UiEvent::SetInstallerCode(code));
+    });
+    window.on_installer_settings_changed(move |cutoff_on, cutoff_c, max_starts, boot_self_test| {
+        // Nothing to change before the settings came in
+        let Some(mut changed) = installer_settings.borrow().clone() else {
+            return;
+        };
+        changed.high_temp_cutoff_c = cutoff_on.then_some(cutoff_c);
+        changed.max_compressor_starts_per_hour = max_starts.max(1) as u32;
+        changed.boot_self_test = boot_self_test;
+        installer_settings_bus.publish_command(CommandSource::Touch, UiEvent::InstallerSettingsUpdate(changed));
+    });
+    window.on_seasonal_lockout_changed(move |heat_on, heat_c, cool_on, cool_c| {
+        seasonal_lockout_bus.publish_command(
+            CommandSource::Touch,
+            UiEvent::SeasonalLockoutUpdate {
+                heat_above_c: heat_on.then_some(heat_c),
+                cool_below_c: cool_on.then_some(cool_c),
+            },
+        );
+    });
+    window.on_time_format_changed(move |clock, date_order| {
+        let time_format = TimeFormat {
+            clock: ClockFormat::try_from(clock).unwrap(),
+            date_order: DateOrder::try_from(date_order).unwrap(),
+        };
+        time_format_bus.publish_command(CommandSource::Touch, UiEvent::TimeFormatUpdate(time_format));
+    });
+    window.on_next_timezone(move || {
+        let Some(window) = timezone_window.upgrade() else {
+            return;
+        };
+        let next = timezone::next(window.get_timezone().as_str());
+        timezone_bus.publish_command(CommandSource::Touch, UiEvent::TimezoneUpdate(next.to_string()));
+    });
+    // Touch commands skip validation, so the addresses are checked here and the screen says what's wrong
+    window.on_network_settings_changed(move |static_ip, address, prefix_len, gateway, dns| {
+        let Some(window) = network_window.upgrade() else {
+            return;
+        };
+        let static_ip = if static_ip {
+            let parsed = (|| {
+                Some(StaticIp {
+                    address: address.parse().ok()?,
+                    prefix_len: u8::try_from(prefix_len).ok()?,
+                    gateway: gateway.parse().ok()?,
+                    dns: if dns.is_empty() { None } else { Some(dns.parse().ok()?) },
+                })
+            })();
+            match parsed {
+                Some(static_ip) if static_ip.is_valid() => Some(static_ip),
+                Some(_) => {
+                    window.set_network_error(SharedString::from("Address and gateway must be on the same subnet"));
+                    return;
+                }
+                None => {
+                    window.set_network_error(SharedString::from("Not an IP address, like 192.168.1.20"));
+                    return;
+                }
+            }
+        } else {
+            None
+        };
+        let hostname = window.get_hostname();
+        let network = if hostname.is_empty() {
+            // Settings not received yet
+            NetworkSettings { static_ip, ..NetworkSettings::default() }
+        } else {
+            NetworkSettings { hostname: hostname.to_string(), static_ip }
+        };
+        network_bus.publish_command(CommandSource::Touch, UiEvent::NetworkSettingsUpdate(network));
+    });
+    // 0 turns proximity wake off, anything else is the sensitivity
+    window.on_proximity_wake_changed(move |e| {
+        let proximity_wake = match ProximitySensitivity::try_from(e) {
+            Ok(sensitivity) => ProximityWake { enabled: true, sensitivity },
+            Err(_) => ProximityWake { enabled: false, ..Default::default() },
+        };
+        proximity_wake_bus.publish_command(CommandSource::Touch, UiEvent::ProximityWakeUpdate(proximity_wake));
+    });
+    window.on_burn_in_changed(move |pixel_shift, wash_hour| {
+        let burn_in = BurnInProtection {
+            pixel_shift,
+            nightly_wash_at: (wash_hour >= 0).then(|| NaiveTime::from_hms_opt(wash_hour as u32 % 24, 0, 0)).flatten(),
+        };
+        burn_in_bus.publish_command(CommandSource::Touch, UiEvent::BurnInProtectionUpdate(burn_in));
+    });
+    // Each tap moves to the next profile, then to manual control, then back to the first one
+    window.on_next_control_sensor(move || {
+        let Some(window) = control_sensor_window.upgrade() else {
+            return;
+        };
+        let names: Vec<String> = window.get_remote_sensors().iter().map(|name| name.to_string()).collect();
+        let mut choices = vec!["Onboard".to_string()];
+        choices.extend(names.iter().cloned());
+        if names.len() > 1 {
+            choices.push("Average".to_string());
+        }
+        let current = window.get_control_sensor_choice();
+        let next = choices
+            .iter()
+            .position(|choice| *choice == current.as_str())
+            .map_or(0, |index| (index + 1) % choices.len());
+        let sensors = match choices[next].as_str() {
+            "Onboard" => Vec::new(),
+            "Average" => names.into_iter().map(|name| WeightedSensor { sensor: SensorRef::Remote(name), weight: 1.0 }).collect(),
+            name => vec![WeightedSensor { sensor: SensorRef::Remote(name.to_string()), weight: 1.0 }],
+        };
+        control_sensor_bus.publish_command(CommandSource::Touch, UiEvent::SensorSelectionUpdate { sensors, periods: None });
     });
+    window.on_create_schedule_profile(move || {
+        let Some(window) = create_schedule_window.upgrade() else {
+            return;
+        };
+        let taken: Vec<SharedString> = window.get_schedule_profiles().iter().collect();
+        let Some(name) = next_schedule_profile_name("", &taken) else {
+            return;
+        };
+        create_schedule_bus.publish_command(
+            CommandSource::Touch,
+            UiEvent::ScheduleProfileUpdate { name: name.to_string(), schedule: Some(WeeklySchedule::default()) },
+        );
+    });
+    window.on_rename_schedule_profile(move |from| {
+        let Some(window) = rename_schedule_window.upgrade() else {
+            return;
+        };
+        let taken: Vec<SharedString> = window.get_schedule_profiles().iter().collect();
+        let Some(to) = next_schedule_profile_name(from.as_str(), &taken) else {
+            return;
+        };
+        rename_schedule_bus.publish_command(CommandSource::Touch, UiEvent::RenameScheduleProfile { from: from.to_string(), to: to.to_string() });
+    });
+    window.on_remove_schedule_profile(move |name| {
+        remove_schedule_bus.publish_command(CommandSource::Touch, UiEvent::ScheduleProfileUpdate { name: name.to_string(), schedule: None });
+    });
+    window.on_schedule_exception(move |days_from_today, follows| {
+        let Some(window) = schedule_exception_window.upgrade() else {
+            return;
+        };
+        let name = window.get_schedule_profile();
+        if name.is_empty() || !clock::is_set() {
+            return;
+        }
+        let date = clock::local_now().date() + chrono::Days::new(days_from_today.max(0) as u64);
+        let follows = u8::try_from(follows).ok().and_then(|day| Weekday::try_from(day).ok());
+        schedule_exception_bus.publish_command(CommandSource::Touch, UiEvent::ScheduleExceptionUpdate { name: name.to_string(), date, follows });
+    });
+    window.on_next_schedule_profile(move || {
+        let Some(window) = window_weak.upgrade() else {
+            return;
+        };
+        let profiles: Vec<SharedString> = window.get_schedule_profiles().iter().collect();
+        let current = window.get_schedule_profile();
+        let next = match profiles.iter().position(|name| *name == current) {
+            Some(index) => profiles.get(index + 1),
+            None => profiles.first(),
+        };
+        schedule_profile_bus.publish_command(CommandSource::Touch, UiEvent::ActivateScheduleProfile(next.map(|name| name.to_string())));
+    });
+}
+
+/// Names the touch screen gives schedule profiles, there's no keyboard to type one
+const SCHEDULE_PROFILE_NAMES: [&str; 8] = ["Home", "Work", "Weekend", "Summer", "Winter", "Holiday", "Night", "Guests"];
+
+/// The preset name after `current` that isn't taken yet, going round the list
+fn next_schedule_profile_name(current: &str, taken: &[SharedString]) -> Option<&'static str> {
+    let after = SCHEDULE_PROFILE_NAMES.iter().position(|name| *name == current).map_or(0, |index| index + 1);
+    (0..SCHEDULE_PROFILE_NAMES.len())
+        .map(|offset| SCHEDULE_PROFILE_NAMES[(after + offset) % SCHEDULE_PROFILE_NAMES.len()])
+        .find(|name| !taken.iter().any(|taken| taken.as_str() == *name))
 }
 
-fn regiser_event_receiver_timer(window: &MainWindow, rx: Receiver<BackendEvent>) -> slint::Timer {
+/// Word the status line into `out`, in Fahrenheit or Celsius at the display precision
+fn write_status(out: &mut String, status: StatusMessage, (use_fahrenheit, precision): (bool, DisplayPrecision)) {
+    use std::fmt::Write;
+    out.clear();
+    let _ = match status {
+        StatusMessage::NoTemperature => write!(out, "No temperature reading"),
+        StatusMessage::TimedOut => write!(out, "Paused after running too long"),
+        StatusMessage::SeasonalLockout => write!(out, "Locked out by outdoor temperature"),
+        StatusMessage::HighTempCutoff => write!(out, "Too hot, heating cut off"),
+        StatusMessage::WindowOpen => write!(out, "Window open, heating paused"),
+        StatusMessage::Waiting { target_c } if use_fahrenheit => {
+            let target_f = precision.round(crate::Controller::celsius_to_fahrenheit(target_c));
+            write!(out, "Waiting for {:.*}°F", precision.decimals(), target_f)
+        }
+        StatusMessage::Waiting { target_c } => write!(out, "Waiting for {:.*}°C", precision.decimals(), precision.round(target_c)),
+        StatusMessage::Heating => write!(out, "Heating"),
+        StatusMessage::StartingFan => write!(out, "Starting fan"),
+        StatusMessage::Dehumidifying => write!(out, "Dehumidifying"),
+        StatusMessage::Cooling => write!(out, "Cooling"),
+        StatusMessage::Resting { remaining_secs } => write!(out, "Defrosting for {}m {}s", remaining_secs / 60, remaining_secs % 60),
+        StatusMessage::Idle => write!(out, "Idling"),
+        StatusMessage::Off => write!(out, "Off"),
+    };
+}
+
+/// Show when sleep and the vacation end in the user's time format
+fn write_until(window: &MainWindow, sleep_until: Option<NaiveTime>, away_until: Option<NaiveDate>, time_format: &TimeFormat) {
+    window.set_sleep_until(SharedString::from(sleep_until.map(|until| time_format.time(until)).unwrap_or_default()));
+    window.set_away_until(SharedString::from(away_until.map(|until| time_format.date(until)).unwrap_or_default()));
+}
+
+/// List the active profile's exception days in the user's date format
+fn write_exceptions(window: &MainWindow, exceptions: &[ScheduleException], time_format: &TimeFormat) {
+    let lines: Vec<SharedString> = exceptions
+        .iter()
+        .map(|exception| SharedString::from(format!("{} as {}", time_format.date(exception.date), exception.follows)))
+        .collect();
+    window.set_schedule_exceptions(slint::ModelRc::new(slint::VecModel::from(lines)));
+}
+
+fn regiser_event_receiver_timer(
+    window: &MainWindow,
+    rx: Subscription,
+    mut brightness: AutoBrightness,
+    mut proximity: ProximityDetector,
+    backlight_i2c: SharedI2c,
+    installer_settings: Rc<RefCell<Option<InstallerSettings>>>,
+) -> slint::Timer {
     let window_weak = window.as_weak();
+    window.set_light_sensor(brightness.has_sensor());
+    window.set_firmware_version(SharedString::from(ota::FIRMWARE_VERSION));
+    window.set_build_hash(SharedString::from(ota::BUILD_HASH));
+    window.set_proximity_sensor(proximity.has_sensor());
+    window.set_espnow_sensors(cfg!(feature = "espnow"));
     let timer = slint::Timer::default();
+    // What the debug screen last showed, to only rebuild it when there are new lines
+    let mut log_view = None;
+    // Switched off while the enclosure is too hot
+    let mut backlight_on = true;
+    // When a touch command was acked, the settings or snapshot following soon after shows its
+    // effect. Both are only sent on changes, a command that changed nothing has no response.
+    let mut awaiting_touch_response: Option<std::time::Instant> = None;
+    // Last status line shown and the units it was worded in, it's only reworded when either
+    // changes. The text buffer is reused across updates.
+    let mut status: Option<StatusMessage> = None;
+    let mut status_units = (false, DisplayPrecision::Tenth);
+    let mut status_text = String::new();
+    // Kept to reword them when the time format changes
+    let mut time_format = TimeFormat::default();
+    let mut sleep_until = None;
+    let mut away_until = None;
+    let mut schedule_exceptions = Vec::new();
     let callback = move || {
         // On call, upgrade the weak reference to a strong reference.
         let window = window_weak.upgrade().unwrap();
-        while let Ok(msg) = rx.try_recv() {
+        crate::metrics::record_stack_watermark("ui");
+        window.set_brightness(brightness.update() as i32);
+        let uptime = crate::metrics::uptime().as_secs();
+        window.set_uptime(SharedString::from(format!("{}d {}h {}m", uptime / 86400, uptime / 3600 % 24, uptime / 60 % 60)));
+        let fps = slint_platform::fps() as i32;
+        if window.get_fps() != fps {
+            window.set_fps(fps);
+        }
+        if window.get_developer_overlay() {
+            let (feedback_ms, response_ms) = slint_platform::touch_latency_ms();
+            window.set_touch_feedback_ms(feedback_ms as i32);
+            window.set_touch_response_ms(response_ms as i32);
+        }
+        if proximity.someone_near() {
+            window.invoke_wake();
+        }
+        if window.get_showing_logs() {
+            let view = (crate::log_tail::generation(), window.get_log_level());
+            if log_view != Some(view) {
+                let min_level = match view.1 {
+                    0 => log::Level::Error,
+                    1 => log::Level::Warn,
+                    2 => log::Level::Info,
+                    _ => log::Level::Debug,
+                };
+                // Newest first, so the latest lines are on screen without scrolling
+                let lines: Vec<SharedString> = crate::log_tail::lines(min_level).iter().rev().map(|line| SharedString::from(line.summary())).collect();
+                window.set_log_lines(slint::ModelRc::new(slint::VecModel::from(lines)));
+                log_view = Some(view);
+            }
+        } else {
+            log_view = None;
+        }
+        while let Ok(Message::State(msg)) = rx.try_recv() {
             match msg {
                 BackendEvent::CurrentTempCUpdate(temp_c) => {
                     window.set_current_temp_c(temp_c);
                 }
                 BackendEvent::CurrentStateMessage(message) => {
-                    window.set_thermostat_state(SharedString::from(message));
+                    if status != Some(message) {
+                        status = Some(message);
+                        write_status(&mut status_text, message, status_units);
+                        window.set_thermostat_state(SharedString::from(status_text.as_str()));
+                    }
+                }
+                BackendEvent::Snapshot(snapshot) => {
+                    if awaiting_touch_response.take().is_some_and(|acked| acked.elapsed() < Duration::from_secs(2)) {
+                        slint_platform::expect_touch_response();
+                    }
+                    window.set_sensor_ok(snapshot.current_temp_c.is_some());
+                    window.set_control_sensor(SharedString::from(snapshot.control_sensors.join("+")));
+                    let names: Vec<SharedString> = snapshot.remote_sensors.iter().map(|sensor| SharedString::from(sensor.name.as_str())).collect();
+                    if !window.get_remote_sensors().iter().eq(names.iter().cloned()) {
+                        window.set_remote_sensors(slint::ModelRc::new(slint::VecModel::from(names)));
+                    }
+                    let use_fahrenheit = window.get_use_fahrenheit();
+                    let freshness: Vec<SharedString> = snapshot
+                        .remote_sensors
+                        .iter()
+                        .map(|sensor| {
+                            let temp = if use_fahrenheit { crate::Controller::celsius_to_fahrenheit(sensor.temp_c) } else { sensor.temp_c };
+                            let age = match sensor.age_secs {
+                                secs if secs < 60 => format!("{}s ago", secs),
+                                secs => format!("{} min ago", secs / 60),
+                            };
+                            let stale = if sensor.stale { ", STALE" } else { "" };
+                            let battery = match sensor.battery {
+                                Some(Battery { percent: Some(percent), .. }) if percent <= LOW_BATTERY_PERCENT => format!(", battery LOW {}%", percent),
+                                Some(Battery { percent: Some(percent), .. }) => format!(", battery {}%", percent),
+                                Some(Battery { millivolts, percent: None }) if millivolts > 0 => format!(", battery {:.2}V", millivolts as f32 / 1000.0),
+                                _ => String::new(),
+                            };
+                            SharedString::from(format!("{}: {:.1}{}, {}{}{}", sensor.name, temp, if use_fahrenheit { "°F" } else { "°C" }, age, stale, battery))
+                        })
+                        .collect();
+                    if !window.get_remote_sensor_freshness().iter().eq(freshness.iter().cloned()) {
+                        window.set_remote_sensor_freshness(slint::ModelRc::new(slint::VecModel::from(freshness)));
+                    }
+                    window.set_runtime_state(match snapshot.state {
+                        ThermostatRuntimeState::Waiting | ThermostatRuntimeState::Idle | ThermostatRuntimeState::Off => 0,
+                        ThermostatRuntimeState::Heating => 1,
+                        ThermostatRuntimeState::Cooling => 2,
+                        ThermostatRuntimeState::Resting => 3,
+                        ThermostatRuntimeState::FanLead => 4,
+                    });
+                    window.set_fan_running(snapshot.fan_running);
+                    if (snapshot.sleep_until, snapshot.away_until) != (sleep_until, away_until) {
+                        (sleep_until, away_until) = (snapshot.sleep_until, snapshot.away_until);
+                        write_until(&window, sleep_until, away_until, &time_format);
+                    }
+                    if snapshot.schedule_exceptions != schedule_exceptions {
+                        schedule_exceptions = snapshot.schedule_exceptions;
+                        write_exceptions(&window, &schedule_exceptions, &time_format);
+                    }
+                    window.set_boost_secs(snapshot.boost_remaining_secs.map_or(0, |secs| secs as i32));
+                    window.set_fan_timer_secs(snapshot.fan_timer_remaining_secs.map_or(0, |secs| secs as i32));
+                    window.set_rest_progress(snapshot.rest_progress.unwrap_or(0.0));
+                    if let Some(temp_c) = snapshot.current_temp_c {
+                        window.set_current_temp_c(temp_c);
+                    }
+                    window.set_temp_trend(snapshot.trend as i32);
+                    window.set_has_humidity(snapshot.capabilities.humidity);
+                    window.set_humidity_percent(snapshot.humidity_percent.unwrap_or(0.0).round() as i32);
+                    window.set_has_outdoor_temp(snapshot.capabilities.outdoor_temp);
+                    window.set_outdoor_temp_c(snapshot.outdoor_temp_c.unwrap_or(0.0));
+                    window.set_has_air_quality(snapshot.capabilities.air_quality);
+                    window.set_co2_ppm(snapshot.co2_ppm.map_or(0, i32::from));
+                    window.set_ventilating(snapshot.ventilating);
+                    window.set_ventilation_held(snapshot.ventilation_held);
+                    window.set_frost_protecting(snapshot.frost_protecting);
+                    window.set_quiet_hours(snapshot.quiet_hours);
+                    window.set_installer_unlocked(snapshot.installer_unlocked);
+                    window.set_api_token(SharedString::from(snapshot.api_token.as_ref().map_or("", |token| token.0.as_str())));
+                    window.set_screen_wash(snapshot.screen_wash);
+                    if !snapshot.screen_wash {
+                        window.set_wash_dismissed(false);
+                    }
+                    window.set_reduced_power(snapshot.reduced_power);
+                    window.set_brownouts(snapshot.brownouts as i32);
+                    window.set_free_heap_kb((snapshot.memory.free_heap_bytes / 1024) as i32);
+                    window.set_min_free_heap_kb((snapshot.memory.min_free_heap_bytes / 1024) as i32);
+                    window.set_free_internal_kb((snapshot.memory.free_internal_bytes / 1024) as i32);
+                    window.set_free_psram_kb((snapshot.memory.free_psram_bytes / 1024) as i32);
+                    window.set_largest_free_block_kb((snapshot.memory.largest_free_block_bytes / 1024) as i32);
+                    window.set_alloc_failures(snapshot.memory.alloc_failures as i32);
+                    let stacks = snapshot
+                        .memory
+                        .stack_free_bytes
+                        .iter()
+                        .map(|(thread, bytes)| format!("{} {}B", thread, bytes))
+                        .collect::<Vec<_>>()
+                        .join(", ");
+                    window.set_stack_free(SharedString::from(stacks));
+                    let timings = snapshot.timings.iter().map(|stats| stats.summary()).collect::<Vec<_>>().join(", ");
+                    window.set_timings(SharedString::from(timings));
+                    window.set_online(snapshot.online);
+                    window.set_ip_address(SharedString::from(snapshot.ip_address.map(|ip| ip.to_string()).unwrap_or_default()));
+                    window.set_has_remote_access(snapshot.remote_access.is_some());
+                    window.set_remote_access_up(snapshot.remote_access.unwrap_or(false));
+                    window.set_has_enclosure_temp(snapshot.enclosure_temp_c.is_some());
+                    window.set_enclosure_temp_c(snapshot.enclosure_temp_c.unwrap_or(0.0));
+                    window.set_enclosure_overheated(snapshot.enclosure_overheated);
+                    if backlight_on == snapshot.enclosure_overheated {
+                        match crate::bsp::set_backlight(&backlight_i2c, !snapshot.enclosure_overheated) {
+                            Ok(()) => backlight_on = !snapshot.enclosure_overheated,
+                            Err(e) => log::error!("Failed to switch the backlight: {}", e),
+                        }
+                    }
+                    let clock_sync = match snapshot.clock_synced_secs_ago {
+                        Some(secs) => {
+                            let drift = snapshot.clock_drift_ppm.map(|ppm| format!(", drift {:.0} ppm", ppm)).unwrap_or_default();
+                            format!("Clock synced {} min ago{}", secs / 60, drift)
+                        }
+                        None => "Clock not synced".to_string(),
+                    };
+                    window.set_clock_sync(SharedString::from(clock_sync));
+                    window.set_open_window(snapshot.open_window);
+                    window.set_demo_active(snapshot.demo_temp);
+                    window.set_demand_response(snapshot.demand_response);
+                    window.set_peak_phase(snapshot.peak.map_or(0, |phase| phase as i32));
+                    window.set_temp_slope_c_per_hour(snapshot.slope_c_per_hour.unwrap_or(0.0));
+                    window.set_heat_overshoot_known(snapshot.heat_overshoot.average_c.is_some());
+                    window.set_heat_overshoot_last_c(snapshot.heat_overshoot.last_c.unwrap_or(0.0));
+                    window.set_heat_overshoot_average_c(snapshot.heat_overshoot.average_c.unwrap_or(0.0));
+                    window.set_comfort_score(snapshot.comfort.comfort_score.map_or(-1, i32::from));
+                    window.set_average_cycle_mins(snapshot.comfort.average_cycle_secs.map_or(-1, |secs| (secs / 60) as i32));
+                    window.set_max_overshoot_c(snapshot.comfort.max_overshoot_c.unwrap_or(0.0));
+                    match snapshot.setpoint_estimate {
+                        Some(estimate) => {
+                            window.set_estimate_target_c(estimate.target_c);
+                            window.set_estimate_minutes(estimate.minutes as i32);
+                        }
+                        None => window.set_estimate_minutes(-1),
+                    }
+                }
+                BackendEvent::Alert(message) => {
+                    window.set_alert_message(SharedString::from(message));
+                }
+                BackendEvent::TransitionLogUpdate(entries) => {
+                    let entries: Vec<SharedString> = entries.into_iter().map(SharedString::from).collect();
+                    window.set_transition_entries(slint::ModelRc::new(slint::VecModel::from(entries)));
+                }
+                BackendEvent::TemperatureHistoryUpdate(days) => {
+                    let days: Vec<SharedString> = days.into_iter().map(SharedString::from).collect();
+                    window.set_temperature_history(slint::ModelRc::new(slint::VecModel::from(days)));
+                }
+                BackendEvent::AuditLogUpdate(entries) => {
+                    let entries: Vec<SharedString> = entries.into_iter().map(SharedString::from).collect();
+                    window.set_audit_entries(slint::ModelRc::new(slint::VecModel::from(entries)));
+                }
+                BackendEvent::SettingsUpdate(settings) => {
+                    if awaiting_touch_response.take().is_some_and(|acked| acked.elapsed() < Duration::from_secs(2)) {
+                        slint_platform::expect_touch_response();
+                    }
+                    window.set_target_temp_c(settings.target_temp_c);
+                    window.set_hvac_mode(settings.mode as i32);
+                    window.set_comfort_profile(settings.comfort_profile as i32);
+                    window.set_custom_heat_differential_c(settings.custom_comfort.heat_differential_c);
+                    window.set_custom_cool_differential_c(settings.custom_comfort.cool_differential_c);
+                    window.set_custom_heat_anticipator_c(settings.custom_comfort.heat_anticipator_c);
+                    window.set_custom_heat_fan_run_on_secs(settings.custom_comfort.heat_fan_run_on_secs as i32);
+                    window.set_custom_cool_fan_run_on_secs(settings.custom_comfort.cool_fan_run_on_secs as i32);
+                    window.set_rest_mode(settings.rest_mode as i32);
+                    window.set_fan_mode(settings.fan_mode as i32);
+                    window.set_use_fahrenheit(settings.use_fahrenheit);
+                    window.set_display_precision(settings.display_precision as i32);
+                    let units = (settings.use_fahrenheit, settings.display_precision);
+                    if units != status_units {
+                        status_units = units;
+                        if let Some(message) = status {
+                            write_status(&mut status_text, message, status_units);
+                            window.set_thermostat_state(SharedString::from(status_text.as_str()));
+                        }
+                    }
+                    window.set_pixel_shift(settings.burn_in.pixel_shift);
+                    window.set_wash_hour(settings.burn_in.nightly_wash_at.map_or(-1, |at| at.hour() as i32));
+                    window.set_wash_at(SharedString::from(settings.burn_in.nightly_wash_at.map(|at| settings.time_format.time(at)).unwrap_or_default()));
+                    window.set_auto_brightness(settings.brightness.auto);
+                    window.set_manual_brightness(settings.brightness.manual_percent as i32);
+                    brightness.configure(settings.brightness);
+                    let proximity_wake = settings.proximity_wake;
+                    window.set_proximity_wake(if proximity_wake.enabled { proximity_wake.sensitivity as i32 } else { 0 });
+                    proximity.configure(proximity_wake);
+                    window.set_update_channel(settings.update_channel as i32);
+                    window.set_control_sensor_choice(SharedString::from(match settings.sensor_selection.sensors.as_slice() {
+                        [] => "Onboard".to_string(),
+                        [WeightedSensor { sensor: SensorRef::Remote(name), .. }] => name.clone(),
+                        [WeightedSensor { sensor: SensorRef::Onboard, .. }] => "Onboard".to_string(),
+                        _ => "Average".to_string(),
+                    }));
+                    window.set_sleep_wake_mins((settings.sleep.wake_time.hour() * 60 + settings.sleep.wake_time.minute()) as i32);
+                    window.set_vacation_on(settings.vacation.is_some());
+                    if let Some(vacation) = &settings.vacation {
+                        let today = clock::is_set().then(|| clock::local_now().date()).unwrap_or(vacation.start);
+                        window.set_vacation_starts_in_days((vacation.start - today).num_days().max(0) as i32);
+                        window.set_vacation_days((vacation.end - vacation.start).num_days() as i32 + 1);
+                        window.set_vacation_heat_c(vacation.heat_setpoint_c);
+                        window.set_vacation_cool_c(vacation.cool_setpoint_c);
+                    }
+                    window.set_heat_lockout_on(settings.heat_lockout_above_c.is_some());
+                    window.set_heat_lockout_c(settings.heat_lockout_above_c.unwrap_or(18.0));
+                    window.set_cool_lockout_on(settings.cool_lockout_below_c.is_some());
+                    window.set_cool_lockout_c(settings.cool_lockout_below_c.unwrap_or(10.0));
+                    window.set_high_temp_cutoff_on(settings.high_temp_cutoff_c.is_some());
+                    window.set_high_temp_cutoff_c(settings.high_temp_cutoff_c.unwrap_or(30.0));
+                    window.set_max_compressor_starts(settings.max_compressor_starts_per_hour as i32);
+                    window.set_boot_self_test(settings.boot_self_test);
+                    *installer_settings.borrow_mut() = Some(InstallerSettings::from_settings(&settings));
+                    window.set_schedule_profile(SharedString::from(settings.active_schedule.unwrap_or_default()));
+                    window.set_timezone(SharedString::from(settings.timezone.as_str()));
+                    window.set_clock_format(settings.time_format.clock as i32);
+                    window.set_date_order(settings.time_format.date_order as i32);
+                    if settings.time_format != time_format {
+                        time_format = settings.time_format;
+                        write_until(&window, sleep_until, away_until, &time_format);
+                        write_exceptions(&window, &schedule_exceptions, &time_format);
+                    }
+                    window.set_hostname(SharedString::from(settings.network.hostname.as_str()));
+                    window.set_network_static(settings.network.static_ip.is_some());
+                    if let Some(static_ip) = settings.network.static_ip {
+                        window.set_static_address(SharedString::from(static_ip.address.to_string()));
+                        window.set_static_prefix(static_ip.prefix_len as i32);
+                        window.set_static_gateway(SharedString::from(static_ip.gateway.to_string()));
+                        window.set_static_dns(SharedString::from(static_ip.dns.map(|dns| dns.to_string()).unwrap_or_default()));
+                    }
+                }
+                BackendEvent::WiringCheck(lines) => {
+                    let (warnings, lines): (Vec<String>, Vec<String>) = lines.into_iter().partition(|line| line.starts_with('!'));
+                    let lines: Vec<SharedString> = lines.into_iter().map(SharedString::from).collect();
+                    let warnings: Vec<SharedString> = warnings.iter().map(|warning| SharedString::from(warning.trim_start_matches('!').trim_start())).collect();
+                    window.set_wiring_check(slint::ModelRc::new(slint::VecModel::from(lines)));
+                    window.set_wiring_warnings(slint::ModelRc::new(slint::VecModel::from(warnings)));
+                }
+                BackendEvent::ScheduleProfilesUpdate(names) => {
+                    let names: Vec<SharedString> = names.into_iter().map(SharedString::from).collect();
+                    window.set_schedule_profiles(slint::ModelRc::new(slint::VecModel::from(names)));
+                }
+                // Touch commands rarely get rejected (wrong installer code), show why
+                BackendEvent::CommandAck { source: CommandSource::Touch, outcome: CommandOutcome::Rejected(rejection), .. } => {
+                    window.set_alert_message(SharedString::from(rejection.to_string()));
+                    slint_platform::expect_touch_response();
+                }
+                BackendEvent::CommandAck { source: CommandSource::Touch, .. } => {
+                    awaiting_touch_response = Some(std::time::Instant::now());
+                }
+                // Only meant for network sources, nothing to show for them here. Adjusted touch
+                // commands come back with the settings update anyway.
+                BackendEvent::CommandAck { .. }
+                | BackendEvent::SettingsExport(_)
+                | BackendEvent::DiagnosticsExport { .. }
+                | BackendEvent::Summary(_) => {}
+                // For the MQTT thread
+                BackendEvent::SigningSecretChanged => {}
+                // For the api
+                BackendEvent::ApiCredentialsChanged(_) | BackendEvent::InstallerSession { .. } => {}
+                // For the ESP-NOW thread
+                BackendEvent::OpenSensorPairing | BackendEvent::SensorUnpaired(_) => {}
+                BackendEvent::SensorPairing(status) => {
+                    window.set_sensor_pairing(SharedString::from(match status {
+                        PairingStatus::Listening => "Press the pairing button on the sensor...".to_string(),
+                        PairingStatus::Paired(name) => format!("Paired {}", name),
+                        PairingStatus::TimedOut => "No sensor found, try again".to_string(),
+                        PairingStatus::Full => "Too many sensors, unpair one first".to_string(),
+                    }));
+                }
+                BackendEvent::SelfTestReport(report) => {
+                    let failures = report.failures();
+                    window.set_self_test_summary(SharedString::from(match failures {
+                        0 => "Self-test: passed".to_string(),
+                        failures => format!("Self-test: {} failed", failures),
+                    }));
+                    let lines: Vec<SharedString> = report.summary_lines().into_iter().map(SharedString::from).collect();
+                    window.set_self_test_results(slint::ModelRc::new(slint::VecModel::from(lines)));
+                    // Only get in the way when something is wrong
+                    window.set_showing_self_test(failures > 0);
+                }
+                BackendEvent::UpdateStatus(status) => {
+                    let (text, available) = match status {
+                        UpdateStatus::Checking => ("Checking for updates...".to_string(), false),
+                        UpdateStatus::UpToDate => ("Firmware is up to date".to_string(), false),
+                        UpdateStatus::Available { version, changelog } => (format!("Version {} available\n{}", version, changelog), true),
+                        UpdateStatus::Installing(Some(percent)) => (format!("Installing... {}%", percent), false),
+                        UpdateStatus::Installing(None) => ("Installing...".to_string(), false),
+                        UpdateStatus::Failed(reason) => (format!("Update failed: {}", reason), false),
+                    };
+                    window.set_update_status(SharedString::from(text));
+                    window.set_update_available(available);
+                }
+                BackendEvent::CycleStatsUpdate(counts) => {
+                    window.set_compressor_starts_last_hour(counts.starts_last_hour as i32);
+                    window.set_compressor_starts_last_day(counts.starts_last_day as i32);
                 }
             }
         }
@@ -0,0 +1,37 @@
+// Away periods with explicit dates. While one is active the away setpoints replace
+// the normal setpoint, and the normal setpoint resumes on its own afterwards.
+
+use chrono::NaiveDate;
+use serde::{Deserialize, Serialize};
+
+use crate::events::ModeStatus;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vacation {
+    /// First day away
+    pub start: NaiveDate,
+    /// Last day away, normal operation resumes the day after
+    pub end: NaiveDate,
+    /// Setpoints while away, in Celsius
+    pub heat_setpoint_c: f32,
+    pub cool_setpoint_c: f32,
+}
+
+impl Vacation {
+    pub fn is_active(&self, today: NaiveDate) -> bool {
+        (self.start..=self.end).contains(&today)
+    }
+
+    pub fn is_over(&self, today: NaiveDate) -> bool {
+        today > self.end
+    }
+
+    /// Setpoint to use while away for the given mode, None when off
+    pub fn setpoint_c(&self, mode: &ModeStatus) -> Option<f32> {
+        match mode {
+            ModeStatus::Heat => Some(self.heat_setpoint_c),
+            ModeStatus::Cool => Some(self.cool_setpoint_c),
+            ModeStatus::Off => None,
+        }
+    }
+}
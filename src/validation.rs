@@ -0,0 +1,451 @@
+// Validation and rate limiting for commands that don't come from the touch screen.
+// The touch ui can only produce sane values, network sources can send anything.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::auth::{ApiCredentials, MIN_PASSWORD_LEN, MIN_TOKEN_LEN};
+use crate::demand_response::MAX_EVENT_DURATION_MINS;
+use crate::events::{Command, CommandSource, UiEvent};
+use crate::frost_stat::FrostStat;
+use crate::hex;
+use crate::installer::{Secret, MAX_CODE_LEN, MIN_CODE_LEN};
+use crate::network::{NetworkSettings, MAX_HOSTNAME_LEN};
+use crate::remote_sensors::{Battery, BleSensor, SensorPeriod, SensorRef, WeightedSensor, MAX_REMOTE_SENSORS};
+use crate::schedule::{WeeklySchedule, MAX_SCHEDULE_EXCEPTIONS};
+use crate::signing::MIN_SECRET_LEN;
+use crate::timezone;
+
+/// Setpoints are clamped into this range, same as the ui slider (Celsius)
+pub const TARGET_TEMP_MIN_C: f32 = 15.0;
+pub const TARGET_TEMP_MAX_C: f32 = 27.0;
+/// Setpoints outside this range are considered absurd and rejected instead of clamped (Celsius)
+const TARGET_TEMP_REJECT_BELOW_C: f32 = 5.0;
+const TARGET_TEMP_REJECT_ABOVE_C: f32 = 35.0;
+/// Custom comfort settings are clamped into these ranges (Celsius, seconds)
+const DIFFERENTIAL_MIN_C: f32 = 0.2;
+const DIFFERENTIAL_MAX_C: f32 = 3.0;
+const ANTICIPATOR_MAX_C: f32 = 1.5;
+const FAN_RUN_ON_MAX_SECS: u32 = 10 * 60;
+/// Longest the fan timer can run
+const FAN_TIMER_MAX: Duration = Duration::from_hours(12);
+/// Outdoor temperature lockouts are clamped into this range (Celsius)
+const LOCKOUT_MIN_C: f32 = -40.0;
+const LOCKOUT_MAX_C: f32 = 40.0;
+/// Profile names have to fit on the main screen
+const MAX_SCHEDULE_PROFILE_NAME_LEN: usize = 16;
+/// Remote sensor names show next to the current temperature
+const MAX_SENSOR_NAME_LEN: usize = 16;
+/// Remote room temperatures outside this range are considered broken sensors (Celsius)
+const REMOTE_TEMP_MIN_C: f32 = -20.0;
+const REMOTE_TEMP_MAX_C: f32 = 60.0;
+/// Outdoor readings outside this range are taken as a broken sensor (Celsius)
+const OUTDOOR_TEMP_MIN_C: f32 = -60.0;
+const OUTDOOR_TEMP_MAX_C: f32 = 60.0;
+/// The high temperature cutoff is clamped into this range, above any setpoint (Celsius)
+const HIGH_TEMP_CUTOFF_MIN_C: f32 = 30.0;
+const HIGH_TEMP_CUTOFF_MAX_C: f32 = 60.0;
+/// Frost-stat thresholds and hysteresis are clamped into these ranges (Celsius)
+const FROST_STAT_MIN_C: f32 = -10.0;
+const FROST_STAT_MAX_C: f32 = 10.0;
+const FROST_STAT_HYSTERESIS_MIN_C: f32 = 0.5;
+const FROST_STAT_HYSTERESIS_MAX_C: f32 = 5.0;
+
+/// Number of commands a source can send back to back
+const RATE_LIMIT_BURST: f32 = 5.0;
+/// Commands per second a source regains after using up its burst
+const RATE_LIMIT_REFILL_PER_SEC: f32 = 0.5;
+
+#[derive(Debug, Clone, PartialEq, Serialize, thiserror::Error)]
+pub enum CommandRejection {
+    #[error("target temperature {0}°C is out of range")]
+    TargetTempOutOfRange(f32),
+    #[error("target temperature is not a number")]
+    TargetTempNotANumber,
+    #[error("comfort setting is not a number")]
+    ComfortSettingNotANumber,
+    #[error("end date is before start date")]
+    InvalidDateRange,
+    #[error("schedule profile name must be 1 to {MAX_SCHEDULE_PROFILE_NAME_LEN} characters")]
+    InvalidScheduleProfileName,
+    #[error("no schedule profile named {0:?}")]
+    UnknownScheduleProfile(String),
+    #[error("there already is a schedule profile named {0:?}")]
+    ScheduleProfileExists(String),
+    #[error("a schedule profile can have at most {MAX_SCHEDULE_EXCEPTIONS} exception days")]
+    TooManyScheduleExceptions,
+    #[error("too many commands from {0:?}")]
+    RateLimited(CommandSource),
+    #[error("lockout temperature is not a number")]
+    LockoutNotANumber,
+    #[error("wrong installer code")]
+    WrongInstallerCode,
+    #[error("only an installer can change this")]
+    InstallerOnly,
+    #[error("this system has no cooling")]
+    NoCooling,
+    #[error("this system has no heating")]
+    NoHeating,
+    #[error("this system has no fan")]
+    NoFan,
+    #[error("boost needs heat or cool mode")]
+    BoostNeedsMode,
+    #[error("the clock isn't set yet")]
+    ClockNotSet,
+    #[error("held by a recent change from {0:?}")]
+    HeldBy(CommandSource),
+    #[error("sensor name must be 1 to {MAX_SENSOR_NAME_LEN} characters")]
+    InvalidSensorName,
+    #[error("sensor reading out of range")]
+    InvalidSensorReading,
+    #[error("sensor weight must be a positive number")]
+    InvalidSensorWeight,
+    #[error("Bluetooth address must look like a4:c1:38:12:34:56")]
+    InvalidSensorAddress,
+    #[error("at most {MAX_REMOTE_SENSORS} remote sensors")]
+    TooManySensors,
+    #[error("only possible at the thermostat")]
+    TouchOnly,
+    #[error("ESP-NOW sensors aren't built in")]
+    NoEspNow,
+    #[error("hostname must be 1 to {MAX_HOSTNAME_LEN} letters, digits or dashes")]
+    InvalidHostname,
+    #[error("static address and gateway must be on the same subnet")]
+    InvalidStaticIp,
+    #[error("unknown time zone {0:?}")]
+    UnknownTimezone(String),
+    #[error("WireGuard keys must be base64 as printed by wg, with an endpoint and port")]
+    InvalidWireguardConfig,
+    #[error("demo temperature {0}°C is out of range")]
+    DemoTempOutOfRange(f32),
+    #[error("frost-stat setting is not a number")]
+    FrostStatNotANumber,
+    #[error("high temperature cutoff is not a number")]
+    CutoffNotANumber,
+    #[error("signing secret must be at least {MIN_SECRET_LEN} bytes, hex encoded")]
+    InvalidSigningSecret,
+    #[error("installer code must be {MIN_CODE_LEN} to {MAX_CODE_LEN} digits")]
+    InvalidInstallerCode,
+    #[error("api credentials need a token of at least {MIN_TOKEN_LEN} characters or a username and a password of at least {MIN_PASSWORD_LEN}")]
+    WeakApiCredentials,
+    #[error("TLS certificate and key must be PEM encoded")]
+    InvalidTlsMaterial,
+    #[error("the thermostat is busy, try again")]
+    QueueFull,
+    #[error("replaced by a newer setpoint")]
+    Superseded,
+}
+
+/// Check a command is sane, clamping values that are only slightly out of range.
+pub fn validate(command: Command) -> Result<Command, CommandRejection> {
+    let event = match command.event {
+        UiEvent::TargetTempUpdate(target_temp_c) => {
+            UiEvent::TargetTempUpdate(validate_target_temp(target_temp_c)?)
+        }
+        UiEvent::VacationUpdate(Some(mut vacation)) => {
+            if vacation.end < vacation.start {
+                return Err(CommandRejection::InvalidDateRange);
+            }
+            vacation.heat_setpoint_c = validate_target_temp(vacation.heat_setpoint_c)?;
+            vacation.cool_setpoint_c = validate_target_temp(vacation.cool_setpoint_c)?;
+            UiEvent::VacationUpdate(Some(vacation))
+        }
+        UiEvent::ScheduleExceptionUpdate { name, date, follows } => {
+            UiEvent::ScheduleExceptionUpdate { name: validate_schedule_profile_name(name)?, date, follows }
+        }
+        UiEvent::RenameScheduleProfile { from, to } => UiEvent::RenameScheduleProfile {
+            from: validate_schedule_profile_name(from)?,
+            to: validate_schedule_profile_name(to)?,
+        },
+        UiEvent::ScheduleProfileUpdate { name, schedule } => {
+            let name = validate_schedule_profile_name(name)?;
+            let schedule = schedule.map(validate_schedule).transpose()?;
+            UiEvent::ScheduleProfileUpdate { name, schedule }
+        }
+        UiEvent::RemoteTempUpdate { sensor, temp_c, battery } => validate_remote_temp(sensor, temp_c, battery)?,
+        UiEvent::OutdoorTempUpdate(temp_c) if !(OUTDOOR_TEMP_MIN_C..=OUTDOOR_TEMP_MAX_C).contains(&temp_c) => {
+            return Err(CommandRejection::InvalidSensorReading);
+        }
+        UiEvent::DemoTemperature(Some(temp_c)) if !(TARGET_TEMP_REJECT_BELOW_C..=TARGET_TEMP_REJECT_ABOVE_C).contains(&temp_c) => {
+            return Err(CommandRejection::DemoTempOutOfRange(temp_c));
+        }
+        UiEvent::BleSensorsUpdate(sensors) => UiEvent::BleSensorsUpdate(validate_ble_sensors(sensors)?),
+        UiEvent::UnpairSensor(sensor) => UiEvent::UnpairSensor(validate_sensor_name(sensor)?),
+        UiEvent::SensorSelectionUpdate { sensors, periods } => UiEvent::SensorSelectionUpdate {
+            sensors: validate_weighted_sensors(sensors)?,
+            periods: periods
+                .map(|periods| {
+                    periods
+                        .into_iter()
+                        .map(|period| Ok(SensorPeriod { sensors: validate_weighted_sensors(period.sensors)?, ..period }))
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?,
+        },
+        UiEvent::ImportConfig(mut backup) => {
+            backup.settings.target_temp_c = validate_target_temp(backup.settings.target_temp_c)?;
+            backup.settings.heat_setpoint_c = validate_target_temp(backup.settings.heat_setpoint_c)?;
+            backup.settings.cool_setpoint_c = validate_target_temp(backup.settings.cool_setpoint_c)?;
+            backup.settings.ble_sensors = validate_ble_sensors(std::mem::take(&mut backup.settings.ble_sensors))?;
+            backup.settings.timezone = validate_timezone(std::mem::take(&mut backup.settings.timezone))?;
+            backup.settings.network = validate_network_settings(std::mem::take(&mut backup.settings.network))?;
+            backup.settings.frost_stat = backup.settings.frost_stat.take().map(validate_frost_stat).transpose()?;
+            backup.settings.high_temp_cutoff_c = validate_high_temp_cutoff(backup.settings.high_temp_cutoff_c)?;
+            if backup.settings.wireguard.as_ref().is_some_and(|wireguard| !wireguard.is_valid()) {
+                return Err(CommandRejection::InvalidWireguardConfig);
+            }
+            for profile in backup.schedule_profiles.iter_mut() {
+                profile.name = validate_schedule_profile_name(std::mem::take(&mut profile.name))?;
+                profile.schedule = validate_schedule(profile.schedule.clone())?;
+            }
+            UiEvent::ImportConfig(backup)
+        }
+        UiEvent::CustomComfortUpdate(mut comfort) => {
+            if [comfort.heat_differential_c, comfort.cool_differential_c, comfort.heat_anticipator_c].iter().any(|value| value.is_nan()) {
+                return Err(CommandRejection::ComfortSettingNotANumber);
+            }
+            comfort.heat_differential_c = comfort.heat_differential_c.clamp(DIFFERENTIAL_MIN_C, DIFFERENTIAL_MAX_C);
+            comfort.cool_differential_c = comfort.cool_differential_c.clamp(DIFFERENTIAL_MIN_C, DIFFERENTIAL_MAX_C);
+            comfort.heat_anticipator_c = comfort.heat_anticipator_c.clamp(0.0, ANTICIPATOR_MAX_C);
+            comfort.heat_fan_run_on_secs = comfort.heat_fan_run_on_secs.min(FAN_RUN_ON_MAX_SECS);
+            comfort.cool_fan_run_on_secs = comfort.cool_fan_run_on_secs.min(FAN_RUN_ON_MAX_SECS);
+            UiEvent::CustomComfortUpdate(comfort)
+        }
+        UiEvent::SeasonalLockoutUpdate { heat_above_c, cool_below_c } => UiEvent::SeasonalLockoutUpdate {
+            heat_above_c: validate_lockout(heat_above_c)?,
+            cool_below_c: validate_lockout(cool_below_c)?,
+        },
+        UiEvent::InstallerSettingsUpdate(mut installer_settings) => {
+            installer_settings.heat_lockout_above_c = validate_lockout(installer_settings.heat_lockout_above_c)?;
+            installer_settings.cool_lockout_below_c = validate_lockout(installer_settings.cool_lockout_below_c)?;
+            installer_settings.high_temp_cutoff_c = validate_high_temp_cutoff(installer_settings.high_temp_cutoff_c)?;
+            installer_settings.frost_stat = installer_settings.frost_stat.map(validate_frost_stat).transpose()?;
+            UiEvent::InstallerSettingsUpdate(installer_settings)
+        }
+        UiEvent::TimezoneUpdate(timezone) => UiEvent::TimezoneUpdate(validate_timezone(timezone)?),
+        UiEvent::NetworkSettingsUpdate(network) => UiEvent::NetworkSettingsUpdate(validate_network_settings(network)?),
+        UiEvent::ManualBrightnessUpdate(percent) => UiEvent::ManualBrightnessUpdate(percent.min(100)),
+        UiEvent::FanTimer(duration) => UiEvent::FanTimer(duration.min(FAN_TIMER_MAX)),
+        UiEvent::SetSigningSecret(Some(secret)) => UiEvent::SetSigningSecret(Some(validate_signing_secret(secret)?)),
+        UiEvent::SetInstallerCode(Some(code)) => UiEvent::SetInstallerCode(Some(validate_installer_code(code)?)),
+        UiEvent::SetApiCredentials(credentials) => UiEvent::SetApiCredentials(validate_api_credentials(credentials)?),
+        UiEvent::SetTlsMaterial(Some(tls)) if !tls.is_valid() => return Err(CommandRejection::InvalidTlsMaterial),
+        UiEvent::DemandResponseSignal(Some(duration_mins)) => {
+            UiEvent::DemandResponseSignal(Some(duration_mins.min(MAX_EVENT_DURATION_MINS)))
+        }
+        // Enum values are checked when the transport decodes them
+        event => event,
+    };
+    Ok(Command { event, ..command })
+}
+
+fn validate_lockout(lockout_c: Option<f32>) -> Result<Option<f32>, CommandRejection> {
+    match lockout_c {
+        Some(lockout_c) if lockout_c.is_nan() => Err(CommandRejection::LockoutNotANumber),
+        lockout_c => Ok(lockout_c.map(|lockout_c| lockout_c.clamp(LOCKOUT_MIN_C, LOCKOUT_MAX_C))),
+    }
+}
+
+fn validate_high_temp_cutoff(cutoff_c: Option<f32>) -> Result<Option<f32>, CommandRejection> {
+    match cutoff_c {
+        Some(cutoff_c) if cutoff_c.is_nan() => Err(CommandRejection::CutoffNotANumber),
+        cutoff_c => Ok(cutoff_c.map(|cutoff_c| cutoff_c.clamp(HIGH_TEMP_CUTOFF_MIN_C, HIGH_TEMP_CUTOFF_MAX_C))),
+    }
+}
+
+fn validate_frost_stat(mut frost_stat: FrostStat) -> Result<FrostStat, CommandRejection> {
+    if frost_stat.threshold_c.is_nan() || frost_stat.hysteresis_c.is_nan() {
+        return Err(CommandRejection::FrostStatNotANumber);
+    }
+    frost_stat.threshold_c = frost_stat.threshold_c.clamp(FROST_STAT_MIN_C, FROST_STAT_MAX_C);
+    frost_stat.hysteresis_c = frost_stat.hysteresis_c.clamp(FROST_STAT_HYSTERESIS_MIN_C, FROST_STAT_HYSTERESIS_MAX_C);
+    for sensor in frost_stat.sensors.iter_mut() {
+        if let SensorRef::Remote(name) = sensor {
+            *name = validate_sensor_name(std::mem::take(name))?;
+        }
+    }
+    Ok(frost_stat)
+}
+
+fn validate_signing_secret(secret: Secret) -> Result<Secret, CommandRejection> {
+    let hex = secret.0.trim().to_ascii_lowercase();
+    match hex::decode(&hex) {
+        Ok(bytes) if bytes.len() >= MIN_SECRET_LEN => Ok(Secret(hex)),
+        _ => Err(CommandRejection::InvalidSigningSecret),
+    }
+}
+
+/// Installer codes have to be enterable on the keypad
+fn validate_installer_code(code: Secret) -> Result<Secret, CommandRejection> {
+    let code = code.0.trim();
+    if !(MIN_CODE_LEN..=MAX_CODE_LEN).contains(&code.len()) || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(CommandRejection::InvalidInstallerCode);
+    }
+    Ok(Secret(code.to_string()))
+}
+
+/// Refuse credentials that would lock everyone out or are easy to guess
+fn validate_api_credentials(mut credentials: ApiCredentials) -> Result<ApiCredentials, CommandRejection> {
+    credentials.token = credentials.token.filter(|token| !token.0.trim().is_empty());
+    if credentials.token.as_ref().is_some_and(|token| token.0.trim().len() < MIN_TOKEN_LEN) {
+        return Err(CommandRejection::WeakApiCredentials);
+    }
+    if let Some((username, password)) = &credentials.basic {
+        if username.trim().is_empty() || username.contains(':') || password.0.len() < MIN_PASSWORD_LEN {
+            return Err(CommandRejection::WeakApiCredentials);
+        }
+    }
+    if !credentials.is_configured() {
+        return Err(CommandRejection::WeakApiCredentials);
+    }
+    Ok(credentials)
+}
+
+/// Reject absurd setpoints and clamp the rest into the range the ui allows.
+fn validate_target_temp(target_temp_c: f32) -> Result<f32, CommandRejection> {
+    if target_temp_c.is_nan() {
+        return Err(CommandRejection::TargetTempNotANumber);
+    }
+    if !(TARGET_TEMP_REJECT_BELOW_C..=TARGET_TEMP_REJECT_ABOVE_C).contains(&target_temp_c) {
+        return Err(CommandRejection::TargetTempOutOfRange(target_temp_c));
+    }
+    Ok(target_temp_c.clamp(TARGET_TEMP_MIN_C, TARGET_TEMP_MAX_C))
+}
+
+fn validate_schedule(mut schedule: WeeklySchedule) -> Result<WeeklySchedule, CommandRejection> {
+    if schedule.exceptions.len() > MAX_SCHEDULE_EXCEPTIONS {
+        return Err(CommandRejection::TooManyScheduleExceptions);
+    }
+    for period in schedule.days.iter_mut().flatten() {
+        period.heat_setpoint_c = validate_target_temp(period.heat_setpoint_c)?;
+        period.cool_setpoint_c = validate_target_temp(period.cool_setpoint_c)?;
+    }
+    for day in schedule.days.iter_mut() {
+        day.sort_by_key(|period| period.start);
+    }
+    Ok(schedule)
+}
+
+fn validate_schedule_profile_name(name: String) -> Result<String, CommandRejection> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_SCHEDULE_PROFILE_NAME_LEN {
+        return Err(CommandRejection::InvalidScheduleProfileName);
+    }
+    Ok(name.to_string())
+}
+
+/// Checks a remote sensor reading, for the network api and the sensor radios alike
+pub fn validate_remote_temp(sensor: String, temp_c: f32, battery: Option<Battery>) -> Result<UiEvent, CommandRejection> {
+    if !(REMOTE_TEMP_MIN_C..=REMOTE_TEMP_MAX_C).contains(&temp_c) {
+        return Err(CommandRejection::InvalidSensorReading);
+    }
+    if battery.and_then(|battery| battery.percent).is_some_and(|percent| percent > 100) {
+        return Err(CommandRejection::InvalidSensorReading);
+    }
+    Ok(UiEvent::RemoteTempUpdate { sensor: validate_sensor_name(sensor)?, temp_c, battery })
+}
+
+pub fn validate_sensor_name(name: String) -> Result<String, CommandRejection> {
+    let name = name.trim();
+    if name.is_empty() || name.chars().count() > MAX_SENSOR_NAME_LEN {
+        return Err(CommandRejection::InvalidSensorName);
+    }
+    Ok(name.to_string())
+}
+
+fn validate_weighted_sensors(sensors: Vec<WeightedSensor>) -> Result<Vec<WeightedSensor>, CommandRejection> {
+    sensors
+        .into_iter()
+        .map(|weighted| {
+            if !weighted.weight.is_finite() || weighted.weight < 0.0 {
+                return Err(CommandRejection::InvalidSensorWeight);
+            }
+            let sensor = match weighted.sensor {
+                SensorRef::Remote(name) => SensorRef::Remote(validate_sensor_name(name)?),
+                onboard => onboard,
+            };
+            Ok(WeightedSensor { sensor, ..weighted })
+        })
+        .collect()
+}
+
+fn validate_ble_sensors(sensors: Vec<BleSensor>) -> Result<Vec<BleSensor>, CommandRejection> {
+    if sensors.len() > MAX_REMOTE_SENSORS {
+        return Err(CommandRejection::TooManySensors);
+    }
+    sensors
+        .into_iter()
+        .map(|sensor| {
+            let address = sensor.address.trim().to_ascii_lowercase();
+            let valid = address.len() == 17
+                && address.split(':').all(|byte| byte.len() == 2 && byte.chars().all(|c| c.is_ascii_hexdigit()));
+            if !valid {
+                return Err(CommandRejection::InvalidSensorAddress);
+            }
+            Ok(BleSensor { address, name: validate_sensor_name(sensor.name)? })
+        })
+        .collect()
+}
+
+fn validate_timezone(timezone: String) -> Result<String, CommandRejection> {
+    let timezone = timezone.trim().to_string();
+    if timezone::posix(&timezone).is_none() {
+        return Err(CommandRejection::UnknownTimezone(timezone));
+    }
+    Ok(timezone)
+}
+
+fn validate_network_settings(mut network: NetworkSettings) -> Result<NetworkSettings, CommandRejection> {
+    network.hostname = network.hostname.trim().to_ascii_lowercase();
+    let hostname = network.hostname.as_str();
+    let valid = (1..=MAX_HOSTNAME_LEN).contains(&hostname.len())
+        && hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        && !hostname.starts_with('-')
+        && !hostname.ends_with('-');
+    if !valid {
+        return Err(CommandRejection::InvalidHostname);
+    }
+    if network.static_ip.is_some_and(|static_ip| !static_ip.is_valid()) {
+        return Err(CommandRejection::InvalidStaticIp);
+    }
+    Ok(network)
+}
+
+struct Bucket {
+    tokens: f32,
+    last_refill: Instant,
+}
+
+/// Token bucket rate limiter, one bucket per command source.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<CommandSource, Bucket>,
+}
+
+impl RateLimiter {
+    /// Take a token for `source`, failing if it has sent too many commands recently.
+    pub fn check(&mut self, source: CommandSource) -> Result<(), CommandRejection> {
+        let now = Instant::now();
+        let bucket = self.buckets.entry(source).or_insert(Bucket {
+            tokens: RATE_LIMIT_BURST,
+            last_refill: now,
+        });
+        let refill = now.duration_since(bucket.last_refill).as_secs_f32() * RATE_LIMIT_REFILL_PER_SEC;
+        bucket.tokens = (bucket.tokens + refill).min(RATE_LIMIT_BURST);
+        bucket.last_refill = now;
+        if bucket.tokens < 1.0 {
+            return Err(CommandRejection::RateLimited(source));
+        }
+        bucket.tokens -= 1.0;
+        Ok(())
+    }
+}
+
+impl CommandSource {
+    /// Commands from the network have to be validated and rate limited before use
+    pub fn is_network(&self) -> bool {
+        matches!(self, CommandSource::Mqtt | CommandSource::Http)
+    }
+}
@@ -0,0 +1,49 @@
+// Fire and forget POSTs to user configured urls.
+
+use std::thread;
+
+use embedded_svc::http::client::Client;
+use embedded_svc::io::Write;
+use esp_idf_svc::http::client::{Configuration, EspHttpConnection};
+
+/// TLS needs a lot more stack than the default pthread size
+const WEBHOOK_STACK_SIZE: usize = 8192;
+
+/// POST a JSON `body` to `url` on a background thread so the control loop never waits on the network.
+pub fn post_json_async(url: String, body: String) {
+    post_async(url, vec![("content-type".to_string(), "application/json".to_string())], body);
+}
+
+/// POST `body` with the given headers to `url` on a background thread.
+pub fn post_async(url: String, headers: Vec<(String, String)>, body: String) {
+    let spawned = thread::Builder::new()
+        .stack_size(WEBHOOK_STACK_SIZE)
+        .spawn(move || {
+            if let Err(e) = post(&url, &headers, &body) {
+                log::error!("POST to {} failed: {}", url, e);
+            }
+        });
+    if let Err(e) = spawned {
+        log::error!("Failed to spawn webhook thread: {}", e);
+    }
+}
+
+pub fn post(url: &str, headers: &[(String, String)], body: &str) -> anyhow::Result<()> {
+    let connection = EspHttpConnection::new(&Configuration {
+        crt_bundle_attach: Some(esp_idf_svc::sys::esp_crt_bundle_attach),
+        ..Default::default()
+    })?;
+    let mut client = Client::wrap(connection);
+    let content_length = body.len().to_string();
+    let mut all_headers: Vec<(&str, &str)> = headers.iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+    all_headers.push(("content-length", content_length.as_str()));
+    let mut request = client.post(url, &all_headers)?;
+    request.write_all(body.as_bytes())?;
+    request.flush()?;
+    let response = request.submit()?;
+    let status = response.status();
+    if !(200..300).contains(&status) {
+        anyhow::bail!("unexpected status {}", status);
+    }
+    Ok(())
+}
@@ -0,0 +1,222 @@
+// WireGuard client (the esp_wireguard component) for remote access without port forwarding or
+// a vendor cloud. The thermostat is one peer of a WireGuard server the user runs, on their
+// router or a small VPS; every phone or laptop that should reach the thermostat is another
+// peer of that server, so any number of users get in through it while the thermostat only
+// knows the one peer. Traffic from the LAN keeps going straight over Wi-Fi, only the tunnel
+// address is routed through WireGuard.
+//
+// Configured through `Settings::wireguard`, usually from a settings import. The backend hands
+// the configuration over with `configure`, the network thread brings the tunnel up once the
+// station is online and the clock is set (the handshake carries a timestamp the server checks),
+// and restarts it when the configuration changes.
+
+use std::ffi::CString;
+use std::fmt;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use esp_idf_svc::sys::{
+    esp_wireguard_connect, esp_wireguard_disconnect, esp_wireguard_init, esp_wireguardif_peer_is_up, wireguard_config_t,
+    wireguard_ctx_t, ESP_OK,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::clock;
+
+/// Time between attempts to bring the tunnel up while it fails, e.g. the endpoint doesn't resolve
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+/// Local UDP port the tunnel sends from and listens on
+const LISTEN_PORT: i32 = 51820;
+/// Length of a base64 encoded Curve25519 key
+const KEY_LEN: usize = 44;
+
+static CONFIG: Mutex<Option<WireguardSettings>> = Mutex::new(None);
+static UP: AtomicBool = AtomicBool::new(false);
+
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+pub struct WireguardSettings {
+    /// The thermostat's private key, base64 as printed by `wg genkey`
+    pub private_key: String,
+    /// The thermostat's address inside the tunnel, e.g. 10.7.0.5
+    pub address: Ipv4Addr,
+    /// Netmask of the tunnel network, traffic to it goes through the tunnel
+    pub netmask: Ipv4Addr,
+    /// The server's public key, base64
+    pub peer_public_key: String,
+    /// Optional extra symmetric key shared with the server, base64
+    pub preshared_key: Option<String>,
+    /// Host name or IP address of the server
+    pub endpoint: String,
+    pub port: u16,
+    /// Keeps the NAT mapping on the home router open so the server can always reach the
+    /// thermostat (seconds, 0 to disable)
+    pub persistent_keepalive_secs: u16,
+}
+
+/// Leaves the private and preshared keys out, commands end up in the audit log as Debug output
+impl fmt::Debug for WireguardSettings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WireguardSettings")
+            .field("address", &self.address)
+            .field("netmask", &self.netmask)
+            .field("peer_public_key", &self.peer_public_key)
+            .field("endpoint", &self.endpoint)
+            .field("port", &self.port)
+            .field("persistent_keepalive_secs", &self.persistent_keepalive_secs)
+            .finish_non_exhaustive()
+    }
+}
+
+impl WireguardSettings {
+    /// Whether the keys look like WireGuard keys and the endpoint can be handed to the component
+    pub fn is_valid(&self) -> bool {
+        let key_valid = |key: &str| key.len() == KEY_LEN && key.ends_with('=') && key.bytes().all(|b| b.is_ascii_alphanumeric() || b"+/=".contains(&b));
+        key_valid(&self.private_key)
+            && key_valid(&self.peer_public_key)
+            && self.preshared_key.as_deref().is_none_or(key_valid)
+            && !self.endpoint.is_empty()
+            && !self.endpoint.contains('\0')
+            && self.port != 0
+    }
+}
+
+/// Hand the configuration from the settings to the network thread, None to take the tunnel down
+pub fn configure(settings: Option<&WireguardSettings>) {
+    if let Ok(mut config) = CONFIG.lock() {
+        if config.as_ref() != settings {
+            *config = settings.cloned();
+        }
+    }
+}
+
+/// Whether the tunnel is up, i.e. the handshake with the server went through
+pub fn is_up() -> bool {
+    UP.load(Ordering::Relaxed)
+}
+
+/// Whether a tunnel is configured at all
+pub fn is_configured() -> bool {
+    CONFIG.lock().is_ok_and(|config| config.is_some())
+}
+
+/// A running tunnel. The component keeps pointers into the strings and the config for as long
+/// as it runs, so they live here next to the context.
+struct Running {
+    settings: WireguardSettings,
+    _strings: Vec<CString>,
+    _config: Box<wireguard_config_t>,
+    ctx: Box<wireguard_ctx_t>,
+}
+
+impl Running {
+    fn start(settings: WireguardSettings) -> anyhow::Result<Self> {
+        let cstring = |s: &str| CString::new(s).map_err(|_| anyhow!("WireGuard setting contains a NUL byte"));
+        let mut strings = vec![
+            cstring(&settings.private_key)?,
+            cstring(&settings.peer_public_key)?,
+            cstring(&settings.address.to_string())?,
+            cstring(&settings.netmask.to_string())?,
+            cstring(&settings.endpoint)?,
+        ];
+        if let Some(preshared_key) = &settings.preshared_key {
+            strings.push(cstring(preshared_key)?);
+        }
+        let mut config = Box::new(wireguard_config_t {
+            private_key: strings[0].as_ptr() as *mut _,
+            listen_port: LISTEN_PORT,
+            fw_mark: 0,
+            public_key: strings[1].as_ptr() as *mut _,
+            preshared_key: strings.get(5).map_or(std::ptr::null_mut(), |key| key.as_ptr() as *mut _),
+            // The component uses the allowed ip both as the interface address and as what is
+            // routed to the peer
+            allowed_ip: strings[2].as_ptr() as *mut _,
+            allowed_ip_mask: strings[3].as_ptr() as *mut _,
+            endpoint: strings[4].as_ptr() as *mut _,
+            port: settings.port as i32,
+            persistent_keepalive: settings.persistent_keepalive_secs as i32,
+        });
+        let mut ctx = Box::new(wireguard_ctx_t {
+            config: std::ptr::null_mut(),
+            netif: std::ptr::null_mut(),
+            netif_default: std::ptr::null_mut(),
+        });
+        // SAFETY: config and the strings it points to are boxed and kept alive in `Running`
+        // until after `esp_wireguard_disconnect`
+        let err = unsafe { esp_wireguard_init(config.as_mut(), ctx.as_mut()) };
+        if err != ESP_OK {
+            anyhow::bail!("esp_wireguard_init failed ({})", err);
+        }
+        // Dropped on failure from here on, which disconnects whatever connect got to set up
+        let mut running = Self { settings, _strings: strings, _config: config, ctx };
+        // SAFETY: ctx was initialized above
+        let err = unsafe { esp_wireguard_connect(running.ctx.as_mut()) };
+        if err != ESP_OK {
+            anyhow::bail!("esp_wireguard_connect failed ({})", err);
+        }
+        Ok(running)
+    }
+
+    fn peer_is_up(&mut self) -> bool {
+        // SAFETY: ctx is connected for as long as `Running` exists
+        unsafe { esp_wireguardif_peer_is_up(self.ctx.as_mut()) == ESP_OK }
+    }
+}
+
+impl Drop for Running {
+    fn drop(&mut self) {
+        // SAFETY: ctx is connected, the config it points to is dropped only after this
+        let err = unsafe { esp_wireguard_disconnect(self.ctx.as_mut()) };
+        if err != ESP_OK {
+            log::error!("esp_wireguard_disconnect failed ({})", err);
+        }
+    }
+}
+
+/// Owned by the network thread, brings the configured tunnel up and down with the connection
+#[derive(Default)]
+pub struct Tunnel {
+    running: Option<Running>,
+    last_attempt: Option<Instant>,
+}
+
+impl Tunnel {
+    /// Call periodically while the station is connected
+    pub fn update(&mut self) {
+        let wanted = CONFIG.lock().ok().and_then(|config| config.clone());
+        if self.running.as_ref().is_some_and(|running| Some(&running.settings) != wanted.as_ref()) {
+            log::info!("WireGuard configuration changed, restarting the tunnel");
+            self.stop();
+        }
+        if let Some(running) = &mut self.running {
+            let up = running.peer_is_up();
+            if UP.swap(up, Ordering::Relaxed) != up {
+                log::info!("WireGuard tunnel {}", if up { "up" } else { "down, waiting for the server" });
+            }
+            return;
+        }
+        let Some(settings) = wanted else {
+            return;
+        };
+        if !clock::is_set() || self.last_attempt.is_some_and(|at| at.elapsed() < RETRY_INTERVAL) {
+            return;
+        }
+        self.last_attempt = Some(Instant::now());
+        log::info!("Starting WireGuard tunnel to {}:{} as {}", settings.endpoint, settings.port, settings.address);
+        match Running::start(settings) {
+            Ok(running) => self.running = Some(running),
+            Err(e) => log::warn!("Failed to start WireGuard, retrying in {}s: {}", RETRY_INTERVAL.as_secs(), e),
+        }
+    }
+
+    /// Take the tunnel down, e.g. because Wi-Fi was lost. `update` brings it back.
+    pub fn stop(&mut self) {
+        if self.running.take().is_some() {
+            log::info!("WireGuard tunnel stopped");
+        }
+        self.last_attempt = None;
+        UP.store(false, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,86 @@
+// Installer wiring check: what each relay does with the current system profile, plus warnings
+// for combinations that won't work the way the installer probably expects. Lines starting with
+// "!" are warnings.
+
+use crate::settings::Settings;
+use crate::system_profile::SystemProfile;
+
+pub fn check(settings: &Settings) -> Vec<String> {
+    let system = settings.system;
+    let mut lines = Vec::new();
+    match system {
+        SystemProfile::ForcedAir => {
+            lines.push("Heat (W): furnace or heat strips".to_string());
+            match settings.dual_fuel {
+                Some(_) => {
+                    lines.push("Cool (Y): compressor, cooling and heat pump heat".to_string());
+                    lines.push("Reversing valve (O/B): heat pump".to_string());
+                }
+                None => {
+                    lines.push("Cool (Y): compressor".to_string());
+                    lines.push("Reversing valve (O/B): unused".to_string());
+                }
+            }
+            lines.push("Fan (G): blower, runs with every call".to_string());
+        }
+        SystemProfile::Hydronic(hydronic) => {
+            lines.push("Heat (W): zone valve".to_string());
+            lines.push("Cool (Y): unused".to_string());
+            lines.push("Reversing valve (O/B): unused".to_string());
+            lines.push("Fan (G): circulator pump, starts once the valve is open".to_string());
+            if hydronic.end_switch {
+                lines.push("End switch input: zone valve end switch".to_string());
+                if cfg!(not(feature = "end-switch")) {
+                    lines.push("! End switch configured, but this build has no end switch input".to_string());
+                }
+            }
+        }
+        SystemProfile::DryContact(dry_contact) => {
+            lines.push(match (dry_contact.heat, dry_contact.millivolt) {
+                (true, true) => "Heat (W): dry contact to the millivolt valve".to_string(),
+                (true, false) => "Heat (W): dry contact, closes on heat calls".to_string(),
+                (false, _) => "Heat (W): unused".to_string(),
+            });
+            lines.push(if dry_contact.cool { "Cool (Y): dry contact, closes on cool calls" } else { "Cool (Y): unused" }.to_string());
+            lines.push("Reversing valve (O/B): unused".to_string());
+            lines.push(if dry_contact.fan { "Fan (G): dry contact, fan mode On only" } else { "Fan (G): unused" }.to_string());
+            if !dry_contact.heat && !dry_contact.cool {
+                lines.push("! No heat or cool relay in use, nothing will be called".to_string());
+            }
+            if dry_contact.millivolt {
+                lines.push("! Millivolt valve: wire only the two valve leads to the heat relay, never 24 VAC".to_string());
+                lines.push("! Millivolt systems have no 24 VAC, power the thermostat from its own supply".to_string());
+                if dry_contact.cool {
+                    lines.push("! Cool relay in use on a millivolt system, check it's really wired to cooling".to_string());
+                }
+            }
+            if settings.dual_fuel.is_some() {
+                lines.push("! Dual fuel is ignored on dry contact".to_string());
+            }
+            if settings.cool_fan_lead_secs > 0 && dry_contact.cool {
+                lines.push("! Fan lead is ignored, the fan never runs with calls".to_string());
+            }
+        }
+    }
+    if let Some(ventilation) = settings.ventilation {
+        lines.push("Ventilation: ERV/HRV call".to_string());
+        if cfg!(not(feature = "erv")) {
+            lines.push("! Ventilation configured, but this build has no ventilation relay".to_string());
+        }
+        if ventilation.interlock.require_fan && !system.has_fan() {
+            lines.push("! Ventilation needs the fan, but there is no fan relay in use".to_string());
+        }
+    }
+    if let Some(frost_stat) = &settings.frost_stat {
+        lines.push(format!("Frost-stat: closes below {:.1}°C", frost_stat.threshold_c));
+        if cfg!(not(feature = "frost-stat")) {
+            lines.push("! Frost-stat configured, but this build has no frost-stat relay".to_string());
+        }
+        if frost_stat.sensors.is_empty() {
+            lines.push("! Frost-stat watches no sensors, it will never close".to_string());
+        }
+    }
+    // The relays only ever switch, nothing is drawn through them
+    lines.push("Power: C wire or separate supply required, no power stealing".to_string());
+    lines
+}